@@ -0,0 +1,76 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use ibcspsuedolang::ast::{AstNode, NodeKind};
+use ibcspsuedolang::flat::{FlatAst, FlatKind};
+use ibcspsuedolang::formatter;
+use ibcspsuedolang::generate::{self, GeneratorOptions};
+use ibcspsuedolang::interpreter::Interpreter;
+use ibcspsuedolang::keywords::Keywords;
+use ibcspsuedolang::lexer::Lexer;
+use ibcspsuedolang::parser::Parser;
+use std::io;
+use std::sync::Arc;
+
+// Compares the boxed tree the parser builds with `FlatAst` on a large
+// generated program. Run with `cargo bench --bench ast`.
+//
+// - `build` times parsing alone, and parsing then flattening, to show what
+//   the flat form costs to make.
+// - `walk` times a pass over every node that counts the identifiers, through
+//   `AstNode::walk` and through `FlatAst::ids`.
+// - `interpret` times a run of the tree, for comparing an interpreter over
+//   the flat form with later.
+
+fn program() -> String {
+    let options = GeneratorOptions { seed: 7, statements: 400, depth: 3, functions: 20, ..GeneratorOptions::default() };
+    formatter::format(&generate::generate(&options), &Keywords::english(), false)
+}
+
+fn parse(source: &str, keywords: &Keywords) -> AstNode {
+    let mut parser = Parser::new(Lexer::new(source).with_keywords(keywords)).expect("generated programs lex");
+    parser.parse().expect("generated programs parse")
+}
+
+fn build(c: &mut Criterion) {
+    let source = program();
+    let keywords = Keywords::english();
+    let mut group = c.benchmark_group("build");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("tree", |b| b.iter(|| parse(black_box(&source), &keywords)));
+    group.bench_function("tree then flat", |b| {
+        b.iter(|| FlatAst::new(&parse(black_box(&source), &keywords)))
+    });
+    group.finish();
+}
+
+fn walk(c: &mut Criterion) {
+    let tree = parse(&program(), &Keywords::english());
+    let flat = FlatAst::new(&tree);
+    let mut group = c.benchmark_group("walk");
+    group.throughput(Throughput::Elements(flat.len() as u64));
+    group.bench_function("tree", |b| {
+        b.iter(|| {
+            let mut count = 0;
+            black_box(&tree).walk(&mut |node| count += matches!(node.kind, NodeKind::Identifier(_)) as usize);
+            count
+        })
+    });
+    group.bench_function("flat", |b| {
+        let flat = black_box(&flat);
+        b.iter(|| flat.ids().filter(|&id| matches!(flat.kind(id), FlatKind::Identifier(_))).count())
+    });
+    group.finish();
+}
+
+fn interpret(c: &mut Criterion) {
+    let tree = Arc::new(parse(&program(), &Keywords::english()));
+    c.bench_function("interpret/tree", |b| {
+        b.iter_batched(
+            || Interpreter::with_output(Box::new(io::sink())),
+            |mut interpreter| interpreter.interpret(tree.clone()).expect("generated programs run"),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, build, walk, interpret);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+#![no_main]
+
+use ibcspsuedolang::ast::AstNode;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|program: AstNode| {
+    ibcspsuedolang::fuzz::format(&program);
+});
@@ -0,0 +1,129 @@
+"""Jupyter kernel for IB pseudocode.
+
+Cells run in one `ibcspsuedolang rpc` session, so variables and procedures
+defined in one cell can be used in the next, and inspecting a name (Shift+Tab)
+shows its value. To install, put this file on
+PYTHONPATH, `ibcspsuedolang` on PATH, and run
+
+    jupyter kernelspec install jupyter/ibcs --user
+
+Programs cannot read input in a notebook; `input` fails with E0206.
+"""
+
+import json
+import subprocess
+
+from ipykernel.kernelapp import IPKernelApp
+from ipykernel.kernelbase import Kernel
+
+
+class IbcsKernel(Kernel):
+    implementation = "ibcspsuedolang"
+    implementation_version = "0.1.0"
+    banner = "IB Computer Science pseudocode"
+    language_info = {
+        "name": "ib-pseudocode",
+        "mimetype": "text/plain",
+        "file_extension": ".txt",
+    }
+
+    def __init__(self, **kwargs):
+        super().__init__(**kwargs)
+        self.requests = 0
+        self.start_session()
+
+    def start_session(self):
+        self.process = subprocess.Popen(
+            ["ibcspsuedolang", "rpc"],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            text=True,
+        )
+
+    def request(self, method, params, on_output):
+        self.requests += 1
+        message = {"jsonrpc": "2.0", "id": self.requests, "method": method, "params": params}
+        self.process.stdin.write(json.dumps(message) + "\n")
+        self.process.stdin.flush()
+        while True:
+            line = self.process.stdout.readline()
+            if not line:
+                raise RuntimeError("ibcspsuedolang rpc exited")
+            message = json.loads(line)
+            if message.get("method") == "output":
+                on_output(message["params"]["text"])
+            elif message.get("id") == self.requests:
+                if "error" in message:
+                    raise RuntimeError(message["error"]["message"])
+                return message["result"]
+
+    def stream(self, name, text):
+        self.send_response(self.iopub_socket, "stream", {"name": name, "text": text})
+
+    def do_execute(self, code, silent, store_history=True, user_expressions=None, allow_stdin=False):
+        def on_output(text):
+            if not silent:
+                self.stream("stdout", text)
+
+        result = self.request("execute", {"source": code}, on_output)
+        warnings = [describe(d) for d in result["diagnostics"] if d["severity"] == "warning"]
+        if warnings and not silent:
+            self.stream("stderr", "\n".join(warnings) + "\n")
+        diagnostics = [d for d in result["diagnostics"] if d["severity"] == "error"]
+        if not diagnostics:
+            return {
+                "status": "ok",
+                "execution_count": self.execution_count,
+                "payload": [],
+                "user_expressions": {},
+            }
+
+        traceback = [describe(diagnostic) for diagnostic in diagnostics]
+        if not silent:
+            self.stream("stderr", "\n".join(traceback) + "\n")
+        return {
+            "status": "error",
+            "execution_count": self.execution_count,
+            "ename": diagnostics[0]["code"],
+            "evalue": diagnostics[0]["message"],
+            "traceback": traceback,
+        }
+
+    def do_inspect(self, code, cursor_pos, detail_level=0, omit_sections=()):
+        name = word_at(code, cursor_pos)
+        variables = self.request("variables", {"items": 100 if detail_level else 10}, lambda text: None)["variables"]
+        found = name in variables
+        return {
+            "status": "ok",
+            "found": found,
+            "data": {"text/plain": "{} = {}".format(name, variables[name])} if found else {},
+            "metadata": {},
+        }
+
+    def do_shutdown(self, restart):
+        self.process.stdin.close()
+        self.process.wait()
+        if restart:
+            self.start_session()
+        return {"status": "ok", "restart": restart}
+
+
+def word_at(code, position):
+    start = end = position
+    while start > 0 and code[start - 1].isalnum():
+        start -= 1
+    while end < len(code) and code[end].isalnum():
+        end += 1
+    return code[start:end]
+
+
+def describe(diagnostic):
+    text = "{}[{}]: {}".format(diagnostic["kind"].capitalize(), diagnostic["code"], diagnostic["message"])
+    span = diagnostic.get("span")
+    if span:
+        text += " at line {}, column {}".format(span["line"], span["column"])
+    return text
+
+
+if __name__ == "__main__":
+    IPKernelApp.launch_instance(kernel_class=IbcsKernel)
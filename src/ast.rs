@@ -1,14 +1,518 @@
-use crate::lexer::Token;
+use crate::color::Colors;
+use crate::keywords::Keywords;
+use crate::lexer::{Span, Token};
+use crate::visit::{self, Fold, Visit};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug)]
-pub enum AstNode {
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct AstNode {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum NodeKind {
     Program(Vec<AstNode>),
     Assignment(String, Box<AstNode>),
-    Output(Box<AstNode>),
+    // The flag is false for `output ... inline`, which leaves off the newline.
+    Output(Box<AstNode>, bool),
+    Input(String, Option<Box<AstNode>>),
     If(Box<AstNode>, Vec<AstNode>, Vec<AstNode>),
     Loop(Box<AstNode>, Vec<AstNode>),
-    BinOp(Box<AstNode>, Token, Box<AstNode>),
+    // `case X of ... endcase`: the value, its labelled branches and the
+    // `default` branch, if there is one.
+    Case(Box<AstNode>, Vec<CaseBranch>, Option<Vec<AstNode>>),
+    BinOp(Box<AstNode>, BinaryOp, Box<AstNode>),
+    // `if A > B then A else B` used as a value.
+    Conditional(Box<AstNode>, Box<AstNode>, Box<AstNode>),
     Number(i64),
+    Float(f64),
     String(String),
     Identifier(String),
+    Null,
+    Call(String, Vec<AstNode>),
+    Array(Vec<AstNode>),
+    // `new Array(rows, cols)`, holding one size per dimension.
+    NewArray(Vec<AstNode>),
+    Index(Box<AstNode>, Box<AstNode>),
+    // `A.append(X)`: the receiver, the method name and its arguments.
+    MethodCall(Box<AstNode>, String, Vec<AstNode>),
+    // `A[i][j] = value`, with one index per level.
+    SetElement(String, Vec<AstNode>, Box<AstNode>),
+    // A `sub` or `function` definition. Shared so the interpreter can keep
+    // calling it without cloning the body, from any thread running the program.
+    Procedure(#[serde(serialize_with = "shared")] Arc<Procedure>),
+    Return(Option<Box<AstNode>>),
+    // `global X, Y` inside a procedure, so assigning to them changes the
+    // program's variables instead of creating locals.
+    Global(Vec<String>),
+    // `error "message"` stops the program with a runtime error.
+    Raise(Box<AstNode>),
+    // `try ... catch ERR ... endtry`: the guarded statements, the variable
+    // that receives the error message and the statements run on an error.
+    Try(Vec<AstNode>, Option<String>, Vec<AstNode>),
+}
+
+// The operator of a `BinOp`, named after the token it is written with. `=`
+// between two values is always the comparison `Eq`, as assigning is a
+// statement of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum BinaryOp {
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    Mod,
+    Div,
+    Eq,
+    NotEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+// `1, 2: ...` runs its body when the value matches any of the labels.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct CaseBranch {
+    pub labels: Vec<AstNode>,
+    pub body: Vec<AstNode>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct Procedure {
+    pub name: String,
+    // The lines of the `///` comments just above the definition.
+    pub doc: Vec<String>,
+    pub kind: ProcedureKind,
+    pub params: Vec<Param>,
+    pub body: Vec<AstNode>,
+}
+
+// A `sub` is run for its effect, while a `function` returns a value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum ProcedureKind {
+    Sub,
+    Function,
+}
+
+// A `ref` parameter is another name for the caller's variable, so assigning to
+// it changes the caller's value. A parameter with a default may be left out of
+// a call, in which case the default is evaluated when the call is made.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct Param {
+    pub name: String,
+    pub by_ref: bool,
+    pub default: Option<AstNode>,
+}
+
+impl AstNode {
+    pub fn new(kind: NodeKind, span: Span) -> Self {
+        AstNode { kind, span }
+    }
+
+    // The tree `Display` gives, colored with `colors`.
+    pub fn to_pretty_string(&self, colors: &Colors) -> String {
+        let mut out = String::new();
+        write_tree(self, 0, colors, &mut out).expect("writing to a String cannot fail");
+        out
+    }
+
+    // The nodes directly inside this one, in source order.
+    pub fn children(&self) -> Vec<&AstNode> {
+        match &self.kind {
+            NodeKind::Program(statements) | NodeKind::Array(statements) | NodeKind::NewArray(statements) => {
+                statements.iter().collect()
+            }
+            NodeKind::Call(_, args) => args.iter().collect(),
+            NodeKind::Assignment(_, expr) | NodeKind::Output(expr, _) => vec![expr],
+            NodeKind::Input(_, prompt) => prompt.iter().map(|p| p.as_ref()).collect(),
+            NodeKind::If(condition, true_branch, false_branch) => std::iter::once(condition.as_ref())
+                .chain(true_branch)
+                .chain(false_branch)
+                .collect(),
+            NodeKind::Loop(condition, body) => std::iter::once(condition.as_ref()).chain(body).collect(),
+            NodeKind::Case(value, branches, default) => std::iter::once(value.as_ref())
+                .chain(branches.iter().flat_map(|branch| branch.labels.iter().chain(&branch.body)))
+                .chain(default.iter().flatten())
+                .collect(),
+            NodeKind::BinOp(left, _, right) | NodeKind::Index(left, right) => vec![left, right],
+            NodeKind::Conditional(condition, then, otherwise) => vec![condition, then, otherwise],
+            NodeKind::MethodCall(receiver, _, args) => std::iter::once(receiver.as_ref()).chain(args).collect(),
+            NodeKind::SetElement(_, indices, expr) => indices.iter().chain(std::iter::once(expr.as_ref())).collect(),
+            NodeKind::Procedure(procedure) => procedure
+                .params
+                .iter()
+                .filter_map(|param| param.default.as_ref())
+                .chain(&procedure.body)
+                .collect(),
+            NodeKind::Return(value) => value.iter().map(|v| v.as_ref()).collect(),
+            NodeKind::Raise(message) => vec![message],
+            NodeKind::Try(body, _, handler) => body.iter().chain(handler).collect(),
+            NodeKind::Number(_) | NodeKind::Float(_) | NodeKind::String(_) | NodeKind::Identifier(_) => vec![],
+            NodeKind::Global(_) | NodeKind::Null => vec![],
+        }
+    }
+
+    // Calls `visit` on this node and everything inside it, parents first.
+    pub fn walk<'a>(&'a self, visit: &mut impl FnMut(&'a AstNode)) {
+        struct Walk<F>(F);
+
+        impl<'a, F: FnMut(&'a AstNode)> Visit<'a> for Walk<F> {
+            fn visit_node(&mut self, node: &'a AstNode) {
+                (self.0)(node);
+                visit::walk_node(self, node);
+            }
+        }
+
+        Walk(visit).visit_node(self);
+    }
+
+    // Like `walk`, visiting nodes in the same order, but able to change them.
+    // A procedure shared with another program is copied before it changes.
+    pub fn walk_mut(&mut self, visit: &mut impl FnMut(&mut AstNode)) {
+        struct WalkMut<F>(F);
+
+        impl<F: FnMut(&mut AstNode)> Fold for WalkMut<F> {
+            fn fold_node(&mut self, mut node: AstNode) -> AstNode {
+                (self.0)(&mut node);
+                visit::fold_node(self, node)
+            }
+        }
+
+        let node = std::mem::replace(self, AstNode::new(NodeKind::Null, self.span));
+        *self = WalkMut(visit).fold_node(node);
+    }
+}
+
+// The tree `run --print-ast` shows, one node per line with children indented
+// under their parent. `Display` gives the same tree without color.
+impl fmt::Display for AstNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tree(self, 0, &Colors::plain(), f)
+    }
+}
+
+impl BinaryOp {
+    // The operator `token` stands for between two values, if any.
+    pub fn from_token(token: &Token) -> Option<BinaryOp> {
+        let op = match token {
+            Token::Plus => BinaryOp::Plus,
+            Token::Minus => BinaryOp::Minus,
+            Token::Star => BinaryOp::Star,
+            Token::Slash => BinaryOp::Slash,
+            Token::Caret => BinaryOp::Caret,
+            Token::Mod => BinaryOp::Mod,
+            Token::Div => BinaryOp::Div,
+            Token::Assign => BinaryOp::Eq,
+            Token::NotEqual => BinaryOp::NotEqual,
+            Token::GreaterThan => BinaryOp::GreaterThan,
+            Token::GreaterThanOrEqual => BinaryOp::GreaterThanOrEqual,
+            Token::LessThan => BinaryOp::LessThan,
+            Token::LessThanOrEqual => BinaryOp::LessThanOrEqual,
+            Token::And => BinaryOp::And,
+            Token::Or => BinaryOp::Or,
+            Token::BAnd => BinaryOp::BitAnd,
+            Token::BOr => BinaryOp::BitOr,
+            Token::BXor => BinaryOp::BitXor,
+            Token::ShiftLeft => BinaryOp::ShiftLeft,
+            Token::ShiftRight => BinaryOp::ShiftRight,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // How the operator is written in a program, with the word operators
+    // spelled in the language of `keywords`.
+    pub fn spelling(self, keywords: &Keywords) -> &str {
+        match self {
+            BinaryOp::Plus => "+",
+            BinaryOp::Minus => "-",
+            BinaryOp::Star => "*",
+            BinaryOp::Slash => "/",
+            BinaryOp::Caret => "^",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::GreaterThan => ">",
+            BinaryOp::GreaterThanOrEqual => ">=",
+            BinaryOp::LessThan => "<",
+            BinaryOp::LessThanOrEqual => "<=",
+            BinaryOp::And => keywords.spelling("and"),
+            BinaryOp::Or => keywords.spelling("or"),
+            BinaryOp::Mod => keywords.spelling("mod"),
+            BinaryOp::Div => keywords.spelling("div"),
+            BinaryOp::BitAnd => keywords.spelling("band"),
+            BinaryOp::BitOr => keywords.spelling("bor"),
+            BinaryOp::BitXor => keywords.spelling("bxor"),
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+        }
+    }
+
+    pub fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::ShiftLeft | BinaryOp::ShiftRight
+        )
+    }
+
+    // Comparisons give 1 or 0.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq
+                | BinaryOp::NotEqual
+                | BinaryOp::GreaterThan
+                | BinaryOp::GreaterThanOrEqual
+                | BinaryOp::LessThan
+                | BinaryOp::LessThanOrEqual
+        )
+    }
+}
+
+impl NodeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeKind::Program(_) => "Program",
+            NodeKind::Assignment(..) => "Assignment",
+            NodeKind::Output(..) => "Output",
+            NodeKind::Input(..) => "Input",
+            NodeKind::If(..) => "If",
+            NodeKind::Loop(..) => "Loop",
+            NodeKind::Case(..) => "Case",
+            NodeKind::BinOp(..) => "BinOp",
+            NodeKind::Conditional(..) => "Conditional",
+            NodeKind::Number(_) => "Number",
+            NodeKind::Float(_) => "Float",
+            NodeKind::String(_) => "String",
+            NodeKind::Identifier(_) => "Identifier",
+            NodeKind::Null => "Null",
+            NodeKind::Call(..) => "Call",
+            NodeKind::Array(_) => "Array",
+            NodeKind::NewArray(_) => "NewArray",
+            NodeKind::Index(..) => "Index",
+            NodeKind::MethodCall(..) => "MethodCall",
+            NodeKind::SetElement(..) => "SetElement",
+            NodeKind::Procedure(_) => "Procedure",
+            NodeKind::Return(_) => "Return",
+            NodeKind::Global(_) => "Global",
+            NodeKind::Raise(_) => "Raise",
+            NodeKind::Try(..) => "Try",
+        }
+    }
+
+    // The lists of statements directly inside a node.
+    pub fn blocks(&self) -> Vec<&Vec<AstNode>> {
+        match self {
+            NodeKind::Program(statements) => vec![statements],
+            NodeKind::If(_, true_branch, false_branch) => vec![true_branch, false_branch],
+            NodeKind::Loop(_, body) => vec![body],
+            NodeKind::Case(_, branches, default) => {
+                branches.iter().map(|branch| &branch.body).chain(default.as_ref()).collect()
+            }
+            NodeKind::Procedure(procedure) => vec![&procedure.body],
+            NodeKind::Try(body, _, handler) => vec![body, handler],
+            _ => vec![],
+        }
+    }
+}
+
+// Procedures are written out whole, as if they were not shared.
+fn shared<S: Serializer>(procedure: &Arc<Procedure>, serializer: S) -> Result<S::Ok, S::Error> {
+    procedure.as_ref().serialize(serializer)
+}
+
+// Writes `node` and everything inside it, `indent` levels in.
+fn write_tree(node: &AstNode, indent: usize, colors: &Colors, out: &mut impl fmt::Write) -> fmt::Result {
+    let indentation = "  ".repeat(indent);
+    match &node.kind {
+        NodeKind::Program(statements) => {
+            writeln!(out, "{}{}", indentation, colors.node("Program"))?;
+            for statement in statements {
+                write_tree(statement, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::Assignment(name, expr) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("Assignment"), name)?;
+            write_tree(expr, indent + 1, colors, out)?;
+        }
+        NodeKind::Output(expr, newline) => {
+            let inline = if *newline { "" } else { " (inline)" };
+            writeln!(out, "{}{}{}", indentation, colors.node("Output"), inline)?;
+            write_tree(expr, indent + 1, colors, out)?;
+        }
+        NodeKind::Input(name, prompt) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("Input"), name)?;
+            if let Some(prompt) = prompt {
+                write_tree(prompt, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::If(condition, true_branch, false_branch) => {
+            writeln!(out, "{}{}", indentation, colors.node("If"))?;
+            write_tree(condition, indent + 1, colors, out)?;
+            writeln!(out, "{}  {}", indentation, colors.dim("True Branch"))?;
+            for statement in true_branch {
+                write_tree(statement, indent + 2, colors, out)?;
+            }
+            writeln!(out, "{}  {}", indentation, colors.dim("False Branch"))?;
+            for statement in false_branch {
+                write_tree(statement, indent + 2, colors, out)?;
+            }
+        }
+        NodeKind::Loop(condition, body) => {
+            writeln!(out, "{}{}", indentation, colors.node("Loop"))?;
+            write_tree(condition, indent + 1, colors, out)?;
+            for statement in body {
+                write_tree(statement, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::Case(value, branches, default) => {
+            writeln!(out, "{}{}", indentation, colors.node("Case"))?;
+            write_tree(value, indent + 1, colors, out)?;
+            for branch in branches {
+                writeln!(out, "{}  {}", indentation, colors.dim("Branch"))?;
+                for label in &branch.labels {
+                    write_tree(label, indent + 2, colors, out)?;
+                }
+                for statement in &branch.body {
+                    write_tree(statement, indent + 2, colors, out)?;
+                }
+            }
+            if let Some(default) = default {
+                writeln!(out, "{}  {}", indentation, colors.dim("Default"))?;
+                for statement in default {
+                    write_tree(statement, indent + 2, colors, out)?;
+                }
+            }
+        }
+        NodeKind::BinOp(left, op, right) => {
+            writeln!(out, "{}{}: {:?}", indentation, colors.node("BinOp"), op)?;
+            write_tree(left, indent + 1, colors, out)?;
+            write_tree(right, indent + 1, colors, out)?;
+        }
+        NodeKind::Conditional(condition, then, otherwise) => {
+            writeln!(out, "{}{}", indentation, colors.node("Conditional"))?;
+            write_tree(condition, indent + 1, colors, out)?;
+            write_tree(then, indent + 1, colors, out)?;
+            write_tree(otherwise, indent + 1, colors, out)?;
+        }
+        NodeKind::Number(value) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("Number"), colors.literal(&value.to_string()))?;
+        }
+        NodeKind::Float(value) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("Float"), colors.literal(&value.to_string()))?;
+        }
+        NodeKind::String(value) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("String"), colors.literal(value))?;
+        }
+        NodeKind::Identifier(name) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("Identifier"), name)?;
+        }
+        NodeKind::Null => {
+            writeln!(out, "{}{}", indentation, colors.node("Null"))?;
+        }
+        NodeKind::Call(name, args) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("Call"), name)?;
+            for arg in args {
+                write_tree(arg, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::Array(items) => {
+            writeln!(out, "{}{}", indentation, colors.node("Array"))?;
+            for item in items {
+                write_tree(item, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::NewArray(sizes) => {
+            writeln!(out, "{}{}", indentation, colors.node("NewArray"))?;
+            for size in sizes {
+                write_tree(size, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::MethodCall(receiver, method, args) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("MethodCall"), method)?;
+            write_tree(receiver, indent + 1, colors, out)?;
+            for arg in args {
+                write_tree(arg, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::Index(target, index) => {
+            writeln!(out, "{}{}", indentation, colors.node("Index"))?;
+            write_tree(target, indent + 1, colors, out)?;
+            write_tree(index, indent + 1, colors, out)?;
+        }
+        NodeKind::SetElement(name, indices, expr) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("SetElement"), name)?;
+            for index in indices {
+                write_tree(index, indent + 1, colors, out)?;
+            }
+            write_tree(expr, indent + 1, colors, out)?;
+        }
+        NodeKind::Procedure(procedure) => {
+            let kind = match procedure.kind {
+                ProcedureKind::Sub => "Sub",
+                ProcedureKind::Function => "Function",
+            };
+            let params: Vec<String> = procedure
+                .params
+                .iter()
+                .map(|param| if param.by_ref { format!("ref {}", param.name) } else { param.name.clone() })
+                .collect();
+            writeln!(out, "{}{}: {}({})", indentation, colors.node(kind), procedure.name, params.join(", "))?;
+            for param in &procedure.params {
+                if let Some(default) = &param.default {
+                    writeln!(out, "{}  {}", indentation, colors.dim(&format!("Default {}", param.name)))?;
+                    write_tree(default, indent + 2, colors, out)?;
+                }
+            }
+            for statement in &procedure.body {
+                write_tree(statement, indent + 1, colors, out)?;
+            }
+        }
+        NodeKind::Global(names) => {
+            writeln!(out, "{}{}: {}", indentation, colors.node("Global"), names.join(", "))?;
+        }
+        NodeKind::Raise(message) => {
+            writeln!(out, "{}{}", indentation, colors.node("Raise"))?;
+            write_tree(message, indent + 1, colors, out)?;
+        }
+        NodeKind::Try(body, name, handler) => {
+            writeln!(out, "{}{}", indentation, colors.node("Try"))?;
+            for statement in body {
+                write_tree(statement, indent + 1, colors, out)?;
+            }
+            let catch = match name {
+                Some(name) => format!("Catch {}", name),
+                None => "Catch".to_string(),
+            };
+            writeln!(out, "{}  {}", indentation, colors.dim(&catch))?;
+            for statement in handler {
+                write_tree(statement, indent + 2, colors, out)?;
+            }
+        }
+        NodeKind::Return(value) => {
+            writeln!(out, "{}{}", indentation, colors.node("Return"))?;
+            if let Some(value) = value {
+                write_tree(value, indent + 1, colors, out)?;
+            }
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,115 @@
+use crate::cache::ProgramCache;
+use crate::desugar;
+use crate::error::Error;
+use crate::interpreter::{Interpreter, RunOptions, Stats, STACK_SIZE};
+use crate::keywords::Keywords;
+use crate::lexer::Lexer;
+use crate::parser::Dialect;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+pub struct BatchResult {
+    pub file: String,
+    pub output: String,
+    pub error: Option<Error>,
+    // Problems the program ran into without stopping.
+    pub warnings: Vec<Error>,
+    // Zero when the file could not be read or parsed.
+    pub stats: Stats,
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Programs are kept in `cache` once parsed and desugared, keyed by their
+// source, keywords and dialect, so running an unchanged file again skips both.
+pub fn run_file(
+    file: &str,
+    keywords: &Keywords,
+    dialect: Dialect,
+    options: RunOptions,
+    cache: &ProgramCache,
+) -> BatchResult {
+    let buffer = SharedBuffer::default();
+    let mut stats = Stats::default();
+    let mut warnings = vec![];
+    let error = fs::read_to_string(file)
+        .map_err(|e| Error::io(format!("Failed to read {}: {}", file, e)))
+        .and_then(|source| {
+            let ast = cache.get_or_parse(&("batch", &source, keywords, dialect), || {
+                desugar::parse(Lexer::new(&source).with_keywords(keywords), dialect)
+            })?;
+            let mut interpreter = Interpreter::with_output(Box::new(buffer.clone()));
+            interpreter.set_input(Box::new(io::empty()), false);
+            interpreter.set_options(options);
+            let result = interpreter.interpret(ast);
+            stats = interpreter.stats();
+            warnings = interpreter.warnings().to_vec();
+            result
+        })
+        .err()
+        .map(|e| e.with_file_names(&[file.to_string()]));
+    let output = String::from_utf8_lossy(&buffer.0.borrow()).into_owned();
+    BatchResult {
+        file: file.to_string(),
+        output,
+        error,
+        warnings: warnings.into_iter().map(|warning| warning.with_file_names(&[file.to_string()])).collect(),
+        stats,
+    }
+}
+
+// Runs every file on a pool of `jobs` worker threads, each with its own
+// interpreter, and hands results to `on_result` in completion order.
+pub fn run(
+    files: &[String],
+    jobs: usize,
+    keywords: &Keywords,
+    dialect: Dialect,
+    options: RunOptions,
+    cache: &ProgramCache,
+    mut on_result: impl FnMut(BatchResult),
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(files.len()) {
+            let sender = sender.clone();
+            let next = &next;
+            thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn_scoped(scope, move || loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(file) = files.get(index) else {
+                        break;
+                    };
+                    if sender.send(run_file(file, keywords, dialect, options, cache)).is_err() {
+                        break;
+                    }
+                })
+                .expect("failed to start a batch worker thread");
+        }
+        drop(sender);
+
+        for result in receiver {
+            on_result(result);
+        }
+    });
+}
@@ -0,0 +1,295 @@
+use crate::ast::{AstNode, CaseBranch, NodeKind, Param, Procedure, ProcedureKind};
+use crate::lexer::Span;
+use std::sync::Arc;
+
+// Builds trees in code, for tests, generated programs and programs that embed
+// the interpreter, without writing source text and parsing it:
+//
+//     use builder::{expr, Block, Program};
+//
+//     let program = Program::builder()
+//         .assign("X", expr::num(3))
+//         .loop_while(
+//             expr::lt(expr::var("X"), expr::num(10)),
+//             Block::new().output(expr::var("X")).assign("X", expr::add(expr::var("X"), expr::num(1))),
+//         )
+//         .build();
+//
+// Built nodes have no place in any source, so they all have an empty span.
+// Nothing is checked until the tree is run: a `return` outside a function
+// fails then, as it would in a program that did not parse.
+
+pub struct Program;
+
+impl Program {
+    pub fn builder() -> Block {
+        Block::new()
+    }
+}
+
+// A list of statements, each method adding one to the end.
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    statements: Vec<AstNode>,
+}
+
+impl Block {
+    pub fn new() -> Self {
+        Block::default()
+    }
+
+    // Adds a statement built some other way.
+    pub fn statement(mut self, statement: AstNode) -> Self {
+        self.statements.push(statement);
+        self
+    }
+
+    pub fn assign(self, name: &str, value: AstNode) -> Self {
+        self.statement(node(NodeKind::Assignment(name.to_string(), Box::new(value))))
+    }
+
+    // `NAME[I][J] = VALUE`, with one index per level.
+    pub fn set_element(self, name: &str, indices: Vec<AstNode>, value: AstNode) -> Self {
+        self.statement(node(NodeKind::SetElement(name.to_string(), indices, Box::new(value))))
+    }
+
+    pub fn output(self, value: AstNode) -> Self {
+        self.statement(node(NodeKind::Output(Box::new(value), true)))
+    }
+
+    // `output VALUE inline`, leaving off the new line.
+    pub fn output_inline(self, value: AstNode) -> Self {
+        self.statement(node(NodeKind::Output(Box::new(value), false)))
+    }
+
+    pub fn input(self, name: &str) -> Self {
+        self.statement(node(NodeKind::Input(name.to_string(), None)))
+    }
+
+    pub fn input_with_prompt(self, name: &str, prompt: AstNode) -> Self {
+        self.statement(node(NodeKind::Input(name.to_string(), Some(Box::new(prompt)))))
+    }
+
+    pub fn if_then(self, condition: AstNode, then: Block) -> Self {
+        self.if_else(condition, then, Block::new())
+    }
+
+    pub fn if_else(self, condition: AstNode, then: Block, otherwise: Block) -> Self {
+        self.statement(node(NodeKind::If(Box::new(condition), then.statements, otherwise.statements)))
+    }
+
+    pub fn loop_while(self, condition: AstNode, body: Block) -> Self {
+        self.statement(node(NodeKind::Loop(Box::new(condition), body.statements)))
+    }
+
+    // `case VALUE of`, with the labels and body of each branch and the
+    // `default` branch, if there is one.
+    pub fn case(self, value: AstNode, branches: Vec<(Vec<AstNode>, Block)>, default: Option<Block>) -> Self {
+        let branches =
+            branches.into_iter().map(|(labels, body)| CaseBranch { labels, body: body.statements }).collect();
+        let default = default.map(|default| default.statements);
+        self.statement(node(NodeKind::Case(Box::new(value), branches, default)))
+    }
+
+    // Calls a sub, or a function whose result is not needed.
+    pub fn call(self, name: &str, args: Vec<AstNode>) -> Self {
+        self.statement(expr::call(name, args))
+    }
+
+    // Calls a method for what it does, as in `LIST.append(X)`.
+    pub fn method(self, receiver: AstNode, name: &str, args: Vec<AstNode>) -> Self {
+        self.statement(expr::method(receiver, name, args))
+    }
+
+    pub fn sub(self, name: &str, params: Vec<Param>, body: Block) -> Self {
+        self.statement(procedure(ProcedureKind::Sub, name, params, body))
+    }
+
+    pub fn function(self, name: &str, params: Vec<Param>, body: Block) -> Self {
+        self.statement(procedure(ProcedureKind::Function, name, params, body))
+    }
+
+    // `return VALUE`, giving a function its result.
+    pub fn return_value(self, value: AstNode) -> Self {
+        self.statement(node(NodeKind::Return(Some(Box::new(value)))))
+    }
+
+    // A bare `return`, leaving a sub.
+    pub fn return_from_sub(self) -> Self {
+        self.statement(node(NodeKind::Return(None)))
+    }
+
+    pub fn global(self, names: &[&str]) -> Self {
+        self.statement(node(NodeKind::Global(names.iter().map(|name| name.to_string()).collect())))
+    }
+
+    // `error MESSAGE`.
+    pub fn raise(self, message: AstNode) -> Self {
+        self.statement(node(NodeKind::Raise(Box::new(message))))
+    }
+
+    // `try ... catch NAME ... endtry`, where NAME receives the error message.
+    pub fn try_catch(self, body: Block, name: Option<&str>, handler: Block) -> Self {
+        self.statement(node(NodeKind::Try(body.statements, name.map(String::from), handler.statements)))
+    }
+
+    // Adds the statements of `other` after these.
+    pub fn append(mut self, other: Block) -> Self {
+        self.statements.extend(other.statements);
+        self
+    }
+
+    // The statements as a whole program, ready to run or format.
+    pub fn build(self) -> AstNode {
+        node(NodeKind::Program(self.statements))
+    }
+
+    pub fn into_statements(self) -> Vec<AstNode> {
+        self.statements
+    }
+}
+
+pub fn param(name: &str) -> Param {
+    Param { name: name.to_string(), by_ref: false, default: None }
+}
+
+// `ref NAME`, another name for the caller's variable.
+pub fn ref_param(name: &str) -> Param {
+    Param { by_ref: true, ..param(name) }
+}
+
+// `NAME = DEFAULT`, which a call may leave out.
+pub fn default_param(name: &str, default: AstNode) -> Param {
+    Param { default: Some(default), ..param(name) }
+}
+
+// A node of any kind, for the few a method does not build.
+pub fn node(kind: NodeKind) -> AstNode {
+    AstNode::new(kind, Span::default())
+}
+
+fn procedure(kind: ProcedureKind, name: &str, params: Vec<Param>, body: Block) -> AstNode {
+    let procedure = Procedure { name: name.to_string(), doc: vec![], kind, params, body: body.statements };
+    node(NodeKind::Procedure(Arc::new(procedure)))
+}
+
+// Expressions, named after what they compute. Comparisons give 1 or 0, as in
+// programs.
+pub mod expr {
+    use super::node;
+    use crate::ast::{AstNode, BinaryOp, NodeKind};
+
+    pub fn num(value: i64) -> AstNode {
+        node(NodeKind::Number(value))
+    }
+
+    pub fn float(value: f64) -> AstNode {
+        node(NodeKind::Float(value))
+    }
+
+    // A string as it is, with no `{...}` in it filled in.
+    pub fn string(text: &str) -> AstNode {
+        node(NodeKind::String(text.to_string()))
+    }
+
+    pub fn var(name: &str) -> AstNode {
+        node(NodeKind::Identifier(name.to_string()))
+    }
+
+    pub fn null() -> AstNode {
+        node(NodeKind::Null)
+    }
+
+    pub fn call(name: &str, args: Vec<AstNode>) -> AstNode {
+        node(NodeKind::Call(name.to_string(), args))
+    }
+
+    pub fn array(items: Vec<AstNode>) -> AstNode {
+        node(NodeKind::Array(items))
+    }
+
+    // `new Array(rows, cols)`, with one size per dimension.
+    pub fn new_array(sizes: Vec<AstNode>) -> AstNode {
+        node(NodeKind::NewArray(sizes))
+    }
+
+    pub fn index(array: AstNode, index: AstNode) -> AstNode {
+        node(NodeKind::Index(Box::new(array), Box::new(index)))
+    }
+
+    pub fn method(receiver: AstNode, name: &str, args: Vec<AstNode>) -> AstNode {
+        node(NodeKind::MethodCall(Box::new(receiver), name.to_string(), args))
+    }
+
+    // `if CONDITION then THEN else OTHERWISE` as a value.
+    pub fn conditional(condition: AstNode, then: AstNode, otherwise: AstNode) -> AstNode {
+        node(NodeKind::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    pub fn binary(left: AstNode, op: BinaryOp, right: AstNode) -> AstNode {
+        node(NodeKind::BinOp(Box::new(left), op, Box::new(right)))
+    }
+
+    // Adds numbers, or joins strings.
+    pub fn add(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Plus, right)
+    }
+
+    pub fn subtract(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Minus, right)
+    }
+
+    pub fn multiply(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Star, right)
+    }
+
+    // `/`, which always gives a real number.
+    pub fn divide(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Slash, right)
+    }
+
+    // `div`, which gives an integer.
+    pub fn integer_divide(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Div, right)
+    }
+
+    pub fn modulo(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Mod, right)
+    }
+
+    pub fn power(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Caret, right)
+    }
+
+    pub fn eq(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Eq, right)
+    }
+
+    pub fn ne(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::NotEqual, right)
+    }
+
+    pub fn lt(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::LessThan, right)
+    }
+
+    pub fn le(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::LessThanOrEqual, right)
+    }
+
+    pub fn gt(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::GreaterThan, right)
+    }
+
+    pub fn ge(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::GreaterThanOrEqual, right)
+    }
+
+    pub fn and(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::And, right)
+    }
+
+    pub fn or(left: AstNode, right: AstNode) -> AstNode {
+        binary(left, BinaryOp::Or, right)
+    }
+}
@@ -0,0 +1,286 @@
+use crate::error::Error;
+use crate::value::Value;
+use std::cmp::Ordering;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Strings are UTF-8, and lengths and positions count grapheme clusters rather
+// than bytes or code points, so "é" and "👍🏽" are one character each.
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, Error> {
+    match name {
+        "length" => {
+            let [value] = arguments(name, args)?;
+            match value {
+                Value::Array(items) => Ok(Value::Number(items.borrow().len() as i64)),
+                _ => Ok(Value::Number(expect_string(&value)?.graphemes(true).count() as i64)),
+            }
+        }
+        "substring" => {
+            let [text, start, length] = arguments(name, args)?;
+            let text = expect_string(&text)?;
+            let (start, length) = (expect_index(&start)?, expect_index(&length)?);
+            let graphemes: Vec<&str> = text.graphemes(true).collect();
+            let end = start.checked_add(length).filter(|&end| end <= graphemes.len()).ok_or_else(|| {
+                Error::runtime(
+                    "E0209",
+                    format!(
+                        "Substring {}..{} is out of range for a string of length {}",
+                        start,
+                        start.saturating_add(length),
+                        graphemes.len()
+                    ),
+                )
+            })?;
+            Ok(Value::string(graphemes[start..end].concat()))
+        }
+        "format" => {
+            let [number, places] = arguments(name, args)?;
+            let places = expect_index(&places)?;
+            if places > MAX_PLACES {
+                return Err(Error::runtime(
+                    "E0209",
+                    format!("format supports at most {} decimal places, got {}", MAX_PLACES, places),
+                ));
+            }
+            let text = match number {
+                Value::Number(n) if places == 0 => n.to_string(),
+                Value::Number(n) => format!("{}.{}", n, "0".repeat(places)),
+                Value::Float(n) => format!("{:.*}", places, n),
+                other => {
+                    return Err(Error::runtime("E0203", format!("Expected a number, got {}", other.type_name())))
+                }
+            };
+            Ok(Value::string(group_thousands(&text)))
+        }
+        // The whole number in a string, or null if it does not hold one, so
+        // a program can check input instead of failing on it:
+        //
+        //     AGE = tryToInteger(TEXT)
+        //     if AGE = null then ...
+        //
+        // Numbers are accepted too, as `input` reads digits as a number, but a
+        // real number only if it has no fractional part.
+        "tryToInteger" => {
+            let [value] = arguments(name, args)?;
+            Ok(match value {
+                Value::Number(n) => Value::Number(n),
+                Value::Float(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                    Value::Number(n as i64)
+                }
+                Value::String(text) => text.trim().parse().map_or(Value::Null, Value::Number),
+                _ => Value::Null,
+            })
+        }
+        // Whole numbers written in base 2 or 16, as in `toBinary(10)` = "1010"
+        // and `toHex(255)` = "FF". Negative numbers get a `-` in front.
+        "toBinary" | "toHex" => {
+            let [number] = arguments(name, args)?;
+            let Value::Number(n) = number else {
+                return Err(Error::runtime("E0203", format!("Expected a whole number, got {}", number.type_name())));
+            };
+            let sign = if n < 0 { "-" } else { "" };
+            Ok(Value::string(match name {
+                "toBinary" => format!("{}{:b}", sign, n.unsigned_abs()),
+                _ => format!("{}{:X}", sign, n.unsigned_abs()),
+            }))
+        }
+        // Characters and their Unicode code points, as in `ord("A")` = 65.
+        "ord" => {
+            let [text] = arguments(name, args)?;
+            let text = expect_string(&text)?;
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Value::Number(c as i64)),
+                _ => Err(Error::runtime(
+                    "E0209",
+                    format!("ord expects a single character, got \"{}\" ({} code points)", text, text.chars().count()),
+                )),
+            }
+        }
+        "chr" => {
+            let [code] = arguments(name, args)?;
+            let code = match code {
+                Value::Number(n) => n,
+                other => {
+                    return Err(Error::runtime("E0203", format!("Expected a number, got {}", other.type_name())))
+                }
+            };
+            u32::try_from(code).ok().and_then(char::from_u32).map(|c| Value::string(c.to_string())).ok_or_else(|| {
+                Error::runtime(
+                    "E0209",
+                    format!("chr expects a Unicode code point from 0 to 1114111, excluding 55296 to 57343, got {}", code),
+                )
+            })
+        }
+        // String and array methods can also be called as functions, as in
+        // `size(A)`, `sort(A)` or `upper(NAME)`.
+        "size" | "contains" | "indexOf" | "sort" | "binarySearch" | "upper" | "lower" | "trim" | "split" | "replace"
+            if !args.is_empty() =>
+        {
+            let mut args = args;
+            let receiver = args.remove(0);
+            method(&receiver, name, args)
+        }
+        _ => Err(Error::runtime("E0207", format!("Unknown function: {}", name))),
+    }
+}
+
+// Methods on strings and arrays, called as `RECEIVER.name(args)`. Arrays are
+// shared, so `append` and `sort` change every name for the array. Strings are
+// not, so string methods give a new string and leave the old one as it was.
+// Positions count from 0, which the interpreter moves to the array base for
+// arrays, and `indexOf` gives -1 when nothing matches.
+pub fn method(receiver: &Value, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+    match (name, receiver) {
+        ("size", Value::String(text)) => {
+            let [] = arguments(name, args)?;
+            Ok(Value::Number(text.graphemes(true).count() as i64))
+        }
+        ("size", Value::Array(items)) => {
+            let [] = arguments(name, args)?;
+            Ok(Value::Number(items.borrow().len() as i64))
+        }
+        ("contains", Value::String(text)) => {
+            let [part] = arguments(name, args)?;
+            Ok(Value::Number(text.contains(expect_string(&part)?) as i64))
+        }
+        ("contains", Value::Array(items)) => {
+            let [item] = arguments(name, args)?;
+            Ok(Value::Number(items.borrow().contains(&item) as i64))
+        }
+        ("indexOf", Value::String(text)) => {
+            let [part] = arguments(name, args)?;
+            let position = text
+                .find(expect_string(&part)?)
+                .map_or(-1, |byte| text[..byte].graphemes(true).count() as i64);
+            Ok(Value::Number(position))
+        }
+        ("indexOf", Value::Array(items)) => {
+            let [item] = arguments(name, args)?;
+            Ok(Value::Number(items.borrow().iter().position(|i| *i == item).map_or(-1, |i| i as i64)))
+        }
+        ("upper", Value::String(text)) => {
+            let [] = arguments(name, args)?;
+            Ok(Value::string(text.to_uppercase()))
+        }
+        ("lower", Value::String(text)) => {
+            let [] = arguments(name, args)?;
+            Ok(Value::string(text.to_lowercase()))
+        }
+        // Spaces, tabs and line breaks at either end.
+        ("trim", Value::String(text)) => {
+            let [] = arguments(name, args)?;
+            Ok(Value::string(text.trim().to_string()))
+        }
+        // The parts between each separator, as in `"a,b,,c".split(",")` =
+        // `["a", "b", "", "c"]`. An empty separator splits into characters.
+        ("split", Value::String(text)) => {
+            let [separator] = arguments(name, args)?;
+            let separator = expect_string(&separator)?;
+            let parts: Vec<Value> = if separator.is_empty() {
+                text.graphemes(true).map(Value::string).collect()
+            } else {
+                text.split(separator).map(Value::string).collect()
+            };
+            Ok(Value::array(parts))
+        }
+        // Replaces every occurrence, left to right.
+        ("replace", Value::String(text)) => {
+            let [old, new] = arguments(name, args)?;
+            let (old, new) = (expect_string(&old)?, expect_string(&new)?);
+            if old.is_empty() {
+                return Err(Error::runtime("E0209", "replace needs some text to look for, got an empty string"));
+            }
+            Ok(Value::string(text.replace(old, new)))
+        }
+        ("sort", Value::Array(array)) => {
+            let [] = arguments(name, args)?;
+            let mut items = array.borrow_mut();
+            if let Some(pair) = items.windows(2).find(|pair| pair[0].compare(&pair[1]).is_none()) {
+                return Err(Error::runtime(
+                    "E0203",
+                    format!("Cannot sort an array holding {} and {}", pair[0].type_name(), pair[1].type_name()),
+                ));
+            }
+            // Every neighbouring pair is comparable, so all elements are.
+            items.sort_by(|a, b| a.compare(b).unwrap_or(Ordering::Equal));
+            Ok(receiver.clone())
+        }
+        ("binarySearch", Value::Array(items)) => {
+            let [item] = arguments(name, args)?;
+            let mut failure = None;
+            let found = items.borrow().binary_search_by(|probe| {
+                probe.compare(&item).unwrap_or_else(|| {
+                    failure.get_or_insert_with(|| {
+                        Error::runtime(
+                            "E0203",
+                            format!("Cannot search for {} among {}", item.type_name(), probe.type_name()),
+                        )
+                    });
+                    Ordering::Equal
+                })
+            });
+            if let Some(error) = failure {
+                return Err(error);
+            }
+            // The array must already be sorted; otherwise the result is unspecified.
+            Ok(Value::Number(found.map_or(-1, |index| index as i64)))
+        }
+        ("append", Value::Array(items)) => {
+            let [item] = arguments(name, args)?;
+            items.borrow_mut().push(item);
+            // Called for its effect, so there is no result.
+            Ok(Value::Null)
+        }
+        ("copy", Value::Array(items)) => {
+            let [] = arguments(name, args)?;
+            Ok(Value::array(items.borrow().clone()))
+        }
+        (_, receiver) => Err(Error::runtime(
+            "E0207",
+            format!("Unknown method {} for {}", name, receiver.type_name()),
+        )),
+    }
+}
+
+const MAX_PLACES: usize = 20;
+
+// Inserts a comma between each group of three digits before the decimal point.
+fn group_thousands(text: &str) -> String {
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text),
+    };
+    let (whole, fraction) = match unsigned.find('.') {
+        Some(dot) => unsigned.split_at(dot),
+        None => (unsigned, ""),
+    };
+    let mut grouped = String::new();
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+fn arguments<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N], Error> {
+    args.try_into().map_err(|args: Vec<Value>| {
+        Error::runtime("E0208", format!("{} expects {} argument(s), got {}", name, N, args.len()))
+    })
+}
+
+fn expect_string(value: &Value) -> Result<&str, Error> {
+    match value {
+        Value::String(text) => Ok(text),
+        other => Err(Error::runtime("E0203", format!("Expected a string, got {}", other.type_name()))),
+    }
+}
+
+fn expect_index(value: &Value) -> Result<usize, Error> {
+    match value {
+        Value::Number(n) => usize::try_from(*n)
+            .map_err(|_| Error::runtime("E0209", format!("Expected a non-negative index, got {}", n))),
+        other => Err(Error::runtime("E0203", format!("Expected a number, got {}", other.type_name()))),
+    }
+}
@@ -0,0 +1,166 @@
+use crate::ast::AstNode;
+use crate::error::Error;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Parsed programs kept by a hash of the source they came from, so watching,
+// batch runs and the server do not lex and parse a file again when its text
+// has not changed. The key is whatever decides the result besides the
+// source, hashed together with it:
+//
+//     let program = cache.get_or_parse(&(source, &keywords, dialect), || parse(source))?;
+//
+// Each entry also keeps the key in full, as the bytes hashing it reads, so
+// two keys with the same hash never share a program.
+//
+// Programs that fail to parse are kept too, with their error. The cache is
+// shared between threads, and once it holds `capacity` programs the one used
+// longest ago makes way for the next.
+
+const DEFAULT_CAPACITY: usize = 256;
+
+pub struct ProgramCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Entries {
+    programs: BTreeMap<u64, Entry>,
+    // Counts lookups, to tell which entry was used longest ago.
+    clock: u64,
+}
+
+struct Entry {
+    key: Vec<u8>,
+    program: Result<Arc<AstNode>, Error>,
+    used: u64,
+}
+
+// How well the cache is doing, for tuning and for the server's `/stats`.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub hit_rate: f64,
+}
+
+impl Default for ProgramCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgramCache {
+    pub const fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub const fn with_capacity(capacity: usize) -> Self {
+        ProgramCache {
+            capacity,
+            entries: Mutex::new(Entries { programs: BTreeMap::new(), clock: 0 }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // The program kept for `key`, or the result of `parse`, kept for next
+    // time. `parse` runs without the cache locked, so two threads asking for
+    // the same new program may both parse it.
+    pub fn get_or_parse(
+        &self,
+        key: &impl Hash,
+        parse: impl FnOnce() -> Result<AstNode, Error>,
+    ) -> Result<Arc<AstNode>, Error> {
+        let (key, bytes) = hash(key);
+        {
+            let mut entries = self.lock();
+            entries.clock += 1;
+            let clock = entries.clock;
+            if let Some(entry) = entries.programs.get_mut(&key).filter(|entry| entry.key == bytes) {
+                entry.used = clock;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return entry.program.clone();
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let program = parse().map(Arc::new);
+
+        let mut entries = self.lock();
+        if entries.programs.len() >= self.capacity && !entries.programs.contains_key(&key) {
+            let oldest = entries.programs.iter().min_by_key(|(_, entry)| entry.used).map(|(&key, _)| key);
+            if let Some(oldest) = oldest {
+                entries.programs.remove(&oldest);
+            }
+        }
+        if self.capacity > 0 {
+            let used = entries.clock;
+            entries.programs.insert(key, Entry { key: bytes, program: program.clone(), used });
+        }
+        program
+    }
+
+    // Forgets the program kept for `key`, so the next lookup parses it again.
+    // Returns whether there was one.
+    pub fn invalidate(&self, key: &impl Hash) -> bool {
+        let (key, bytes) = hash(key);
+        let mut entries = self.lock();
+        match entries.programs.get(&key) {
+            Some(entry) if entry.key == bytes => entries.programs.remove(&key).is_some(),
+            _ => false,
+        }
+    }
+
+    // Forgets every program, keeping the counts of hits and misses.
+    pub fn clear(&self) {
+        self.lock().programs.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (hits, misses) = (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed));
+        let lookups = hits + misses;
+        CacheStats {
+            hits,
+            misses,
+            entries: self.lock().programs.len(),
+            hit_rate: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+        }
+    }
+
+    // A thread that panicked while holding the lock left the map as it was
+    // between statements, which is still a working cache.
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// The hash of `key`, with the bytes it was hashed from.
+fn hash(key: &impl Hash) -> (u64, Vec<u8>) {
+    let mut recorder = Recorder::default();
+    key.hash(&mut recorder);
+    (recorder.finish(), recorder.0)
+}
+
+// A hasher that keeps every byte it is given.
+#[derive(Default)]
+struct Recorder(Vec<u8>);
+
+impl Hasher for Recorder {
+    fn finish(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&self.0);
+        hasher.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+}
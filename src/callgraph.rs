@@ -0,0 +1,201 @@
+use crate::ast::{AstNode, NodeKind};
+
+// Which of the program's procedures call which. Routine 0 is the main
+// program; the others are its procedures in the order they are defined.
+#[derive(Debug)]
+pub struct CallGraph {
+    pub routines: Vec<Routine>,
+}
+
+#[derive(Debug)]
+pub struct Routine {
+    // None for the main program.
+    pub name: Option<String>,
+    // The routines this one calls, in the order of their first call.
+    pub calls: Vec<usize>,
+}
+
+impl Routine {
+    pub fn title(&self) -> &str {
+        self.name.as_deref().unwrap_or("main program")
+    }
+}
+
+pub fn build(program: &AstNode) -> CallGraph {
+    let statements = match &program.kind {
+        NodeKind::Program(statements) => statements.as_slice(),
+        _ => std::slice::from_ref(program),
+    };
+    let procedures: Vec<_> = statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            NodeKind::Procedure(procedure) => Some(procedure),
+            _ => None,
+        })
+        .collect();
+    let index = |name: &str| procedures.iter().position(|procedure| procedure.name == name).map(|index| index + 1);
+
+    let calls = |bodies: Vec<&AstNode>| {
+        let mut calls = vec![];
+        for body in bodies {
+            body.walk(&mut |node| {
+                if let NodeKind::Call(name, _) = &node.kind {
+                    if let Some(callee) = index(name).filter(|callee| !calls.contains(callee)) {
+                        calls.push(callee);
+                    }
+                }
+            });
+        }
+        calls
+    };
+    let main = statements.iter().filter(|statement| !matches!(statement.kind, NodeKind::Procedure(_))).collect();
+    let mut routines = vec![Routine { name: None, calls: calls(main) }];
+    for procedure in &procedures {
+        // Defaults are evaluated by the callee, so calls in them count as its own.
+        let defaults = procedure.params.iter().filter_map(|param| param.default.as_ref());
+        let calls = calls(defaults.chain(&procedure.body).collect());
+        routines.push(Routine { name: Some(procedure.name.clone()), calls });
+    }
+    CallGraph { routines }
+}
+
+impl CallGraph {
+    // The groups of routines that call each other in a cycle, including a
+    // routine that calls itself, found with Tarjan's algorithm.
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let count = self.routines.len();
+        let mut state = Tarjan {
+            graph: self,
+            index: vec![None; count],
+            low: vec![0; count],
+            stack: vec![],
+            on_stack: vec![false; count],
+            next: 0,
+            groups: vec![],
+        };
+        for routine in 0..count {
+            if state.index[routine].is_none() {
+                state.visit(routine);
+            }
+        }
+        let mut groups: Vec<Vec<usize>> = state
+            .groups
+            .into_iter()
+            .filter(|group| group.len() > 1 || self.routines[group[0]].calls.contains(&group[0]))
+            .map(|mut group| {
+                group.sort();
+                group
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    // The calls as a tree from the main program, followed by a tree for each
+    // procedure it never reaches. A routine is expanded once; later calls to
+    // it are marked, as are calls back into a routine on the current path.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let mut expanded = vec![false; self.routines.len()];
+        for root in 0..self.routines.len() {
+            if !expanded[root] {
+                out.push_str(self.routines[root].title());
+                out.push('\n');
+                self.tree(root, "", &mut vec![root], &mut expanded, &mut out);
+            }
+        }
+        let cycles = self.cycles();
+        if !cycles.is_empty() {
+            out.push_str("\nRecursion:\n");
+            for cycle in cycles {
+                let names: Vec<&str> = cycle.iter().map(|&routine| self.routines[routine].title()).collect();
+                out.push_str(&format!("  {}\n", names.join(", ")));
+            }
+        }
+        out
+    }
+
+    fn tree(&self, routine: usize, prefix: &str, path: &mut Vec<usize>, expanded: &mut [bool], out: &mut String) {
+        expanded[routine] = true;
+        let calls = &self.routines[routine].calls;
+        for (position, &callee) in calls.iter().enumerate() {
+            let last = position + 1 == calls.len();
+            let note = if path.contains(&callee) {
+                " (recursive)"
+            } else if expanded[callee] {
+                " (see above)"
+            } else {
+                ""
+            };
+            let branch = if last { "└── " } else { "├── " };
+            out.push_str(&format!("{}{}{}{}\n", prefix, branch, self.routines[callee].title(), note));
+            if note.is_empty() {
+                path.push(callee);
+                let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                self.tree(callee, &prefix, path, expanded, out);
+                path.pop();
+            }
+        }
+    }
+
+    // The graph in DOT, with calls that are part of a cycle drawn in red.
+    pub fn to_dot(&self) -> String {
+        let cycles = self.cycles();
+        let group = |routine: usize| cycles.iter().position(|cycle| cycle.contains(&routine));
+        let mut out = String::from("digraph calls {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (id, routine) in self.routines.iter().enumerate() {
+            let style = if id == 0 { ", style=rounded" } else { "" };
+            out.push_str(&format!("    r{} [label=\"{}\"{}];\n", id, routine.title(), style));
+        }
+        for (id, routine) in self.routines.iter().enumerate() {
+            for &callee in &routine.calls {
+                let recursive = group(id).is_some() && group(id) == group(callee);
+                let color = if recursive { " [color=red]" } else { "" };
+                out.push_str(&format!("    r{} -> r{}{};\n", id, callee, color));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+struct Tarjan<'a> {
+    graph: &'a CallGraph,
+    index: Vec<Option<usize>>,
+    low: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    next: usize,
+    groups: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, routine: usize) {
+        self.index[routine] = Some(self.next);
+        self.low[routine] = self.next;
+        self.next += 1;
+        self.stack.push(routine);
+        self.on_stack[routine] = true;
+        for &callee in &self.graph.routines[routine].calls {
+            match self.index[callee] {
+                None => {
+                    self.visit(callee);
+                    self.low[routine] = self.low[routine].min(self.low[callee]);
+                }
+                Some(index) if self.on_stack[callee] => self.low[routine] = self.low[routine].min(index),
+                Some(_) => {}
+            }
+        }
+        if Some(self.low[routine]) == self.index[routine] {
+            let mut group = vec![];
+            while let Some(member) = self.stack.pop() {
+                self.on_stack[member] = false;
+                group.push(member);
+                if member == routine {
+                    break;
+                }
+            }
+            self.groups.push(group);
+        }
+    }
+}
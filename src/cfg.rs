@@ -0,0 +1,327 @@
+use crate::ast::{AstNode, NodeKind, ProcedureKind};
+use crate::formatter;
+use crate::keywords::Keywords;
+use crate::lexer::Span;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// A control-flow graph of basic blocks: runs of statements that always execute
+// together, ending in an optional condition that picks the next block. Block 0
+// is the entry and block 1 the exit, which holds no statements.
+#[derive(Debug)]
+pub struct Cfg<'a> {
+    // The procedure this graph is for, or None for the main program.
+    pub name: Option<String>,
+    pub kind: Option<ProcedureKind>,
+    // The procedure's definition, or the whole program.
+    pub span: Span,
+    pub blocks: Vec<Block<'a>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Block<'a> {
+    pub statements: Vec<&'a AstNode>,
+    // The `if` or `loop` condition or the `case` value the block ends with.
+    pub condition: Option<&'a AstNode>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Edge {
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    Next,
+    True,
+    False,
+    // To the body of the branch with this index in a `case`.
+    Case(usize),
+    Default,
+    Return,
+    // Where a runtime error goes: the enclosing `catch`, or out of the graph.
+    Error,
+}
+
+pub const ENTRY: usize = 0;
+pub const EXIT: usize = 1;
+
+// The graph of the main program followed by one for each procedure, in the
+// order they are defined.
+pub fn build(program: &AstNode) -> Vec<Cfg<'_>> {
+    let statements = match &program.kind {
+        NodeKind::Program(statements) => statements.as_slice(),
+        _ => std::slice::from_ref(program),
+    };
+    let main: Vec<&AstNode> =
+        statements.iter().filter(|statement| !matches!(statement.kind, NodeKind::Procedure(_))).collect();
+    let mut graphs = vec![Builder::graph(None, None, program.span, main)];
+    for statement in statements {
+        if let NodeKind::Procedure(procedure) = &statement.kind {
+            let body = procedure.body.iter().collect();
+            let (name, kind) = (Some(procedure.name.clone()), Some(procedure.kind));
+            graphs.push(Builder::graph(name, kind, statement.span, body));
+        }
+    }
+    graphs
+}
+
+struct Builder<'a> {
+    blocks: Vec<Block<'a>>,
+    // The block statements are being added to, or None after a `return` or
+    // `error`, until something jumps to the code that follows.
+    current: Option<usize>,
+    // The `catch` blocks of the `try` statements being built, innermost last.
+    handlers: Vec<usize>,
+}
+
+impl<'a> Builder<'a> {
+    fn graph(name: Option<String>, kind: Option<ProcedureKind>, span: Span, statements: Vec<&'a AstNode>) -> Cfg<'a> {
+        let blocks = vec![Block::default(), Block::default()];
+        let mut builder = Builder { blocks, current: Some(ENTRY), handlers: vec![] };
+        builder.block(statements);
+        if let Some(current) = builder.current {
+            builder.edge(current, EXIT, EdgeKind::Next);
+        }
+        Cfg { name, kind, span, blocks: builder.blocks }
+    }
+
+    fn new_block(&mut self) -> usize {
+        self.blocks.push(Block::default());
+        self.blocks.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize, kind: EdgeKind) {
+        self.blocks[from].edges.push(Edge { to, kind });
+    }
+
+    // The block to add the next statement to. Code after a `return` or
+    // `error` gets a block nothing leads to.
+    fn current(&mut self) -> usize {
+        match self.current {
+            Some(current) => current,
+            None => {
+                let block = self.new_block();
+                self.current = Some(block);
+                block
+            }
+        }
+    }
+
+    // Starts a block reached from `from` by `kind`.
+    fn branch(&mut self, from: usize, kind: EdgeKind) -> usize {
+        let block = self.new_block();
+        self.edge(from, block, kind);
+        self.current = Some(block);
+        block
+    }
+
+    // Joins the blocks in `ends` into a new current block. If every one of
+    // them returned or raised an error, nothing follows them.
+    fn join(&mut self, ends: Vec<Option<usize>>) {
+        if ends.iter().all(Option::is_none) {
+            self.current = None;
+            return;
+        }
+        let block = self.new_block();
+        for end in ends.into_iter().flatten() {
+            self.edge(end, block, EdgeKind::Next);
+        }
+        self.current = Some(block);
+    }
+
+    fn block(&mut self, statements: Vec<&'a AstNode>) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, node: &'a AstNode) {
+        match &node.kind {
+            NodeKind::If(condition, true_branch, false_branch) => {
+                let header = self.header(node, condition);
+                self.branch(header, EdgeKind::True);
+                self.block(true_branch.iter().collect());
+                let true_end = self.current;
+                self.branch(header, EdgeKind::False);
+                self.block(false_branch.iter().collect());
+                let false_end = self.current;
+                self.join(vec![true_end, false_end]);
+            }
+            NodeKind::Loop(condition, body) => {
+                // The condition is checked before every pass, so it gets a
+                // block of its own for the body to return to.
+                let before = self.current();
+                let header = self.new_block();
+                self.edge(before, header, EdgeKind::Next);
+                self.current = Some(header);
+                self.header(node, condition);
+                self.branch(header, EdgeKind::True);
+                self.block(body.iter().collect());
+                if let Some(end) = self.current {
+                    self.edge(end, header, EdgeKind::Next);
+                }
+                self.branch(header, EdgeKind::False);
+            }
+            NodeKind::Case(value, branches, default) => {
+                let header = self.header(node, value);
+                let mut ends = vec![];
+                for (index, branch) in branches.iter().enumerate() {
+                    self.branch(header, EdgeKind::Case(index));
+                    self.block(branch.body.iter().collect());
+                    ends.push(self.current);
+                }
+                self.branch(header, EdgeKind::Default);
+                self.block(default.iter().flatten().collect());
+                ends.push(self.current);
+                self.join(ends);
+            }
+            NodeKind::Try(body, _, handler) => {
+                let before = self.current();
+                let catch = self.new_block();
+                let first = self.blocks.len();
+                self.handlers.push(catch);
+                let start = self.branch(before, EdgeKind::Next);
+                self.blocks[start].statements.push(node);
+                self.block(body.iter().collect());
+                self.handlers.pop();
+                // Any statement of the body may fail.
+                for block in first..self.blocks.len() {
+                    let edges = &self.blocks[block].edges;
+                    if !edges.iter().any(|edge| edge.to == catch) && !self.blocks[block].statements.is_empty() {
+                        self.edge(block, catch, EdgeKind::Error);
+                    }
+                }
+                let body_end = self.current;
+                self.current = Some(catch);
+                self.block(handler.iter().collect());
+                let handler_end = self.current;
+                self.join(vec![body_end, handler_end]);
+            }
+            NodeKind::Return(_) => {
+                let current = self.current();
+                self.blocks[current].statements.push(node);
+                self.edge(current, EXIT, EdgeKind::Return);
+                self.current = None;
+            }
+            NodeKind::Raise(_) => {
+                let current = self.current();
+                self.blocks[current].statements.push(node);
+                let target = self.handlers.last().copied().unwrap_or(EXIT);
+                self.edge(current, target, EdgeKind::Error);
+                self.current = None;
+            }
+            _ => {
+                let current = self.current();
+                self.blocks[current].statements.push(node);
+            }
+        }
+    }
+
+    // Ends the current block with the statement and its condition.
+    fn header(&mut self, node: &'a AstNode, condition: &'a AstNode) -> usize {
+        let current = self.current();
+        self.blocks[current].statements.push(node);
+        self.blocks[current].condition = Some(condition);
+        current
+    }
+}
+
+impl<'a> Cfg<'a> {
+    // Whether each block can be reached from the entry.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reached = vec![false; self.blocks.len()];
+        let mut pending = vec![ENTRY];
+        while let Some(block) = pending.pop() {
+            if !std::mem::replace(&mut reached[block], true) {
+                pending.extend(self.blocks[block].edges.iter().map(|edge| edge.to));
+            }
+        }
+        reached
+    }
+
+    // The blocks that fall off the end of the graph without a `return`.
+    pub fn fall_through(&self) -> Vec<usize> {
+        let reached = self.reachable();
+        (0..self.blocks.len())
+            .filter(|&block| reached[block])
+            .filter(|&block| {
+                self.blocks[block]
+                    .edges
+                    .iter()
+                    .any(|edge| edge.to == EXIT && !matches!(edge.kind, EdgeKind::Return | EdgeKind::Error))
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let node = |node: &AstNode| json!({ "kind": node.kind.name(), "span": node.span });
+        let blocks: Vec<Value> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(id, block)| {
+                json!({
+                    "id": id,
+                    "statements": block.statements.iter().map(|statement| node(statement)).collect::<Vec<_>>(),
+                    "condition": block.condition.map(node),
+                    "edges": block.edges,
+                })
+            })
+            .collect();
+        json!({ "name": self.name, "entry": ENTRY, "exit": EXIT, "blocks": blocks })
+    }
+
+    // The graph as a DOT cluster, with node names prefixed by `prefix` so
+    // several graphs can share a file. Statements are labelled with the first
+    // line `fmt` prints for them.
+    fn dot(&self, prefix: &str, keywords: &Keywords) -> String {
+        let title = self.name.as_deref().unwrap_or("main program");
+        let mut out = format!("    subgraph cluster_{} {{\n        label=\"{}\";\n", prefix, escape(title));
+        for (id, block) in self.blocks.iter().enumerate() {
+            let label = match id {
+                ENTRY if block.statements.is_empty() => "entry".to_string(),
+                EXIT => "exit".to_string(),
+                _ => block
+                    .statements
+                    .iter()
+                    .map(|statement| {
+                        let text = formatter::format(statement, keywords, false);
+                        format!("{}\\l", escape(text.lines().next().unwrap_or_default().trim()))
+                    })
+                    .collect(),
+            };
+            out.push_str(&format!("        {}_{} [label=\"{}\"];\n", prefix, id, label));
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            for edge in &block.edges {
+                let label = match edge.kind {
+                    EdgeKind::Next => String::new(),
+                    EdgeKind::Case(index) => format!(" [label=\"case {}\"]", index + 1),
+                    EdgeKind::Error => " [label=\"error\", style=dashed]".to_string(),
+                    kind => format!(" [label=\"{}\"]", json!(kind).as_str().unwrap_or_default()),
+                };
+                out.push_str(&format!("        {}_{} -> {}_{}{};\n", prefix, id, prefix, edge.to, label));
+            }
+        }
+        out.push_str("    }\n");
+        out
+    }
+}
+
+// The graphs in one DOT file, one cluster each.
+pub fn to_dot(graphs: &[Cfg], keywords: &Keywords) -> String {
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+    for (index, graph) in graphs.iter().enumerate() {
+        out.push_str(&graph.dot(&format!("g{}", index), keywords));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,95 @@
+use serde::Deserialize;
+use std::env;
+use std::io::{self, IsTerminal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Colors {
+    enabled: bool,
+}
+
+impl Colors {
+    pub fn new(choice: ColorChoice, stream: Stream) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                let terminal = match stream {
+                    Stream::Stdout => io::stdout().is_terminal(),
+                    Stream::Stderr => io::stderr().is_terminal(),
+                };
+                !no_color && terminal
+            }
+        };
+        Colors { enabled }
+    }
+
+    pub fn plain() -> Self {
+        Colors { enabled: false }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn paint(&self, text: &str, code: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn error(&self, text: &str) -> String {
+        self.paint(text, "1;31")
+    }
+
+    pub fn warning(&self, text: &str) -> String {
+        self.paint(text, "1;33")
+    }
+
+    pub fn success(&self, text: &str) -> String {
+        self.paint(text, "32")
+    }
+
+    pub fn header(&self, text: &str) -> String {
+        self.paint(text, "1")
+    }
+
+    pub fn dim(&self, text: &str) -> String {
+        self.paint(text, "2")
+    }
+
+    pub fn node(&self, text: &str) -> String {
+        self.paint(text, "36")
+    }
+
+    pub fn literal(&self, text: &str) -> String {
+        self.paint(text, "33")
+    }
+}
@@ -0,0 +1,95 @@
+use crate::color::ColorChoice;
+use crate::error::{Error, ErrorFormat};
+use crate::interpreter::{Division, RunOptions, Undefined};
+use crate::keywords::Keywords;
+use crate::parser::Dialect;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "ibc.toml";
+
+// Settings read from `ibc.toml`. Every field is optional so that command line
+// flags can override whatever the file sets.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub color: Option<ColorChoice>,
+    pub error_format: Option<ErrorFormat>,
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub jit: Option<bool>,
+    pub lang: Option<String>,
+    pub dialect: Option<Dialect>,
+    pub terminal: Option<bool>,
+    // The index of the first element of an array, 0 or 1.
+    pub array_base: Option<i64>,
+    // Whether reading a variable before setting it is an error.
+    pub undefined: Option<Undefined>,
+    // How `div` and `mod` round negative results.
+    pub division: Option<Division>,
+    pub batch: BatchConfig,
+    pub lint: LintConfig,
+    pub metrics: MetricsConfig,
+    // Keyword spellings per language, e.g. `[keywords.de]` with `if = "wenn"`.
+    pub keywords: BTreeMap<String, BTreeMap<String, String>>,
+    // Extra notation accepted for keywords, e.g. `"end if" = "endif"`.
+    pub aliases: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BatchConfig {
+    pub jobs: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LintConfig {
+    pub strict: Option<bool>,
+    pub banned_builtins: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub max_routine_lines: Option<usize>,
+    pub max_globals: Option<usize>,
+}
+
+// Limits checked by `metrics`, for each routine of a program.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub max_statements: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub max_complexity: Option<usize>,
+    pub max_variables: Option<usize>,
+}
+
+impl Config {
+    // The settings every runner applies to the programs it runs.
+    pub fn run_options(&self) -> RunOptions {
+        RunOptions {
+            array_base: self.array_base.unwrap_or(0),
+            undefined: self.undefined.unwrap_or_default(),
+            division: self.division.unwrap_or_default(),
+        }
+    }
+
+    // Loads `path` if given, otherwise `ibc.toml` from the working directory
+    // when it exists.
+    pub fn load(path: Option<&str>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(CONFIG_FILE).exists() => CONFIG_FILE,
+            None => return Ok(Config::default()),
+        };
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::config(format!("Failed to read {}: {}", path, e)))?;
+        toml::from_str(&text).map_err(|e| Error::config(format!("Invalid {}: {}", path, e.message())))
+    }
+
+    // The keywords of `lang`, or of the configured language when none is
+    // given, with the configured aliases.
+    pub fn keywords(&self, lang: Option<&str>) -> Result<Keywords, Error> {
+        let lang = lang.or(self.lang.as_deref()).unwrap_or("en");
+        Keywords::for_language(lang, &self.keywords)?.with_aliases(&self.aliases)
+    }
+}
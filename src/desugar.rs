@@ -0,0 +1,145 @@
+use crate::ast::{AstNode, NodeKind};
+use crate::error::Error;
+use crate::lexer::{Lexer, Span};
+use crate::parser::{Dialect, Parser};
+use crate::visit::{self, Fold};
+
+// Rewrites a parsed program into fewer kinds of statement before it runs, so
+// the interpreter and the JIT see fewer forms. Conditionals nested inside
+// larger expressions stay in the core, so the interpreter still evaluates
+// `NodeKind::Conditional`. Every runner reads programs through `parse`, or
+// desugars a tree it already has, so a program runs and counts its statements
+// the same way whatever runs it.
+//
+// Each rewrite is a `Pass`, and a `Pipeline` runs its passes in order, each
+// on the tree the one before it left. Programs that embed the interpreter can
+// add passes of their own around the standard ones:
+//
+//     let mut pipeline = Pipeline::standard().with(InlineConstants);
+//     let program = pipeline.run(program)?;
+//
+// Some sugar never reaches a pass. The parser already reads `X += 1` as
+// `X = X + 1`, and `else if` as an `if` alone in the else branch, because the
+// formatter, the linter and the other tools rely on those shapes too.
+
+pub trait Pass {
+    // A short name, for listing a pipeline and adding passes around this one.
+    fn name(&self) -> &str;
+
+    fn run(&mut self, program: AstNode) -> Result<AstNode, Error>;
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Pipeline {
+    // A pipeline with no passes, which leaves programs as they are.
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    // The passes `parse` and `desugar` run, which every runner applies before
+    // it runs a program.
+    pub fn standard() -> Self {
+        Pipeline::new().with(ConditionalStatements)
+    }
+
+    // Adds a pass after the others.
+    pub fn with(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    // Adds a pass just before the one called `name`, or after the others if
+    // there is none.
+    pub fn before(mut self, name: &str, pass: impl Pass + 'static) -> Self {
+        let index = self.passes.iter().position(|other| other.name() == name).unwrap_or(self.passes.len());
+        self.passes.insert(index, Box::new(pass));
+        self
+    }
+
+    // Leaves out the pass called `name`.
+    pub fn without(mut self, name: &str) -> Self {
+        self.passes.retain(|pass| pass.name() != name);
+        self
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    // Runs each pass in turn, stopping at the first that fails.
+    pub fn run(&mut self, program: AstNode) -> Result<AstNode, Error> {
+        self.passes.iter_mut().try_fold(program, |program, pass| pass.run(program))
+    }
+}
+
+// Runs the standard passes over `program`.
+pub fn desugar(program: AstNode) -> Result<AstNode, Error> {
+    Pipeline::standard().run(program)
+}
+
+// Reads a program to run from what `lexer` reads: parsed in `dialect`, then
+// desugared.
+pub fn parse(lexer: Lexer, dialect: Dialect) -> Result<AstNode, Error> {
+    desugar(Parser::new(lexer)?.with_dialect(dialect).parse()?)
+}
+
+// Turns a conditional expression that makes up a whole assignment, output or
+// return into an `if` statement with the rest of the statement repeated in
+// each branch, the way `format --strict` writes it:
+//
+//     X = if A > B then A else B    ->    if A > B then X = A else X = B endif
+//
+// Conditionals inside larger expressions are left alone. So are the values of
+// array elements, whose indices are worked out before the value.
+pub struct ConditionalStatements;
+
+impl Pass for ConditionalStatements {
+    fn name(&self) -> &str {
+        "conditional-statements"
+    }
+
+    fn run(&mut self, program: AstNode) -> Result<AstNode, Error> {
+        Ok(self.fold_node(program))
+    }
+}
+
+impl Fold for ConditionalStatements {
+    fn fold_node(&mut self, node: AstNode) -> AstNode {
+        let node = visit::fold_node(self, node);
+        let span = node.span;
+        match node.kind {
+            NodeKind::Assignment(name, value) if is_conditional(&value) => {
+                branches(*value, span, &|value| NodeKind::Assignment(name.clone(), value))
+            }
+            NodeKind::Output(value, newline) if is_conditional(&value) => {
+                branches(*value, span, &|value| NodeKind::Output(value, newline))
+            }
+            NodeKind::Return(Some(value)) if is_conditional(&value) => {
+                branches(*value, span, &|value| NodeKind::Return(Some(value)))
+            }
+            kind => AstNode::new(kind, span),
+        }
+    }
+}
+
+fn is_conditional(node: &AstNode) -> bool {
+    matches!(node.kind, NodeKind::Conditional(..))
+}
+
+// The statement `statement` builds around `value`, as an `if` for each
+// conditional in the way. A conditional in the else branch becomes an
+// `else if`.
+fn branches(value: AstNode, span: Span, statement: &dyn Fn(Box<AstNode>) -> NodeKind) -> AstNode {
+    match value.kind {
+        NodeKind::Conditional(condition, then, otherwise) => {
+            let then = branches(*then, span, statement);
+            let otherwise = branches(*otherwise, span, statement);
+            AstNode::new(NodeKind::If(condition, vec![then], vec![otherwise]), span)
+        }
+        kind => AstNode::new(statement(Box::new(AstNode::new(kind, value.span))), span),
+    }
+}
@@ -0,0 +1,191 @@
+use crate::ast::{AstNode, NodeKind, Procedure, ProcedureKind};
+use crate::formatter;
+use crate::keywords::Keywords;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+impl DocFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "markdown" => Some(DocFormat::Markdown),
+            "html" => Some(DocFormat::Html),
+            _ => None,
+        }
+    }
+}
+
+// What one procedure's doc comments say. The comments are read as plain text
+// with three kinds of special line:
+//
+//     /// Adds up the items of LIST from START on.
+//     /// LIST: the numbers to add
+//     /// START: where to begin, counting from 0
+//     /// Returns: the total
+//     /// Example:
+//     /// output total([1, 2, 3], 1)
+//
+// A line starting with the name of a parameter and a colon describes that
+// parameter, and one starting with `Returns:` the value a function returns.
+// Every line after `Example:` or `Examples:` is example code. The rest is the
+// description, with blank lines between paragraphs.
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    // The definition's first line, such as `function total(ref LIST, START = 0)`.
+    signature: String,
+    paragraphs: Vec<String>,
+    // Each parameter, after `ref` if it is passed by reference, with what its
+    // line says about it.
+    params: Vec<(String, Option<String>)>,
+    returns: Option<String>,
+    examples: Vec<String>,
+}
+
+// Reference documentation for the procedures of `program`, in the order
+// they are defined. Procedures without doc comments are listed too.
+pub fn document(program: &AstNode, keywords: &Keywords, format: DocFormat) -> String {
+    let statements = match &program.kind {
+        NodeKind::Program(statements) => statements.as_slice(),
+        _ => std::slice::from_ref(program),
+    };
+    let entries: Vec<Entry> = statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            NodeKind::Procedure(procedure) => Some(entry(procedure, keywords)),
+            _ => None,
+        })
+        .collect();
+    match format {
+        DocFormat::Markdown => markdown(&entries),
+        DocFormat::Html => html(&entries),
+    }
+}
+
+fn entry(procedure: &Procedure, keywords: &Keywords) -> Entry {
+    let keyword = match procedure.kind {
+        ProcedureKind::Sub => "sub",
+        ProcedureKind::Function => "function",
+    };
+    let mut entry = Entry {
+        name: procedure.name.clone(),
+        signature: format!("{} {}", keywords.spelling(keyword), formatter::signature(procedure, keywords)),
+        paragraphs: vec![],
+        params: vec![],
+        returns: None,
+        examples: vec![],
+    };
+    let mut descriptions: Vec<(&str, String)> = vec![];
+    let mut paragraph: Vec<&str> = vec![];
+    let mut lines = procedure.doc.iter();
+    for line in lines.by_ref() {
+        if line == "Example:" || line == "Examples:" {
+            break;
+        }
+        let labelled = line.split_once(':').map(|(label, text)| (label.trim(), text.trim()));
+        match labelled {
+            Some((label, text)) if procedure.params.iter().any(|param| param.name == label) => {
+                descriptions.push((label, text.to_string()));
+            }
+            Some(("Returns", text)) => entry.returns = Some(text.to_string()),
+            _ if line.trim().is_empty() => {
+                if !paragraph.is_empty() {
+                    entry.paragraphs.push(paragraph.join(" "));
+                    paragraph.clear();
+                }
+            }
+            _ => paragraph.push(line.trim()),
+        }
+    }
+    if !paragraph.is_empty() {
+        entry.paragraphs.push(paragraph.join(" "));
+    }
+    entry.examples = lines.cloned().collect();
+    while entry.examples.last().is_some_and(|line| line.trim().is_empty()) {
+        entry.examples.pop();
+    }
+
+    for param in &procedure.params {
+        let spelled = if param.by_ref {
+            format!("{} {}", keywords.spelling("ref"), param.name)
+        } else {
+            param.name.clone()
+        };
+        let description = descriptions.iter().find(|(name, _)| *name == param.name).map(|(_, text)| text.clone());
+        entry.params.push((spelled, description));
+    }
+    entry
+}
+
+fn markdown(entries: &[Entry]) -> String {
+    let mut out = String::from("# Procedures\n\n");
+    for entry in entries {
+        out.push_str(&format!("- [{}](#{})\n", entry.name, entry.name));
+    }
+    for entry in entries {
+        out.push_str(&format!("\n## {}\n\n```\n{}\n```\n", entry.name, entry.signature));
+        for paragraph in &entry.paragraphs {
+            out.push_str(&format!("\n{}\n", paragraph));
+        }
+        if !entry.params.is_empty() {
+            out.push_str("\n**Parameters**\n\n");
+            for (name, description) in &entry.params {
+                match description {
+                    Some(description) => out.push_str(&format!("- `{}`: {}\n", name, description)),
+                    None => out.push_str(&format!("- `{}`\n", name)),
+                }
+            }
+        }
+        if let Some(returns) = &entry.returns {
+            out.push_str(&format!("\n**Returns** {}\n", returns));
+        }
+        if !entry.examples.is_empty() {
+            out.push_str(&format!("\n**Example**\n\n```\n{}\n```\n", entry.examples.join("\n")));
+        }
+    }
+    out
+}
+
+fn html(entries: &[Entry]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Procedures</title>\n</head>\n<body>\n",
+    );
+    out.push_str("<h1>Procedures</h1>\n<ul>\n");
+    for entry in entries {
+        out.push_str(&format!("<li><a href=\"#{}\">{}</a></li>\n", escape(&entry.name), escape(&entry.name)));
+    }
+    out.push_str("</ul>\n");
+    for entry in entries {
+        out.push_str(&format!("<section id=\"{}\">\n<h2>{}</h2>\n", escape(&entry.name), escape(&entry.name)));
+        out.push_str(&format!("<pre><code>{}</code></pre>\n", escape(&entry.signature)));
+        for paragraph in &entry.paragraphs {
+            out.push_str(&format!("<p>{}</p>\n", escape(paragraph)));
+        }
+        if !entry.params.is_empty() {
+            out.push_str("<h3>Parameters</h3>\n<dl>\n");
+            for (name, description) in &entry.params {
+                out.push_str(&format!("<dt><code>{}</code></dt>\n", escape(name)));
+                if let Some(description) = description {
+                    out.push_str(&format!("<dd>{}</dd>\n", escape(description)));
+                }
+            }
+            out.push_str("</dl>\n");
+        }
+        if let Some(returns) = &entry.returns {
+            out.push_str(&format!("<h3>Returns</h3>\n<p>{}</p>\n", escape(returns)));
+        }
+        if !entry.examples.is_empty() {
+            out.push_str(&format!("<h3>Example</h3>\n<pre><code>{}</code></pre>\n", escape(&entry.examples.join("\n"))));
+        }
+        out.push_str("</section>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,62 @@
+use crate::ast::AstNode;
+use crate::desugar;
+use crate::error::Error;
+use crate::interpreter::{Interpreter, Limits, RunOptions, Stats};
+use std::io::{self, Cursor};
+use std::sync::Arc;
+
+// What a program did with one set of inputs.
+#[derive(Debug)]
+pub struct Outcome {
+    pub output: String,
+    pub error: Option<Error>,
+    pub stats: Stats,
+}
+
+// The first place two runs differ. Lines are numbered from 1; a missing line
+// means that run's output had already ended.
+#[derive(Debug)]
+pub enum Divergence {
+    Output { line: usize, expected: Option<String>, actual: Option<String> },
+    // The outputs match but the runs did not stop the same way.
+    Error,
+}
+
+// Runs `program` with `input` as standard input, without showing prompts.
+// Callers pass the tree as parsed, which their own tools work on, and it is
+// desugared here as every runner does.
+pub fn run(program: &Arc<AstNode>, input: &str, limits: Limits, options: RunOptions) -> Outcome {
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    interpreter.set_input(Box::new(Cursor::new(input.to_string())), false);
+    interpreter.set_limits(limits);
+    interpreter.set_options(options);
+    let error = desugar::desugar(AstNode::clone(program))
+        .and_then(|program| interpreter.interpret(Arc::new(program)))
+        .err();
+    Outcome { output: interpreter.output_so_far().to_string(), error, stats: interpreter.stats() }
+}
+
+// Compares `actual` with `expected` line by line, then by how they stopped.
+// Two runs that stop with an error agree if the error codes match.
+pub fn compare(expected: &Outcome, actual: &Outcome) -> Option<Divergence> {
+    let mut expected_lines = expected.output.split_inclusive('\n');
+    let mut actual_lines = actual.output.split_inclusive('\n');
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => break,
+            (left, right) if left == right => {}
+            (left, right) => {
+                return Some(Divergence::Output {
+                    line,
+                    expected: left.map(String::from),
+                    actual: right.map(String::from),
+                })
+            }
+        }
+    }
+    let code = |outcome: &Outcome| outcome.error.as_ref().map(|error| error.code);
+    if code(expected) != code(actual) {
+        return Some(Divergence::Error);
+    }
+    None
+}
@@ -0,0 +1,240 @@
+use crate::color::Colors;
+use crate::lexer::Span;
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Io,
+    Config,
+    Syntax,
+    Runtime,
+    // A program ran past a limit set with `Interpreter::set_limits`. Unlike
+    // runtime errors, `try` cannot catch it.
+    Limit,
+    Lint,
+    // A refactoring that cannot be done, such as renaming to a name the
+    // program already uses.
+    Refactor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(ErrorFormat::Human),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    pub file: Option<String>,
+    pub related: Vec<Related>,
+}
+
+// Another place in the program an error is about, such as where a name it
+// reports was first defined.
+#[derive(Debug, Clone)]
+pub struct Related {
+    pub message: String,
+    pub span: Span,
+    pub file: Option<String>,
+}
+
+impl Error {
+    pub fn syntax(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Error {
+            kind: ErrorKind::Syntax,
+            code,
+            message: message.into(),
+            span: Some(span),
+            file: None,
+            related: vec![],
+        }
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        Error {
+            kind: ErrorKind::Io,
+            code: "E0001",
+            message: message.into(),
+            span: None,
+            file: None,
+            related: vec![],
+        }
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Error {
+            kind: ErrorKind::Config,
+            code: "E0002",
+            message: message.into(),
+            span: None,
+            file: None,
+            related: vec![],
+        }
+    }
+
+    // A style problem reported by `lint`; the program still runs.
+    pub fn lint(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Error {
+            kind: ErrorKind::Lint,
+            code,
+            message: message.into(),
+            span: Some(span),
+            file: None,
+            related: vec![],
+        }
+    }
+
+    pub fn refactor(code: &'static str, message: impl Into<String>) -> Self {
+        Error {
+            kind: ErrorKind::Refactor,
+            code,
+            message: message.into(),
+            span: None,
+            file: None,
+            related: vec![],
+        }
+    }
+
+    pub fn severity(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::Lint => "warning",
+            _ => "error",
+        }
+    }
+
+    pub fn runtime(code: &'static str, message: impl Into<String>) -> Self {
+        Error {
+            kind: ErrorKind::Runtime,
+            code,
+            message: message.into(),
+            span: None,
+            file: None,
+            related: vec![],
+        }
+    }
+
+    pub fn limit(code: &'static str, message: impl Into<String>) -> Self {
+        Error {
+            kind: ErrorKind::Limit,
+            code,
+            message: message.into(),
+            span: None,
+            file: None,
+            related: vec![],
+        }
+    }
+
+    // Attaches `span` unless a more precise one was already recorded.
+    pub fn or_span(mut self, span: Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    pub fn with_related(mut self, message: impl Into<String>, span: Span) -> Self {
+        self.related.push(Related { message: message.into(), span, file: None });
+        self
+    }
+
+    // Resolves the span's file index against the files making up the program.
+    pub fn with_file_names(mut self, files: &[String]) -> Self {
+        if let (None, Some(span)) = (&self.file, self.span) {
+            self.file = files.get(span.file).cloned();
+        }
+        for related in self.related.iter_mut().filter(|related| related.file.is_none()) {
+            related.file = files.get(related.span.file).cloned();
+        }
+        self
+    }
+
+    pub fn render(&self, colors: &Colors) -> String {
+        let kind = match self.kind {
+            ErrorKind::Io => "Error",
+            ErrorKind::Config => "Configuration error",
+            ErrorKind::Syntax => "Syntax error",
+            ErrorKind::Runtime => "Runtime error",
+            ErrorKind::Limit => "Limit exceeded",
+            ErrorKind::Lint => "Warning",
+            ErrorKind::Refactor => "Refactoring error",
+        };
+        let label = format!("{}[{}]", kind, self.code);
+        let label = match self.kind {
+            ErrorKind::Lint => colors.warning(&label),
+            _ => colors.error(&label),
+        };
+        let mut text = format!("{}: {}", label, self.message);
+        text.push_str(&colors.dim(&location(self.file.as_deref(), self.span)));
+        for related in &self.related {
+            let location = location(related.file.as_deref(), Some(related.span));
+            text.push_str(&format!("\n  {}: {}{}", colors.dim("note"), related.message, colors.dim(&location)));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let kind = match self.kind {
+            ErrorKind::Io => "io",
+            ErrorKind::Config => "config",
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::Runtime => "runtime",
+            ErrorKind::Limit => "limit",
+            ErrorKind::Lint => "lint",
+            ErrorKind::Refactor => "refactor",
+        };
+        json!({
+            "severity": self.severity(),
+            "code": self.code,
+            "kind": kind,
+            "message": self.message,
+            "file": self.file,
+            "span": self.span,
+            "related": self.related.iter().map(|related| json!({
+                "message": related.message,
+                "file": related.file,
+                "span": related.span,
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn format(&self, format: ErrorFormat, colors: &Colors) -> String {
+        match format {
+            ErrorFormat::Human => self.render(colors),
+            ErrorFormat::Json => self.to_json().to_string(),
+        }
+    }
+}
+
+fn location(file: Option<&str>, span: Option<Span>) -> String {
+    match (file, span) {
+        (Some(file), Some(span)) => format!(" at line {}, column {} of {}", span.line, span.column, file),
+        (None, Some(span)) => format!(" at line {}, column {}", span.line, span.column),
+        (Some(file), None) => format!(" in {}", file),
+        (None, None) => String::new(),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(&Colors::plain()))
+    }
+}
+
+impl std::error::Error for Error {}
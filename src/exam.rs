@@ -0,0 +1,109 @@
+use crate::ast::{AstNode, NodeKind};
+use crate::error::Error;
+use crate::keywords::Keywords;
+use crate::lexer::{Lexer, Span, Token};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+// Checks a program against the notation of IB exam papers, for marking mock
+// exams: keywords in capitals, variable names in capitals, and only the
+// constructs the IB pseudocode guide describes. Files are read with
+// `Keywords::exam`, so a program written in lowercase still parses and every
+// problem in it can be reported.
+
+pub const RULES: [(&str, &str); 3] = [
+    ("W0018", "Keywords are written in capitals"),
+    ("W0019", "Variable names are written in capitals"),
+    ("W0020", "Only constructs from the IB pseudocode guide are used"),
+];
+
+// How a program did on one rule.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Compliance {
+    pub code: &'static str,
+    pub rule: &'static str,
+    pub problems: usize,
+}
+
+// The exam notation problems in `program`, read from `sources` with
+// `keywords`, which should be `Keywords::exam()`.
+pub fn check(sources: &[String], program: &AstNode, keywords: &Keywords) -> Result<Vec<Error>, Error> {
+    let mut warnings = vec![];
+    for (file, source) in sources.iter().enumerate() {
+        for info in Lexer::for_file(source, file).with_keywords(keywords).tokenize()? {
+            let text = &source[info.span.offset..info.span.end_offset];
+            match &info.token {
+                Token::Identifier(_) | Token::Number(_) | Token::Float(_) | Token::Eof => {}
+                Token::String(text) if text.contains('{') => {
+                    let message = "Expressions in braces inside strings are not part of IB pseudocode; join with +";
+                    warnings.push(Error::lint("W0020", message, info.span));
+                }
+                _ if text.starts_with(char::is_alphabetic) && text != text.to_uppercase() => {
+                    let message = format!("Keywords are written in capitals: {}, not {}", text.to_uppercase(), text);
+                    warnings.push(Error::lint("W0018", message, info.span));
+                }
+                _ => {}
+            }
+        }
+    }
+    variable_names(program, &mut warnings);
+    constructs(program, &mut warnings);
+    warnings.sort_by_key(|warning| warning.span.map(|span| (span.file, span.offset)));
+    Ok(warnings)
+}
+
+// Each rule with how many of `warnings` break it.
+pub fn report(warnings: &[Error]) -> Vec<Compliance> {
+    RULES
+        .iter()
+        .map(|&(code, rule)| Compliance {
+            code,
+            rule,
+            problems: warnings.iter().filter(|warning| warning.code == code).count(),
+        })
+        .collect()
+}
+
+// Reported once for each name, where it first appears.
+fn variable_names(program: &AstNode, warnings: &mut Vec<Error>) {
+    let mut seen = BTreeSet::new();
+    let mut check = |name: &str, span: Span| {
+        if name != name.to_uppercase() && seen.insert(name.to_string()) {
+            let message = format!("Variable names are written in capitals: {}, not {}", name.to_uppercase(), name);
+            warnings.push(Error::lint("W0019", message, span));
+        }
+    };
+    program.walk(&mut |node| match &node.kind {
+        NodeKind::Identifier(name)
+        | NodeKind::Assignment(name, _)
+        | NodeKind::Input(name, _)
+        | NodeKind::SetElement(name, ..)
+        | NodeKind::Try(_, Some(name), _) => check(name, node.span),
+        NodeKind::Procedure(procedure) => procedure.params.iter().for_each(|param| check(&param.name, node.span)),
+        NodeKind::Global(names) => names.iter().for_each(|name| check(name, node.span)),
+        _ => {}
+    });
+}
+
+fn constructs(program: &AstNode, warnings: &mut Vec<Error>) {
+    program.walk(&mut |node| {
+        let construct = match &node.kind {
+            NodeKind::Case(..) => "case",
+            NodeKind::Try(..) => "try and catch",
+            NodeKind::Raise(_) => "error",
+            NodeKind::Conditional(..) => "An if expression",
+            NodeKind::Global(_) => "global",
+            NodeKind::Output(_, false) => "output inline",
+            NodeKind::BinOp(_, op, _) if op.is_bitwise() => "A bitwise operator",
+            NodeKind::Input(_, Some(_)) => "A prompt for input",
+            NodeKind::Procedure(procedure) if procedure.params.iter().any(|param| param.by_ref) => "ref",
+            NodeKind::Procedure(procedure) if procedure.params.iter().any(|param| param.default.is_some()) => {
+                "A default value for a parameter"
+            }
+            _ => return,
+        };
+        let message = format!("{} is not part of IB pseudocode", construct);
+        warnings.push(Error::lint("W0020", message, node.span));
+    });
+}
@@ -0,0 +1,69 @@
+use crate::color::Colors;
+use crate::error::Error;
+use crate::keywords::Keywords;
+use crate::lexer::{Lexer, Token};
+use crate::parser::{Dialect, Parser};
+
+// The `explore` REPL: each statement typed is shown as the tokens the lexer
+// reads beside the tree the parser builds from them, without running it. For
+// students learning how a language is read, and for checking a change to the
+// grammar.
+
+// What to show for `source`: its tokens on the left and its tree, or the
+// error that stopped the lexer or parser, on the right. Gives `None` when
+// `source` stops part way through a statement, so the caller can read another
+// line, unless `finished` says no more is coming.
+pub fn explore(source: &str, keywords: &Keywords, dialect: Dialect, colors: &Colors, finished: bool) -> Option<String> {
+    let mut lexer = Lexer::new(source).with_keywords(keywords);
+    let mut tokens = vec![];
+    let mut lex_error = None;
+    loop {
+        match lexer.get_next_token() {
+            Ok(info) if info.token == Token::Eof => break,
+            Ok(info) => {
+                let span = info.span;
+                tokens.push(format!("{:<8}{:?}", format!("{}:{}", span.line, span.column), info.token));
+            }
+            Err(err) => {
+                lex_error = Some(err);
+                break;
+            }
+        }
+    }
+
+    let tree = match lex_error {
+        Some(err) => Err(err),
+        None => Parser::new(Lexer::new(source).with_keywords(keywords))
+            .and_then(|parser| parser.with_dialect(dialect).parse()),
+    };
+    let tree = match tree {
+        Ok(tree) => tree.to_pretty_string(colors),
+        Err(err) if !finished && unfinished(source, &err) => return None,
+        Err(err) => err.render(colors),
+    };
+    Some(side_by_side(&tokens, &tree, colors))
+}
+
+// The parser ran out of tokens, or a long string is still open.
+fn unfinished(source: &str, err: &Error) -> bool {
+    let Some(span) = err.span else {
+        return false;
+    };
+    span.offset >= source.trim_end().len() || (err.code == "E0102" && source[span.offset..].starts_with("\"\"\""))
+}
+
+// The right column may be colored, so only the left one is padded.
+fn side_by_side(left: &[String], right: &str, colors: &Colors) -> String {
+    let width = left.iter().map(|line| line.chars().count()).max().unwrap_or(0).max("Tokens".len());
+    let right: Vec<&str> = right.lines().collect();
+    let header = colors.header(&format!("{:<width$}", "Tokens"));
+    let mut out = format!("{}  {} {}\n", header, colors.dim("│"), colors.header("Tree"));
+    for row in 0..left.len().max(right.len()) {
+        let token = left.get(row).map_or("", String::as_str);
+        let node = right.get(row).copied().unwrap_or("");
+        let row = format!("{:<width$}  {} {}", token, colors.dim("│"), node);
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+    out
+}
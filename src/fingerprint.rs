@@ -0,0 +1,140 @@
+use crate::ast::{AstNode, NodeKind};
+use crate::equivalence;
+use crate::formatter;
+use crate::interpreter::{Limits, RunOptions};
+use crate::keywords::Keywords;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+// Fingerprints for spotting submissions too alike to be independent work.
+// Renaming variables, rewording messages, reformatting and writing keywords
+// in another language leave them unchanged:
+//
+// - `structure` hashes the program's shape: the formatted tree with names
+//   replaced by the order they first appear in and string text removed.
+// - `execution` hashes what the program printed, and how it stopped, on each
+//   test input.
+//
+// Pairs are compared by how many runs of GRAM consecutive words of their
+// shapes they share, so a copy with a few statements added or moved still
+// stands out. Hashes are FNV-1a, which gives the same fingerprint on every
+// machine and every build.
+
+const GRAM: usize = 5;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Fingerprint {
+    pub file: String,
+    pub structure: String,
+    pub execution: String,
+    #[serde(skip)]
+    grams: BTreeSet<u64>,
+}
+
+// Two submissions and how alike their shapes are, from 0 to 1.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pair {
+    pub first: String,
+    pub second: String,
+    pub similarity: f64,
+    pub same_output: bool,
+}
+
+// `program` is run once for each of `inputs`, within `limits`, so this wants
+// a thread with a large stack like any other run.
+pub fn fingerprint(
+    file: &str,
+    program: &Arc<AstNode>,
+    inputs: &[String],
+    limits: Limits,
+    options: RunOptions,
+) -> Fingerprint {
+    let shape = shape(program);
+    let words: Vec<&str> = shape.split_whitespace().collect();
+    let grams = words.windows(GRAM.min(words.len()).max(1)).map(|gram| fnv(gram.join(" ").as_bytes())).collect();
+
+    let mut runs = String::new();
+    for input in inputs {
+        let outcome = equivalence::run(program, input, limits, options);
+        for line in outcome.output.lines() {
+            runs.push_str(line.trim_end());
+            runs.push('\n');
+        }
+        runs.push_str(&format!("stopped: {}\n", outcome.error.map_or("", |error| error.code)));
+    }
+
+    Fingerprint {
+        file: file.to_string(),
+        structure: format!("{:016x}", fnv(shape.as_bytes())),
+        execution: format!("{:016x}", fnv(runs.as_bytes())),
+        grams,
+    }
+}
+
+// Every pair at least `threshold` alike, most alike first.
+pub fn similar(fingerprints: &[Fingerprint], threshold: f64) -> Vec<Pair> {
+    let mut pairs = vec![];
+    for (index, first) in fingerprints.iter().enumerate() {
+        for second in &fingerprints[index + 1..] {
+            let shared = first.grams.intersection(&second.grams).count();
+            let total = first.grams.union(&second.grams).count();
+            let similarity = if total == 0 { 1.0 } else { shared as f64 / total as f64 };
+            if similarity >= threshold {
+                pairs.push(Pair {
+                    first: first.file.clone(),
+                    second: second.file.clone(),
+                    similarity,
+                    same_output: first.execution == second.execution,
+                });
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    pairs
+}
+
+// The program with each name it defines replaced by one saying when it first
+// appears, `V1` for variables and `P1` for procedures, doc comments removed,
+// strings cut down to the braces they interpolate, and formatted with English
+// keywords. Builtins and methods keep their names.
+pub fn shape(program: &AstNode) -> String {
+    let mut procedures = BTreeSet::new();
+    program.walk(&mut |node| {
+        if let NodeKind::Procedure(procedure) = &node.kind {
+            procedures.insert(procedure.name.clone());
+        }
+    });
+
+    let mut names = HashMap::new();
+    let mut rename = |name: &mut String| {
+        let prefix = if procedures.contains(name) { "P" } else { "V" };
+        let count = names.values().filter(|other: &&String| other.starts_with(prefix)).count();
+        *name = names.entry(name.clone()).or_insert_with(|| format!("{}{}", prefix, count + 1)).clone();
+    };
+    let mut program = program.clone();
+    program.walk_mut(&mut |node| match &mut node.kind {
+        NodeKind::Assignment(name, _)
+        | NodeKind::Input(name, _)
+        | NodeKind::Identifier(name)
+        | NodeKind::SetElement(name, ..)
+        | NodeKind::Try(_, Some(name), _) => rename(name),
+        NodeKind::Call(name, _) if procedures.contains(name) => rename(name),
+        NodeKind::Global(globals) => globals.iter_mut().for_each(&mut rename),
+        NodeKind::Procedure(procedure) => {
+            let procedure = Arc::make_mut(procedure);
+            rename(&mut procedure.name);
+            procedure.params.iter_mut().for_each(|param| rename(&mut param.name));
+            procedure.doc.clear();
+        }
+        NodeKind::String(text) => *text = text.matches('{').map(|_| "{}").collect(),
+        _ => {}
+    });
+    formatter::format(&program, &Keywords::english(), false)
+}
+
+fn fnv(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
@@ -0,0 +1,443 @@
+use crate::ast::{AstNode, NodeKind};
+use crate::error::Error;
+use crate::formatter;
+use crate::keywords::{Keywords, KEYWORDS};
+use crate::lexer::{Lexer, Span, Token, TokenInfo};
+use crate::parser::{Dialect, Parser};
+use crate::visit::{self, Fold};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+// Fixes for lint warnings that are mechanical enough to make without asking,
+// as edits to the text of each file, so the layout and comments stay as the
+// author left them.
+//
+// A file that does not parse only gets the fixes that can make it parse:
+// keywords in the wrong case, such as `If`, and `==` where pseudocode writes
+// `=`. A file that parses gets the fixes the tree shows are safe: parentheses
+// around a whole expression, and assignments of a literal to a variable
+// nothing reads. As with refactorings, the changed text must parse to the
+// tree the fixes asked for.
+
+// How many times `fix` looks again for fixes that earlier ones uncovered,
+// such as the second pair in `((X))`.
+const ROUNDS: usize = 10;
+
+// A warning and the edits that fix it.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub warning: Error,
+    pub edits: Vec<Edit>,
+}
+
+// Replaces `range` of the text of file `file` with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub file: usize,
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+// The files after fixing, with the warnings that were fixed. Spans in the
+// warnings point into the text as it was when each fix was made.
+#[derive(Debug, Clone)]
+pub struct Fixed {
+    pub sources: Vec<String>,
+    pub fixed: Vec<Error>,
+}
+
+// The fixes for `sources`, which are the files of one program in order.
+pub fn suggestions(sources: &[String], keywords: &Keywords, dialect: Dialect) -> Result<Vec<Suggestion>, Error> {
+    let mut programs = vec![];
+    let mut repairs = vec![];
+    for (file, source) in sources.iter().enumerate() {
+        match parse(source, file, keywords, dialect) {
+            Ok(program) => programs.push(program),
+            Err(error) => {
+                let found = repairs_for(source, file, keywords)?;
+                if found.is_empty() {
+                    return Err(error);
+                }
+                repairs.extend(found);
+            }
+        }
+    }
+    if !repairs.is_empty() {
+        return Ok(repairs);
+    }
+
+    let mut suggestions = vec![];
+    let read = reads(&programs);
+    for (file, (source, program)) in sources.iter().zip(&programs).enumerate() {
+        let tokens = Lexer::for_file(source, file).with_keywords(keywords).tokenize()?;
+        redundant_parentheses(program, &tokens, &mut suggestions);
+        unused_variables(program, source, &read, &mut suggestions);
+    }
+    suggestions.sort_by_key(|suggestion| suggestion.warning.span.map(|span| (span.file, span.offset)));
+    Ok(suggestions)
+}
+
+// Makes every fix `suggestions` finds, looking again after each round until
+// there are none left.
+pub fn fix(sources: &[String], keywords: &Keywords, dialect: Dialect) -> Result<Fixed, Error> {
+    let mut fixed = Fixed { sources: sources.to_vec(), fixed: vec![] };
+    for _ in 0..ROUNDS {
+        let suggestions = suggestions(&fixed.sources, keywords, dialect)?;
+        if suggestions.is_empty() {
+            break;
+        }
+        // Trees are only there to check against when every file parsed.
+        let before: Option<Vec<AstNode>> = fixed
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(file, source)| parse(source, file, keywords, dialect).ok())
+            .collect();
+
+        // Fixes that touch text another fix in this round changes wait for
+        // the next round.
+        let mut edits: Vec<Edit> = vec![];
+        let mut removed = BTreeSet::new();
+        for suggestion in suggestions {
+            let overlaps = suggestion.edits.iter().any(|edit| {
+                edits.iter().any(|taken| {
+                    taken.file == edit.file && taken.range.start < edit.range.end && edit.range.start < taken.range.end
+                })
+            });
+            if overlaps {
+                continue;
+            }
+            edits.extend(suggestion.edits);
+            if suggestion.warning.code == "W0008" {
+                removed.extend(suggestion.warning.span.map(place));
+            }
+            fixed.fixed.push(suggestion.warning);
+        }
+        let after: Vec<String> =
+            fixed.sources.iter().enumerate().map(|(file, source)| apply(source, file, &edits)).collect();
+
+        if let Some(before) = before {
+            check(&before, &after, &removed, keywords, dialect)?;
+        }
+        fixed.sources = after;
+    }
+    Ok(fixed)
+}
+
+// The lines that differ between `old` and `new`, as a unified diff of the
+// file `name`. Empty when they are the same.
+pub fn diff(name: &str, old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let lines = line_diff(&old_lines, &new_lines);
+    let changed: Vec<usize> = (0..lines.len()).filter(|&index| lines[index].0 != ' ').collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", name, name);
+    let mut next = 0;
+    while next < changed.len() {
+        // A hunk runs on while the next change is close enough that their
+        // context would meet.
+        let start = changed[next].saturating_sub(CONTEXT);
+        let mut last = changed[next];
+        while next < changed.len() && changed[next] <= last + 2 * CONTEXT {
+            last = changed[next];
+            next += 1;
+        }
+        let end = (last + CONTEXT + 1).min(lines.len());
+
+        let before = |tag: char| lines[..start].iter().filter(|(t, _)| *t != tag).count();
+        let within = |tag: char| lines[start..end].iter().filter(|(t, _)| *t != tag).count();
+        let (old_start, new_start) = (before('+'), before('-'));
+        let (old_count, new_count) = (within('+'), within('-'));
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        ));
+        for (tag, line) in &lines[start..end] {
+            out.push_str(&format!("{}{}\n", tag, line));
+        }
+    }
+    out
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    // An empty range names the line before it, as `diff -u` does.
+    let first = if count == 0 { start } else { start + 1 };
+    if count == 1 {
+        first.to_string()
+    } else {
+        format!("{},{}", first, count)
+    }
+}
+
+// The shortest way to turn `old` into `new`, by Myers' algorithm: each line
+// tagged ' ' when both have it, '-' when only `old` does and '+' when only
+// `new` does.
+fn line_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let offset = n + m + 1;
+    let mut furthest = vec![0isize; 2 * offset as usize + 1];
+    // `furthest` as it was before each number of changes was tried, to walk
+    // back through.
+    let mut trace = vec![];
+    let at = |k: isize| (k + offset) as usize;
+    'search: for changes in 0..=n + m {
+        trace.push(furthest.clone());
+        for k in (-changes..=changes).step_by(2) {
+            let down = k == -changes || (k != changes && furthest[at(k - 1)] < furthest[at(k + 1)]);
+            let mut x = if down { furthest[at(k + 1)] } else { furthest[at(k - 1)] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut x, mut y) = (n, m);
+    for (changes, furthest) in trace.iter().enumerate().rev() {
+        let changes = changes as isize;
+        let k = x - y;
+        let down = k == -changes || (k != changes && furthest[at(k - 1)] < furthest[at(k + 1)]);
+        let previous_k = if down { k + 1 } else { k - 1 };
+        let previous_x = furthest[at(previous_k)];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            lines.push((' ', old[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if changes > 0 {
+            if x == previous_x {
+                lines.push(('+', new[previous_y as usize]));
+            } else {
+                lines.push(('-', old[previous_x as usize]));
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    lines.reverse();
+    lines
+}
+
+fn parse(source: &str, file: usize, keywords: &Keywords, dialect: Dialect) -> Result<AstNode, Error> {
+    Parser::new(Lexer::for_file(source, file).with_keywords(keywords))?.with_dialect(dialect).parse()
+}
+
+fn apply(source: &str, file: usize, edits: &[Edit]) -> String {
+    let mut edits: Vec<&Edit> = edits.iter().filter(|edit| edit.file == file).collect();
+    edits.sort_by_key(|edit| edit.range.start);
+    let mut fixed = String::with_capacity(source.len());
+    let mut offset = 0;
+    for edit in edits {
+        fixed.push_str(&source[offset..edit.range.start]);
+        fixed.push_str(&edit.text);
+        offset = edit.range.end;
+    }
+    fixed.push_str(&source[offset..]);
+    fixed
+}
+
+// The fixed text must mean what the program meant, less the assignments that
+// were removed.
+fn check(
+    before: &[AstNode],
+    after: &[String],
+    removed: &BTreeSet<(usize, usize, usize)>,
+    keywords: &Keywords,
+    dialect: Dialect,
+) -> Result<(), Error> {
+    for (file, (program, source)) in before.iter().zip(after).enumerate() {
+        let actual = parse(source, file, keywords, dialect)
+            .map_err(|error| Error::refactor("E0403", format!("Fixing would break the program: {}", error.message)))?;
+        let expected = Remove { removed }.fold_node(program.clone());
+        if formatter::format(&actual, keywords, false) != formatter::format(&expected, keywords, false) {
+            return Err(Error::refactor("E0403", "Fixing would change what the program does"));
+        }
+    }
+    Ok(())
+}
+
+// Spans are compared by where they are.
+fn place(span: Span) -> (usize, usize, usize) {
+    (span.file, span.offset, span.end_offset)
+}
+
+struct Remove<'r> {
+    removed: &'r BTreeSet<(usize, usize, usize)>,
+}
+
+impl Fold for Remove<'_> {
+    fn fold_block(&mut self, statements: Vec<AstNode>) -> Vec<AstNode> {
+        let kept = statements.into_iter().filter(|statement| !self.removed.contains(&place(statement.span))).collect();
+        visit::fold_block(self, kept)
+    }
+}
+
+// Keywords in the wrong case, which lex as names, and `==`, which lexes as
+// two `=`.
+fn repairs_for(source: &str, file: usize, keywords: &Keywords) -> Result<Vec<Suggestion>, Error> {
+    let tokens = Lexer::for_file(source, file).with_keywords(keywords).tokenize()?;
+    // Words in capitals may be variables, which are written that way, so
+    // they only count as keywords when nothing is assigned to them.
+    let assigned: BTreeSet<&str> = tokens
+        .windows(2)
+        .filter_map(|pair| match (&pair[0].token, &pair[1].token) {
+            (Token::Identifier(name), Token::Assign | Token::LBracket) => Some(name.as_str()),
+            (Token::Input | Token::Global | Token::Catch | Token::Ref | Token::Comma, Token::Identifier(name)) => {
+                Some(name.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut suggestions = vec![];
+    for (index, info) in tokens.iter().enumerate() {
+        match &info.token {
+            Token::Identifier(word) => {
+                let Some(spelling) = KEYWORDS
+                    .iter()
+                    .map(|keyword| keywords.spelling(keyword))
+                    .find(|spelling| spelling.to_lowercase() == word.to_lowercase())
+                else {
+                    continue;
+                };
+                let capitals = word.chars().all(|c| !c.is_lowercase());
+                if capitals && assigned.contains(word.as_str()) {
+                    continue;
+                }
+                let message = format!("Keywords are written {}, not {}", spelling, word);
+                suggestions.push(Suggestion {
+                    warning: Error::lint("W0005", message, info.span),
+                    edits: vec![Edit {
+                        file,
+                        range: info.span.offset..info.span.end_offset,
+                        text: spelling.to_string(),
+                    }],
+                });
+            }
+            Token::Assign => {
+                let Some(next) = tokens.get(index + 1) else {
+                    continue;
+                };
+                let previous = index.checked_sub(1).map(|previous| &tokens[previous].token);
+                let doubled = next.token == Token::Assign && next.span.offset == info.span.end_offset;
+                if doubled && previous != Some(&Token::Assign) {
+                    suggestions.push(Suggestion {
+                        warning: Error::lint("W0006", "Equality is written =, not ==", info.span.to(next.span)),
+                        edits: vec![Edit { file, range: next.span.offset..next.span.end_offset, text: String::new() }],
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(suggestions)
+}
+
+// Parentheses around the whole value of a statement, such as `X = (A + B)`.
+fn redundant_parentheses(program: &AstNode, tokens: &[TokenInfo], suggestions: &mut Vec<Suggestion>) {
+    program.walk(&mut |node| {
+        let value = match &node.kind {
+            NodeKind::Assignment(_, value)
+            | NodeKind::Output(value, _)
+            | NodeKind::If(value, ..)
+            | NodeKind::Loop(value, _)
+            | NodeKind::Case(value, ..)
+            | NodeKind::SetElement(_, _, value)
+            | NodeKind::Return(Some(value))
+            | NodeKind::Raise(value) => value,
+            _ => return,
+        };
+        // Parts of an interpolated string have no tokens of their own.
+        let Ok(first) = tokens.binary_search_by_key(&value.span.offset, |info| info.span.offset) else {
+            return;
+        };
+        let Some(last) = tokens[first..].iter().position(|info| info.span.end_offset == value.span.end_offset) else {
+            return;
+        };
+        let last = first + last;
+        let (Some(open), Some(close)) = (first.checked_sub(1).map(|open| &tokens[open]), tokens.get(last + 1)) else {
+            return;
+        };
+        if open.token != Token::LParen || close.token != Token::RParen {
+            return;
+        }
+        let mut depth = 0;
+        for info in &tokens[first..=last] {
+            match info.token {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => return,
+                Token::RParen => depth -= 1,
+                _ => {}
+            }
+        }
+        suggestions.push(Suggestion {
+            warning: Error::lint("W0007", "These parentheses are not needed", open.span.to(close.span)),
+            edits: vec![
+                Edit { file: open.span.file, range: open.span.offset..open.span.end_offset, text: String::new() },
+                Edit { file: close.span.file, range: close.span.offset..close.span.end_offset, text: String::new() },
+            ],
+        });
+    });
+}
+
+// Every name the program reads, in any file.
+fn reads(programs: &[AstNode]) -> BTreeSet<String> {
+    let mut read = BTreeSet::new();
+    for program in programs {
+        program.walk(&mut |node| match &node.kind {
+            NodeKind::Identifier(name) => {
+                read.insert(name.clone());
+            }
+            // Assigning to a parameter can change the caller's variable.
+            NodeKind::Procedure(procedure) => read.extend(procedure.params.iter().map(|param| param.name.clone())),
+            _ => {}
+        });
+    }
+    read
+}
+
+// Assignments of a literal to a variable nothing reads, which can go without
+// changing what the program does. The line goes with the statement when
+// nothing else is on it.
+fn unused_variables(program: &AstNode, source: &str, read: &BTreeSet<String>, suggestions: &mut Vec<Suggestion>) {
+    program.walk(&mut |node| {
+        let NodeKind::Assignment(name, value) = &node.kind else {
+            return;
+        };
+        if read.contains(name) || !literal(value) {
+            return;
+        }
+        let span = node.span;
+        let line_start = source[..span.offset].rfind('\n').map_or(0, |newline| newline + 1);
+        let rest = &source[span.end_offset..];
+        let line_end = span.end_offset + rest.find('\n').map_or(rest.len(), |newline| newline + 1);
+        let alone =
+            source[line_start..span.offset].trim().is_empty() && source[span.end_offset..line_end].trim().is_empty();
+        let range = if alone { line_start..line_end } else { span.offset..span.end_offset };
+        suggestions.push(Suggestion {
+            warning: Error::lint("W0008", format!("{} is set here but never used", name), span),
+            edits: vec![Edit { file: span.file, range, text: String::new() }],
+        });
+    });
+}
+
+fn literal(node: &AstNode) -> bool {
+    match &node.kind {
+        NodeKind::Number(_) | NodeKind::Float(_) | NodeKind::String(_) | NodeKind::Null => true,
+        NodeKind::Array(items) => items.iter().all(literal),
+        _ => false,
+    }
+}
@@ -0,0 +1,323 @@
+use crate::ast::{AstNode, BinaryOp, NodeKind, ProcedureKind};
+use crate::lexer::Span;
+use std::collections::HashMap;
+use std::ops::Range;
+
+// A program as one flat list of nodes instead of a tree of boxes, for passes
+// that look at every node and gain from having them close together in memory:
+//
+//     let flat = FlatAst::new(&program);
+//     let calls = flat.ids().filter(|&id| matches!(flat.kind(id), FlatKind::Call(_))).count();
+//
+// Nodes are stored parents first, in the order `AstNode::walk` visits them,
+// so a node's descendants follow it in one run and the whole program is a
+// plain loop over `ids`. Each node's children are listed together in a
+// second array, as `u32` indices. Names and strings are stored once each and
+// referred to by `Symbol`, and spans are kept apart from the nodes, so a pass
+// that does not need them does not load them.
+//
+// Lists of statements become `Block` nodes and procedure parameters `Param`
+// nodes, so every part of the tree has a node of its own. Doc comments are
+// left out. The interpreter and the other tools still work on the tree; see
+// `benches/ast.rs` for how the two compare.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// A name or string, looked up with `FlatAst::text`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// The kind of a node and what it holds besides its children, which are given
+// in order after each variant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlatKind {
+    // Statements.
+    Program,
+    // Statements of a branch, loop or procedure body.
+    Block,
+    // Value.
+    Assignment(Symbol),
+    // Value; false for `output ... inline`.
+    Output(bool),
+    // Prompt, if there is one.
+    Input(Symbol),
+    // Condition, `Block`, `Block`.
+    If,
+    // Condition, `Block`.
+    Loop,
+    // Value, `Branch` for each labelled branch, then a `Block` for `default`
+    // if there is one.
+    Case,
+    // Labels, `Block`.
+    Branch,
+    // Left, right.
+    BinOp(BinaryOp),
+    // Condition, then, otherwise.
+    Conditional,
+    Number(i64),
+    Float(f64),
+    String(Symbol),
+    Identifier(Symbol),
+    Null,
+    // Arguments.
+    Call(Symbol),
+    // Items.
+    Array,
+    // Sizes.
+    NewArray,
+    // Array, index.
+    Index,
+    // Receiver, arguments.
+    MethodCall(Symbol),
+    // Indices, value.
+    SetElement(Symbol),
+    // `Param` for each parameter, `Block`.
+    Procedure(Symbol, ProcedureKind),
+    // Default, if there is one; true for `ref`.
+    Param(Symbol, bool),
+    // Value, if there is one.
+    Return,
+    // `Identifier` for each name.
+    Global,
+    // Message.
+    Raise,
+    // `Block`, `Block`; the variable that receives the error, if any.
+    Try(Option<Symbol>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FlatNode {
+    kind: FlatKind,
+    // Where the node's children are in `links`.
+    children: (u32, u32),
+    // One past the node's last descendant.
+    end: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FlatAst {
+    nodes: Vec<FlatNode>,
+    spans: Vec<Span>,
+    links: Vec<NodeId>,
+    symbols: Vec<String>,
+}
+
+impl FlatAst {
+    pub fn new(program: &AstNode) -> Self {
+        let mut builder = Builder::default();
+        builder.node(program);
+        builder.flat
+    }
+
+    // The node the tree was built from, the first in the list.
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn kind(&self, id: NodeId) -> FlatKind {
+        self.nodes[id.index()].kind
+    }
+
+    pub fn span(&self, id: NodeId) -> Span {
+        self.spans[id.index()]
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        let (start, count) = self.nodes[id.index()].children;
+        &self.links[start as usize..(start + count) as usize]
+    }
+
+    pub fn text(&self, symbol: Symbol) -> &str {
+        &self.symbols[symbol.0 as usize]
+    }
+
+    // Every node, parents first.
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> {
+        (0..self.nodes.len() as u32).map(NodeId)
+    }
+
+    // The node and everything inside it, parents first.
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> {
+        self.range(id).map(|index| NodeId(index as u32))
+    }
+
+    // Where the node and its descendants are in the list.
+    pub fn range(&self, id: NodeId) -> Range<usize> {
+        id.index()..self.nodes[id.index()].end as usize
+    }
+}
+
+impl From<&AstNode> for FlatAst {
+    fn from(program: &AstNode) -> Self {
+        FlatAst::new(program)
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    flat: FlatAst,
+    interned: HashMap<String, Symbol>,
+}
+
+impl Builder {
+    fn symbol(&mut self, text: &str) -> Symbol {
+        if let Some(&symbol) = self.interned.get(text) {
+            return symbol;
+        }
+        let symbol = Symbol(self.flat.symbols.len() as u32);
+        self.flat.symbols.push(text.to_string());
+        self.interned.insert(text.to_string(), symbol);
+        symbol
+    }
+
+    // Adds a node, then its children after it by calling `children`, which
+    // returns their ids.
+    fn add(&mut self, kind: FlatKind, span: Span, children: impl FnOnce(&mut Self) -> Vec<NodeId>) -> NodeId {
+        let id = NodeId(self.flat.nodes.len() as u32);
+        self.flat.nodes.push(FlatNode { kind, children: (0, 0), end: 0 });
+        self.flat.spans.push(span);
+        let children = children(self);
+        let end = self.flat.nodes.len() as u32;
+        let node = &mut self.flat.nodes[id.index()];
+        node.children = (self.flat.links.len() as u32, children.len() as u32);
+        node.end = end;
+        self.flat.links.extend(children);
+        id
+    }
+
+    fn nodes(&mut self, nodes: &[AstNode]) -> Vec<NodeId> {
+        nodes.iter().map(|node| self.node(node)).collect()
+    }
+
+    // Statements get a `Block` spanning the statement they belong to.
+    fn block(&mut self, statements: &[AstNode], span: Span) -> NodeId {
+        self.add(FlatKind::Block, span, |this| this.nodes(statements))
+    }
+
+    fn node(&mut self, node: &AstNode) -> NodeId {
+        let span = node.span;
+        match &node.kind {
+            NodeKind::Program(statements) => self.add(FlatKind::Program, span, |this| this.nodes(statements)),
+            NodeKind::Assignment(name, value) => {
+                let name = self.symbol(name);
+                self.add(FlatKind::Assignment(name), span, |this| vec![this.node(value)])
+            }
+            NodeKind::Output(value, newline) => {
+                self.add(FlatKind::Output(*newline), span, |this| vec![this.node(value)])
+            }
+            NodeKind::Input(name, prompt) => {
+                let name = self.symbol(name);
+                self.add(FlatKind::Input(name), span, |this| prompt.iter().map(|prompt| this.node(prompt)).collect())
+            }
+            NodeKind::If(condition, then, otherwise) => self.add(FlatKind::If, span, |this| {
+                vec![this.node(condition), this.block(then, span), this.block(otherwise, span)]
+            }),
+            NodeKind::Loop(condition, body) => {
+                self.add(FlatKind::Loop, span, |this| vec![this.node(condition), this.block(body, span)])
+            }
+            NodeKind::Case(value, branches, default) => self.add(FlatKind::Case, span, |this| {
+                let mut children = vec![this.node(value)];
+                for branch in branches {
+                    children.push(this.add(FlatKind::Branch, span, |this| {
+                        let mut children = this.nodes(&branch.labels);
+                        children.push(this.block(&branch.body, span));
+                        children
+                    }));
+                }
+                children.extend(default.as_ref().map(|default| this.block(default, span)));
+                children
+            }),
+            NodeKind::BinOp(left, op, right) => {
+                self.add(FlatKind::BinOp(*op), span, |this| vec![this.node(left), this.node(right)])
+            }
+            NodeKind::Conditional(condition, then, otherwise) => self.add(FlatKind::Conditional, span, |this| {
+                vec![this.node(condition), this.node(then), this.node(otherwise)]
+            }),
+            NodeKind::Number(value) => self.add(FlatKind::Number(*value), span, |_| vec![]),
+            NodeKind::Float(value) => self.add(FlatKind::Float(*value), span, |_| vec![]),
+            NodeKind::String(text) => {
+                let text = self.symbol(text);
+                self.add(FlatKind::String(text), span, |_| vec![])
+            }
+            NodeKind::Identifier(name) => {
+                let name = self.symbol(name);
+                self.add(FlatKind::Identifier(name), span, |_| vec![])
+            }
+            NodeKind::Null => self.add(FlatKind::Null, span, |_| vec![]),
+            NodeKind::Call(name, args) => {
+                let name = self.symbol(name);
+                self.add(FlatKind::Call(name), span, |this| this.nodes(args))
+            }
+            NodeKind::Array(items) => self.add(FlatKind::Array, span, |this| this.nodes(items)),
+            NodeKind::NewArray(sizes) => self.add(FlatKind::NewArray, span, |this| this.nodes(sizes)),
+            NodeKind::Index(array, index) => {
+                self.add(FlatKind::Index, span, |this| vec![this.node(array), this.node(index)])
+            }
+            NodeKind::MethodCall(receiver, name, args) => {
+                let name = self.symbol(name);
+                self.add(FlatKind::MethodCall(name), span, |this| {
+                    let mut children = vec![this.node(receiver)];
+                    children.extend(this.nodes(args));
+                    children
+                })
+            }
+            NodeKind::SetElement(name, indices, value) => {
+                let name = self.symbol(name);
+                self.add(FlatKind::SetElement(name), span, |this| {
+                    let mut children = this.nodes(indices);
+                    children.push(this.node(value));
+                    children
+                })
+            }
+            NodeKind::Procedure(procedure) => {
+                let name = self.symbol(&procedure.name);
+                self.add(FlatKind::Procedure(name, procedure.kind), span, |this| {
+                    let mut children: Vec<NodeId> = procedure
+                        .params
+                        .iter()
+                        .map(|param| {
+                            let name = this.symbol(&param.name);
+                            this.add(FlatKind::Param(name, param.by_ref), span, |this| {
+                                param.default.iter().map(|default| this.node(default)).collect()
+                            })
+                        })
+                        .collect();
+                    children.push(this.block(&procedure.body, span));
+                    children
+                })
+            }
+            NodeKind::Return(value) => {
+                self.add(FlatKind::Return, span, |this| value.iter().map(|value| this.node(value)).collect())
+            }
+            NodeKind::Global(names) => self.add(FlatKind::Global, span, |this| {
+                names
+                    .iter()
+                    .map(|name| {
+                        let name = this.symbol(name);
+                        this.add(FlatKind::Identifier(name), span, |_| vec![])
+                    })
+                    .collect()
+            }),
+            NodeKind::Raise(message) => self.add(FlatKind::Raise, span, |this| vec![this.node(message)]),
+            NodeKind::Try(body, name, handler) => {
+                let name = name.as_deref().map(|name| self.symbol(name));
+                self.add(FlatKind::Try(name), span, |this| vec![this.block(body, span), this.block(handler, span)])
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use crate::ast::{AstNode, BinaryOp, NodeKind};
+use crate::formatter;
+use crate::keywords::Keywords;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlowchartFormat {
+    Mermaid,
+    Dot,
+}
+
+impl FlowchartFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mermaid" => Some(FlowchartFormat::Mermaid),
+            "dot" => Some(FlowchartFormat::Dot),
+            _ => None,
+        }
+    }
+}
+
+// The standard flowchart symbols.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    Terminal,
+    Process,
+    InputOutput,
+    Decision,
+    // A step that calls one of the program's procedures.
+    Subroutine,
+}
+
+struct Node {
+    shape: Shape,
+    label: String,
+    // The procedure the node belongs to, or None for the main program.
+    group: Option<usize>,
+}
+
+struct Edge {
+    from: usize,
+    to: usize,
+    label: Option<&'static str>,
+    // For the jump from a `try` to its `catch` block when an error occurs.
+    dashed: bool,
+}
+
+// An edge waiting for the node that comes next.
+type Exit = (usize, Option<&'static str>);
+
+// Draws the control flow of a program as a flowchart: the main program from
+// start to end, then each procedure in its own box from its name to its
+// return. Statements are labelled with their source, as `fmt` prints it with
+// `keywords`.
+pub fn flowchart(program: &AstNode, keywords: &Keywords, format: FlowchartFormat) -> String {
+    let mut chart = Flowchart {
+        keywords,
+        procedures: HashSet::new(),
+        groups: vec![],
+        nodes: vec![],
+        edges: vec![],
+        group: None,
+        end: 0,
+    };
+    let statements = match &program.kind {
+        NodeKind::Program(statements) => statements.as_slice(),
+        _ => std::slice::from_ref(program),
+    };
+    for statement in statements {
+        if let NodeKind::Procedure(procedure) = &statement.kind {
+            chart.procedures.insert(procedure.name.clone());
+        }
+    }
+
+    let start = chart.node(Shape::Terminal, "start".to_string());
+    chart.end = chart.node(Shape::Terminal, "end".to_string());
+    let main: Vec<&AstNode> =
+        statements.iter().filter(|statement| !matches!(statement.kind, NodeKind::Procedure(_))).collect();
+    let exits = chart.block(main, vec![(start, None)]);
+    chart.connect(exits, chart.end);
+
+    for statement in statements {
+        if let NodeKind::Procedure(procedure) = &statement.kind {
+            chart.group = Some(chart.groups.len());
+            chart.groups.push(procedure.name.clone());
+            let header = chart.label(statement).lines().next().unwrap_or_default().to_string();
+            let start = chart.node(Shape::Terminal, header);
+            chart.end = chart.node(Shape::Terminal, chart.keywords.spelling("return").to_string());
+            let exits = chart.block(procedure.body.iter().collect(), vec![(start, None)]);
+            chart.connect(exits, chart.end);
+        }
+    }
+
+    match format {
+        FlowchartFormat::Mermaid => chart.mermaid(),
+        FlowchartFormat::Dot => chart.dot(),
+    }
+}
+
+struct Flowchart<'a> {
+    keywords: &'a Keywords,
+    procedures: HashSet<String>,
+    // The names of the procedures drawn so far, indexed by `Node::group`.
+    groups: Vec<String>,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    group: Option<usize>,
+    // Where `return` leads in the part being drawn.
+    end: usize,
+}
+
+impl Flowchart<'_> {
+    fn node(&mut self, shape: Shape, label: String) -> usize {
+        self.nodes.push(Node { shape, label, group: self.group });
+        self.nodes.len() - 1
+    }
+
+    fn connect(&mut self, exits: Vec<Exit>, to: usize) {
+        for (from, label) in exits {
+            self.edges.push(Edge { from, to, label, dashed: false });
+        }
+    }
+
+    // Adds the statements one after another, starting from `exits`, and
+    // returns the exits of the last one.
+    fn block(&mut self, statements: Vec<&AstNode>, mut exits: Vec<Exit>) -> Vec<Exit> {
+        for statement in statements {
+            exits = self.statement(statement, exits);
+        }
+        exits
+    }
+
+    fn statement(&mut self, node: &AstNode, exits: Vec<Exit>) -> Vec<Exit> {
+        match &node.kind {
+            NodeKind::If(condition, true_branch, false_branch) => {
+                let decision = self.decision(condition, exits);
+                let mut exits = self.block(true_branch.iter().collect(), vec![(decision, Some("yes"))]);
+                exits.extend(self.block(false_branch.iter().collect(), vec![(decision, Some("no"))]));
+                exits
+            }
+            NodeKind::Loop(condition, body) => {
+                let decision = self.decision(condition, exits);
+                let body_exits = self.block(body.iter().collect(), vec![(decision, Some("yes"))]);
+                self.connect(body_exits, decision);
+                vec![(decision, Some("no"))]
+            }
+            // One decision per branch, each falling through to the next.
+            NodeKind::Case(value, branches, default) => {
+                let mut exits = exits;
+                let mut done = vec![];
+                for branch in branches {
+                    let tests: Vec<AstNode> = branch
+                        .labels
+                        .iter()
+                        .map(|label| compare(value, label))
+                        .collect();
+                    let test = tests
+                        .into_iter()
+                        .reduce(|left, right| binary(left, BinaryOp::Or, right))
+                        .expect("case branches have labels");
+                    let decision = self.decision(&test, exits);
+                    done.extend(self.block(branch.body.iter().collect(), vec![(decision, Some("yes"))]));
+                    exits = vec![(decision, Some("no"))];
+                }
+                done.extend(self.block(default.iter().flatten().collect(), exits));
+                done
+            }
+            NodeKind::Try(body, _, handler) => {
+                let label = self.keywords.spelling("try").to_string();
+                let start = self.step(Shape::Process, label, exits);
+                let mut exits = self.block(body.iter().collect(), vec![(start, None)]);
+                let text = self.label(node);
+                let catch = text.lines().find(|line| line.starts_with(self.keywords.spelling("catch")));
+                let catch = self.node(Shape::Process, catch.unwrap_or_default().to_string());
+                self.edges.push(Edge { from: start, to: catch, label: Some("error"), dashed: true });
+                exits.extend(self.block(handler.iter().collect(), vec![(catch, None)]));
+                exits
+            }
+            NodeKind::Return(_) => {
+                let label = self.label(node);
+                let step = self.step(Shape::Process, label, exits);
+                self.connect(vec![(step, None)], self.end);
+                vec![]
+            }
+            // The program stops here.
+            NodeKind::Raise(_) => {
+                let label = self.label(node);
+                self.step(Shape::Terminal, label, exits);
+                vec![]
+            }
+            // A declaration, not a step.
+            NodeKind::Global(_) => exits,
+            _ => {
+                let shape = if self.calls_procedure(node) {
+                    Shape::Subroutine
+                } else if matches!(node.kind, NodeKind::Input(..) | NodeKind::Output(..)) {
+                    Shape::InputOutput
+                } else {
+                    Shape::Process
+                };
+                let label = self.label(node);
+                vec![(self.step(shape, label, exits), None)]
+            }
+        }
+    }
+
+    fn step(&mut self, shape: Shape, label: String, exits: Vec<Exit>) -> usize {
+        let node = self.node(shape, label);
+        self.connect(exits, node);
+        node
+    }
+
+    fn decision(&mut self, condition: &AstNode, exits: Vec<Exit>) -> usize {
+        let label = format!("{}?", self.label(condition));
+        self.step(Shape::Decision, label, exits)
+    }
+
+    // The source of a node, on one line unless it is a procedure or `try`,
+    // whose lines are picked out by the caller.
+    fn label(&self, node: &AstNode) -> String {
+        let text = formatter::format(node, self.keywords, false);
+        if matches!(node.kind, NodeKind::Procedure(_) | NodeKind::Try(..)) {
+            return text;
+        }
+        text.lines().map(str::trim).collect::<Vec<_>>().join(" ")
+    }
+
+    fn calls_procedure(&self, node: &AstNode) -> bool {
+        let mut found = false;
+        node.walk(&mut |node| {
+            if let NodeKind::Call(name, _) = &node.kind {
+                found |= self.procedures.contains(name);
+            }
+        });
+        found
+    }
+
+    fn mermaid(&self) -> String {
+        let mut out = String::from("flowchart TD\n");
+        for group in std::iter::once(None).chain((0..self.groups.len()).map(Some)) {
+            let indent = match group {
+                Some(index) => {
+                    out.push_str(&format!("    subgraph p{} [\"{}\"]\n", index, mermaid_text(&self.groups[index])));
+                    "        "
+                }
+                None => "    ",
+            };
+            for (id, node) in self.nodes.iter().enumerate().filter(|(_, node)| node.group == group) {
+                let (open, close) = match node.shape {
+                    Shape::Terminal => ("([", "])"),
+                    Shape::Process => ("[", "]"),
+                    Shape::InputOutput => ("[/", "/]"),
+                    Shape::Decision => ("{", "}"),
+                    Shape::Subroutine => ("[[", "]]"),
+                };
+                out.push_str(&format!("{}n{}{}\"{}\"{}\n", indent, id, open, mermaid_text(&node.label), close));
+            }
+            if group.is_some() {
+                out.push_str("    end\n");
+            }
+        }
+        for edge in &self.edges {
+            let arrow = if edge.dashed { "-.->" } else { "-->" };
+            match edge.label {
+                Some(label) => out.push_str(&format!("    n{} {}|{}| n{}\n", edge.from, arrow, label, edge.to)),
+                None => out.push_str(&format!("    n{} {} n{}\n", edge.from, arrow, edge.to)),
+            }
+        }
+        out
+    }
+
+    fn dot(&self) -> String {
+        let mut out = String::from("digraph flowchart {\n    node [fontname=\"monospace\"];\n");
+        for group in std::iter::once(None).chain((0..self.groups.len()).map(Some)) {
+            let indent = match group {
+                Some(index) => {
+                    out.push_str(&format!("    subgraph cluster_{} {{\n", index));
+                    out.push_str(&format!("        label=\"{}\";\n", dot_text(&self.groups[index])));
+                    "        "
+                }
+                None => "    ",
+            };
+            for (id, node) in self.nodes.iter().enumerate().filter(|(_, node)| node.group == group) {
+                let shape = match node.shape {
+                    Shape::Terminal => "shape=oval",
+                    Shape::Process => "shape=box",
+                    Shape::InputOutput => "shape=parallelogram",
+                    Shape::Decision => "shape=diamond",
+                    Shape::Subroutine => "shape=box, peripheries=2",
+                };
+                out.push_str(&format!("{}n{} [{}, label=\"{}\"];\n", indent, id, shape, dot_text(&node.label)));
+            }
+            if group.is_some() {
+                out.push_str("    }\n");
+            }
+        }
+        for edge in &self.edges {
+            let mut attributes = vec![];
+            if let Some(label) = edge.label {
+                attributes.push(format!("label=\"{}\"", label));
+            }
+            if edge.dashed {
+                attributes.push("style=dashed".to_string());
+            }
+            let attributes =
+                if attributes.is_empty() { String::new() } else { format!(" [{}]", attributes.join(", ")) };
+            out.push_str(&format!("    n{} -> n{}{};\n", edge.from, edge.to, attributes));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn binary(left: AstNode, op: BinaryOp, right: AstNode) -> AstNode {
+    let span = left.span;
+    AstNode::new(NodeKind::BinOp(Box::new(left), op, Box::new(right)), span)
+}
+
+fn compare(value: &AstNode, label: &AstNode) -> AstNode {
+    binary(value.clone(), BinaryOp::Eq, label.clone())
+}
+
+// Mermaid labels are quoted; quotes and angle brackets inside them are written
+// as entity codes, and line breaks from long strings as `<br>`.
+fn mermaid_text(text: &str) -> String {
+    text.replace('"', "#quot;").replace('<', "#lt;").replace('>', "#gt;").replace('\n', "<br>")
+}
+
+fn dot_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
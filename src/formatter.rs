@@ -0,0 +1,401 @@
+use crate::ast::{AstNode, BinaryOp, NodeKind, Procedure, ProcedureKind};
+use crate::keywords::Keywords;
+use std::collections::BTreeSet;
+
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 80;
+
+// Prints a program back as source in a canonical layout, spelling keywords in
+// the language given by `keywords`. Formatting a program parsed with one set of
+// keywords and printing it with another translates it between languages.
+// With `strict`, conditional expressions that make up a whole assignment,
+// output or return are written out as `if` statements, as IB pseudocode has
+// no conditional expressions.
+pub fn format(program: &AstNode, keywords: &Keywords, strict: bool) -> String {
+    let mut formatter = Formatter { keywords, strict, out: String::new() };
+    formatter.statement(program, 0);
+    formatter.out
+}
+
+// A procedure's name and parameters as its definition spells them, such as
+// `total(ref LIST, START = 0)`.
+pub fn signature(procedure: &Procedure, keywords: &Keywords) -> String {
+    Formatter { keywords, strict: false, out: String::new() }.parameters(procedure)
+}
+
+// The variables and procedures in `program` named with a word that `keywords`
+// reads as a keyword, such as a variable `LOOP` in IB notation. Printed with
+// those keywords, the program would not parse back.
+pub fn keyword_clashes(program: &AstNode, keywords: &Keywords) -> BTreeSet<String> {
+    let is_keyword = |name: &String| {
+        keywords.canonical(name).is_some() || keywords.aliases().iter().any(|alias| alias.words == [name.as_str()])
+    };
+    let mut clashes = BTreeSet::new();
+    program.walk(&mut |node| {
+        let names: Vec<&String> = match &node.kind {
+            NodeKind::Procedure(procedure) => {
+                std::iter::once(&procedure.name).chain(procedure.params.iter().map(|param| &param.name)).collect()
+            }
+            NodeKind::Identifier(name)
+            | NodeKind::Assignment(name, _)
+            | NodeKind::Input(name, _)
+            | NodeKind::SetElement(name, ..)
+            | NodeKind::Call(name, _)
+            | NodeKind::Try(_, Some(name), _) => vec![name],
+            NodeKind::Global(names) => names.iter().collect(),
+            _ => vec![],
+        };
+        clashes.extend(names.into_iter().filter(|name| is_keyword(name)).cloned());
+    });
+    clashes
+}
+
+struct Formatter<'a> {
+    keywords: &'a Keywords,
+    strict: bool,
+    out: String,
+}
+
+impl Formatter<'_> {
+    fn line(&mut self, depth: usize, text: &str) {
+        self.out.push_str(&INDENT.repeat(depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn keyword(&self, keyword: &str) -> &str {
+        self.keywords.spelling(keyword)
+    }
+
+    fn parameters(&self, procedure: &Procedure) -> String {
+        let params: Vec<String> = procedure
+            .params
+            .iter()
+            .map(|param| {
+                if param.by_ref {
+                    format!("{} {}", self.keyword("ref"), param.name)
+                } else if let Some(default) = &param.default {
+                    format!("{} = {}", param.name, self.expr(default))
+                } else {
+                    param.name.clone()
+                }
+            })
+            .collect();
+        format!("{}({})", procedure.name, params.join(", "))
+    }
+
+    fn block(&mut self, statements: &[AstNode], depth: usize) {
+        for statement in statements {
+            self.statement(statement, depth);
+        }
+    }
+
+    fn statement(&mut self, node: &AstNode, depth: usize) {
+        match &node.kind {
+            NodeKind::Program(statements) => self.block(statements, depth),
+            NodeKind::Assignment(name, expr) => {
+                self.value_statement(depth, &format!("{} = ", name), expr, "");
+            }
+            NodeKind::SetElement(name, indices, expr) => {
+                let indices: String = indices.iter().map(|index| format!("[{}]", self.expr(index))).collect();
+                self.value_statement(depth, &format!("{}{} = ", name, indices), expr, "");
+            }
+            NodeKind::Output(expr, newline) => {
+                let prefix = format!("{} ", self.keyword("output"));
+                let suffix = if *newline { String::new() } else { format!(" {}", self.keyword("inline")) };
+                self.value_statement(depth, &prefix, expr, &suffix);
+            }
+            NodeKind::Input(name, prompt) => {
+                let mut text = format!("{} {}", self.keyword("input"), name);
+                if let Some(prompt) = prompt {
+                    text = format!("{} {} {}", text, self.keyword("prompt"), self.expr(prompt));
+                }
+                self.line(depth, &text);
+            }
+            NodeKind::If(..) => {
+                self.if_clause(node, depth, "");
+                let text = self.keyword("endif").to_string();
+                self.line(depth, &text);
+            }
+            NodeKind::Loop(condition, body) => {
+                let header = format!("{} {}", self.keyword("loop"), self.keyword("while"));
+                self.header(depth, &header, condition, "");
+                self.block(body, depth + 1);
+                let text = self.keyword("endloop").to_string();
+                self.line(depth, &text);
+            }
+            NodeKind::Case(value, branches, default) => {
+                let text = format!("{} {} {}", self.keyword("case"), self.expr(value), self.keyword("of"));
+                self.line(depth, &text);
+                for branch in branches {
+                    let labels: Vec<String> = branch.labels.iter().map(|label| self.expr(label)).collect();
+                    self.line(depth + 1, &format!("{}:", labels.join(", ")));
+                    self.block(&branch.body, depth + 2);
+                }
+                if let Some(default) = default {
+                    let text = format!("{}:", self.keyword("default"));
+                    self.line(depth + 1, &text);
+                    self.block(default, depth + 2);
+                }
+                let text = self.keyword("endcase").to_string();
+                self.line(depth, &text);
+            }
+            NodeKind::Procedure(procedure) => {
+                let (keyword, end) = match procedure.kind {
+                    ProcedureKind::Sub => ("sub", "endsub"),
+                    ProcedureKind::Function => ("function", "endfunction"),
+                };
+                for line in &procedure.doc {
+                    let text = if line.is_empty() { "///".to_string() } else { format!("/// {}", line) };
+                    self.line(depth, &text);
+                }
+                let text = format!("{} {}", self.keyword(keyword), self.parameters(procedure));
+                self.line(depth, &text);
+                self.block(&procedure.body, depth + 1);
+                let text = self.keyword(end).to_string();
+                self.line(depth, &text);
+            }
+            NodeKind::Raise(message) => {
+                let prefix = format!("{} ", self.keyword("error"));
+                self.value_statement(depth, &prefix, message, "");
+            }
+            NodeKind::Try(body, name, handler) => {
+                let text = self.keyword("try").to_string();
+                self.line(depth, &text);
+                self.block(body, depth + 1);
+                let mut text = self.keyword("catch").to_string();
+                if let Some(name) = name {
+                    text = format!("{} {}", text, name);
+                }
+                self.line(depth, &text);
+                self.block(handler, depth + 1);
+                let text = self.keyword("endtry").to_string();
+                self.line(depth, &text);
+            }
+            NodeKind::Global(names) => {
+                let text = format!("{} {}", self.keyword("global"), names.join(", "));
+                self.line(depth, &text);
+            }
+            NodeKind::Return(Some(value)) => {
+                let prefix = format!("{} ", self.keyword("return"));
+                self.value_statement(depth, &prefix, value, "");
+            }
+            NodeKind::Return(None) => {
+                let text = self.keyword("return").to_string();
+                self.line(depth, &text);
+            }
+            _ => {
+                let text = self.expr(node);
+                self.line(depth, &text);
+            }
+        }
+    }
+
+    // Prints a statement made of `prefix`, a value and `suffix`, such as an
+    // assignment. In strict mode a conditional value becomes an `if` statement
+    // with the rest of the statement repeated in each branch.
+    fn value_statement(&mut self, depth: usize, prefix: &str, value: &AstNode, suffix: &str) {
+        if self.strict && matches!(value.kind, NodeKind::Conditional(..)) {
+            let keyword = self.keyword("if").to_string();
+            self.conditional_clause(depth, prefix, value, suffix, &keyword);
+            let text = self.keyword("endif").to_string();
+            self.line(depth, &text);
+        } else {
+            let text = format!("{}{}{}", prefix, self.expr(value), suffix);
+            self.line(depth, &text);
+        }
+    }
+
+    // Prints a conditional value as `if` up to its `endif`, turning a
+    // conditional in the else branch into an `else if`.
+    fn conditional_clause(&mut self, depth: usize, prefix: &str, value: &AstNode, suffix: &str, keyword: &str) {
+        let NodeKind::Conditional(condition, then, otherwise) = &value.kind else {
+            return;
+        };
+        let then_keyword = format!(" {}", self.keyword("then"));
+        self.header(depth, keyword, condition, &then_keyword);
+        self.value_statement(depth + 1, prefix, then, suffix);
+        if matches!(otherwise.kind, NodeKind::Conditional(..)) {
+            let keyword = format!("{} {}", self.keyword("else"), self.keyword("if"));
+            self.conditional_clause(depth, prefix, otherwise, suffix, &keyword);
+        } else {
+            let text = self.keyword("else").to_string();
+            self.line(depth, &text);
+            self.value_statement(depth + 1, prefix, otherwise, suffix);
+        }
+    }
+
+    // Prints an `if` without its `endif`. An else branch holding only another
+    // `if` is printed as an `else if` chain.
+    fn if_clause(&mut self, node: &AstNode, depth: usize, prefix: &str) {
+        let NodeKind::If(condition, true_branch, false_branch) = &node.kind else {
+            return;
+        };
+        let header = format!("{}{}", prefix, self.keyword("if"));
+        let then = format!(" {}", self.keyword("then"));
+        self.header(depth, &header, condition, &then);
+        self.block(true_branch, depth + 1);
+        match false_branch.as_slice() {
+            [] => {}
+            [else_if @ AstNode { kind: NodeKind::If(..), .. }] => {
+                let prefix = format!("{} ", self.keyword("else"));
+                self.if_clause(else_if, depth, &prefix);
+            }
+            _ => {
+                let text = self.keyword("else").to_string();
+                self.line(depth, &text);
+                self.block(false_branch, depth + 1);
+            }
+        }
+    }
+
+    // Prints the first line of an `if` or `loop`. A condition too long for one
+    // line is broken before each `and`/`or`, with the rest indented one level.
+    fn header(&mut self, depth: usize, keyword: &str, condition: &AstNode, suffix: &str) {
+        let text = format!("{} {}{}", keyword, self.expr(condition), suffix);
+        if INDENT.len() * depth + text.chars().count() <= MAX_WIDTH {
+            self.line(depth, &text);
+            return;
+        }
+
+        let mut parts = vec![];
+        let mut first = condition;
+        while let NodeKind::BinOp(left, op @ (BinaryOp::And | BinaryOp::Or), right) = &first.kind {
+            parts.push(format!("{} {}", self.operator(op), self.operand(right, precedence(op))));
+            first = left;
+        }
+        let text = format!("{} {}", keyword, self.operand(first, 0));
+        self.line(depth, &text);
+        let last = parts.len().saturating_sub(1);
+        for (index, part) in parts.iter().rev().enumerate() {
+            let text = if index == last { format!("{}{}", part, suffix) } else { part.clone() };
+            self.line(depth + 1, &text);
+        }
+    }
+
+    fn expr(&self, node: &AstNode) -> String {
+        match &node.kind {
+            NodeKind::Number(value) => value.to_string(),
+            // Printed in full so the literal reads back as the same float.
+            NodeKind::Float(value) if value.fract() == 0.0 => format!("{:.1}", value),
+            NodeKind::Float(value) => value.to_string(),
+            NodeKind::String(value) => quote(&escape_braces(value)),
+            NodeKind::Identifier(name) => name.clone(),
+            NodeKind::Null => self.keyword("null").to_string(),
+            NodeKind::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| self.expr(item)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            NodeKind::NewArray(sizes) => {
+                let sizes: Vec<String> = sizes.iter().map(|size| self.expr(size)).collect();
+                format!("{} Array({})", self.keyword("new"), sizes.join(", "))
+            }
+            NodeKind::Index(target, index) => format!("{}[{}]", self.operand(target, u8::MAX), self.expr(index)),
+            NodeKind::MethodCall(receiver, method, args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.expr(arg)).collect();
+                format!("{}.{}({})", self.operand(receiver, u8::MAX), method, args.join(", "))
+            }
+            NodeKind::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.expr(arg)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            NodeKind::Conditional(condition, then, otherwise) => format!(
+                "{} {} {} {} {} {}",
+                self.keyword("if"),
+                self.expr(condition),
+                self.keyword("then"),
+                self.expr(then),
+                self.keyword("else"),
+                self.expr(otherwise)
+            ),
+            NodeKind::BinOp(left, op, right) => {
+                if let Some(text) = self.interpolated_string(node) {
+                    return text;
+                }
+                let precedence = precedence(op);
+                // Operators are left-associative, so a right operand of equal
+                // precedence needs parentheses to keep its grouping. `^` is
+                // the other way round.
+                let (left, right) = if *op == BinaryOp::Caret {
+                    (self.operand(left, precedence), self.operand(right, precedence - 1))
+                } else {
+                    (self.operand(left, precedence - 1), self.operand(right, precedence))
+                };
+                format!("{} {} {}", left, self.operator(op), right)
+            }
+            _ => String::new(),
+        }
+    }
+
+    // The parser turns `"Total is {TOTAL}"` into a chain of `+` whose nodes all
+    // share the string's span, which no hand-written concatenation does. Such a
+    // chain is printed back as the original string.
+    fn interpolated_string(&self, node: &AstNode) -> Option<String> {
+        let mut parts = vec![];
+        let mut current = node;
+        while let NodeKind::BinOp(left, BinaryOp::Plus, right) = &current.kind {
+            if current.span != node.span {
+                return None;
+            }
+            parts.push(right.as_ref());
+            current = left;
+        }
+        match &current.kind {
+            NodeKind::String(_) if current.span == node.span => parts.push(current),
+            _ => return None,
+        }
+
+        let mut text = String::new();
+        for part in parts.into_iter().rev() {
+            match &part.kind {
+                NodeKind::String(value) if part.span == node.span => text.push_str(&escape_braces(value)),
+                _ => text.push_str(&format!("{{{}}}", self.expr(part))),
+            }
+        }
+        Some(quote(&text))
+    }
+
+    fn operand(&self, node: &AstNode, parent: u8) -> String {
+        match &node.kind {
+            NodeKind::BinOp(_, op, _) if precedence(op) <= parent => format!("({})", self.expr(node)),
+            // The else branch would take in whatever follows.
+            NodeKind::Conditional(..) if parent > 0 => format!("({})", self.expr(node)),
+            _ => self.expr(node),
+        }
+    }
+
+    fn operator(&self, op: &BinaryOp) -> &str {
+        op.spelling(self.keywords)
+    }
+}
+
+// Binding strength of each binary operator, mirroring the parser's levels.
+fn precedence(op: &BinaryOp) -> u8 {
+    match op {
+        BinaryOp::And | BinaryOp::Or => 1,
+        BinaryOp::BitOr => 3,
+        BinaryOp::BitXor => 4,
+        BinaryOp::BitAnd => 5,
+        BinaryOp::ShiftLeft | BinaryOp::ShiftRight => 6,
+        BinaryOp::Plus | BinaryOp::Minus => 7,
+        BinaryOp::Star | BinaryOp::Slash | BinaryOp::Mod | BinaryOp::Div => 8,
+        BinaryOp::Caret => 9,
+        _ => 2,
+    }
+}
+
+// Text over several lines or holding quotes is written as a long string,
+// starting on the line after its opening quotes.
+fn quote(text: &str) -> String {
+    if text.contains('\n') {
+        format!("\"\"\"\n{}\"\"\"", text)
+    } else if text.contains('"') {
+        format!("\"\"\"{}\"\"\"", text)
+    } else {
+        format!("\"{}\"", text)
+    }
+}
+
+fn escape_braces(text: &str) -> String {
+    text.replace('{', "{{").replace('}', "}}")
+}
@@ -0,0 +1,73 @@
+use crate::ast::AstNode;
+use crate::formatter;
+use crate::interpreter::{Interpreter, Limits, STACK_SIZE};
+use crate::keywords::Keywords;
+use crate::lexer::Lexer;
+use crate::parser::{Dialect, Parser};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Entry points for the targets in fuzz/. Each takes whatever the fuzzer made
+// up and must return without panicking, however malformed it is; a panic or
+// a failed assertion is a bug. Errors are the expected answer to bad input.
+
+// Keeps a program that never stops from stalling the fuzzer, or one that
+// keeps doubling a string from exhausting its memory.
+const LIMITS: Limits = Limits {
+    steps: Some(10_000),
+    output: Some(64 * 1024),
+    time: Some(Duration::from_secs(1)),
+    memory: Some(16 * 1024 * 1024),
+};
+
+pub fn lex(data: &[u8]) {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = Lexer::new(source).tokenize();
+    }
+}
+
+// The program in `data`, if it is UTF-8 that parses. The extended dialect
+// accepts everything the IB one does, so it is the one tried.
+pub fn parse(data: &[u8]) -> Option<AstNode> {
+    let source = std::str::from_utf8(data).ok()?;
+    Parser::new(Lexer::new(source)).ok()?.with_dialect(Dialect::Extended).parse().ok()
+}
+
+pub fn interpret(data: &[u8]) {
+    if let Some(program) = parse(data) {
+        run(program);
+    }
+}
+
+// Prints a made-up tree. When the text parses, printing what was parsed must
+// give the same text again, and the program is run.
+pub fn format(program: &AstNode) {
+    let keywords = Keywords::english();
+    let Some(parsed) = parse(formatter::format(program, &keywords, false).as_bytes()) else {
+        return;
+    };
+    let text = formatter::format(&parsed, &keywords, false);
+    let reparsed = parse(text.as_bytes()).expect("formatted program does not parse");
+    assert_eq!(text, formatter::format(&reparsed, &keywords, false), "formatting is not stable");
+    run(parsed);
+}
+
+// Runs `program` with no input on a thread with the stack the command line
+// gives it, passing on any panic.
+fn run(program: AstNode) {
+    let program = Arc::new(program);
+    let result = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+            interpreter.set_input(Box::new(io::empty()), false);
+            interpreter.set_limits(LIMITS);
+            let _ = interpreter.interpret(program);
+        })
+        .expect("failed to start the interpreter thread")
+        .join();
+    if let Err(panic) = result {
+        std::panic::resume_unwind(panic);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::ast::{AstNode, BinaryOp};
+use crate::builder::{self, expr, Block, Program};
+use crate::formatter;
+use crate::interpreter::{Interpreter, Limits, STACK_SIZE};
+use crate::keywords::Keywords;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+// The statements a generated program is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Construct {
+    Assign,
+    Output,
+    If,
+    Loop,
+    Case,
+    Array,
+}
+
+impl Construct {
+    pub const ALL: [Construct; 6] =
+        [Construct::Assign, Construct::Output, Construct::If, Construct::Loop, Construct::Case, Construct::Array];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Construct::Assign => "assign",
+            Construct::Output => "output",
+            Construct::If => "if",
+            Construct::Loop => "loop",
+            Construct::Case => "case",
+            Construct::Array => "array",
+        }
+    }
+}
+
+// How often each construct is picked, relative to the others.
+#[derive(Debug, Clone)]
+pub struct Mix {
+    pub weights: Vec<(Construct, u32)>,
+}
+
+impl Default for Mix {
+    fn default() -> Self {
+        Mix {
+            weights: vec![
+                (Construct::Assign, 4),
+                (Construct::Output, 2),
+                (Construct::If, 2),
+                (Construct::Loop, 1),
+                (Construct::Case, 1),
+                (Construct::Array, 1),
+            ],
+        }
+    }
+}
+
+impl Mix {
+    // Reads weights such as `if=3,loop=0`, starting from the defaults.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut mix = Mix::default();
+        for part in text.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (name, weight) = part.split_once('=').ok_or_else(|| format!("Expected NAME=WEIGHT, got {}", part))?;
+            let weight: u32 = weight.trim().parse().map_err(|_| format!("Invalid weight for {}: {}", name, weight))?;
+            let entry = mix.weights.iter_mut().find(|(construct, _)| construct.name() == name.trim()).ok_or_else(|| {
+                let names: Vec<&str> = Construct::ALL.iter().map(|construct| construct.name()).collect();
+                format!("Unknown construct {}; expected one of {}", name, names.join(", "))
+            })?;
+            entry.1 = weight;
+        }
+        if mix.weights.iter().all(|(_, weight)| *weight == 0) {
+            return Err("At least one construct needs a weight above 0".to_string());
+        }
+        Ok(mix)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneratorOptions {
+    pub seed: u64,
+    // Statements in the main program, not counting those inside others.
+    pub statements: usize,
+    // How deeply `if`, `loop` and `case` may nest.
+    pub depth: usize,
+    pub functions: usize,
+    pub mix: Mix,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        GeneratorOptions { seed: 0, statements: 8, depth: 2, functions: 1, mix: Mix::default() }
+    }
+}
+
+const VARIABLES: [&str; 5] = ["A", "B", "C", "D", "E"];
+const ARRAYS: [&str; 2] = ["P", "Q"];
+// One loop counter per level of nesting.
+const COUNTERS: [&str; 4] = ["I", "J", "K", "L"];
+const PARAMETERS: [&str; 2] = ["X", "Y"];
+
+// Generates a random program in the standard dialect, the same for the same
+// options. Generated programs always finish without errors: loops count up to
+// a small bound, values in loops are kept small with `mod`, arrays are only
+// indexed within their length and functions do not call each other.
+pub fn generate(options: &GeneratorOptions) -> AstNode {
+    let mut rng = Rng(options.seed);
+    let lengths = ARRAYS.iter().map(|_| 2 + rng.below(3)).collect();
+    let mut generator = Generator {
+        rng,
+        options,
+        variables: vec![],
+        arrays: vec![],
+        lengths,
+        counters: 0,
+        functions: vec![],
+    };
+    let mut program = Program::builder();
+    for index in 0..options.functions {
+        program = generator.function(program, format!("f{}", index + 1));
+    }
+    program = program.append(generator.block(options.statements, 0));
+    // End by showing every variable, so the program's effect can be checked
+    // against a trace table.
+    for name in generator.variables.clone() {
+        program = program.output(expr::add(expr::string(&format!("{} = ", name)), expr::var(&name)));
+    }
+    program.build()
+}
+
+// Generates the program for `seed`, then checks that it prints and parses back
+// to the same text and runs without an error or a crash. Returns what went
+// wrong, if anything.
+pub fn fuzz(options: &GeneratorOptions, keywords: &Keywords) -> Option<String> {
+    let text = formatter::format(&generate(options), keywords, false);
+    let program = match Parser::new(Lexer::new(&text).with_keywords(keywords)).and_then(|mut parser| parser.parse()) {
+        Ok(program) => program,
+        Err(e) => return Some(format!("the generated program does not parse: {}\n{}", e.message, text)),
+    };
+    let reformatted = formatter::format(&program, keywords, false);
+    if reformatted != text {
+        return Some(format!("formatting the program changes it:\n{}\n---\n{}", text, reformatted));
+    }
+    // `fmt --canonical ib` and back again gives the program as it was.
+    for spaced in [true, false] {
+        let ib = Keywords::ib(spaced);
+        let notation = formatter::format(&program, &ib, false);
+        let back = Parser::new(Lexer::new(&notation).with_keywords(&ib))
+            .and_then(|mut parser| parser.parse())
+            .map(|program| formatter::format(&program, keywords, false));
+        if !back.is_ok_and(|back| back == text) {
+            return Some(format!("writing the program in IB notation changes it:\n{}\n---\n{}", text, notation));
+        }
+    }
+
+    let program = Arc::new(program);
+    let result = std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || {
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+                    interpreter.set_input(Box::new(io::empty()), false);
+                    interpreter.set_limits(Limits {
+                        steps: Some(1_000_000),
+                        output: None,
+                        time: Some(Duration::from_secs(5)),
+                        memory: None,
+                    });
+                    interpreter.interpret(program.clone())
+                }))
+            })
+            .expect("failed to start the interpreter thread")
+            .join()
+            .unwrap_or_else(Err)
+    });
+    match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("the program stopped with {}: {}\n{}", e.code, e.message, text)),
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| panic.downcast_ref::<&str>().map(|message| message.to_string()))
+                .unwrap_or_default();
+            Some(format!("the interpreter panicked: {}\n{}", message, text))
+        }
+    }
+}
+
+struct Generator<'a> {
+    rng: Rng,
+    options: &'a GeneratorOptions,
+    // The variables and arrays that certainly have a value at this point,
+    // with the length of each array.
+    variables: Vec<String>,
+    arrays: Vec<(String, usize)>,
+    // The length of each of ARRAYS. An array keeps its length whenever it is
+    // assigned, so indexes stay valid whichever branches run.
+    lengths: Vec<usize>,
+    // How many loops the statement being generated is inside.
+    counters: usize,
+    // The functions defined so far, with how many parameters each takes.
+    functions: Vec<(String, usize)>,
+}
+
+impl Generator<'_> {
+    fn block(&mut self, count: usize, depth: usize) -> Block {
+        let mut block = Block::new();
+        for _ in 0..count {
+            block = self.statement(block, depth);
+        }
+        block
+    }
+
+    // A nested block, whose variables are forgotten after it as it may not
+    // run.
+    fn nested(&mut self, depth: usize) -> Block {
+        let (variables, arrays) = (self.variables.len(), self.arrays.len());
+        let count = 1 + self.rng.below(3);
+        let block = self.block(count, depth + 1);
+        self.variables.truncate(variables);
+        self.arrays.truncate(arrays);
+        block
+    }
+
+    // `block` with one more statement, or two for a loop and its counter.
+    fn statement(&mut self, block: Block, depth: usize) -> Block {
+        let nesting = depth < self.options.depth && self.counters < COUNTERS.len();
+        let choices: Vec<(Construct, u32)> = self
+            .options
+            .mix
+            .weights
+            .iter()
+            .copied()
+            .filter(|(construct, _)| nesting || !matches!(construct, Construct::If | Construct::Loop | Construct::Case))
+            .collect();
+        let total: u32 = choices.iter().map(|(_, weight)| weight).sum();
+        let mut pick = if total == 0 { 0 } else { self.rng.below(total as usize) as u32 };
+        let construct = choices
+            .iter()
+            .find(|(_, weight)| {
+                let found = pick < *weight;
+                pick = pick.saturating_sub(*weight);
+                found
+            })
+            .map_or(Construct::Assign, |(construct, _)| *construct);
+
+        match construct {
+            Construct::Assign => {
+                let name = self.target();
+                let value = self.expression(2);
+                let value = self.kept_small(value);
+                self.define(&name);
+                block.assign(&name, value)
+            }
+            Construct::Output => {
+                let value = match self.variables.len() {
+                    0 => self.expression(2),
+                    count => {
+                        let name = self.variables[self.rng.below(count)].clone();
+                        expr::add(expr::string(&format!("{} is ", name)), expr::var(&name))
+                    }
+                };
+                block.output(value)
+            }
+            Construct::If => {
+                let condition = self.condition();
+                let true_branch = self.nested(depth);
+                let false_branch = if self.rng.below(2) == 0 { self.nested(depth) } else { Block::new() };
+                block.if_else(condition, true_branch, false_branch)
+            }
+            Construct::Loop => {
+                let counter = COUNTERS[self.counters];
+                let limit = expr::num(1 + self.rng.below(4) as i64);
+                let condition = expr::lt(expr::var(counter), limit);
+                self.counters += 1;
+                let body = self.nested(depth);
+                self.counters -= 1;
+                let body = body.assign(counter, expr::add(expr::var(counter), expr::num(1)));
+                block.assign(counter, expr::num(0)).loop_while(condition, body)
+            }
+            Construct::Case => {
+                let value = expr::modulo(self.expression(1), expr::num(3));
+                let branches = (0..1 + self.rng.below(3))
+                    .map(|label| (vec![expr::num(label as i64)], self.nested(depth)))
+                    .collect();
+                let default = if self.rng.below(2) == 0 { Some(self.nested(depth)) } else { None };
+                block.case(value, branches, default)
+            }
+            Construct::Array => match self.arrays.len() {
+                // Sets an element of an array that already exists.
+                count if count > 0 && self.rng.below(2) == 0 => {
+                    let (name, length) = self.arrays[self.rng.below(count)].clone();
+                    let index = expr::num(self.rng.below(length) as i64);
+                    let value = self.expression(2);
+                    let value = self.kept_small(value);
+                    block.set_element(&name, vec![index], value)
+                }
+                _ => {
+                    let index = self.rng.below(ARRAYS.len());
+                    let (name, length) = (ARRAYS[index].to_string(), self.lengths[index]);
+                    let elements = (0..length).map(|_| self.expression(1)).collect();
+                    if !self.arrays.iter().any(|(other, _)| *other == name) {
+                        self.arrays.push((name.clone(), length));
+                    }
+                    block.assign(&name, expr::array(elements))
+                }
+            },
+        }
+    }
+
+    // `program` with a new function called `name`.
+    fn function(&mut self, program: Block, name: String) -> Block {
+        let arity = 1 + self.rng.below(PARAMETERS.len());
+        let params: Vec<&str> = PARAMETERS[..arity].to_vec();
+        // The body sees only its parameters.
+        let outer = std::mem::replace(&mut self.variables, params.iter().map(|param| param.to_string()).collect());
+        let outer_arrays = std::mem::take(&mut self.arrays);
+        let outer_functions = std::mem::take(&mut self.functions);
+        let body = if self.rng.below(2) == 0 {
+            Block::new().return_value(self.expression(2))
+        } else {
+            let condition = self.condition();
+            let true_branch = Block::new().return_value(self.expression(2));
+            let false_branch = Block::new().return_value(self.expression(2));
+            Block::new().if_else(condition, true_branch, false_branch)
+        };
+        self.variables = outer;
+        self.arrays = outer_arrays;
+        self.functions = outer_functions;
+        self.functions.push((name.clone(), arity));
+        program.function(&name, params.into_iter().map(builder::param).collect(), body)
+    }
+
+    // A variable to assign: a new one while there are some left, otherwise
+    // one that already has a value.
+    fn target(&mut self) -> String {
+        let unused: Vec<&str> = VARIABLES.iter().copied().filter(|name| !self.variables.iter().any(|v| v == name)).collect();
+        if !unused.is_empty() && (self.variables.is_empty() || self.rng.below(2) == 0) {
+            return unused[self.rng.below(unused.len())].to_string();
+        }
+        self.variables[self.rng.below(self.variables.len())].clone()
+    }
+
+    fn define(&mut self, name: &str) {
+        if !self.variables.iter().any(|v| v == name) {
+            self.variables.push(name.to_string());
+        }
+    }
+
+    // Inside a loop, a value that could grow on every pass is kept below 1000.
+    fn kept_small(&self, value: AstNode) -> AstNode {
+        match self.counters {
+            0 => value,
+            _ => expr::modulo(value, expr::num(1000)),
+        }
+    }
+
+    fn condition(&mut self) -> AstNode {
+        let left = self.comparison();
+        match self.rng.below(4) {
+            0 => expr::and(left, self.comparison()),
+            1 => expr::or(left, self.comparison()),
+            _ => left,
+        }
+    }
+
+    fn comparison(&mut self) -> AstNode {
+        let op = match self.rng.below(6) {
+            0 => BinaryOp::Eq,
+            1 => BinaryOp::NotEqual,
+            2 => BinaryOp::LessThan,
+            3 => BinaryOp::LessThanOrEqual,
+            4 => BinaryOp::GreaterThan,
+            _ => BinaryOp::GreaterThanOrEqual,
+        };
+        expr::binary(self.expression(1), op, self.expression(1))
+    }
+
+    // A whole-number expression using only names that have values.
+    fn expression(&mut self, depth: usize) -> AstNode {
+        if depth == 0 || self.rng.below(5) < 2 {
+            return self.operand();
+        }
+        match self.rng.below(6) {
+            0 if !self.functions.is_empty() => {
+                let (name, arity) = self.functions[self.rng.below(self.functions.len())].clone();
+                let args = (0..arity).map(|_| self.expression(depth - 1)).collect();
+                expr::call(&name, args)
+            }
+            // A small factor keeps products from overflowing.
+            1 => expr::multiply(self.expression(depth - 1), expr::num(1 + self.rng.below(5) as i64)),
+            2 => expr::modulo(self.expression(depth - 1), expr::num(2 + self.rng.below(8) as i64)),
+            3 => expr::subtract(self.expression(depth - 1), self.expression(depth - 1)),
+            _ => expr::add(self.expression(depth - 1), self.expression(depth - 1)),
+        }
+    }
+
+    fn operand(&mut self) -> AstNode {
+        let counters = &COUNTERS[..self.counters];
+        let choices = self.variables.len() + counters.len() + self.arrays.len();
+        if choices == 0 || self.rng.below(3) == 0 {
+            return expr::num(self.rng.below(10) as i64);
+        }
+        let pick = self.rng.below(choices);
+        if let Some(name) = self.variables.get(pick) {
+            return expr::var(name);
+        }
+        if let Some(counter) = counters.get(pick - self.variables.len()) {
+            return expr::var(counter);
+        }
+        let (name, length) = self.arrays[pick - self.variables.len() - counters.len()].clone();
+        let index = expr::num(self.rng.below(length) as i64);
+        expr::index(expr::var(&name), index)
+    }
+}
+
+// SplitMix64, which is plenty for picking constructs and needs no crate.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
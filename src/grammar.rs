@@ -0,0 +1,428 @@
+use crate::keywords::{Keywords, KEYWORDS};
+use crate::lexer::{Token, OPERATORS};
+use serde_json::{json, Value};
+
+// A TextMate grammar, as used by VS Code, Sublime Text and TextMate itself,
+// built from the lexer's keyword and operator tables so highlighting accepts
+// exactly what the interpreter does. Keywords are spelled as in `keywords`,
+// including its aliases.
+pub fn tmlanguage(keywords: &Keywords) -> Value {
+    let mut keyword_patterns = vec![];
+    let scopes = [
+        "keyword.control",
+        "keyword.other",
+        "keyword.operator.word",
+        "storage.type",
+        "storage.modifier",
+        "constant.language.null",
+    ];
+    for scope in scopes {
+        let mut words: Vec<String> = KEYWORDS
+            .iter()
+            .filter(|keyword| keyword_scope(keyword) == scope)
+            .map(|keyword| keywords.spelling(keyword).to_string())
+            .collect();
+        words.extend(
+            keywords
+                .aliases()
+                .iter()
+                .filter(|alias| keyword_scope(alias.keywords[0]) == scope)
+                .map(|alias| alias.words.join("[ \\t]+")),
+        );
+        // Longer words first, so an alias like `end if` wins over `end`.
+        words.sort_by_key(|word| std::cmp::Reverse(word.len()));
+        keyword_patterns.push(json!({
+            "name": format!("{}.ibpseudocode", scope),
+            "match": format!("\\b(?:{})\\b", words.join("|")),
+        }));
+    }
+
+    let (punctuation, operators): (Vec<_>, Vec<_>) = OPERATORS.iter().partition(|(_, token)| {
+        matches!(
+            token,
+            Token::LParen
+                | Token::RParen
+                | Token::LBracket
+                | Token::RBracket
+                | Token::Comma
+                | Token::Colon
+                | Token::Dot
+                | Token::Semicolon
+        )
+    });
+    let alternatives = |symbols: Vec<&(&str, Token)>| {
+        symbols.iter().map(|(text, _)| escape(text)).collect::<Vec<_>>().join("|")
+    };
+
+    json!({
+        "$schema": "https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json",
+        "name": "IB Pseudocode",
+        "scopeName": "source.ibpseudocode",
+        "patterns": [
+            { "include": "#comments" },
+            { "include": "#strings" },
+            { "include": "#numbers" },
+            { "include": "#keywords" },
+            { "include": "#calls" },
+            { "include": "#variables" },
+            { "include": "#operators" },
+        ],
+        "repository": {
+            "comments": {
+                "name": "comment.line.documentation.ibpseudocode",
+                "match": "///.*$",
+            },
+            // Strings end at the line unless they start with three quotes,
+            // and `{expression}` inside one is code.
+            "strings": {
+                "patterns": [
+                    {
+                        "name": "string.quoted.triple.ibpseudocode",
+                        "begin": "\"\"\"",
+                        "end": "\"\"\"",
+                        "patterns": [{ "include": "#interpolation" }],
+                    },
+                    {
+                        "name": "string.quoted.double.ibpseudocode",
+                        "begin": "\"",
+                        "end": "\"|$",
+                        "patterns": [{ "include": "#interpolation" }],
+                    },
+                ],
+            },
+            "interpolation": {
+                "name": "meta.embedded.interpolation.ibpseudocode",
+                "begin": "\\{",
+                "end": "\\}",
+                "patterns": [{ "include": "$self" }],
+            },
+            "numbers": {
+                "name": "constant.numeric.ibpseudocode",
+                "match": concat!(
+                    "\\b0[xX][0-9a-fA-F](?:_?[0-9a-fA-F])*\\b|\\b0[bB][01](?:_?[01])*\\b",
+                    "|\\b[0-9](?:_?[0-9])*(?:\\.[0-9](?:_?[0-9])*)?(?:[eE][+-]?[0-9](?:_?[0-9])*)?\\b",
+                ),
+            },
+            "keywords": { "patterns": keyword_patterns },
+            "calls": {
+                "match": "\\b([\\p{L}][\\p{L}\\p{N}]*)(?=[ \\t]*\\()",
+                "captures": { "1": { "name": "entity.name.function.ibpseudocode" } },
+            },
+            "variables": {
+                "name": "variable.other.ibpseudocode",
+                "match": "\\b[\\p{L}][\\p{L}\\p{N}]*\\b",
+            },
+            "operators": {
+                "patterns": [
+                    { "name": "keyword.operator.ibpseudocode", "match": alternatives(operators) },
+                    { "name": "punctuation.separator.ibpseudocode", "match": alternatives(punctuation) },
+                ],
+            },
+        },
+    })
+}
+
+// The TextMate scope for a canonical keyword.
+fn keyword_scope(keyword: &str) -> &'static str {
+    match keyword {
+        "output" | "input" | "prompt" | "inline" | "new" => "keyword.other",
+        "and" | "or" | "not" | "mod" | "div" | "band" | "bor" | "bxor" => "keyword.operator.word",
+        "sub" | "endsub" | "function" | "endfunction" => "storage.type",
+        "ref" | "global" => "storage.modifier",
+        "null" => "constant.language.null",
+        _ => "keyword.control",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| if "\\^$.|?*+()[]{}".contains(c) { format!("\\{}", c) } else { c.to_string() })
+        .collect()
+}
+
+// The grammar the parser accepts, in ISO EBNF with English keywords. Every
+// quoted word is a keyword except `Array`, and every quoted symbol is an
+// operator; `check_ebnf` holds it to that.
+const EBNF: &str = r#"(* Statements may be separated by ";" to put several on one line; line
+   breaks never end a statement. "+=", "-=", "*=" and try need the extended
+   dialect. *)
+program = statements ;
+statements = { ";" } , { statement , { ";" } } ;
+statement = assignment | postfix | output | input | if_statement | loop
+          | case | procedure | return | global | error | try ;
+
+(* The target is a variable or array element; a postfix statement must end
+   in a call. *)
+assignment = postfix , ( "=" | "+=" | "-=" | "*=" ) , expression ;
+output = "output" , expression , [ "inline" ] ;
+input = "input" , identifier , [ "prompt" , expression ] ;
+
+if_statement = if_clause , "endif" ;
+(* An "if" on the same line as "else" continues the chain. *)
+if_clause = "if" , condition , "then" , statements ,
+            [ "else" , ( if_clause | statements ) ] ;
+loop = "loop" , "while" , condition , statements , "endloop" ;
+case = "case" , expression , "of" , { case_branch } ,
+       [ "default" , ":" , statements ] , "endcase" ;
+case_branch = literal , { "," , literal } , ":" , statements ;
+literal = number | string ;
+
+(* Only at the top level of a program. Lines starting with "///" just above
+   it are its doc comments; elsewhere "///" starts a comment that is ignored. *)
+procedure = "sub" , identifier , parameters , statements , "endsub"
+          | "function" , identifier , parameters , statements , "endfunction" ;
+(* Parameters with defaults come last; a ref parameter has no default. *)
+parameters = "(" , [ parameter , { "," , parameter } ] , ")" ;
+parameter = [ "ref" ] , identifier , [ "=" , expression ] ;
+(* Inside a procedure; a function returns a value and a sub does not. *)
+return = "return" , [ condition ] ;
+global = "global" , identifier , { "," , identifier } ;
+error = "error" , expression ;
+(* The variable must be on the "catch" line. *)
+try = "try" , statements , "catch" , [ identifier ] , statements , "endtry" ;
+
+condition = comparison , { ( "and" | "or" ) , comparison } ;
+comparison = expression , { ( "=" | "!=" | ">" | ">=" | "<" | "<=" ) , expression } ;
+(* Bitwise operators are only in the extended dialect. *)
+expression = bit_xor , { "bor" , bit_xor } ;
+bit_xor = bit_and , { "bxor" , bit_and } ;
+bit_and = shift , { "band" , shift } ;
+shift = additive , { ( "<<" | ">>" ) , additive } ;
+additive = term , { ( "+" | "-" ) , term } ;
+term = power , { ( "*" | "/" | "mod" | "div" ) , power } ;
+(* Groups to the right. *)
+power = postfix , [ ( "^" | "**" ) , power ] ;
+postfix = primary , { "[" , expression , "]" | "." , identifier , arguments } ;
+primary = number | string | identifier , [ arguments ] | "null"
+        | "[" , [ expression , { "," , expression } ] , "]"
+        | "new" , "Array" , arguments
+        | "(" , expression , ")"
+        | "if" , condition , "then" , expression , "else" , expression ;
+arguments = "(" , [ expression , { "," , expression } ] , ")" ;
+
+(* Words that are not keywords, numbers and strings. Text in braces inside a
+   string is an interpolated expression; "{{" and "}}" are literal braces. *)
+identifier = letter , { letter | digit } ;
+(* Underscores between digits, exponents and numbers in base 16 or 2 are only
+   in the extended dialect. *)
+number = digits , [ "." , digits ] , [ exponent ]
+       | ? 0x or 0X ? , hex_digit , { [ underscore ] , hex_digit }
+       | ? 0b or 0B ? , bit , { [ underscore ] , bit } ;
+exponent = ? e or E ? , [ "+" | "-" ] , digits ;
+digits = digit , { [ underscore ] , digit } ;
+(* Three quotes start a long string, which may span lines and hold quotes; a
+   line break right after the opening quotes is not part of its text. *)
+string = quote , { ? any character except a quote or line break ? } , quote
+       | quote , quote , quote , { ? any character up to the next three quotes ? } , quote , quote , quote ;
+quote = ? a double quote ? ;
+underscore = ? an underscore ? ;
+letter = ? any Unicode letter ? ;
+digit = ? 0 to 9 ? ;
+hex_digit = digit | ? a to f or A to F ? ;
+bit = ? 0 or 1 ? ;
+"#;
+
+// Keywords the lexer reserves that no rule uses yet.
+const RESERVED: [&str; 1] = ["not"];
+
+// The grammar with keywords spelled as in `keywords`.
+pub fn ebnf(keywords: &Keywords) -> String {
+    let mut text = String::new();
+    for (index, piece) in EBNF.split('"').enumerate() {
+        if index > 0 {
+            text.push('"');
+        }
+        // Odd pieces are between double quotes.
+        match KEYWORDS.iter().find(|keyword| index % 2 == 1 && **keyword == piece) {
+            Some(keyword) => text.push_str(keywords.spelling(keyword)),
+            None => text.push_str(piece),
+        }
+    }
+    text
+}
+
+// A rule of the EBNF, as drawn by `railroad`.
+#[derive(Debug, Clone)]
+pub struct Production {
+    pub name: String,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Terminal(String),
+    NonTerminal(String),
+    // `? text ?`, described in words rather than defined.
+    Special(String),
+    Sequence(Vec<Expr>),
+    Choice(Vec<Expr>),
+    Optional(Box<Expr>),
+    Repeat(Box<Expr>),
+}
+
+// The rules of the grammar with keywords spelled as in `keywords`.
+pub fn productions(keywords: &Keywords) -> Vec<Production> {
+    parse(&ebnf(keywords)).expect("the grammar is well formed")
+}
+
+// Checks the grammar against the lexer's tables and itself: every rule used
+// is defined and every rule defined is used, every quoted word is a keyword,
+// and every keyword and operator appears. Returns the problems found.
+pub fn check_ebnf() -> Vec<String> {
+    let productions = match parse(EBNF) {
+        Ok(productions) => productions,
+        Err(problem) => return vec![problem],
+    };
+    let mut problems = vec![];
+    let rules: Vec<&str> = productions.iter().map(|production| production.name.as_str()).collect();
+    let mut used = vec![];
+    let mut terminals = vec![];
+    for production in &productions {
+        production.body.visit(&mut |expr| match expr {
+            Expr::Terminal(text) => terminals.push(text.as_str()),
+            Expr::NonTerminal(name) => used.push(name.as_str()),
+            _ => {}
+        });
+    }
+    used.sort();
+    used.dedup();
+
+    for name in &used {
+        if !rules.contains(name) {
+            problems.push(format!("Rule {} is used but not defined", name));
+        }
+    }
+    for name in rules.iter().skip(1) {
+        if !used.contains(name) {
+            problems.push(format!("Rule {} is defined but never used", name));
+        }
+    }
+    for terminal in &terminals {
+        let is_word = terminal.starts_with(char::is_alphabetic);
+        if is_word && *terminal != "Array" && !KEYWORDS.contains(terminal) {
+            problems.push(format!("\"{}\" is not a keyword", terminal));
+        }
+        if !is_word && !OPERATORS.iter().any(|(text, _)| text == terminal) {
+            problems.push(format!("\"{}\" is not an operator", terminal));
+        }
+    }
+    for keyword in KEYWORDS.iter().filter(|keyword| !RESERVED.contains(keyword)) {
+        if !terminals.contains(keyword) {
+            problems.push(format!("Keyword {} does not appear in the grammar", keyword));
+        }
+    }
+    for (text, _) in &OPERATORS {
+        if !terminals.contains(text) {
+            problems.push(format!("Operator {} does not appear in the grammar", text));
+        }
+    }
+    problems
+}
+
+impl Expr {
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Expr)) {
+        f(self);
+        match self {
+            Expr::Sequence(items) | Expr::Choice(items) => items.iter().for_each(|item| item.visit(f)),
+            Expr::Optional(item) | Expr::Repeat(item) => item.visit(f),
+            _ => {}
+        }
+    }
+}
+
+enum Lexeme {
+    Name(String),
+    Terminal(String),
+    Special(String),
+    Symbol(char),
+}
+
+// Reads the subset of ISO EBNF the grammar is written in.
+fn parse(text: &str) -> Result<Vec<Production>, String> {
+    let mut lexemes = vec![];
+    let mut chars = strip_comments(text).chars().collect::<Vec<_>>().into_iter().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' => lexemes.push(Lexeme::Terminal(chars.by_ref().take_while(|&next| next != '"').collect())),
+            '?' => {
+                let text: String = chars.by_ref().take_while(|&next| next != '?').collect();
+                lexemes.push(Lexeme::Special(text.trim().to_string()));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(next) = chars.next_if(|next| next.is_alphanumeric() || *next == '_') {
+                    name.push(next);
+                }
+                lexemes.push(Lexeme::Name(name));
+            }
+            c => lexemes.push(Lexeme::Symbol(c)),
+        }
+    }
+
+    let mut lexemes = lexemes.into_iter().peekable();
+    let mut productions = vec![];
+    while let Some(lexeme) = lexemes.next() {
+        let Lexeme::Name(name) = lexeme else {
+            return Err(format!("Expected a rule name after rule {}", productions.len()));
+        };
+        if !matches!(lexemes.next(), Some(Lexeme::Symbol('='))) {
+            return Err(format!("Expected = after {}", name));
+        }
+        let body = choice(&mut lexemes).map_err(|problem| format!("In rule {}: {}", name, problem))?;
+        if !matches!(lexemes.next(), Some(Lexeme::Symbol(';'))) {
+            return Err(format!("Expected ; at the end of rule {}", name));
+        }
+        productions.push(Production { name, body });
+    }
+    Ok(productions)
+}
+
+type Lexemes = std::iter::Peekable<std::vec::IntoIter<Lexeme>>;
+
+// alternatives = sequence , { "|" , sequence }
+fn choice(lexemes: &mut Lexemes) -> Result<Expr, String> {
+    let mut items = vec![sequence(lexemes)?];
+    while lexemes.next_if(|lexeme| matches!(lexeme, Lexeme::Symbol('|'))).is_some() {
+        items.push(sequence(lexemes)?);
+    }
+    Ok(if items.len() == 1 { items.pop().unwrap() } else { Expr::Choice(items) })
+}
+
+// sequence = item , { "," , item }
+fn sequence(lexemes: &mut Lexemes) -> Result<Expr, String> {
+    let mut items = vec![item(lexemes)?];
+    while lexemes.next_if(|lexeme| matches!(lexeme, Lexeme::Symbol(','))).is_some() {
+        items.push(item(lexemes)?);
+    }
+    Ok(if items.len() == 1 { items.pop().unwrap() } else { Expr::Sequence(items) })
+}
+
+fn item(lexemes: &mut Lexemes) -> Result<Expr, String> {
+    let (close, wrap): (char, fn(Expr) -> Expr) = match lexemes.next() {
+        Some(Lexeme::Name(name)) => return Ok(Expr::NonTerminal(name)),
+        Some(Lexeme::Terminal(text)) => return Ok(Expr::Terminal(text)),
+        Some(Lexeme::Special(text)) => return Ok(Expr::Special(text)),
+        Some(Lexeme::Symbol('[')) => (']', |item| Expr::Optional(Box::new(item))),
+        Some(Lexeme::Symbol('{')) => ('}', |item| Expr::Repeat(Box::new(item))),
+        Some(Lexeme::Symbol('(')) => (')', |item| item),
+        Some(Lexeme::Symbol(c)) => return Err(format!("Unexpected {}", c)),
+        None => return Err("Unexpected end of the grammar".to_string()),
+    };
+    let inner = choice(lexemes)?;
+    match lexemes.next() {
+        Some(Lexeme::Symbol(c)) if c == close => Ok(wrap(inner)),
+        _ => Err(format!("Expected {}", close)),
+    }
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("(*") {
+        result.push_str(&rest[..start]);
+        rest = rest[start..].find("*)").map_or("", |end| &rest[start + end + 2..]);
+    }
+    result.push_str(rest);
+    result
+}
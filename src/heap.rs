@@ -0,0 +1,147 @@
+use crate::value::{Array, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+// Arrays live on the heap behind `Rc<RefCell<...>>`, so copying an array value
+// copies a pointer and every copy sees the same elements, as IB pseudocode
+// expects of `B = A`. Reference counting frees an array when the last value
+// pointing at it goes, except for arrays in a cycle, such as `A.append(A)`,
+// which keep each other alive. Each thread keeps a list of the arrays made on
+// it, so `collect_cycles` can find and free those, and `objects` can show what
+// is still alive.
+//
+// The list holds weak references, so it never keeps an array alive itself.
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+#[derive(Default)]
+struct Heap {
+    arrays: Vec<(usize, Weak<RefCell<Vec<Value>>>)>,
+    next_id: usize,
+    // The length at which freed arrays are next dropped from the list.
+    limit: usize,
+}
+
+// A live array, as listed by `objects`.
+#[derive(Debug, Clone)]
+pub struct Object {
+    // Numbered in the order arrays were made on this thread, from 1.
+    pub id: usize,
+    // Values pointing at the array, from variables, other arrays and anything
+    // else that holds one.
+    pub references: usize,
+    pub array: Array,
+}
+
+// Makes an array and adds it to this thread's list.
+pub fn allocate(items: Vec<Value>) -> Array {
+    let array = Rc::new(RefCell::new(items));
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        if heap.arrays.len() >= heap.limit {
+            heap.arrays.retain(|(_, array)| array.strong_count() > 0);
+            heap.limit = (heap.arrays.len() * 2).max(1024);
+        }
+        heap.next_id += 1;
+        let id = heap.next_id;
+        heap.arrays.push((id, Rc::downgrade(&array)));
+    });
+    array
+}
+
+// How many arrays have been made on this thread, freed or not.
+pub fn allocated() -> usize {
+    HEAP.with(|heap| heap.borrow().next_id)
+}
+
+// The arrays on this thread that are still alive, oldest first.
+pub fn objects() -> Vec<Object> {
+    live()
+        .into_iter()
+        // Less the reference `live` holds.
+        .map(|(id, array)| Object { id, references: Rc::strong_count(&array) - 1, array })
+        .collect()
+}
+
+// Frees the arrays on this thread that only other arrays point at, which
+// nothing else can reach, by emptying them. Returns how many there were.
+//
+// An array is reachable when more values point at it than there are
+// elements pointing at it in the arrays on the list, or when an array that
+// is reachable holds it. This needs no roots, so it is safe with any number
+// of interpreters and snapshots on the thread.
+pub fn collect_cycles() -> usize {
+    let arrays: Vec<Array> = live().into_iter().map(|(_, array)| array).collect();
+    let index: HashMap<*const RefCell<Vec<Value>>, usize> =
+        arrays.iter().enumerate().map(|(number, array)| (Rc::as_ptr(array), number)).collect();
+    let children = |array: &Array| -> Option<Vec<usize>> {
+        let items = array.try_borrow().ok()?;
+        Some(
+            items
+                .iter()
+                .filter_map(|item| match item {
+                    Value::Array(child) => index.get(&Rc::as_ptr(child)).copied(),
+                    _ => None,
+                })
+                .collect(),
+        )
+    };
+
+    // An array being changed right now cannot be looked into, so it and
+    // everything it holds are kept.
+    let edges: Vec<Option<Vec<usize>>> = arrays.iter().map(children).collect();
+    let mut internal = vec![0; arrays.len()];
+    for child in edges.iter().flatten().flatten() {
+        internal[*child] += 1;
+    }
+    let mut reachable = vec![false; arrays.len()];
+    let mut pending: Vec<usize> = (0..arrays.len())
+        .filter(|&number| edges[number].is_none() || Rc::strong_count(&arrays[number]) - 1 > internal[number])
+        .collect();
+    while let Some(number) = pending.pop() {
+        if reachable[number] {
+            continue;
+        }
+        reachable[number] = true;
+        pending.extend(edges[number].iter().flatten().copied());
+    }
+
+    let garbage: Vec<&Array> = arrays.iter().zip(&reachable).filter(|(_, &kept)| !kept).map(|(a, _)| a).collect();
+    // Each array's elements are dropped after its borrow ends, as dropping
+    // them can free other arrays.
+    for array in &garbage {
+        let items = std::mem::take(&mut *array.borrow_mut());
+        drop(items);
+    }
+    garbage.len()
+}
+
+// Bytes held by the arrays alive on this thread, the strings in them and the
+// strings among `roots`. A string many values share is counted once.
+pub fn footprint<'a>(roots: impl IntoIterator<Item = &'a Value>) -> usize {
+    let mut strings = HashSet::new();
+    let mut text = |value: &Value| match value {
+        Value::String(string) if strings.insert(Rc::as_ptr(string) as *const u8) => string.len(),
+        _ => 0,
+    };
+    let mut bytes: usize = roots.into_iter().map(&mut text).sum();
+    for (_, array) in live() {
+        // An array being changed right now is counted by what it had room for.
+        if let Ok(items) = array.try_borrow() {
+            bytes += items.capacity() * std::mem::size_of::<Value>();
+            bytes += items.iter().map(&mut text).sum::<usize>();
+        }
+    }
+    bytes
+}
+
+fn live() -> Vec<(usize, Array)> {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.arrays.retain(|(_, array)| array.strong_count() > 0);
+        heap.arrays.iter().filter_map(|(id, array)| Some((*id, array.upgrade()?))).collect()
+    })
+}
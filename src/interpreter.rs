@@ -1,151 +1,1536 @@
-use crate::ast::AstNode;
-use crate::lexer::Token;
-use std::collections::HashMap;
+use crate::ast::{AstNode, BinaryOp, NodeKind, Param, Procedure, ProcedureKind};
+use crate::builtins;
+use crate::error::{Error, ErrorKind};
+use crate::heap;
+use crate::lexer::Span;
+use crate::terminal;
+use crate::value::{Array, Value};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
 
+// Where a variable's value lives. A `ref` parameter shares the caller's slot,
+// so assigning to the parameter changes the caller's variable.
+type Slot = Rc<RefCell<Value>>;
+
+// The variables of one running procedure. Its parameters and anything it
+// assigns are local, unless the name was declared `global`.
+#[derive(Default)]
+struct Frame {
+    procedure: String,
+    call_site: Span,
+    locals: HashMap<String, Slot>,
+    globals: HashSet<String>,
+}
+
+// A procedure call in progress, as reported by `Interpreter::call_stack`.
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub procedure: String,
+    // Where the procedure was called from.
+    pub call_site: Span,
+    pub locals: BTreeMap<String, Value>,
+}
+
+// Where a program stopped with an error nothing caught, and the variables as
+// they were when it happened, before the calls in progress were abandoned.
+// Kept by the interpreter until the next program starts, for a debugger or
+// notebook to show.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub span: Option<Span>,
+    pub variables: BTreeMap<String, Value>,
+    pub call_stack: Vec<StackFrame>,
+}
+
+// A copy of everything a program has done so far, made by
+// `Interpreter::snapshot`. Restoring it rewinds the interpreter to that point,
+// including where execution is, as many times as needed. Input and output
+// streams are not part of it.
+pub struct StateSnapshot {
+    globals: HashMap<String, Slot>,
+    frames: Vec<Frame>,
+    procedures: HashMap<String, Arc<Procedure>>,
+    tasks: Vec<Task>,
+    returned: Option<Value>,
+    transcript: String,
+}
+
+// What `Interpreter::step` did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepResult {
+    // Ran the statement at this span, or went back to the top of this loop.
+    Stepped(Span),
+    // The program has finished.
+    Done,
+}
+
+// The code a task's statements belong to.
+#[derive(Clone)]
+enum Root {
+    Program(Arc<AstNode>),
+    Procedure(Arc<Procedure>),
+}
+
+// A block of statements being run, one per step. `path` leads from the root
+// to the block through compound statements, as pairs of a statement's index
+// and the branch taken: 0 or 1 for the two halves of an `if` or `try`, and
+// the branch number for `case`, with one past the last for `default`.
+#[derive(Clone)]
+struct Task {
+    root: Root,
+    path: Rc<[(usize, usize)]>,
+    next: usize,
+    kind: TaskKind,
+}
+
+#[derive(Clone)]
+enum TaskKind {
+    Block,
+    // A loop body, after which the condition is checked again.
+    Loop,
+    // A `try` body, whose runtime errors go to the handler.
+    Try,
+    // A procedure body, with the array elements given as `ref` arguments.
+    Call { elements: Vec<(Array, usize, Slot)>, call_site: Span },
+}
+
+// Bounds on what one program may do, for running code nobody has checked.
+// Each is unlimited when `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    // Statements run, counting those in calls inside expressions.
+    pub steps: Option<u64>,
+    // Bytes written, including prompts.
+    pub output: Option<usize>,
+    // Time from `start` until the program finishes.
+    pub time: Option<Duration>,
+    // Bytes the program's strings and arrays may take at once, as
+    // `heap::footprint` counts them, so a program that keeps making them
+    // stops with an error rather than taking all the memory there is. Arrays
+    // made on this thread by anything else count as the program's own.
+    pub memory: Option<usize>,
+}
+
+// Settings that change what a program computes, rather than how far it may
+// run. Every way of running a program takes the same options, so it gives the
+// same results under `run`, `batch`, `serve` and the commands that compare
+// runs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunOptions {
+    // The index of the first element of every array, 0 or 1.
+    pub array_base: i64,
+    pub undefined: Undefined,
+    pub division: Division,
+}
+
+// What reading a variable that was never set does. Some textbooks assume
+// variables start at 0, so `zero` reads one as 0, or as "" beside a string,
+// and records a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Undefined {
+    #[default]
+    Strict,
+    Zero,
+}
+
+impl Undefined {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "strict" => Some(Undefined::Strict),
+            "zero" => Some(Undefined::Zero),
+            _ => None,
+        }
+    }
+}
+
+// How `div` and `mod` round when an operand is negative. IB only
+// defines them for operands that are not, where both agree. `truncate` rounds
+// the quotient toward zero, so `-7 div 2` is -3 and `-7 mod 2` is -1, taking
+// the sign of the number divided, as in Java and C. `floor` rounds it down, so
+// `-7 div 2` is -4 and `-7 mod 2` is 1, taking the sign of the divisor, as in
+// Python.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Division {
+    #[default]
+    Truncate,
+    Floor,
+}
+
+impl Division {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "truncate" => Some(Division::Truncate),
+            "floor" => Some(Division::Floor),
+            _ => None,
+        }
+    }
+}
+
+// What the last program started did, as reported by `Interpreter::stats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    // Statements run, counting those in calls inside expressions.
+    pub statements: u64,
+    // Times a loop body was started.
+    pub loop_iterations: u64,
+    // The most procedure calls in progress at once.
+    pub max_call_depth: usize,
+    // The most variables that existed at once, globals and locals together.
+    pub peak_variables: usize,
+    // Arrays made, including copies and those builtins return.
+    pub arrays: usize,
+    pub time: Duration,
+}
+
+impl Stats {
+    // For the `run` results of `serve` and `rpc`, with the time in
+    // milliseconds.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "statements": self.statements,
+            "loopIterations": self.loop_iterations,
+            "maxCallDepth": self.max_call_depth,
+            "peakVariables": self.peak_variables,
+            "arrays": self.arrays,
+            "timeMs": self.time.as_secs_f64() * 1000.0,
+        })
+    }
+}
+
+const MAX_CALL_DEPTH: usize = 1000;
+
+// A call inside an expression nests several Rust frames, so programs run on a
+// thread with a stack large enough to reach MAX_CALL_DEPTH and report it as an
+// error.
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// An interpreter and its values stay on the thread that made them. Parsed
+// programs are Send and Sync, so threads can share one as an `Arc<AstNode>`,
+// each running it with an interpreter of its own.
 pub struct Interpreter {
-    variables: HashMap<String, i64>,
-    in_condition: bool,
+    globals: HashMap<String, Slot>,
+    // The local variables of each procedure being run, innermost last.
+    frames: Vec<Frame>,
+    procedures: HashMap<String, Arc<Procedure>>,
+    // The blocks being run, innermost last.
+    tasks: Vec<Task>,
+    // The result of the call that finished last.
+    returned: Option<Value>,
+    output: Box<dyn Write>,
+    input: Box<dyn BufRead>,
+    show_prompts: bool,
+    terminal: bool,
+    // Everything written so far, for `output_so_far`.
+    transcript: String,
+    limits: Limits,
+    steps: u64,
+    deadline: Option<Instant>,
+    stats: Stats,
+    started: Instant,
+    // How long the program took, once `interpret` has run it to the end.
+    finished: Option<Duration>,
+    // `heap::allocated` when the program started.
+    arrays_before: usize,
+    // The length of the transcript when the program started.
+    output_start: usize,
+    failure: Option<Failure>,
+    // The index of the first element of every array.
+    array_base: i64,
+    undefined: Undefined,
+    division: Division,
+    // Variables read before they were set, each warned about once.
+    unset_reads: HashSet<String>,
+    warnings: Vec<Error>,
+    // Bytes charged against `Limits::memory`: the footprint when it was last
+    // measured, and everything made since.
+    charged: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Arrays in cycles outlive the variables that held them, so they are freed
+// once the interpreter lets go of its state.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.pop_tasks(0);
+        self.globals.clear();
+        self.returned = None;
+        heap::collect_cycles();
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
         Interpreter {
-            variables: HashMap::new(),
-            in_condition: false,
+            globals: HashMap::new(),
+            frames: vec![],
+            procedures: HashMap::new(),
+            tasks: vec![],
+            returned: None,
+            output,
+            input: Box::new(BufReader::new(io::stdin())),
+            show_prompts: io::stdin().is_terminal(),
+            terminal: false,
+            transcript: String::new(),
+            limits: Limits::default(),
+            steps: 0,
+            deadline: None,
+            stats: Stats::default(),
+            started: Instant::now(),
+            finished: None,
+            arrays_before: heap::allocated(),
+            output_start: 0,
+            failure: None,
+            array_base: 0,
+            undefined: Undefined::Strict,
+            division: Division::Truncate,
+            unset_reads: HashSet::new(),
+            warnings: vec![],
+            charged: 0,
+        }
+    }
+
+    // Lets the program use the terminal builtins such as `setColor`.
+    pub fn allow_terminal(&mut self, allow: bool) {
+        self.terminal = allow;
+    }
+
+    // Counts array positions from `base`, 0 or 1, for indexing and for the
+    // positions `indexOf` and `binarySearch` give. `size` is still the number
+    // of elements.
+    pub fn set_array_base(&mut self, base: i64) {
+        self.array_base = base;
+    }
+
+    pub fn set_options(&mut self, options: RunOptions) {
+        self.set_array_base(options.array_base);
+        self.set_undefined(options.undefined);
+        self.set_division(options.division);
+    }
+
+    pub fn set_undefined(&mut self, undefined: Undefined) {
+        self.undefined = undefined;
+    }
+
+    pub fn set_division(&mut self, division: Division) {
+        self.division = division;
+    }
+
+    // Takes effect from the next `start`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // Prompts are only worth printing when someone is typing the input.
+    pub fn set_input(&mut self, input: Box<dyn BufRead>, show_prompts: bool) {
+        self.input = input;
+        self.show_prompts = show_prompts;
+    }
+
+    pub fn interpret(&mut self, program: Arc<AstNode>) -> Result<(), Error> {
+        self.start(program)?;
+        let result = self.run_above(0);
+        self.finished = Some(self.started.elapsed());
+        result
+    }
+
+    // Gets a program ready to run with `step`, replacing any program that has
+    // not finished. Variables and procedures from earlier programs are kept,
+    // so a program can build on the last one like cells in a notebook. A
+    // procedure defined again replaces the earlier definition.
+    pub fn start(&mut self, program: Arc<AstNode>) -> Result<(), Error> {
+        self.pop_tasks(0);
+        self.returned = None;
+        self.steps = 0;
+        self.deadline = self.limits.time.map(|time| Instant::now() + time);
+        self.stats = Stats { max_call_depth: self.frames.len(), ..Stats::default() };
+        self.started = Instant::now();
+        self.finished = None;
+        self.arrays_before = heap::allocated();
+        self.count_variables();
+        self.output_start = self.transcript.len();
+        self.failure = None;
+        self.unset_reads.clear();
+        self.warnings.clear();
+        self.charged = 0;
+        // Arrays the last program left in cycles are freed between programs.
+        heap::collect_cycles();
+        if let NodeKind::Program(statements) = &program.kind {
+            // Procedures can be called before the line that defines them.
+            let mut procedures = HashMap::new();
+            for statement in statements {
+                if let NodeKind::Procedure(procedure) = &statement.kind {
+                    if procedures.insert(procedure.name.clone(), procedure.clone()).is_some() {
+                        return Err(Error::runtime(
+                            "E0212",
+                            format!("Procedure {} is defined more than once", procedure.name),
+                        )
+                        .or_span(statement.span));
+                    }
+                }
+            }
+            self.procedures.extend(procedures);
         }
+        self.tasks.push(Task { root: Root::Program(program), path: Rc::new([]), next: 0, kind: TaskKind::Block });
+        Ok(())
     }
 
-    pub fn interpret(&mut self, node: &AstNode) {
-        match node {
-            AstNode::Program(statements) => {
-                for statement in statements {
-                    self.interpret(statement);
+    // Runs the next statement. Calling a sub or function as a statement steps
+    // into its body; a call inside an expression runs to the end within the
+    // step. After an error that nothing catches, the program is finished.
+    pub fn step(&mut self) -> Result<StepResult, Error> {
+        self.step_above(0)
+    }
+
+    // Steps the tasks above `floor`, leaving those below it alone.
+    fn step_above(&mut self, floor: usize) -> Result<StepResult, Error> {
+        loop {
+            if self.tasks.len() <= floor {
+                return Ok(StepResult::Done);
+            }
+            let task = self.tasks.last_mut().expect("tasks are above the floor");
+            let (root, path, index) = (task.root.clone(), task.path.clone(), task.next);
+            let Some(statement) = block(&root, &path).get(index) else {
+                match self.end_task() {
+                    Ok(Some(span)) => return Ok(StepResult::Stepped(span)),
+                    Ok(None) => continue,
+                    Err(e) => {
+                        let span = e.span;
+                        self.unwind(e, floor)?;
+                        match span {
+                            Some(span) => return Ok(StepResult::Stepped(span)),
+                            None => continue,
+                        }
+                    }
                 }
+            };
+            task.next += 1;
+            self.stats.statements += 1;
+            debug!(line = statement.span.line, "executing {}", statement.kind.name());
+            if let Err(e) = self.count_step().and_then(|_| self.control(statement, &root, &path, index)) {
+                self.unwind(e.or_span(statement.span), floor)?;
+            }
+            return Ok(StepResult::Stepped(statement.span));
+        }
+    }
+
+    fn count_step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if let Some(steps) = self.limits.steps.filter(|&steps| self.steps > steps) {
+            return Err(Error::limit("E0301", format!("The program ran more than {} statements", steps)));
+        }
+        if let (Some(deadline), Some(time)) = (self.deadline, self.limits.time) {
+            if Instant::now() > deadline {
+                return Err(Error::limit(
+                    "E0302",
+                    format!("The program ran for longer than {} ms", time.as_millis()),
+                ));
             }
-            AstNode::Assignment(name, expr) => {
-                let value = self.eval_expr(expr);
-                self.variables.insert(name.clone(), value);
+        }
+        Ok(())
+    }
+
+    // Runs a statement that may start a block of its own.
+    fn control(&mut self, node: &AstNode, root: &Root, path: &[(usize, usize)], index: usize) -> Result<(), Error> {
+        let enter = |this: &mut Self, branch: usize, kind: TaskKind| {
+            let path = path.iter().copied().chain([(index, branch)]).collect();
+            this.tasks.push(Task { root: root.clone(), path, next: 0, kind });
+        };
+        match &node.kind {
+            NodeKind::If(condition, ..) => {
+                let cond_value = self.eval_condition(condition)?;
+                enter(self, if cond_value { 0 } else { 1 }, TaskKind::Block);
             }
-            AstNode::Output(expr) => {
-                if let AstNode::String(value) = &**expr {
-                    println!("{}", value);
-                } else {
-                    let value = self.eval_expr(expr);
-                    println!("{}", value);
+            NodeKind::Loop(condition, _) => {
+                if self.eval_condition(condition)? {
+                    self.stats.loop_iterations += 1;
+                    enter(self, 0, TaskKind::Loop);
                 }
             }
-            AstNode::If(condition, true_branch, false_branch) => {
-                self.in_condition = true;
-                let cond_value = self.eval_expr(condition);
-                self.in_condition = false;
-                if cond_value != 0 {
-                    for statement in true_branch {
-                        self.interpret(statement);
-                    }
-                } else {
-                    for statement in false_branch {
-                        self.interpret(statement);
+            NodeKind::Case(value, branches, default) => {
+                let value = self.eval_expr(value)?;
+                for (number, branch) in branches.iter().enumerate() {
+                    for label in &branch.labels {
+                        if self.eval_expr(label)? == value {
+                            enter(self, number, TaskKind::Block);
+                            return Ok(());
+                        }
                     }
                 }
+                if default.is_some() {
+                    enter(self, branches.len(), TaskKind::Block);
+                }
             }
-            AstNode::Loop(condition, body) => {
-                while self.eval_expr(condition) != 0 {
-                    for statement in body {
-                        self.interpret(statement);
+            NodeKind::Try(..) => enter(self, 0, TaskKind::Try),
+            NodeKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval_expr(value)?,
+                    None => Value::Null,
+                };
+                while let Some(task) = self.tasks.pop() {
+                    if let TaskKind::Call { .. } = task.kind {
+                        return self.leave(task, Some(value));
                     }
                 }
+                unreachable!("return is only parsed inside procedures");
             }
-            _ => panic!("Unknown AST node"),
+            NodeKind::Call(name, args) if self.procedures.contains_key(name) => {
+                let procedure = self.procedures[name].clone();
+                self.enter(procedure, args, node.span)?;
+            }
+            _ => self.execute(node)?,
         }
+        Ok(())
     }
 
-    fn eval_expr(&mut self, node: &AstNode) -> i64 {
-        match node {
-            AstNode::Number(value) => *value,
-            AstNode::String(_) => panic!("Cannot evaluate string as number"),
-            AstNode::Identifier(name) => *self.variables.get(name).expect("Undefined variable"),
-            AstNode::BinOp(left, op, right) => {
-                let left_val = self.eval_expr(left);
-                let right_val = self.eval_expr(right);
-                match op {
-                    Token::Plus => left_val + right_val,
-                    Token::Minus => left_val - right_val,
-                    Token::Star => left_val * right_val,
-                    Token::Slash => left_val / right_val,
-                    Token::Mod => left_val % right_val,
-                    Token::Assign if self.in_condition => (left_val == right_val) as i64,
-                    Token::Assign => {
-                        if let AstNode::Identifier(name) = &**left {
-                            self.variables.insert(name.clone(), right_val);
-                            right_val
-                        } else {
-                            panic!("Invalid assignment target")
-                        }
-                    }
-                    Token::NotEqual => (left_val != right_val) as i64,
-                    Token::GreaterThan => (left_val > right_val) as i64,
-                    Token::GreaterThanOrEqual => (left_val >= right_val) as i64,
-                    Token::LessThan => (left_val < right_val) as i64,
-                    Token::LessThanOrEqual => (left_val <= right_val) as i64,
-                    Token::And => ((left_val != 0) && (right_val != 0)) as i64,
-                    Token::Or => ((left_val != 0) || (right_val != 0)) as i64,
-                    _ => panic!("Unknown binary operator"),
+    // Finishes the innermost task, whose statements have all run. Returns the
+    // span of the loop when its condition is checked again and holds.
+    fn end_task(&mut self) -> Result<Option<Span>, Error> {
+        let mut task = self.tasks.pop().expect("a task has ended");
+        match task.kind {
+            TaskKind::Block | TaskKind::Try => Ok(None),
+            TaskKind::Loop => {
+                let root = task.root.clone();
+                let (&(index, _), parent) = task.path.split_last().expect("loop bodies are inside a loop");
+                let node = &block(&root, parent)[index];
+                let NodeKind::Loop(condition, _) = &node.kind else {
+                    unreachable!("loop bodies are inside a loop");
+                };
+                let holds = self.count_step().and_then(|_| self.eval_condition(condition));
+                if !holds.map_err(|e| e.or_span(node.span))? {
+                    return Ok(None);
                 }
+                task.next = 0;
+                self.tasks.push(task);
+                self.stats.loop_iterations += 1;
+                Ok(Some(node.span))
+            }
+            TaskKind::Call { .. } => self.leave(task, None).map(|_| None),
+        }
+    }
+
+    // Passes an error to the innermost `try` above `floor`. Without one, the
+    // tasks above `floor` are abandoned and the error is returned.
+    fn unwind(&mut self, error: Error, floor: usize) -> Result<(), Error> {
+        let caught = match error.kind {
+            ErrorKind::Runtime => (floor..self.tasks.len())
+                .rev()
+                .find(|&height| matches!(self.tasks[height].kind, TaskKind::Try)),
+            _ => None,
+        };
+        let Some(height) = caught else {
+            // The error passes through here once for each call it leaves, so
+            // the first time has the variables where it happened.
+            if self.failure.is_none() {
+                let (variables, call_stack) = (self.variables(), self.call_stack());
+                self.failure = Some(Failure { span: error.span, variables, call_stack });
+            }
+            self.pop_tasks(floor);
+            return Err(error);
+        };
+        // An error a `try` further out caught was recorded on the way.
+        self.failure = None;
+        debug!(code = error.code, "caught {}", error.message);
+        let Task { root, path, .. } = self.tasks[height].clone();
+        self.pop_tasks(height);
+        let (&(index, _), parent) = path.split_last().expect("try bodies are inside a try");
+        if let NodeKind::Try(_, Some(name), _) = &block(&root, parent)[index].kind {
+            self.assign(name, Value::string(error.message));
+        }
+        let path = parent.iter().copied().chain([(index, 1)]).collect();
+        self.tasks.push(Task { root, path, next: 0, kind: TaskKind::Block });
+        Ok(())
+    }
+
+    // Abandons tasks down to `height`, ending the calls among them.
+    fn pop_tasks(&mut self, height: usize) {
+        while self.tasks.len() > height {
+            if let Some(Task { kind: TaskKind::Call { elements, .. }, .. }) = self.tasks.pop() {
+                self.frames.pop();
+                write_back(elements);
             }
-            _ => panic!("Unknown expression"),
         }
     }
 
-    pub fn print_ast(&self, node: &AstNode, indent: usize) {
-        let indentation = "  ".repeat(indent);
-        match node {
-            AstNode::Program(statements) => {
-                println!("{}Program", indentation);
-                for statement in statements {
-                    self.print_ast(statement, indent + 1);
+    fn execute(&mut self, node: &AstNode) -> Result<(), Error> {
+        match &node.kind {
+            NodeKind::Procedure(_) => {}
+            NodeKind::Raise(message) => {
+                let message = self.eval_expr(message)?;
+                return Err(Error::runtime("E0216", message.to_string()));
+            }
+            NodeKind::Global(names) => {
+                let frame = self.frames.last_mut().expect("global is only parsed inside procedures");
+                for name in names {
+                    if frame.locals.contains_key(name) {
+                        return Err(Error::runtime(
+                            "E0215",
+                            format!("{} is already a local variable; declare it global before using it", name),
+                        ));
+                    }
+                    frame.globals.insert(name.clone());
                 }
             }
-            AstNode::Assignment(name, expr) => {
-                println!("{}Assignment: {}", indentation, name);
-                self.print_ast(expr, indent + 1);
+            NodeKind::Assignment(name, expr) => {
+                let value = self.eval_expr(expr)?;
+                trace!(name = name.as_str(), %value, "assign");
+                self.assign(name, value);
             }
-            AstNode::Output(expr) => {
-                println!("{}Output", indentation);
-                self.print_ast(expr, indent + 1);
+            NodeKind::Output(expr, newline) => {
+                let value = self.eval_expr(expr)?;
+                self.write(&format!("{}{}", value, if *newline { "\n" } else { "" }))?;
             }
-            AstNode::If(condition, true_branch, false_branch) => {
-                println!("{}If", indentation);
-                self.print_ast(condition, indent + 1);
-                println!("{}  True Branch", indentation);
-                for statement in true_branch {
-                    self.print_ast(statement, indent + 2);
+            NodeKind::Input(name, prompt) => {
+                if let Some(prompt) = prompt {
+                    let prompt = self.eval_expr(prompt)?;
+                    if self.show_prompts {
+                        self.write(&prompt.to_string())?;
+                    }
                 }
-                println!("{}  False Branch", indentation);
-                for statement in false_branch {
-                    self.print_ast(statement, indent + 2);
+                let value = self.read_line()?;
+                trace!(name = name.as_str(), %value, "input");
+                self.assign(name, value);
+            }
+            NodeKind::SetElement(name, indices, expr) => {
+                let indices = indices.iter().map(|index| self.eval_expr(index)).collect::<Result<Vec<_>, _>>()?;
+                let value = self.eval_expr(expr)?;
+                trace!(name = name.as_str(), %value, "set element");
+                let (last, path) = indices.split_last().unwrap();
+                let mut target = self.variable(name)?;
+                for (depth, index) in path.iter().enumerate() {
+                    target = element(&target, index, &array_name(name, depth), self.array_base)?;
                 }
+                let Value::Array(array) = &target else {
+                    return Err(Error::runtime("E0203", format!("Cannot index into {}", target.type_name())));
+                };
+                let index = array_index(&array.borrow(), last, &array_name(name, path.len()), self.array_base)?;
+                array.borrow_mut()[index] = value;
+            }
+            NodeKind::Call(..) | NodeKind::MethodCall(..) => {
+                self.eval_expr(node)?;
+            }
+            _ => return Err(Error::runtime("E0299", "Unknown AST node")),
+        }
+        Ok(())
+    }
+
+    // The variables visible where execution is: the globals, with the locals of
+    // the innermost running procedure in place of globals of the same name.
+    // Values are copies, except that arrays are shared as they are in programs.
+    pub fn variables(&self) -> BTreeMap<String, Value> {
+        let mut variables = snapshot(&self.globals);
+        if let Some(frame) = self.frames.last() {
+            variables.extend(snapshot(&frame.locals));
+        }
+        variables
+    }
+
+    // The procedure calls in progress, outermost first.
+    pub fn call_stack(&self) -> Vec<StackFrame> {
+        self.frames
+            .iter()
+            .map(|frame| StackFrame {
+                procedure: frame.procedure.clone(),
+                call_site: frame.call_site,
+                locals: snapshot(&frame.locals),
+            })
+            .collect()
+    }
+
+    // Counts for the program started last, with the time it took, or the time
+    // since it started if it was run with `step` or is still running.
+    // Variables kept from earlier programs count towards the peak, and arrays
+    // made on this thread by anything else while it ran count as its own.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            arrays: heap::allocated() - self.arrays_before,
+            time: self.finished.unwrap_or_else(|| self.started.elapsed()),
+            ..self.stats
+        }
+    }
+
+    // Where the last program stopped with an error nothing caught, if it did.
+    pub fn failure(&self) -> Option<&Failure> {
+        self.failure.as_ref()
+    }
+
+    // Problems the program started last ran into without stopping, such as
+    // reading a variable before setting it with `Undefined::Zero`.
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    // Everything the program has written, including prompts.
+    pub fn output_so_far(&self) -> &str {
+        &self.transcript
+    }
+
+    pub fn snapshot(&self) -> StateSnapshot {
+        let mut copier = Copier::default();
+        StateSnapshot {
+            globals: copier.slots(&self.globals),
+            frames: self.frames.iter().map(|frame| copier.frame(frame)).collect(),
+            procedures: self.procedures.clone(),
+            tasks: self.tasks.iter().map(|task| copier.task(task)).collect(),
+            returned: self.returned.as_ref().map(|value| copier.value(value)),
+            transcript: self.transcript.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &StateSnapshot) {
+        let mut copier = Copier::default();
+        self.globals = copier.slots(&snapshot.globals);
+        self.frames = snapshot.frames.iter().map(|frame| copier.frame(frame)).collect();
+        self.procedures = snapshot.procedures.clone();
+        self.tasks = snapshot.tasks.iter().map(|task| copier.task(task)).collect();
+        self.returned = snapshot.returned.as_ref().map(|value| copier.value(value));
+        self.transcript = snapshot.transcript.clone();
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        let written = self.transcript.len().saturating_sub(self.output_start);
+        if let Some(output) = self.limits.output.filter(|&output| written + text.len() > output) {
+            return Err(Error::limit("E0303", format!("The program wrote more than {} bytes", output)));
+        }
+        self.transcript.push_str(text);
+        write!(self.output, "{}", text)
+            .and_then(|_| self.output.flush())
+            .map_err(|e| Error::io(format!("Failed to write output: {}", e)))
+    }
+
+    // Counts `bytes` the program is making against `Limits::memory`. Freed
+    // strings and arrays are only noticed when the charges pass the limit,
+    // which measures what is still alive, after freeing cycles, and starts
+    // counting again from there.
+    fn charge(&mut self, bytes: usize) -> Result<(), Error> {
+        let Some(memory) = self.limits.memory else {
+            return Ok(());
+        };
+        self.charged = self.charged.saturating_add(bytes);
+        if self.charged > memory {
+            heap::collect_cycles();
+            let roots: Vec<Value> = self
+                .globals
+                .values()
+                .chain(self.frames.iter().flat_map(|frame| frame.locals.values()))
+                .filter_map(|slot| slot.try_borrow().ok().map(|value| value.clone()))
+                .chain(self.returned.clone())
+                .collect();
+            self.charged = heap::footprint(&roots).saturating_add(bytes);
+            if self.charged > memory {
+                return Err(Error::limit(
+                    "E0304",
+                    format!("The program used more than {} bytes for strings and arrays", memory),
+                ));
             }
-            AstNode::Loop(condition, body) => {
-                println!("{}Loop", indentation);
-                self.print_ast(condition, indent + 1);
-                for statement in body {
-                    self.print_ast(statement, indent + 1);
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<Value, Error> {
+        let mut line = String::new();
+        let read = self
+            .input
+            .read_line(&mut line)
+            .map_err(|e| Error::io(format!("Failed to read input: {}", e)))?;
+        if read == 0 {
+            return Err(Error::runtime("E0206", "No more input to read"));
+        }
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        Ok(Value::from_input(line))
+    }
+
+    fn eval_condition(&mut self, node: &AstNode) -> Result<bool, Error> {
+        let value = self.eval_expr(node)?;
+        let number = value.as_f64().ok_or_else(|| {
+            Error::runtime("E0203", format!("Expected a number, got {}", value.type_name())).or_span(node.span)
+        })?;
+        Ok(number != 0.0)
+    }
+
+    fn eval_expr(&mut self, node: &AstNode) -> Result<Value, Error> {
+        let value = self.evaluate(node).map_err(|e| e.or_span(node.span))?;
+        trace!(line = node.span.line, column = node.span.column, %value, "evaluated {}", node.kind.name());
+        Ok(value)
+    }
+
+    fn evaluate(&mut self, node: &AstNode) -> Result<Value, Error> {
+        match &node.kind {
+            NodeKind::Number(value) => Ok(Value::Number(*value)),
+            NodeKind::Float(value) => Ok(Value::Float(*value)),
+            NodeKind::String(value) => Ok(Value::string(value.as_str())),
+            NodeKind::Null => Ok(Value::Null),
+            NodeKind::Identifier(_) if self.unset(node) => Ok(self.start_value(node, &Value::Null)),
+            NodeKind::Identifier(name) => self.variable(name),
+            NodeKind::BinOp(left, op, right) => {
+                let (left_val, right_val) = if self.unset(left) && !self.unset(right) {
+                    let right_val = self.eval_expr(right)?;
+                    (self.start_value(left, &right_val), right_val)
+                } else {
+                    let left_val = self.eval_expr(left)?;
+                    let right_val = match self.unset(right) {
+                        true => self.start_value(right, &left_val),
+                        false => self.eval_expr(right)?,
+                    };
+                    (left_val, right_val)
+                };
+                match op {
+                    BinaryOp::Eq => Ok(Value::Number((left_val == right_val) as i64)),
+                    BinaryOp::NotEqual => Ok(Value::Number((left_val != right_val) as i64)),
+                    BinaryOp::Plus if matches!(left_val, Value::String(_)) || matches!(right_val, Value::String(_)) => {
+                        let text = format!("{}{}", left_val, right_val);
+                        self.charge(text.len())?;
+                        Ok(Value::string(text))
+                    }
+                    _ if op.is_comparison() => {
+                        let ordering = left_val
+                            .compare(&right_val)
+                            .ok_or_else(|| type_mismatch(op, &left_val, &right_val))?;
+                        let result = match op {
+                            BinaryOp::GreaterThan => ordering.is_gt(),
+                            BinaryOp::GreaterThanOrEqual => ordering.is_ge(),
+                            BinaryOp::LessThan => ordering.is_lt(),
+                            _ => ordering.is_le(),
+                        };
+                        Ok(Value::Number(result as i64))
+                    }
+                    _ => match (&left_val, &right_val) {
+                        // A negative power of an integer is a fraction, and `/`
+                        // gives a real number even when it divides exactly, as
+                        // in IB pseudocode; `div` gives an integer.
+                        (Value::Number(l), Value::Number(r))
+                            if *op == BinaryOp::Slash || (*op == BinaryOp::Caret && *r < 0) =>
+                        {
+                            float_arithmetic(op, *l as f64, *r as f64, self.division)
+                        }
+                        (Value::Number(l), Value::Number(r)) => {
+                            arithmetic(op, *l, *r, self.division).map(Value::Number)
+                        }
+                        _ => match (left_val.as_f64(), right_val.as_f64()) {
+                            (Some(l), Some(r)) => float_arithmetic(op, l, r, self.division),
+                            _ => Err(type_mismatch(op, &left_val, &right_val)),
+                        },
+                    },
+                }
+            }
+            NodeKind::Conditional(condition, then, otherwise) => {
+                let cond_value = self.eval_condition(condition)?;
+                self.eval_expr(if cond_value { then } else { otherwise })
+            }
+            NodeKind::Array(items) => {
+                let items = items.iter().map(|item| self.eval_expr(item)).collect::<Result<Vec<_>, _>>()?;
+                self.charge(items.len() * mem::size_of::<Value>())?;
+                Ok(Value::array(items))
+            }
+            NodeKind::NewArray(sizes) => {
+                let sizes = sizes.iter().map(|size| self.eval_expr(size)).collect::<Result<Vec<_>, _>>()?;
+                let lengths = array_lengths(&sizes)?;
+                // Every level of rows down to the elements, before any is made.
+                let (mut rows, mut values) = (1, 0);
+                for length in &lengths {
+                    rows *= length;
+                    values += rows;
                 }
+                self.charge(values * mem::size_of::<Value>())?;
+                Ok(filled(&lengths))
             }
-            AstNode::BinOp(left, op, right) => {
-                println!("{}BinOp: {:?}", indentation, op);
-                self.print_ast(left, indent + 1);
-                self.print_ast(right, indent + 1);
+            NodeKind::Index(target, index) => {
+                let array = self.eval_expr(target)?;
+                let index = self.eval_expr(index)?;
+                element(&array, &index, &describe(target), self.array_base)
             }
-            AstNode::Number(value) => {
-                println!("{}Number: {}", indentation, value);
+            NodeKind::MethodCall(receiver, method, args) => {
+                let receiver = self.eval_expr(receiver)?;
+                let args = args.iter().map(|arg| self.eval_expr(arg)).collect::<Result<Vec<_>, _>>()?;
+                let result = builtins::method(&receiver, method, args)?;
+                // `append` grows the array it is called on by one element.
+                let grown = if method == "append" { mem::size_of::<Value>() } else { 0 };
+                self.charge(result.size() + grown)?;
+                Ok(self.position(method, matches!(receiver, Value::Array(_)), result))
+            }
+            NodeKind::Call(name, args) if name == "isDefined" && !self.procedures.contains_key(name) => {
+                self.is_defined(args)
+            }
+            NodeKind::Call(name, args) if self.procedures.contains_key(name) => {
+                let procedure = self.procedures[name].clone();
+                self.call(procedure, args, node.span)
+            }
+            NodeKind::Call(name, args) => {
+                let args = args.iter().map(|arg| self.eval_expr(arg)).collect::<Result<Vec<_>, _>>()?;
+                if terminal::BUILTINS.contains(&name.as_str()) {
+                    if !self.terminal {
+                        return Err(Error::runtime(
+                            "E0210",
+                            format!("{} needs terminal access; run with --terminal to allow it", name),
+                        ));
+                    }
+                    self.write(&terminal::sequence(name, &args)?)?;
+                    // Terminal builtins are run for their effect and have no result.
+                    return Ok(Value::Null);
+                }
+                let on_array = matches!(args.first(), Some(Value::Array(_)));
+                let result = builtins::call(name, args)?;
+                self.charge(result.size())?;
+                Ok(self.position(name, on_array, result))
+            }
+            _ => Err(Error::runtime("E0299", "Unknown expression")),
+        }
+    }
+
+    // Builtins count positions in an array from 0, so the ones they give are
+    // moved to the array base. -1 still means nothing was found.
+    fn position(&self, name: &str, on_array: bool, result: Value) -> Value {
+        match (name, result) {
+            ("indexOf" | "binarySearch", Value::Number(index)) if on_array && index >= 0 => {
+                Value::Number(index + self.array_base)
             }
-            AstNode::String(value) => {
-                println!("{}String: {}", indentation, value);
+            (_, result) => result,
+        }
+    }
+
+    // Runs a user-defined procedure to the end and gives its result.
+    fn call(&mut self, procedure: Arc<Procedure>, args: &[AstNode], call_site: Span) -> Result<Value, Error> {
+        let floor = self.tasks.len();
+        self.enter(procedure, args, call_site)?;
+        self.run_above(floor)?;
+        Ok(self.returned.take().expect("a finished call has a result"))
+    }
+
+    fn run_above(&mut self, floor: usize) -> Result<(), Error> {
+        while let StepResult::Stepped(_) = self.step_above(floor)? {}
+        Ok(())
+    }
+
+    // Starts running a user-defined procedure. A `ref` parameter given a
+    // variable shares that variable's slot. Given an array element, it gets a
+    // slot of its own whose final value is written back into the array after
+    // the call.
+    fn enter(&mut self, procedure: Arc<Procedure>, args: &[AstNode], call_site: Span) -> Result<(), Error> {
+        let total = procedure.params.len();
+        let required = procedure.params.iter().filter(|param| param.default.is_none()).count();
+        if args.len() < required || args.len() > total {
+            let expected = if required == total {
+                total.to_string()
+            } else {
+                format!("{} to {}", required, total)
+            };
+            return Err(Error::runtime(
+                "E0208",
+                format!(
+                    "{} takes {} argument{}, got {}",
+                    procedure.name,
+                    expected,
+                    if total == 1 { "" } else { "s" },
+                    args.len()
+                ),
+            ));
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(Error::runtime(
+                "E0213",
+                format!("Too many nested calls (more than {}); check for endless recursion", MAX_CALL_DEPTH),
+            ));
+        }
+
+        let mut frame = Frame { procedure: procedure.name.clone(), call_site, ..Frame::default() };
+        let mut elements = vec![];
+        for (param, arg) in procedure.params.iter().zip(args) {
+            let slot = match &arg.kind {
+                NodeKind::Identifier(name) if param.by_ref => self
+                    .slot(name)
+                    .cloned()
+                    .ok_or_else(|| self.undefined_variable(name).or_span(arg.span))?,
+                NodeKind::Index(target, index) if param.by_ref => {
+                    let array = self.eval_expr(target)?;
+                    let index = self.eval_expr(index)?;
+                    let Value::Array(array) = array else {
+                        return Err(Error::runtime("E0203", format!("Cannot index into {}", array.type_name()))
+                            .or_span(target.span));
+                    };
+                    let index = array_index(&array.borrow(), &index, &describe(target), self.array_base)
+                        .map_err(|e| e.or_span(arg.span))?;
+                    let slot = Rc::new(RefCell::new(array.borrow()[index].clone()));
+                    elements.push((array, index, slot.clone()));
+                    slot
+                }
+                _ if param.by_ref => {
+                    return Err(Error::runtime(
+                        "E0204",
+                        format!("ref parameter {} needs a variable or array element", param.name),
+                    )
+                    .or_span(arg.span));
+                }
+                _ => Rc::new(RefCell::new(self.eval_expr(arg)?)),
+            };
+            frame.locals.insert(param.name.clone(), slot);
+        }
+
+        trace!(name = procedure.name.as_str(), depth = self.frames.len() + 1, "call");
+        self.frames.push(frame);
+        self.stats.max_call_depth = self.stats.max_call_depth.max(self.frames.len());
+        // Defaults are evaluated inside the call, so they can use the
+        // parameters before them.
+        let defaults = self.defaults(&procedure.params[args.len()..]);
+        if let Err(e) = defaults {
+            self.frames.pop();
+            write_back(elements);
+            return Err(e);
+        }
+        self.count_variables();
+        let kind = TaskKind::Call { elements, call_site };
+        self.tasks.push(Task { root: Root::Procedure(procedure), path: Rc::new([]), next: 0, kind });
+        Ok(())
+    }
+
+    // Ends a call with the value it returned, if any.
+    fn leave(&mut self, task: Task, value: Option<Value>) -> Result<(), Error> {
+        let (Root::Procedure(procedure), TaskKind::Call { elements, call_site }) = (task.root, task.kind) else {
+            unreachable!("only calls are left");
+        };
+        self.frames.pop();
+        write_back(elements);
+        self.returned = match value {
+            Some(value) => Some(value),
+            None if procedure.kind == ProcedureKind::Function => {
+                return Err(Error::runtime(
+                    "E0214",
+                    format!("Function {} ended without returning a value", procedure.name),
+                )
+                .or_span(call_site));
             }
-            AstNode::Identifier(name) => {
-                println!("{}Identifier: {}", indentation, name);
+            // A sub has no result.
+            None => Some(Value::Null),
+        };
+        Ok(())
+    }
+
+    fn defaults(&mut self, params: &[Param]) -> Result<(), Error> {
+        for param in params {
+            let default = param.default.as_ref().expect("parameters after the arguments have defaults");
+            let value = self.eval_expr(default)?;
+            let frame = self.frames.last_mut().expect("defaults are evaluated inside a call");
+            frame.locals.insert(param.name.clone(), Rc::new(RefCell::new(value)));
+        }
+        Ok(())
+    }
+
+    // `isDefined(X)` is 0 when X is null, an undefined variable or an array
+    // element past the end, and 1 otherwise.
+    fn is_defined(&mut self, args: &[AstNode]) -> Result<Value, Error> {
+        let [arg] = args else {
+            return Err(Error::runtime(
+                "E0208",
+                format!("isDefined expects 1 argument(s), got {}", args.len()),
+            ));
+        };
+        if self.unset(arg) {
+            return Ok(Value::Number(0));
+        }
+        let defined = match self.eval_expr(arg) {
+            Ok(value) => value != Value::Null,
+            Err(e) if matches!(e.code, "E0200" | "E0211") => false,
+            Err(e) => return Err(e),
+        };
+        Ok(Value::Number(defined as i64))
+    }
+
+    // Locals of the running procedure hide globals of the same name. Names
+    // not assigned in the procedure are read from the globals.
+    fn slot(&self, name: &str) -> Option<&Slot> {
+        self.frames
+            .last()
+            .and_then(|frame| frame.locals.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    // Assigning inside a procedure creates a local unless the name was
+    // declared `global`.
+    fn assign(&mut self, name: &str, value: Value) {
+        let variables = match self.frames.last_mut() {
+            Some(frame) if !frame.globals.contains(name) => &mut frame.locals,
+            _ => &mut self.globals,
+        };
+        match variables.get(name) {
+            Some(slot) => *slot.borrow_mut() = value,
+            None => {
+                variables.insert(name.to_string(), Rc::new(RefCell::new(value)));
+                self.count_variables();
             }
         }
     }
+
+    // Updates the peak number of variables, which only grows when a variable
+    // is made or a call starts.
+    fn count_variables(&mut self) {
+        let count = self.globals.len() + self.frames.iter().map(|frame| frame.locals.len()).sum::<usize>();
+        self.stats.peak_variables = self.stats.peak_variables.max(count);
+    }
+
+    fn variable(&self, name: &str) -> Result<Value, Error> {
+        self.slot(name).map(|slot| slot.borrow().clone()).ok_or_else(|| self.undefined_variable(name))
+    }
+
+    // Suggests a variable that exists with a similar name, as the name read
+    // is often a typo or differs only in case.
+    fn undefined_variable(&self, name: &str) -> Error {
+        let mut names: Vec<&String> = self.globals.keys().collect();
+        if let Some(frame) = self.frames.last() {
+            names.extend(frame.locals.keys());
+        }
+        let similar = names
+            .into_iter()
+            .map(|other| (edit_distance(&name.to_lowercase(), &other.to_lowercase()), other))
+            .filter(|&(distance, _)| distance <= name.chars().count() / 3)
+            .min();
+        let message = match similar {
+            Some((_, other)) => format!("Undefined variable: {}; did you mean {}?", name, other),
+            None => format!("Undefined variable: {}", name),
+        };
+        Error::runtime("E0200", message)
+    }
+
+    // A variable that has not been set, when those read as a starting value.
+    fn unset(&self, node: &AstNode) -> bool {
+        let unset = matches!(&node.kind, NodeKind::Identifier(name) if self.slot(name).is_none());
+        unset && self.undefined == Undefined::Zero
+    }
+
+    // What the unset variable `node` reads as beside `other`: "" next to a
+    // string and 0 otherwise.
+    fn start_value(&mut self, node: &AstNode, other: &Value) -> Value {
+        let NodeKind::Identifier(name) = &node.kind else {
+            unreachable!("only variables are unset");
+        };
+        let (value, shown) = match other {
+            Value::String(_) => (Value::string(""), "\"\""),
+            _ => (Value::Number(0), "0"),
+        };
+        if self.unset_reads.insert(name.clone()) {
+            let message = format!("{} is read before it is set, so it starts as {}", name, shown);
+            self.warnings.push(Error::lint("W0021", message, node.span));
+        }
+        value
+    }
+}
+
+// Deep-copies interpreter state so that nothing in the copy is shared with the
+// original. Slots and arrays shared within the original, such as a `ref`
+// parameter and its variable or two names for one array, stay shared within
+// the copy.
+#[derive(Default)]
+struct Copier {
+    slots: HashMap<*const RefCell<Value>, Slot>,
+    arrays: HashMap<*const RefCell<Vec<Value>>, Array>,
+}
+
+impl Copier {
+    fn frame(&mut self, frame: &Frame) -> Frame {
+        Frame {
+            procedure: frame.procedure.clone(),
+            call_site: frame.call_site,
+            locals: self.slots(&frame.locals),
+            globals: frame.globals.clone(),
+        }
+    }
+
+    fn task(&mut self, task: &Task) -> Task {
+        let kind = match &task.kind {
+            TaskKind::Call { elements, call_site } => TaskKind::Call {
+                elements: elements
+                    .iter()
+                    .map(|(array, index, slot)| (self.array(array), *index, self.slot(slot)))
+                    .collect(),
+                call_site: *call_site,
+            },
+            kind => kind.clone(),
+        };
+        Task { kind, ..task.clone() }
+    }
+
+    fn slots(&mut self, slots: &HashMap<String, Slot>) -> HashMap<String, Slot> {
+        slots.iter().map(|(name, slot)| (name.clone(), self.slot(slot))).collect()
+    }
+
+    fn slot(&mut self, slot: &Slot) -> Slot {
+        if let Some(copy) = self.slots.get(&Rc::as_ptr(slot)) {
+            return copy.clone();
+        }
+        let copy = Rc::new(RefCell::new(self.value(&slot.borrow())));
+        self.slots.insert(Rc::as_ptr(slot), copy.clone());
+        copy
+    }
+
+    fn value(&mut self, value: &Value) -> Value {
+        match value {
+            Value::Array(array) => Value::Array(self.array(array)),
+            _ => value.clone(),
+        }
+    }
+
+    fn array(&mut self, array: &Array) -> Array {
+        if let Some(copy) = self.arrays.get(&Rc::as_ptr(array)) {
+            return copy.clone();
+        }
+        // Registered before the items are copied, so an array that contains
+        // itself is copied once.
+        let copy = heap::allocate(vec![]);
+        self.arrays.insert(Rc::as_ptr(array), copy.clone());
+        let items = array.borrow().iter().map(|item| self.value(item)).collect();
+        *copy.borrow_mut() = items;
+        copy
+    }
+}
+
+// Copies the final values of `ref` arguments back into their arrays.
+fn write_back(elements: Vec<(Array, usize, Slot)>) {
+    for (array, index, slot) in elements {
+        if let Some(element) = array.borrow_mut().get_mut(index) {
+            *element = slot.borrow().clone();
+        }
+    }
+}
+
+// The statements of the block that `path` leads to from `root`.
+fn block<'a>(root: &'a Root, path: &[(usize, usize)]) -> &'a [AstNode] {
+    let mut statements = match root {
+        Root::Program(program) => match &program.kind {
+            NodeKind::Program(statements) => statements.as_slice(),
+            _ => std::slice::from_ref(program.as_ref()),
+        },
+        Root::Procedure(procedure) => procedure.body.as_slice(),
+    };
+    for &(index, branch) in path {
+        statements = match (&statements[index].kind, branch) {
+            (NodeKind::If(_, true_branch, _), 0) => true_branch,
+            (NodeKind::If(_, _, false_branch), _) => false_branch,
+            (NodeKind::Loop(_, body), _) => body,
+            (NodeKind::Case(_, branches, default), _) => match branches.get(branch) {
+                Some(branch) => &branch.body,
+                None => default.as_deref().unwrap_or_default(),
+            },
+            (NodeKind::Try(body, _, _), 0) => body,
+            (NodeKind::Try(_, _, handler), _) => handler,
+            _ => unreachable!("paths only pass through compound statements"),
+        };
+    }
+    statements
+}
+
+// The value of an expression that reads no variables and calls nothing, such
+// as `1 = 1`, worked out as running it would. None when it does either, or
+// when working it out fails.
+pub fn constant(expression: &AstNode) -> Option<Value> {
+    let mut fixed = true;
+    expression.walk(&mut |node| {
+        let reads = matches!(node.kind, NodeKind::Identifier(_) | NodeKind::Call(..) | NodeKind::NewArray(_));
+        fixed &= !reads;
+    });
+    if !fixed {
+        return None;
+    }
+    Interpreter::with_output(Box::new(io::sink())).evaluate(expression).ok()
+}
+
+// A place in the source of a program, counted as in `Span`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+// The innermost expression at a position, and its value.
+#[derive(Debug)]
+pub struct Hover {
+    pub span: Span,
+    pub value: Result<Value, Error>,
+}
+
+// How long a hover may take, so that a call in the expression that never
+// returns does not hang the editor.
+const HOVER_STEPS: u64 = 100_000;
+const HOVER_TIME: Duration = Duration::from_millis(200);
+
+// What the expression at `position` in `program` is now, with the variables
+// of `state`, for showing under the cursor while a run is paused. It is worked
+// out on a copy of the state, so calls in it change nothing and write nowhere.
+// None when there is no expression at `position`.
+pub fn evaluate_at(program: &AstNode, position: Position, state: &Interpreter) -> Option<Hover> {
+    let at = (position.line, position.column);
+    let mut found: Option<&AstNode> = None;
+    program.walk(&mut |node| {
+        let span = node.span;
+        let expression = matches!(
+            node.kind,
+            NodeKind::Number(_)
+                | NodeKind::Float(_)
+                | NodeKind::String(_)
+                | NodeKind::Identifier(_)
+                | NodeKind::Null
+                | NodeKind::BinOp(..)
+                | NodeKind::Conditional(..)
+                | NodeKind::Array(_)
+                | NodeKind::NewArray(_)
+                | NodeKind::Index(..)
+                | NodeKind::MethodCall(..)
+                | NodeKind::Call(..)
+        );
+        let length = |span: Span| span.end_offset - span.offset;
+        // Parts of an interpolated string share its span, so the first node
+        // with a span, the whole string, is kept.
+        if expression
+            && span.file == position.file
+            && (span.line, span.column) <= at
+            && at < (span.end_line, span.end_column)
+            && found.is_none_or(|outer| length(span) < length(outer.span))
+        {
+            found = Some(node);
+        }
+    });
+    let node = found?;
+
+    let mut scratch = Interpreter::with_output(Box::new(io::sink()));
+    scratch.restore(&state.snapshot());
+    scratch.set_input(Box::new(io::empty()), false);
+    scratch.array_base = state.array_base;
+    scratch.undefined = state.undefined;
+    scratch.division = state.division;
+    scratch.limits = Limits { steps: Some(HOVER_STEPS), output: None, time: Some(HOVER_TIME), memory: None };
+    scratch.deadline = Some(Instant::now() + HOVER_TIME);
+    Some(Hover { span: node.span, value: scratch.eval_expr(node) })
+}
+
+// Letters to add, remove or change to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + (x != *y) as usize).min(above + 1).min(row[j] + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+fn snapshot(slots: &HashMap<String, Slot>) -> BTreeMap<String, Value> {
+    slots.iter().map(|(name, slot)| (name.clone(), slot.borrow().clone())).collect()
+}
+
+const MAX_ARRAY_ELEMENTS: i64 = 10_000_000;
+
+// The length of each level of a `new Array` of the given sizes, which
+// `filled` makes with every element set to zero.
+fn array_lengths(sizes: &[Value]) -> Result<Vec<usize>, Error> {
+    let mut total: i64 = 1;
+    let mut lengths = vec![];
+    for size in sizes {
+        let length = match size {
+            Value::Number(n) if *n >= 0 => *n,
+            Value::Number(n) => return Err(Error::runtime("E0211", format!("Array size cannot be negative, got {}", n))),
+            other => return Err(Error::runtime("E0203", format!("Expected a number, got {}", other.type_name()))),
+        };
+        total = total.saturating_mul(length);
+        if total > MAX_ARRAY_ELEMENTS {
+            return Err(Error::runtime(
+                "E0211",
+                format!("Arrays can hold at most {} elements", MAX_ARRAY_ELEMENTS),
+            ));
+        }
+        lengths.push(length as usize);
+    }
+    Ok(lengths)
+}
+
+// Each row gets its own array, so rows do not alias one another.
+fn filled(lengths: &[usize]) -> Value {
+    match lengths {
+        [] => Value::Number(0),
+        [length, rest @ ..] => Value::array((0..*length).map(|_| filled(rest)).collect()),
+    }
+}
+
+// `name` is what to call the array in an error, as `describe` gives it, and
+// `base` is the index of its first element.
+fn array_index(array: &[Value], index: &Value, name: &str, base: i64) -> Result<usize, Error> {
+    let index = match index {
+        Value::Number(index) => index,
+        Value::Float(_) => {
+            let message = format!("Array index must be an integer, got {}; div divides giving an integer", index);
+            return Err(Error::runtime("E0203", message));
+        }
+        other => {
+            return Err(Error::runtime("E0203", format!("Array index must be a number, got {}", other.type_name())))
+        }
+    };
+    index.checked_sub(base).and_then(|i| usize::try_from(i).ok()).filter(|&i| i < array.len()).ok_or_else(|| {
+        let first = if base == 0 { String::new() } else { format!(", first index {}", base) };
+        Error::runtime(
+            "E0211",
+            format!("Index {} is out of bounds for {} (length {}{})", index, name, array.len(), first),
+        )
+    })
+}
+
+fn element(value: &Value, index: &Value, name: &str, base: i64) -> Result<Value, Error> {
+    match value {
+        Value::Array(items) => {
+            let items = items.borrow();
+            Ok(items[array_index(&items, index, name, base)?].clone())
+        }
+        other => Err(Error::runtime("E0203", format!("Cannot index into {}", other.type_name()))),
+    }
+}
+
+// What to call the array `node` gives in an error: its variable, or the
+// variable it is found inside.
+fn describe(node: &AstNode) -> String {
+    match &node.kind {
+        NodeKind::Identifier(name) => name.clone(),
+        NodeKind::Index(target, _) => match describe(target) {
+            name if name.starts_with("an array") => name,
+            name => format!("an array in {}", name),
+        },
+        _ => "an array".to_string(),
+    }
+}
+
+// The same for an array `depth` indices into the variable `name`.
+fn array_name(name: &str, depth: usize) -> String {
+    if depth == 0 {
+        name.to_string()
+    } else {
+        format!("an array in {}", name)
+    }
+}
+
+fn type_mismatch(op: &BinaryOp, left: &Value, right: &Value) -> Error {
+    Error::runtime(
+        "E0203",
+        format!("Cannot apply {:?} to {} and {}", op, left.type_name(), right.type_name()),
+    )
+}
+
+fn arithmetic(op: &BinaryOp, left: i64, right: i64, division: Division) -> Result<i64, Error> {
+    let overflow = || Error::runtime("E0202", "Integer overflow");
+    match op {
+        BinaryOp::Plus => left.checked_add(right).ok_or_else(overflow),
+        BinaryOp::Minus => left.checked_sub(right).ok_or_else(overflow),
+        BinaryOp::Star => left.checked_mul(right).ok_or_else(overflow),
+        BinaryOp::Div | BinaryOp::Mod if right == 0 => Err(Error::runtime("E0201", "Division by zero")),
+        BinaryOp::Div | BinaryOp::Mod => {
+            let (quotient, remainder) = divide(left, right, division).ok_or_else(overflow)?;
+            Ok(if *op == BinaryOp::Mod { remainder } else { quotient })
+        }
+        BinaryOp::Caret => match u32::try_from(right) {
+            Ok(exponent) => left.checked_pow(exponent).ok_or_else(overflow),
+            // Only 0, 1 and -1 survive a huge exponent.
+            Err(_) => match left {
+                0 | 1 => Ok(left),
+                -1 => Ok(if right % 2 == 0 { 1 } else { -1 }),
+                _ => Err(overflow()),
+            },
+        },
+        BinaryOp::And => Ok(((left != 0) && (right != 0)) as i64),
+        BinaryOp::Or => Ok(((left != 0) || (right != 0)) as i64),
+        BinaryOp::BitAnd => Ok(left & right),
+        BinaryOp::BitOr => Ok(left | right),
+        BinaryOp::BitXor => Ok(left ^ right),
+        BinaryOp::ShiftLeft | BinaryOp::ShiftRight if !(0..64).contains(&right) => Err(Error::runtime(
+            "E0209",
+            format!("Cannot shift by {} places; shift by 0 to 63", right),
+        )),
+        // Shifting left doubles, so bits shifted out of the sign overflow.
+        BinaryOp::ShiftLeft => {
+            let shifted = left << right;
+            if shifted >> right == left {
+                Ok(shifted)
+            } else {
+                Err(overflow())
+            }
+        }
+        // Rounds down, as `div` by a power of 2 would for positive numbers.
+        BinaryOp::ShiftRight => Ok(left >> right),
+        _ => Err(Error::runtime("E0205", format!("Unknown binary operator: {:?}", op))),
+    }
+}
+
+// The quotient and remainder of integer division, rounded as `division` says.
+// `None` when the quotient overflows, as `i64::MIN div -1` does.
+fn divide(left: i64, right: i64, division: Division) -> Option<(i64, i64)> {
+    let (quotient, remainder) = (left.checked_div(right)?, left.checked_rem(right)?);
+    if division == Division::Floor && remainder != 0 && (remainder < 0) != (right < 0) {
+        return Some((quotient - 1, remainder + right));
+    }
+    Some((quotient, remainder))
+}
+
+// Arithmetic on real numbers, used when either operand is a float. `and` and
+// `or` stay integer-only since they combine comparison results. `div` and
+// `mod` round as they do for integers.
+fn float_arithmetic(op: &BinaryOp, left: f64, right: f64, division: Division) -> Result<Value, Error> {
+    let result = match op {
+        BinaryOp::Plus => left + right,
+        BinaryOp::Minus => left - right,
+        BinaryOp::Star => left * right,
+        BinaryOp::Slash | BinaryOp::Div | BinaryOp::Mod if right == 0.0 => {
+            return Err(Error::runtime("E0201", "Division by zero"))
+        }
+        BinaryOp::Slash => left / right,
+        BinaryOp::Div if division == Division::Floor => (left / right).floor(),
+        BinaryOp::Div => (left / right).trunc(),
+        BinaryOp::Mod => {
+            let remainder = left % right;
+            if division == Division::Floor && remainder != 0.0 && (remainder < 0.0) != (right < 0.0) {
+                remainder + right
+            } else {
+                remainder
+            }
+        }
+        BinaryOp::Caret if left == 0.0 && right < 0.0 => return Err(Error::runtime("E0201", "Division by zero")),
+        BinaryOp::Caret if left < 0.0 && right.fract() != 0.0 => {
+            return Err(Error::runtime(
+                "E0209",
+                format!("Cannot raise the negative number {} to the fractional power {}", left, right),
+            ))
+        }
+        BinaryOp::Caret => left.powf(right),
+        BinaryOp::And | BinaryOp::Or => {
+            return Err(Error::runtime("E0203", format!("Cannot apply {:?} to real numbers", op)))
+        }
+        _ if op.is_bitwise() => {
+            return Err(Error::runtime("E0203", format!("Cannot apply {:?} to real numbers; it needs integers", op)))
+        }
+        _ => return Err(Error::runtime("E0205", format!("Unknown binary operator: {:?}", op))),
+    };
+    if result.is_finite() {
+        Ok(Value::Float(result))
+    } else {
+        Err(Error::runtime("E0202", "Number too large"))
+    }
 }
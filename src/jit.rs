@@ -0,0 +1,406 @@
+use crate::ast::{AstNode, BinaryOp, NodeKind};
+use crate::error::Error;
+use crate::interpreter::Division;
+use crate::visit::{self, Visit};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Linkage, Module};
+use std::collections::HashMap;
+use std::io::Write;
+
+pub struct JitContext {
+    error: Option<i64>,
+}
+
+// Neither helper ends the line; the compiled code outputs "\n" itself unless
+// the statement is `output ... inline`.
+extern "C" fn jit_output_number(value: i64) {
+    let mut stdout = std::io::stdout();
+    let _ = write!(stdout, "{}", value).and_then(|_| stdout.flush());
+}
+
+extern "C" fn jit_output_string(ptr: *const u8, len: i64) {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, len as usize) };
+    let mut stdout = std::io::stdout();
+    let _ = write!(stdout, "{}", String::from_utf8_lossy(bytes)).and_then(|_| stdout.flush());
+}
+
+extern "C" fn jit_fail(ctx: *mut JitContext, code: i64) {
+    unsafe { (*ctx).error = Some(code) };
+}
+
+pub struct CompiledProgram {
+    // Keeps the generated code and the string literals it points into alive.
+    _module: JITModule,
+    _strings: Vec<String>,
+    // Indexed by the code passed to `jit_fail`.
+    failures: Vec<Error>,
+    entry: extern "C" fn(*mut JitContext),
+}
+
+impl CompiledProgram {
+    pub fn run(&self) -> Result<(), Error> {
+        let mut ctx = JitContext { error: None };
+        (self.entry)(&mut ctx);
+        match ctx.error {
+            None => Ok(()),
+            Some(code) => Err(self.failures[code as usize].clone()),
+        }
+    }
+}
+
+// Returns None when the program uses something outside the numeric subset,
+// in which case the caller should fall back to the interpreter. `div` and `mod`
+// round as `division` says, as they do in the interpreter.
+pub fn compile(program: &AstNode, division: Division) -> Option<CompiledProgram> {
+    if !is_supported(program) {
+        return None;
+    }
+
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").ok()?;
+    flag_builder.set("is_pic", "false").ok()?;
+    flag_builder.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flag_builder))
+        .ok()?;
+
+    let mut jit_builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    jit_builder.symbol("jit_output_number", jit_output_number as *const u8);
+    jit_builder.symbol("jit_output_string", jit_output_string as *const u8);
+    jit_builder.symbol("jit_fail", jit_fail as *const u8);
+    let mut module = JITModule::new(jit_builder);
+    let pointer = module.target_config().pointer_type();
+
+    let mut output_number_sig = module.make_signature();
+    output_number_sig.params.push(AbiParam::new(types::I64));
+    let output_number = module
+        .declare_function("jit_output_number", Linkage::Import, &output_number_sig)
+        .ok()?;
+
+    let mut output_string_sig = module.make_signature();
+    output_string_sig.params.push(AbiParam::new(pointer));
+    output_string_sig.params.push(AbiParam::new(types::I64));
+    let output_string = module
+        .declare_function("jit_output_string", Linkage::Import, &output_string_sig)
+        .ok()?;
+
+    let mut fail_sig = module.make_signature();
+    fail_sig.params.push(AbiParam::new(pointer));
+    fail_sig.params.push(AbiParam::new(types::I64));
+    let fail = module.declare_function("jit_fail", Linkage::Import, &fail_sig).ok()?;
+
+    let mut ctx = module.make_context();
+    ctx.func.signature.params.push(AbiParam::new(pointer));
+    let main = module
+        .declare_function("main", Linkage::Export, &ctx.func.signature)
+        .ok()?;
+
+    let mut variables = Variables::default();
+    variables.visit_node(program);
+    let names = variables.0;
+
+    let mut strings = vec![];
+    let mut failures = vec![];
+    let mut function_ctx = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut function_ctx);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+
+        let mut variables = HashMap::new();
+        let zero = builder.ins().iconst(types::I64, 0);
+        for (index, name) in names.iter().enumerate() {
+            let value = Variable::from_u32(index as u32 * 2);
+            let defined = Variable::from_u32(index as u32 * 2 + 1);
+            builder.declare_var(value, types::I64);
+            builder.declare_var(defined, types::I64);
+            builder.def_var(value, zero);
+            builder.def_var(defined, zero);
+            variables.insert(name.clone(), (value, defined));
+        }
+
+        let mut translator = Translator {
+            ctx: builder.block_params(entry)[0],
+            output_number: module.declare_func_in_func(output_number, builder.func),
+            output_string: module.declare_func_in_func(output_string, builder.func),
+            fail: module.declare_func_in_func(fail, builder.func),
+            builder,
+            variables,
+            strings: &mut strings,
+            failures: &mut failures,
+            pointer,
+            division,
+        };
+        translator.statement(program);
+        translator.builder.ins().return_(&[]);
+        translator.builder.finalize();
+    }
+
+    module.define_function(main, &mut ctx).ok()?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().ok()?;
+
+    let code = module.get_finalized_function(main);
+    let entry = unsafe { std::mem::transmute::<*const u8, extern "C" fn(*mut JitContext)>(code) };
+
+    Some(CompiledProgram {
+        _module: module,
+        _strings: strings,
+        failures,
+        entry,
+    })
+}
+
+fn is_supported(node: &AstNode) -> bool {
+    match &node.kind {
+        NodeKind::Program(statements) => statements.iter().all(is_supported),
+        NodeKind::Assignment(_, expr) => is_supported(expr),
+        NodeKind::Output(expr, _) => matches!(expr.kind, NodeKind::String(_)) || is_supported(expr),
+        NodeKind::If(condition, true_branch, false_branch) => {
+            is_supported(condition) && true_branch.iter().all(is_supported) && false_branch.iter().all(is_supported)
+        }
+        NodeKind::Loop(condition, body) => is_supported(condition) && body.iter().all(is_supported),
+        NodeKind::BinOp(left, op, right) => {
+            // Powers, `/`, which gives a real number, and shifts are left to
+            // the interpreter.
+            let op_supported =
+                !matches!(op, BinaryOp::Caret | BinaryOp::Slash | BinaryOp::ShiftLeft | BinaryOp::ShiftRight);
+            op_supported && is_supported(left) && is_supported(right)
+        }
+        NodeKind::Number(_) | NodeKind::Identifier(_) => true,
+        NodeKind::Float(_) | NodeKind::String(_) | NodeKind::Input(..) | NodeKind::Call(..) | NodeKind::Array(_) => false,
+        NodeKind::NewArray(_) | NodeKind::Index(..) | NodeKind::SetElement(..) | NodeKind::MethodCall(..) => false,
+        NodeKind::Case(..) | NodeKind::Procedure(_) | NodeKind::Return(_) | NodeKind::Global(_) => false,
+        NodeKind::Conditional(..) | NodeKind::Null | NodeKind::Raise(_) | NodeKind::Try(..) => false,
+    }
+}
+
+// The variables a supported program assigns or reads, in the order they
+// first appear.
+#[derive(Default)]
+struct Variables(Vec<String>);
+
+impl<'a> Visit<'a> for Variables {
+    fn visit_node(&mut self, node: &'a AstNode) {
+        if let NodeKind::Assignment(name, _) | NodeKind::Identifier(name) = &node.kind {
+            if !self.0.contains(name) {
+                self.0.push(name.clone());
+            }
+        }
+        visit::walk_node(self, node);
+    }
+}
+
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    variables: HashMap<String, (Variable, Variable)>,
+    strings: &'a mut Vec<String>,
+    failures: &'a mut Vec<Error>,
+    ctx: Value,
+    pointer: types::Type,
+    division: Division,
+    output_number: FuncRef,
+    output_string: FuncRef,
+    fail: FuncRef,
+}
+
+impl Translator<'_> {
+    fn statement(&mut self, node: &AstNode) {
+        match &node.kind {
+            NodeKind::Program(statements) => {
+                for statement in statements {
+                    self.statement(statement);
+                }
+            }
+            NodeKind::Assignment(name, expr) => {
+                let value = self.expr(expr);
+                self.assign(name, value);
+            }
+            NodeKind::Output(expr, newline) => {
+                if let NodeKind::String(value) = &expr.kind {
+                    self.output_text(value.clone());
+                } else {
+                    let value = self.expr(expr);
+                    self.builder.ins().call(self.output_number, &[value]);
+                }
+                if *newline {
+                    self.output_text("\n".to_string());
+                }
+            }
+            NodeKind::If(condition, true_branch, false_branch) => {
+                let cond_value = self.expr(condition);
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                let merge_block = self.builder.create_block();
+                self.builder.ins().brif(cond_value, then_block, &[], else_block, &[]);
+
+                self.builder.switch_to_block(then_block);
+                self.builder.seal_block(then_block);
+                for statement in true_branch {
+                    self.statement(statement);
+                }
+                self.builder.ins().jump(merge_block, &[]);
+
+                self.builder.switch_to_block(else_block);
+                self.builder.seal_block(else_block);
+                for statement in false_branch {
+                    self.statement(statement);
+                }
+                self.builder.ins().jump(merge_block, &[]);
+
+                self.builder.switch_to_block(merge_block);
+                self.builder.seal_block(merge_block);
+            }
+            NodeKind::Loop(condition, body) => {
+                let header_block = self.builder.create_block();
+                let body_block = self.builder.create_block();
+                let exit_block = self.builder.create_block();
+                self.builder.ins().jump(header_block, &[]);
+
+                self.builder.switch_to_block(header_block);
+                let cond_value = self.expr(condition);
+                self.builder.ins().brif(cond_value, body_block, &[], exit_block, &[]);
+
+                self.builder.switch_to_block(body_block);
+                self.builder.seal_block(body_block);
+                for statement in body {
+                    self.statement(statement);
+                }
+                self.builder.ins().jump(header_block, &[]);
+
+                self.builder.switch_to_block(exit_block);
+                self.builder.seal_block(header_block);
+                self.builder.seal_block(exit_block);
+            }
+            _ => unreachable!("expression in statement position"),
+        }
+    }
+
+    fn expr(&mut self, node: &AstNode) -> Value {
+        match &node.kind {
+            NodeKind::Number(value) => self.builder.ins().iconst(types::I64, *value),
+            NodeKind::Identifier(name) => {
+                let (value, defined) = self.variables[name];
+                let defined = self.builder.use_var(defined);
+                let error = Error::runtime("E0200", format!("Undefined variable: {}", name));
+                self.fail_if(IntCC::Equal, defined, 0, error.or_span(node.span));
+                self.builder.use_var(value)
+            }
+            NodeKind::BinOp(left, op, right) => {
+                let left_val = self.expr(left);
+                let right_val = self.expr(right);
+                let overflow = Error::runtime("E0202", "Integer overflow").or_span(node.span);
+                match op {
+                    BinaryOp::Plus | BinaryOp::Minus | BinaryOp::Star => {
+                        let (result, overflows) = match op {
+                            BinaryOp::Plus => self.builder.ins().sadd_overflow(left_val, right_val),
+                            BinaryOp::Minus => self.builder.ins().ssub_overflow(left_val, right_val),
+                            _ => self.builder.ins().smul_overflow(left_val, right_val),
+                        };
+                        self.fail_if(IntCC::NotEqual, overflows, 0, overflow.clone());
+                        result
+                    }
+                    BinaryOp::Div | BinaryOp::Mod => {
+                        let error = Error::runtime("E0201", "Division by zero").or_span(node.span);
+                        self.fail_if(IntCC::Equal, right_val, 0, error);
+                        let is_min = self.builder.ins().icmp_imm(IntCC::Equal, left_val, i64::MIN);
+                        let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, right_val, -1);
+                        let overflows = self.builder.ins().band(is_min, is_minus_one);
+                        self.fail_if(IntCC::NotEqual, overflows, 0, overflow.clone());
+                        let quotient = self.builder.ins().sdiv(left_val, right_val);
+                        let remainder = self.builder.ins().srem(left_val, right_val);
+                        match (self.division, op) {
+                            (Division::Truncate, BinaryOp::Mod) => remainder,
+                            (Division::Truncate, _) => quotient,
+                            // Flooring moves the quotient down one, and the
+                            // remainder over by the divisor, when the
+                            // remainder and the divisor have opposite signs.
+                            (Division::Floor, _) => {
+                                let inexact = self.builder.ins().icmp_imm(IntCC::NotEqual, remainder, 0);
+                                let signs = self.builder.ins().bxor(remainder, right_val);
+                                let opposite = self.builder.ins().icmp_imm(IntCC::SignedLessThan, signs, 0);
+                                let adjust = self.builder.ins().band(inexact, opposite);
+                                if *op == BinaryOp::Mod {
+                                    let moved = self.builder.ins().iadd(remainder, right_val);
+                                    self.builder.ins().select(adjust, moved, remainder)
+                                } else {
+                                    let adjust = self.builder.ins().uextend(types::I64, adjust);
+                                    self.builder.ins().isub(quotient, adjust)
+                                }
+                            }
+                        }
+                    }
+                    BinaryOp::Eq => self.compare(IntCC::Equal, left_val, right_val),
+                    BinaryOp::NotEqual => self.compare(IntCC::NotEqual, left_val, right_val),
+                    BinaryOp::GreaterThan => self.compare(IntCC::SignedGreaterThan, left_val, right_val),
+                    BinaryOp::GreaterThanOrEqual => {
+                        self.compare(IntCC::SignedGreaterThanOrEqual, left_val, right_val)
+                    }
+                    BinaryOp::LessThan => self.compare(IntCC::SignedLessThan, left_val, right_val),
+                    BinaryOp::LessThanOrEqual => self.compare(IntCC::SignedLessThanOrEqual, left_val, right_val),
+                    BinaryOp::And | BinaryOp::Or => {
+                        let left_true = self.builder.ins().icmp_imm(IntCC::NotEqual, left_val, 0);
+                        let right_true = self.builder.ins().icmp_imm(IntCC::NotEqual, right_val, 0);
+                        let result = if *op == BinaryOp::And {
+                            self.builder.ins().band(left_true, right_true)
+                        } else {
+                            self.builder.ins().bor(left_true, right_true)
+                        };
+                        self.builder.ins().uextend(types::I64, result)
+                    }
+                    BinaryOp::BitAnd => self.builder.ins().band(left_val, right_val),
+                    BinaryOp::BitOr => self.builder.ins().bor(left_val, right_val),
+                    BinaryOp::BitXor => self.builder.ins().bxor(left_val, right_val),
+                    _ => unreachable!("unsupported operator reached the JIT"),
+                }
+            }
+            _ => unreachable!("unsupported expression reached the JIT"),
+        }
+    }
+
+    fn output_text(&mut self, text: String) {
+        let ptr = self.builder.ins().iconst(self.pointer, text.as_ptr() as i64);
+        let len = self.builder.ins().iconst(types::I64, text.len() as i64);
+        self.strings.push(text);
+        self.builder.ins().call(self.output_string, &[ptr, len]);
+    }
+
+    fn assign(&mut self, name: &str, value: Value) {
+        let (variable, defined) = self.variables[name];
+        let one = self.builder.ins().iconst(types::I64, 1);
+        self.builder.def_var(variable, value);
+        self.builder.def_var(defined, one);
+    }
+
+    fn compare(&mut self, cc: IntCC, left: Value, right: Value) -> Value {
+        let result = self.builder.ins().icmp(cc, left, right);
+        self.builder.ins().uextend(types::I64, result)
+    }
+
+    // Reports `error` through the context and returns from the program when
+    // `value cc imm` holds.
+    fn fail_if(&mut self, cc: IntCC, value: Value, imm: i64, error: Error) {
+        let code = self.failures.len() as i64;
+        self.failures.push(error);
+        let condition = self.builder.ins().icmp_imm(cc, value, imm);
+        let fail_block = self.builder.create_block();
+        let continue_block = self.builder.create_block();
+        self.builder.ins().brif(condition, fail_block, &[], continue_block, &[]);
+
+        self.builder.switch_to_block(fail_block);
+        self.builder.seal_block(fail_block);
+        let code = self.builder.ins().iconst(types::I64, code);
+        self.builder.ins().call(self.fail, &[self.ctx, code]);
+        self.builder.ins().return_(&[]);
+
+        self.builder.switch_to_block(continue_block);
+        self.builder.seal_block(continue_block);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::error::Error;
+use std::collections::BTreeMap;
+
+// The canonical (English) keywords. Every language spells each of these once,
+// in the same order.
+pub const KEYWORDS: [&str; 36] = [
+    "output", "input", "prompt", "if", "then", "else", "endif", "loop", "endloop", "while", "and", "or", "not",
+    "mod", "div", "inline", "new", "sub", "endsub", "function", "endfunction", "return", "ref",
+    "global", "case", "of", "default", "endcase", "null",
+    "error", "try", "catch", "endtry",
+    "band", "bor", "bxor",
+];
+
+const SPANISH: [&str; 36] = [
+    "salida", "entrada", "mensaje", "si", "entonces", "sino", "finsi", "bucle", "finbucle", "mientras", "y", "o",
+    "no", "mod", "div", "enlinea", "nuevo", "procedimiento", "finprocedimiento", "funcion", "finfuncion", "retornar",
+    "ref", "global", "caso", "de", "otro", "fincaso", "nulo",
+    "error", "intentar", "capturar", "finintentar",
+    "band", "bor", "bxor",
+];
+
+const FRENCH: [&str; 36] = [
+    "afficher", "saisir", "invite", "si", "alors", "sinon", "finsi", "boucle", "finboucle", "tantque", "et", "ou",
+    "non", "mod", "div", "enligne", "nouveau", "procedure", "finprocedure", "fonction", "finfonction", "retourner",
+    "ref", "globale", "cas", "de", "defaut", "fincas", "nul",
+    "erreur", "essayer", "attraper", "finessayer",
+    "band", "bor", "bxor",
+];
+
+pub const LANGUAGES: [&str; 3] = ["en", "es", "fr"];
+
+// The spelling of each keyword in one language. Source written in that
+// language is lexed into the same tokens as English source, and the formatter
+// uses the spellings to print a program back out.
+#[derive(Debug, Clone, Hash)]
+pub struct Keywords {
+    spellings: Vec<String>,
+    aliases: Vec<Alias>,
+}
+
+// Alternative notation for one or more keywords, such as `end if` for `endif`
+// or `elseif` for `else if`. `words` must appear on one line, separated by
+// spaces or tabs.
+#[derive(Debug, Clone, Hash)]
+pub struct Alias {
+    pub words: Vec<String>,
+    pub keywords: Vec<&'static str>,
+}
+
+impl Keywords {
+    pub fn english() -> Self {
+        Self::from_table(&KEYWORDS)
+    }
+
+    // The keywords as IB exam papers print them, in capitals. Closing keywords
+    // are spelled as two words, `END IF`, when `spaced`, and as one, `ENDIF`,
+    // otherwise. Either spelling is read.
+    pub fn ib(spaced: bool) -> Self {
+        let mut keywords = Keywords { spellings: KEYWORDS.iter().map(|k| k.to_uppercase()).collect(), aliases: vec![] };
+        for (index, keyword) in KEYWORDS.iter().enumerate() {
+            let Some(rest) = keyword.strip_prefix("end") else {
+                continue;
+            };
+            let words = vec!["END".to_string(), rest.to_uppercase()];
+            if spaced {
+                let joined = std::mem::replace(&mut keywords.spellings[index], words.join(" "));
+                keywords.aliases.push(Alias { words: vec![joined], keywords: vec![keyword] });
+            }
+            keywords.aliases.push(Alias { words, keywords: vec![keyword] });
+        }
+        keywords.aliases.sort_by_key(|alias| std::cmp::Reverse(alias.words.len()));
+        keywords
+    }
+
+    // The keywords in IB capitals, with the lowercase English spellings read
+    // too, so a program in either, or a mix, can be checked against exam
+    // notation.
+    pub fn exam() -> Self {
+        let mut keywords = Self::ib(true);
+        for keyword in KEYWORDS {
+            keywords.aliases.push(Alias { words: vec![keyword.to_string()], keywords: vec![keyword] });
+            if let Some(rest) = keyword.strip_prefix("end") {
+                let words = vec!["end".to_string(), rest.to_string()];
+                keywords.aliases.push(Alias { words, keywords: vec![keyword] });
+            }
+        }
+        keywords.aliases.sort_by_key(|alias| std::cmp::Reverse(alias.words.len()));
+        keywords
+    }
+
+    fn from_table(table: &[&str; 36]) -> Self {
+        Keywords { spellings: table.iter().map(|s| s.to_string()).collect(), aliases: vec![] }
+    }
+
+    // Looks up `lang` among the built-in languages and the `[keywords.<lang>]`
+    // tables from the config. A table overrides individual keywords of a
+    // built-in language, or defines a new language on top of English.
+    pub fn for_language(lang: &str, custom: &BTreeMap<String, BTreeMap<String, String>>) -> Result<Self, Error> {
+        let mut keywords = match lang {
+            "en" => Self::english(),
+            "es" => Self::from_table(&SPANISH),
+            "fr" => Self::from_table(&FRENCH),
+            _ if custom.contains_key(lang) => Self::english(),
+            _ => {
+                let mut known: Vec<&str> = LANGUAGES.to_vec();
+                known.extend(custom.keys().map(String::as_str).filter(|name| !LANGUAGES.contains(name)));
+                return Err(Error::config(format!(
+                    "Unknown language '{}', expected one of: {}",
+                    lang,
+                    known.join(", ")
+                )));
+            }
+        };
+
+        for (keyword, spelling) in custom.get(lang).into_iter().flatten() {
+            let Some(index) = KEYWORDS.iter().position(|k| k == keyword) else {
+                return Err(Error::config(format!("Unknown keyword '{}' in [keywords.{}]", keyword, lang)));
+            };
+            if !spelling.starts_with(char::is_alphabetic) || !spelling.chars().all(char::is_alphanumeric) {
+                return Err(Error::config(format!(
+                    "Keyword '{}' in [keywords.{}] must be a letter followed by letters and digits, got '{}'",
+                    keyword, lang, spelling
+                )));
+            }
+            keywords.spellings[index] = spelling.clone();
+        }
+
+        for (index, spelling) in keywords.spellings.iter().enumerate() {
+            if let Some(other) = keywords.spellings[..index].iter().position(|s| s == spelling) {
+                return Err(Error::config(format!(
+                    "Keywords '{}' and '{}' are both spelled '{}' in language '{}'",
+                    KEYWORDS[other], KEYWORDS[index], spelling, lang
+                )));
+            }
+        }
+
+        Ok(keywords)
+    }
+
+    // Adds the `[aliases]` table from the config. Each key is the notation to
+    // accept and each value the canonical keywords it stands for.
+    pub fn with_aliases(mut self, aliases: &BTreeMap<String, String>) -> Result<Self, Error> {
+        for (notation, expansion) in aliases {
+            let words: Vec<String> = notation.split_whitespace().map(String::from).collect();
+            let valid_word = |word: &String| word.starts_with(char::is_alphabetic) && word.chars().all(char::is_alphanumeric);
+            if words.is_empty() || !words.iter().all(valid_word) {
+                return Err(Error::config(format!(
+                    "Alias '{}' must be one or more words made of letters and digits",
+                    notation
+                )));
+            }
+            let keywords = expansion
+                .split_whitespace()
+                .map(|word| {
+                    KEYWORDS.iter().copied().find(|k| *k == word).ok_or_else(|| {
+                        Error::config(format!("Alias '{}' maps to '{}', which is not a keyword", notation, word))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if keywords.is_empty() {
+                return Err(Error::config(format!("Alias '{}' must map to at least one keyword", notation)));
+            }
+            self.aliases.push(Alias { words, keywords });
+        }
+        // Try longer notations first so `end if` wins over a plain `end`.
+        self.aliases.sort_by_key(|alias| std::cmp::Reverse(alias.words.len()));
+        Ok(self)
+    }
+
+    pub fn aliases(&self) -> &[Alias] {
+        &self.aliases
+    }
+
+    // Maps a word written in this language to the canonical keyword, if it is one.
+    pub fn canonical(&self, word: &str) -> Option<&'static str> {
+        self.spellings.iter().position(|s| s == word).map(|index| KEYWORDS[index])
+    }
+
+    pub fn spelling(&self, keyword: &str) -> &str {
+        let index = KEYWORDS.iter().position(|k| *k == keyword).expect("not a keyword");
+        &self.spellings[index]
+    }
+}
@@ -1,19 +1,38 @@
+use crate::error::Error;
+use crate::keywords::Keywords;
+use serde::Serialize;
+use std::collections::VecDeque;
 use std::str::Chars;
+use tracing::trace;
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum Token {
     Assign,
     Output,
+    Input,
+    Prompt,
     Identifier(String),
     Number(i64),
+    Float(f64),
     String(String),
     Plus,
     Minus,
     Star,
     Slash,
+    Caret,
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
     LParen,
     RParen,
+    LBracket,
+    RBracket,
     Comma,
+    Colon,
+    Dot,
+    Semicolon,
     If,
     Then,
     Else,
@@ -31,47 +50,180 @@ pub enum Token {
     Not,
     Mod,
     Div,
+    Inline,
+    New,
+    Sub,
+    EndSub,
+    Function,
+    EndFunction,
+    Return,
+    Ref,
+    Global,
+    Case,
+    Of,
+    Default,
+    EndCase,
+    Null,
+    Error,
+    Try,
+    Catch,
+    EndTry,
+    BAnd,
+    BOr,
+    BXor,
+    ShiftLeft,
+    ShiftRight,
     Eof,
 }
 
-#[derive(Debug, Clone)]
-pub struct TokenInfo {
-    pub token: Token,
+// Every operator and punctuation mark, longest first so that `>=` is not read
+// as `>` followed by `=`.
+pub const OPERATORS: [(&str, Token); 25] = [
+    ("**", Token::Caret),
+    ("<<", Token::ShiftLeft),
+    (">>", Token::ShiftRight),
+    ("+=", Token::PlusAssign),
+    ("-=", Token::MinusAssign),
+    ("*=", Token::StarAssign),
+    (">=", Token::GreaterThanOrEqual),
+    ("<=", Token::LessThanOrEqual),
+    ("!=", Token::NotEqual),
+    ("=", Token::Assign),
+    ("^", Token::Caret),
+    ("+", Token::Plus),
+    ("-", Token::Minus),
+    ("*", Token::Star),
+    ("/", Token::Slash),
+    ("(", Token::LParen),
+    (")", Token::RParen),
+    ("[", Token::LBracket),
+    ("]", Token::RBracket),
+    (",", Token::Comma),
+    (":", Token::Colon),
+    (".", Token::Dot),
+    (";", Token::Semicolon),
+    (">", Token::GreaterThan),
+    ("<", Token::LessThan),
+];
+
+// Lines and columns are 1-based and columns count grapheme clusters, so they
+// match what a reader sees. Offsets are UTF-8 byte offsets into the source for
+// tools that edit text. End positions are exclusive. `file` is the index of the
+// source file in the program being run.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct Span {
+    #[serde(skip)]
+    pub file: usize,
     pub line: usize,
     pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub offset: usize,
+    pub end_offset: usize,
+}
+
+impl Span {
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            file: self.file,
+            line: self.line,
+            column: self.column,
+            end_line: other.end_line,
+            end_column: other.end_column,
+            offset: self.offset,
+            end_offset: other.end_offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenInfo {
+    pub token: Token,
+    pub span: Span,
+}
+
+// A `/// text` comment, which documents the procedure defined on the lines
+// below it. `text` is what follows the slashes, less one space.
+#[derive(Debug, Clone)]
+pub struct DocComment {
+    pub text: String,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct Lexer<'a> {
+    source: &'a str,
     input: Chars<'a>,
     current_char: Option<char>,
+    offset: usize,
     line: usize,
-    column: usize,
+    line_start: usize,
+    file: usize,
+    keywords: Option<&'a Keywords>,
+    pending: VecDeque<TokenInfo>,
+    doc_comments: Vec<DocComment>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
-        let mut lexer = Lexer {
-            input: input.chars(),
-            current_char: None,
+        Self::for_file(input, 0)
+    }
+
+    pub fn for_file(input: &'a str, file: usize) -> Self {
+        let mut input_chars = input.chars();
+        Lexer {
+            source: input,
+            current_char: input_chars.next(),
+            input: input_chars,
+            offset: 0,
             line: 1,
-            column: 0,
-        };
-        lexer.advance();
-        lexer
+            line_start: 0,
+            file,
+            keywords: None,
+            pending: VecDeque::new(),
+            doc_comments: vec![],
+        }
+    }
+
+    // A lexer over `source[start..end]` that reports positions within the whole
+    // source, used to parse expressions embedded in string literals.
+    pub fn slice(&self, start: usize, end: usize) -> Lexer<'a> {
+        let before = &self.source[..start];
+        let mut input = self.source[start..end].chars();
+        Lexer {
+            current_char: input.next(),
+            input,
+            offset: start,
+            line: before.matches('\n').count() + 1,
+            line_start: before.rfind('\n').map_or(0, |index| index + 1),
+            pending: VecDeque::new(),
+            doc_comments: vec![],
+            ..self.clone()
+        }
+    }
+
+    // Reads keywords spelled in another language; English is the default.
+    pub fn with_keywords(mut self, keywords: &'a Keywords) -> Self {
+        self.keywords = Some(keywords);
+        self
     }
 
     fn advance(&mut self) {
         if let Some(c) = self.current_char {
+            self.offset += c.len_utf8();
             if c == '\n' {
                 self.line += 1;
-                self.column = 0;
-            } else {
-                self.column += 1;
+                self.line_start = self.offset;
             }
         }
         self.current_char = self.input.next();
     }
 
+    fn column(&self) -> usize {
+        self.source[self.line_start..self.offset].graphemes(true).count() + 1
+    }
+
     fn identifier(&mut self) -> String {
         let mut result = String::new();
         while let Some(c) = self.current_char {
@@ -85,35 +237,269 @@ impl<'a> Lexer<'a> {
         result
     }
 
-    fn number(&mut self) -> i64 {
-        let mut result = String::new();
+    // Digits in base `radix`. An `_` between two digits groups them, as in
+    // 1_000_000, and is left out of `result`.
+    fn digits(&mut self, result: &mut String, radix: u32) {
         while let Some(c) = self.current_char {
-            if c.is_digit(10) {
+            if c.is_digit(radix) {
                 result.push(c);
                 self.advance();
+            } else if c == '_'
+                && result.ends_with(|c: char| c.is_digit(radix))
+                && self.input.clone().next().is_some_and(|c| c.is_digit(radix))
+            {
+                self.advance();
             } else {
                 break;
             }
         }
-        result.parse().unwrap()
     }
 
-    fn string(&mut self) -> String {
+    // Reads an integer, or a float when the digits continue after a `.` or
+    // have an exponent, as in 1.5e3. `0x` and `0b` start integers in base 16
+    // and 2. The parser decides whether the dialect allows all but plain
+    // digits.
+    fn number(&mut self) -> Result<Token, Error> {
+        let (line, column, offset) = (self.line, self.column(), self.offset);
+        let mut result = String::new();
+        let mut rest = self.input.clone();
+        let radix = match (self.current_char, rest.next()) {
+            (Some('0'), Some('x' | 'X')) => 16,
+            (Some('0'), Some('b' | 'B')) => 2,
+            _ => 10,
+        };
+        if radix != 10 && rest.next().is_some_and(|c| c.is_digit(radix)) {
+            self.advance();
+            self.advance();
+            self.digits(&mut result, radix);
+            return i64::from_str_radix(&result, radix).map(Token::Number).map_err(|_| {
+                Error::syntax(
+                    "E0103",
+                    format!("Integer literal {} is too large", &self.source[offset..self.offset]),
+                    self.span_from(line, column, offset),
+                )
+            });
+        }
+        self.digits(&mut result, 10);
+        let mut float = false;
+        if self.current_char == Some('.') && self.input.clone().next().is_some_and(|c| c.is_ascii_digit()) {
+            result.push('.');
+            self.advance();
+            self.digits(&mut result, 10);
+            float = true;
+        }
+        let rest = self.input.as_str();
+        let exponent = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+        if matches!(self.current_char, Some('e' | 'E')) && exponent.starts_with(|c: char| c.is_ascii_digit()) {
+            result.push('e');
+            self.advance();
+            if let Some(sign @ ('+' | '-')) = self.current_char {
+                result.push(sign);
+                self.advance();
+            }
+            self.digits(&mut result, 10);
+            float = true;
+        }
+        if float {
+            return match result.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(Token::Float(value)),
+                _ => Err(Error::syntax(
+                    "E0103",
+                    format!("Float literal {} is too large", result),
+                    self.span_from(line, column, offset),
+                )),
+            };
+        }
+        result.parse().map(Token::Number).map_err(|_| {
+            Error::syntax(
+                "E0103",
+                format!("Integer literal {} is too large", result),
+                self.span_from(line, column, offset),
+            )
+        })
+    }
+
+    // Strings end at the closing quote and may not span lines, so a missing
+    // quote is reported where the line ends instead of swallowing the file.
+    fn string(&mut self) -> Result<String, Error> {
+        let (line, column) = (self.line, self.column());
         let mut result = String::new();
         self.advance(); // Skip the opening quote
-        while let Some(c) = self.current_char {
-            if c == '"' {
+        loop {
+            match self.current_char {
+                Some('"') => break,
+                Some('\n') | None => {
+                    let (end_line, end_column, end_offset) = (self.line, self.column(), self.offset);
+                    return Err(Error::syntax(
+                        "E0102",
+                        format!("Unterminated string literal starting at line {}, column {}", line, column),
+                        self.span_from(end_line, end_column, end_offset),
+                    ));
+                }
+                Some(c) => {
+                    result.push(c);
+                    self.advance();
+                }
+            }
+        }
+        self.advance(); // Skip the closing quote
+        Ok(result)
+    }
+
+    // Strings in three quotes may span lines, for menus and banners, and hold
+    // quotes. The text is kept as written up to the next three quotes,
+    // except for a line break right after the opening ones, so the text can
+    // start on a line of its own.
+    fn long_string(&mut self) -> Result<String, Error> {
+        let (line, column, offset) = (self.line, self.column(), self.offset);
+        for _ in 0..3 {
+            self.advance();
+        }
+        let opening = self.span_from(line, column, offset);
+        if self.current_char == Some('\r') && self.input.as_str().starts_with('\n') {
+            self.advance();
+        }
+        if self.current_char == Some('\n') {
+            self.advance();
+        }
+        let mut result = String::new();
+        loop {
+            match self.current_char {
+                Some('"') if self.input.as_str().starts_with("\"\"") => break,
+                None => {
+                    return Err(Error::syntax(
+                        "E0102",
+                        format!("Unterminated string literal starting at line {}, column {}", line, column),
+                        opening,
+                    ));
+                }
+                Some(c) => {
+                    result.push(c);
+                    self.advance();
+                }
+            }
+        }
+        for _ in 0..3 {
+            self.advance();
+        }
+        Ok(result)
+    }
+
+    fn span_from(&self, line: usize, column: usize, offset: usize) -> Span {
+        Span {
+            file: self.file,
+            line,
+            column,
+            end_line: self.line,
+            end_column: self.column(),
+            offset,
+            end_offset: self.offset,
+        }
+    }
+
+    // Consumes the longest operator at the current position.
+    fn operator(&mut self) -> Option<Token> {
+        let c = self.current_char?;
+        let rest = self.input.as_str();
+        let (text, token) = OPERATORS
+            .iter()
+            .find(|(text, _)| text.starts_with(c) && rest.starts_with(&text[c.len_utf8()..]))?;
+        for _ in text.chars() {
+            self.advance();
+        }
+        Some(token.clone())
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.current_char, Some(' ' | '\t')) {
+            self.advance();
+        }
+    }
+
+    // Matches a configured alias starting with `first`, which has just been
+    // read, consuming the rest of its words. Leaves the input untouched when
+    // no alias matches.
+    fn alias(&mut self, first: &str) -> Option<Vec<&'static str>> {
+        let keywords = self.keywords?;
+        for alias in keywords.aliases().iter().filter(|alias| alias.words[0] == first) {
+            let saved = self.clone();
+            let matched = alias.words[1..].iter().all(|word| {
+                self.skip_blanks();
+                self.current_char.is_some_and(char::is_alphabetic) && self.identifier() == *word
+            });
+            if matched {
+                return Some(alias.keywords.clone());
+            }
+            *self = saved;
+        }
+        None
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    // The doc comments passed so far, in source order.
+    pub fn doc_comments(&self) -> &[DocComment] {
+        &self.doc_comments
+    }
+
+    // The text of the doc comments on the lines just above `line`, with no
+    // gap between them and it.
+    pub fn doc_above(&self, line: usize) -> Vec<String> {
+        let mut next = line;
+        let mut lines: Vec<String> = vec![];
+        for comment in self.doc_comments.iter().rev() {
+            if comment.span.line + 1 != next {
                 break;
             }
-            result.push(c);
+            next = comment.span.line;
+            lines.push(comment.text.clone());
+        }
+        lines.reverse();
+        lines
+    }
+
+    fn doc_comment(&mut self, line: usize, column: usize, offset: usize) {
+        let mut text = String::new();
+        while let Some(c) = self.current_char.filter(|&c| c != '\n') {
+            text.push(c);
             self.advance();
         }
-        self.advance(); // Skip the closing quote
-        result
+        let text = &text["///".len()..];
+        let text = text.strip_prefix(' ').unwrap_or(text).trim_end().to_string();
+        let span = self.span_from(line, column, offset);
+        self.doc_comments.push(DocComment { text, span });
+    }
+
+    // Lexes the rest of the input, ending with the Eof token.
+    pub fn tokenize(&mut self) -> Result<Vec<TokenInfo>, Error> {
+        let mut tokens = vec![];
+        loop {
+            let info = self.get_next_token()?;
+            let done = info.token == Token::Eof;
+            tokens.push(info);
+            if done {
+                return Ok(tokens);
+            }
+        }
     }
 
-    pub fn get_next_token(&mut self) -> TokenInfo {
+    pub fn get_next_token(&mut self) -> Result<TokenInfo, Error> {
+        if let Some(info) = self.pending.pop_front() {
+            return Ok(info);
+        }
         while let Some(c) = self.current_char {
+            let (line, column, offset) = (self.line, self.column(), self.offset);
+            if self.source[offset..].starts_with("///") {
+                self.doc_comment(line, column, offset);
+                continue;
+            }
+            if let Some(token) = self.operator() {
+                let span = self.span_from(line, column, offset);
+                trace!(?token, line = span.line, column = span.column, "token");
+                return Ok(TokenInfo { token, span });
+            }
             let token = match c {
                 ' ' | '\t' | '\r' => {
                     self.advance();
@@ -123,98 +509,101 @@ impl<'a> Lexer<'a> {
                     self.advance();
                     continue;
                 }
-                '=' => {
+                // Line breaks never end a statement, so expressions can wrap
+                // anywhere. A trailing `\` is accepted for textbooks that mark
+                // the continuation explicitly.
+                '\\' => {
                     self.advance();
-                    Token::Assign
-                }
-                '+' => {
-                    self.advance();
-                    Token::Plus
-                }
-                '-' => {
-                    self.advance();
-                    Token::Minus
-                }
-                '*' => {
-                    self.advance();
-                    Token::Star
-                }
-                '/' => {
-                    self.advance();
-                    Token::Slash
-                }
-                '(' => {
-                    self.advance();
-                    Token::LParen
-                }
-                ')' => {
-                    self.advance();
-                    Token::RParen
-                }
-                ',' => {
-                    self.advance();
-                    Token::Comma
-                }
-                '>' => {
-                    self.advance();
-                    if self.current_char == Some('=') {
-                        self.advance();
-                        Token::GreaterThanOrEqual
-                    } else {
-                        Token::GreaterThan
-                    }
-                }
-                '<' => {
-                    self.advance();
-                    if self.current_char == Some('=') {
+                    while matches!(self.current_char, Some(' ' | '\t' | '\r')) {
                         self.advance();
-                        Token::LessThanOrEqual
-                    } else {
-                        Token::LessThan
                     }
-                }
-                '!' => {
-                    self.advance();
-                    if self.current_char == Some('=') {
-                        self.advance();
-                        Token::NotEqual
-                    } else {
-                        panic!("Unexpected character: {}", c);
+                    match self.current_char {
+                        Some('\n') | None => continue,
+                        _ => {
+                            return Err(Error::syntax(
+                                "E0100",
+                                "Expected a line break after '\\'",
+                                self.span_from(line, column, offset),
+                            ))
+                        }
                     }
                 }
-                '"' => Token::String(self.string()),
-                c if c.is_digit(10) => Token::Number(self.number()),
+                '"' if self.input.as_str().starts_with("\"\"") => Token::String(self.long_string()?),
+                '"' => Token::String(self.string()?),
+                c if c.is_ascii_digit() => self.number()?,
                 c if c.is_alphabetic() => {
                     let id = self.identifier();
-                    match id.as_str() {
-                        "output" => Token::Output,
-                        "if" => Token::If,
-                        "then" => Token::Then,
-                        "else" => Token::Else,
-                        "endif" => Token::EndIf,
-                        "loop" => Token::Loop,
-                        "endloop" => Token::EndLoop,
-                        "while" => Token::While,
-                        "and" => Token::And,
-                        "or" => Token::Or,
-                        "not" => Token::Not,
-                        "mod" => Token::Mod,
-                        "div" => Token::Div,
-                        _ => Token::Identifier(id),
+                    if let Some(expansion) = self.alias(&id) {
+                        // Every token an alias expands to covers the whole alias.
+                        let span = self.span_from(line, column, offset);
+                        for keyword in expansion {
+                            trace!(alias = id.as_str(), keyword, "token");
+                            self.pending.push_back(TokenInfo { token: keyword_token(keyword).unwrap(), span });
+                        }
+                        return Ok(self.pending.pop_front().unwrap());
                     }
+                    let keyword = match self.keywords {
+                        Some(keywords) => keywords.canonical(&id),
+                        None => Some(id.as_str()),
+                    };
+                    keyword.and_then(keyword_token).unwrap_or(Token::Identifier(id))
+                }
+                _ => {
+                    self.advance();
+                    return Err(Error::syntax("E0100", format!("Unexpected character: {}", c), self.span_from(line, column, offset)));
                 }
-                _ => panic!("Unexpected character: {}", c),
-            };
-            return TokenInfo {
-                token,
-                line: self.line,
-                column: self.column,
             };
+            let span = self.span_from(line, column, offset);
+            trace!(?token, line = span.line, column = span.column, "token");
+            return Ok(TokenInfo { token, span });
         }
-        TokenInfo {
+        Ok(TokenInfo {
             token: Token::Eof,
-            line: self.line,
-            column: self.column,
-        }
+            span: self.span_from(self.line, self.column(), self.offset),
+        })
     }
 }
+
+// The token for a canonical (English) keyword.
+fn keyword_token(keyword: &str) -> Option<Token> {
+    let token = match keyword {
+        "output" => Token::Output,
+        "input" => Token::Input,
+        "prompt" => Token::Prompt,
+        "if" => Token::If,
+        "then" => Token::Then,
+        "else" => Token::Else,
+        "endif" => Token::EndIf,
+        "loop" => Token::Loop,
+        "endloop" => Token::EndLoop,
+        "while" => Token::While,
+        "and" => Token::And,
+        "or" => Token::Or,
+        "not" => Token::Not,
+        "mod" => Token::Mod,
+        "div" => Token::Div,
+        "inline" => Token::Inline,
+        "new" => Token::New,
+        "sub" => Token::Sub,
+        "endsub" => Token::EndSub,
+        "function" => Token::Function,
+        "endfunction" => Token::EndFunction,
+        "return" => Token::Return,
+        "ref" => Token::Ref,
+        "global" => Token::Global,
+        "case" => Token::Case,
+        "of" => Token::Of,
+        "default" => Token::Default,
+        "endcase" => Token::EndCase,
+        "null" => Token::Null,
+        "error" => Token::Error,
+        "try" => Token::Try,
+        "catch" => Token::Catch,
+        "endtry" => Token::EndTry,
+        "band" => Token::BAnd,
+        "bor" => Token::BOr,
+        "bxor" => Token::BXor,
+        _ => return None,
+    };
+    Some(token)
+}
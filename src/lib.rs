@@ -0,0 +1,61 @@
+// Errors carry a span and file name for diagnostics; they are only built on
+// the failure path, so their size is not worth boxing away.
+#![allow(clippy::result_large_err)]
+
+//! Lexer, parser and interpreter for IB Computer Science pseudocode. The
+//! `ibcspsuedolang` binary is a command line front end to this library.
+
+pub mod ast;
+pub mod batch;
+pub mod builder;
+pub mod builtins;
+pub mod cache;
+pub mod callgraph;
+pub mod cfg;
+pub mod color;
+pub mod config;
+pub mod desugar;
+pub mod doc;
+pub mod equivalence;
+pub mod error;
+pub mod exam;
+pub mod explore;
+pub mod fingerprint;
+pub mod fix;
+pub mod flat;
+pub mod flowchart;
+pub mod formatter;
+pub mod generate;
+pub mod grammar;
+pub mod heap;
+pub mod interpreter;
+pub mod keywords;
+pub mod lexer;
+pub mod lint;
+pub mod literate;
+pub mod metrics;
+pub mod mutate;
+pub mod parser;
+pub mod railroad;
+pub mod reduce;
+pub mod refactor;
+pub mod rpc;
+pub mod sarif;
+pub mod semantic;
+pub mod serve;
+pub mod snapshot;
+pub mod terminal;
+pub mod value;
+pub mod visit;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+#[cfg(feature = "jit")]
+pub mod jit;
+
+// Parsed programs, keywords and errors cross threads in batch runs.
+const _: () = {
+    const fn shareable<T: Send + Sync>() {}
+    shareable::<ast::AstNode>();
+    shareable::<error::Error>();
+    shareable::<keywords::Keywords>();
+};
@@ -0,0 +1,477 @@
+use crate::ast::{AstNode, BinaryOp, NodeKind, Procedure, ProcedureKind};
+use crate::cfg;
+use crate::error::Error;
+use crate::interpreter;
+use crate::lexer::Span;
+use crate::visit::{self, Visit};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct LintOptions {
+    // Require every statement to start on its own line.
+    pub strict: bool,
+    // Builtins and methods students may not use, such as `sort` when an
+    // exercise asks for a hand-written sort.
+    pub banned: Vec<String>,
+    // Limits a teacher can set so that work is split into procedures: how
+    // many blocks deep statements may nest, how many lines long a procedure
+    // may be, and how many global variables the program may have.
+    pub max_depth: Option<usize>,
+    pub max_routine_lines: Option<usize>,
+    pub max_globals: Option<usize>,
+}
+
+// Reports style problems in a parsed program. None of them stop the program
+// from running.
+pub fn lint(program: &AstNode, options: &LintOptions) -> Vec<Error> {
+    let mut warnings = vec![];
+    control_flow(program, &mut warnings);
+    similar_names(program, &mut warnings);
+    shadowing(program, &mut warnings);
+    constant_conditions(program, &mut warnings);
+    unchanging_loops(program, &mut warnings);
+    real_division(program, &mut warnings);
+    if options.strict {
+        OneStatementPerLine { warnings: &mut warnings, header: None }.visit_node(program);
+    }
+    if !options.banned.is_empty() {
+        banned_builtins(program, &options.banned, &mut warnings);
+    }
+    if let Some(max) = options.max_depth {
+        Nesting { max, depth: 0, warnings: &mut warnings }.visit_node(program);
+    }
+    if let Some(max) = options.max_routine_lines {
+        long_routines(program, max, &mut warnings);
+    }
+    if let Some(max) = options.max_globals {
+        too_many_globals(program, max, &mut warnings);
+    }
+    warnings.sort_by_key(|warning| warning.span.map(|span| (span.file, span.offset)));
+    warnings
+}
+
+// Statements no path reaches, and functions that can end without returning a
+// value, which fails when it happens.
+fn control_flow(program: &AstNode, warnings: &mut Vec<Error>) {
+    for graph in cfg::build(program) {
+        let reached = graph.reachable();
+        // The first statement of each block nothing reaches, and where the
+        // block's last statement ends.
+        let mut unreachable: Vec<(&AstNode, usize)> = graph
+            .blocks
+            .iter()
+            .zip(reached)
+            .filter(|(block, reached)| !reached && !block.statements.is_empty())
+            .map(|(block, _)| {
+                let end = block.statements.iter().map(|statement| statement.span.end_offset).max();
+                (block.statements[0], end.unwrap_or_default())
+            })
+            .collect();
+        unreachable.sort_by_key(|(statement, _)| (statement.span.file, statement.span.offset));
+        let mut reported = (0, 0);
+        for (statement, end) in unreachable {
+            // Blocks inside code already reported are not reported again.
+            if (statement.span.file, statement.span.offset) < reported {
+                continue;
+            }
+            reported = (statement.span.file, end);
+            warnings.push(Error::lint("W0003", "This statement can never run", statement.span));
+        }
+
+        if graph.kind == Some(ProcedureKind::Function) && !graph.fall_through().is_empty() {
+            let name = graph.name.as_deref().unwrap_or_default();
+            let message = format!("Function {} can reach its end without returning a value", name);
+            warnings.push(Error::lint("W0004", message, graph.span));
+        }
+    }
+}
+
+// Conditions that come out the same every time, and loops that never end
+// because their condition is always true and nothing in them leaves.
+fn constant_conditions(program: &AstNode, warnings: &mut Vec<Error>) {
+    let mut procedures = vec![];
+    program.walk(&mut |node| {
+        if let NodeKind::Procedure(procedure) = &node.kind {
+            procedures.push(procedure.name.as_str());
+        }
+    });
+    program.walk(&mut |node| {
+        let condition = match &node.kind {
+            NodeKind::If(condition, ..) | NodeKind::Loop(condition, _) => condition,
+            NodeKind::Conditional(condition, ..) => condition,
+            _ => return,
+        };
+        let Some(value) = interpreter::constant(condition).and_then(|value| value.as_f64()).map(|n| n != 0.0) else {
+            return;
+        };
+        if let (true, NodeKind::Loop(_, body)) = (value, &node.kind) {
+            // A procedure the loop calls may raise an error, which leaves the
+            // loop too.
+            let mut leaves = false;
+            for statement in body {
+                statement.walk(&mut |node| {
+                    leaves |= match &node.kind {
+                        NodeKind::Return(_) | NodeKind::Raise(_) => true,
+                        NodeKind::Call(name, _) => procedures.contains(&name.as_str()),
+                        _ => false,
+                    };
+                });
+            }
+            if !leaves {
+                let message = "This loop never ends: its condition is always true and nothing in it leaves the loop";
+                warnings.push(Error::lint("W0013", message, condition.span));
+            }
+            return;
+        }
+        let message = format!("This condition is always {}", if value { "true" } else { "false" });
+        warnings.push(Error::lint("W0012", message, condition.span));
+    });
+}
+
+// Loops whose body changes none of the variables their condition reads, so
+// they run forever once they start. Loops that call a procedure, which may
+// change a global or a `ref` argument, are left alone, and so are loops with
+// a `return` or `raise` in them, which may leave.
+fn unchanging_loops(program: &AstNode, warnings: &mut Vec<Error>) {
+    program.walk(&mut |node| {
+        let NodeKind::Loop(condition, body) = &node.kind else {
+            return;
+        };
+        let mut read = vec![];
+        let mut elements = false;
+        let mut calls = false;
+        condition.walk(&mut |node| match &node.kind {
+            NodeKind::Identifier(name) if !read.contains(&name) => read.push(name),
+            NodeKind::Index(..) | NodeKind::MethodCall(..) => elements = true,
+            NodeKind::Call(..) => calls = true,
+            _ => {}
+        });
+        if read.is_empty() || calls {
+            return;
+        }
+
+        let mut changed = vec![];
+        let mut unknown = false;
+        for statement in body {
+            statement.walk(&mut |node| match &node.kind {
+                NodeKind::Assignment(name, _)
+                | NodeKind::Input(name, _)
+                | NodeKind::SetElement(name, ..)
+                | NodeKind::Try(_, Some(name), _) => changed.push(name),
+                NodeKind::MethodCall(receiver, ..) => match &receiver.kind {
+                    NodeKind::Identifier(name) => changed.push(name),
+                    _ => unknown = true,
+                },
+                NodeKind::Return(_) | NodeKind::Raise(_) | NodeKind::Call(..) => unknown = true,
+                _ => {}
+            });
+        }
+        // Another name for an array the condition reads may change it.
+        let aliased = elements && !changed.is_empty();
+        if unknown || aliased || read.iter().any(|name| changed.contains(name)) {
+            return;
+        }
+        let names: Vec<&str> = read.iter().map(|name| name.as_str()).collect();
+        let message = format!(
+            "Nothing in this loop changes {}, so once it starts it never ends",
+            match names.split_last() {
+                Some((last, [])) => last.to_string(),
+                Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+                None => unreachable!("the condition reads a variable"),
+            }
+        );
+        warnings.push(Error::lint("W0014", message, condition.span));
+    });
+}
+
+// Locals of a procedure that share a name with a global, which leave the
+// global as it was when the procedure sets them, and procedures that share a
+// name with a variable.
+fn shadowing(program: &AstNode, warnings: &mut Vec<Error>) {
+    let mut globals = Assigned::default();
+    globals.visit_node(program);
+    let mut procedures = vec![];
+    program.walk(&mut |node| {
+        if let NodeKind::Procedure(procedure) = &node.kind {
+            procedures.push((procedure.as_ref(), node.span));
+        }
+    });
+
+    let mut variables: BTreeMap<&str, Span> = globals.names.clone();
+    for &(procedure, span) in &procedures {
+        // Parameters are locals too, but are named for what the caller
+        // passes, which is often a global of the same name.
+        for param in &procedure.params {
+            variables.entry(&param.name).or_insert(span);
+        }
+        let mut locals = Assigned::default();
+        locals.visit_block(&procedure.body);
+        let mut declared: Vec<&String> = vec![];
+        for statement in &procedure.body {
+            statement.walk(&mut |node| {
+                if let NodeKind::Global(names) = &node.kind {
+                    declared.extend(names);
+                }
+            });
+        }
+        for (&name, &local) in &locals.names {
+            variables.entry(name).or_insert(local);
+            let Some(&global) = globals.names.get(name) else {
+                continue;
+            };
+            if declared.iter().any(|declared| *declared == name) {
+                continue;
+            }
+            let message = format!(
+                "{} in {} is a local variable that hides the global {}; add `global {}` to use the global",
+                name, procedure.name, name, name
+            );
+            let related = format!("the global {} is set here", name);
+            warnings.push(Error::lint("W0010", message, local).with_related(related, global));
+        }
+    }
+
+    for &(procedure, span) in &procedures {
+        if let Some(&variable) = variables.get(procedure.name.as_str()) {
+            let message = format!("{} is the name of a procedure and of a variable", procedure.name);
+            warnings.push(Error::lint("W0011", message, variable).with_related("the procedure is defined here", span));
+        }
+    }
+}
+
+// The variables statements set, with where each is first set. Procedures
+// inside them have variables of their own, so they are left out.
+#[derive(Default)]
+struct Assigned<'a> {
+    names: BTreeMap<&'a str, Span>,
+}
+
+impl<'a> Visit<'a> for Assigned<'a> {
+    fn visit_node(&mut self, node: &'a AstNode) {
+        match &node.kind {
+            NodeKind::Assignment(name, _) | NodeKind::Input(name, _) | NodeKind::Try(_, Some(name), _) => {
+                self.names.entry(name).or_insert(node.span);
+            }
+            _ => {}
+        }
+        visit::walk_node(self, node);
+    }
+
+    fn visit_procedure(&mut self, _: &'a Procedure) {}
+}
+
+// Variables whose names are easy to mistake for each other, which are still
+// two variables as names are case-sensitive: the same but for case, such as
+// COUNT and Count, or one letter apart, such as TOTL and TOTAL. Each pair is
+// reported once, where the likelier mistake first appears.
+fn similar_names<'a>(program: &'a AstNode, warnings: &mut Vec<Error>) {
+    // Each name, with where it first appears and how often it is used.
+    let mut names: BTreeMap<&str, (Span, usize)> = BTreeMap::new();
+    let mut used = |name: &'a str, span: Span| names.entry(name).or_insert((span, 0)).1 += 1;
+    program.walk(&mut |node| match &node.kind {
+        NodeKind::Identifier(name)
+        | NodeKind::Assignment(name, _)
+        | NodeKind::Input(name, _)
+        | NodeKind::SetElement(name, ..)
+        | NodeKind::Try(_, Some(name), _) => used(name, node.span),
+        NodeKind::Procedure(procedure) => procedure.params.iter().for_each(|param| used(&param.name, node.span)),
+        _ => {}
+    });
+
+    let names: Vec<(&str, (Span, usize))> = names.into_iter().collect();
+    for (index, &(first, (first_span, first_uses))) in names.iter().enumerate() {
+        for &(second, (second_span, second_uses)) in &names[index + 1..] {
+            let how = if first.eq_ignore_ascii_case(second) {
+                "differ only in case"
+            } else if one_letter_apart(first, second) {
+                "are one letter apart"
+            } else {
+                continue;
+            };
+            // The name used less, or else the one that appears later, is
+            // more likely the mistake.
+            let later = (second_span.file, second_span.offset) > (first_span.file, first_span.offset);
+            let (rare, common, span) = if second_uses < first_uses || (second_uses == first_uses && later) {
+                (second, first, second_span)
+            } else {
+                (first, second, first_span)
+            };
+            let message = format!("{} and {} {}, but are different variables; is {} meant?", rare, common, how, common);
+            warnings.push(Error::lint("W0009", message, span));
+        }
+    }
+}
+
+// Whether one letter changed, added or removed turns `a` into `b`. Short
+// names such as I and J, numbered names such as X1 and X2, and plurals such
+// as ITEM and ITEMS are meant to differ, so they do not count.
+fn one_letter_apart(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let (short, long) = if a.len() <= b.len() { (&a, &b) } else { (&b, &a) };
+    if short.len() < 3 || long.len() - short.len() > 1 {
+        return false;
+    }
+    let prefix = short.iter().zip(long.iter()).take_while(|(x, y)| x == y).count();
+    if short.len() == long.len() {
+        let rest = prefix + 1;
+        let digits = short[prefix].is_ascii_digit() && long[prefix].is_ascii_digit();
+        return short[rest..] == long[rest..] && !digits;
+    }
+    let plural = prefix == short.len() && long[prefix].eq_ignore_ascii_case(&'s');
+    short[prefix..] == long[prefix + 1..] && !plural
+}
+
+// Statements nested more blocks deep than `max`, counted as `metrics` counts
+// them: from the start of the main program or of a procedure, with `else if`
+// as deep as the `if` before it. Statements inside one already reported are
+// not reported again.
+struct Nesting<'w> {
+    max: usize,
+    depth: usize,
+    warnings: &'w mut Vec<Error>,
+}
+
+impl<'a> Visit<'a> for Nesting<'_> {
+    fn visit_node(&mut self, node: &'a AstNode) {
+        if !matches!(node.kind, NodeKind::If(..) | NodeKind::Loop(..) | NodeKind::Case(..) | NodeKind::Try(..)) {
+            return visit::walk_node(self, node);
+        }
+        self.depth += 1;
+        match &node.kind {
+            NodeKind::If(condition, true_branch, false_branch) => {
+                self.visit_node(condition);
+                self.visit_block(true_branch);
+                match false_branch.as_slice() {
+                    [else_if @ AstNode { kind: NodeKind::If(..), .. }] => {
+                        self.depth -= 1;
+                        self.visit_node(else_if);
+                        self.depth += 1;
+                    }
+                    _ => self.visit_block(false_branch),
+                }
+            }
+            _ => visit::walk_node(self, node),
+        }
+        self.depth -= 1;
+    }
+
+    fn visit_block(&mut self, statements: &'a [AstNode]) {
+        for statement in statements {
+            if self.depth > self.max {
+                let message =
+                    format!("This statement is nested {} blocks deep, over the limit of {}", self.depth, self.max);
+                self.warnings.push(Error::lint("W0015", message, statement.span));
+            } else {
+                self.visit_node(statement);
+            }
+        }
+    }
+
+    fn visit_procedure(&mut self, procedure: &'a Procedure) {
+        let depth = std::mem::take(&mut self.depth);
+        visit::walk_procedure(self, procedure);
+        self.depth = depth;
+    }
+}
+
+// `/` where only an integer will do: an array index or size, or beside `div`,
+// `mod` or a bitwise operator. `/` always gives a real number, so the program
+// fails there or works with a fraction.
+fn real_division(program: &AstNode, warnings: &mut Vec<Error>) {
+    program.walk(&mut |node| {
+        let integers: Vec<&AstNode> = match &node.kind {
+            NodeKind::Index(_, index) => vec![index],
+            NodeKind::SetElement(_, indices, _) | NodeKind::NewArray(indices) => indices.iter().collect(),
+            NodeKind::BinOp(left, op, right) if matches!(op, BinaryOp::Div | BinaryOp::Mod) || op.is_bitwise() => {
+                vec![left, right]
+            }
+            _ => return,
+        };
+        for operand in integers {
+            if let NodeKind::BinOp(_, BinaryOp::Slash, _) = operand.kind {
+                let message = "/ gives a real number, but this needs an integer; div divides giving an integer";
+                warnings.push(Error::lint("W0022", message, operand.span));
+            }
+        }
+    });
+}
+
+fn long_routines(program: &AstNode, max: usize, warnings: &mut Vec<Error>) {
+    program.walk(&mut |node| {
+        let NodeKind::Procedure(procedure) = &node.kind else {
+            return;
+        };
+        let lines = node.span.end_line - node.span.line + 1;
+        if lines > max {
+            let message = format!("{} is {} lines long, over the limit of {}", procedure.name, lines, max);
+            warnings.push(Error::lint("W0016", message, node.span));
+        }
+    });
+}
+
+// Reported where the first global over the limit is first set.
+fn too_many_globals(program: &AstNode, max: usize, warnings: &mut Vec<Error>) {
+    let mut globals = Assigned::default();
+    globals.visit_node(program);
+    let mut spans: Vec<Span> = globals.names.into_values().collect();
+    if spans.len() <= max {
+        return;
+    }
+    spans.sort_by_key(|span| (span.file, span.offset));
+    let message = format!("The program has {} global variables, over the limit of {}", spans.len(), max);
+    warnings.push(Error::lint("W0017", message, spans[max]));
+}
+
+fn banned_builtins(program: &AstNode, banned: &[String], warnings: &mut Vec<Error>) {
+    program.walk(&mut |node| {
+        let name = match &node.kind {
+            NodeKind::Call(name, _) | NodeKind::MethodCall(_, name, _) => name,
+            _ => return,
+        };
+        if banned.contains(name) {
+            warnings.push(Error::lint("W0002", format!("{} is not allowed here; write it by hand", name), node.span));
+        }
+    });
+}
+
+// Flags statements that start on the line where the previous statement, or
+// the header of the enclosing `if` or `loop`, ends.
+struct OneStatementPerLine<'w> {
+    warnings: &'w mut Vec<Error>,
+    // The condition of the `if` or `loop` whose block is visited next.
+    header: Option<Span>,
+}
+
+impl<'a> Visit<'a> for OneStatementPerLine<'_> {
+    fn visit_node(&mut self, node: &'a AstNode) {
+        match &node.kind {
+            NodeKind::If(condition, true_branch, false_branch) => {
+                self.header = Some(condition.span);
+                self.visit_block(true_branch);
+                self.visit_block(false_branch);
+            }
+            NodeKind::Loop(condition, body) => {
+                self.header = Some(condition.span);
+                self.visit_block(body);
+            }
+            _ => visit::walk_node(self, node),
+        }
+    }
+
+    fn visit_block(&mut self, statements: &'a [AstNode]) {
+        let mut previous = self.header.take();
+        for statement in statements {
+            if let Some(previous) = previous {
+                if previous.file == statement.span.file && previous.end_line == statement.span.line {
+                    self.warnings.push(Error::lint(
+                        "W0001",
+                        "Statement shares a line with the previous one; put each statement on its own line",
+                        statement.span,
+                    ));
+                }
+            }
+            self.visit_node(statement);
+            previous = Some(statement.span);
+        }
+    }
+}
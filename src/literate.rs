@@ -0,0 +1,137 @@
+use std::ops::Range;
+use std::path::Path;
+
+// Markdown files are run as literate programs: the code is every fenced block
+// whose info string is `ibc`, in order, and the prose around it is ignored.
+
+// One ```ibc block. Offsets are into the Markdown text.
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    // The lines between the fences.
+    pub code: Range<usize>,
+    // Just past the closing fence's line, or the end of the text if the block
+    // is never closed.
+    pub end: usize,
+}
+
+pub fn is_markdown(filename: &str) -> bool {
+    let extension = Path::new(filename).extension().and_then(|extension| extension.to_str());
+    matches!(extension, Some("md" | "markdown"))
+}
+
+// The ```ibc blocks of `markdown`. Blocks fenced with `~~~` count too, and
+// other fenced blocks are skipped whole, so a Markdown example of a ```ibc
+// block inside a longer fence is not run.
+pub fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = vec![];
+    let mut open: Option<(char, usize, bool, usize)> = None;
+    let mut offset = 0;
+    for line in markdown.split_inclusive('\n') {
+        let next = offset + line.len();
+        let fence = fence(line);
+        match (open, fence) {
+            (None, Some((marker, length, info))) => {
+                let code = info.split_whitespace().next() == Some("ibc");
+                open = Some((marker, length, code, next));
+            }
+            (Some((marker, length, code, start)), Some((closing, closing_length, "")))
+                if closing == marker && closing_length >= length =>
+            {
+                if code {
+                    blocks.push(CodeBlock { code: start..offset, end: next });
+                }
+                open = None;
+            }
+            _ => {}
+        }
+        offset = next;
+    }
+    if let Some((_, _, true, start)) = open {
+        blocks.push(CodeBlock { code: start..markdown.len(), end: markdown.len() });
+    }
+    blocks
+}
+
+// The fence character, its length and the info string after it, if `line`
+// opens or closes a fenced block.
+fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let marker = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = trimmed.chars().take_while(|&c| c == marker).count();
+    let info = trimmed[length..].trim();
+    // A backtick fence's info string cannot hold backticks.
+    (length >= 3 && !(marker == '`' && info.contains('`'))).then_some((marker, length, info))
+}
+
+// `markdown` with everything outside `blocks` turned to spaces, keeping the
+// line breaks. Positions in the result are positions in the document, so
+// errors point at the right line of the Markdown file.
+pub fn extract(markdown: &str, blocks: &[CodeBlock]) -> String {
+    let mut code = String::with_capacity(markdown.len());
+    for (offset, c) in markdown.char_indices() {
+        if c == '\n' || blocks.iter().any(|block| block.code.contains(&offset)) {
+            code.push(c);
+        } else {
+            code.extend(std::iter::repeat_n(' ', c.len_utf8()));
+        }
+    }
+    code
+}
+
+// `markdown` with each block followed by an ```output block holding what it
+// printed, replacing the output block a previous render left there. Blocks
+// that printed nothing get none.
+pub fn render(markdown: &str, blocks: &[CodeBlock], outputs: &[String]) -> String {
+    let mut out = String::new();
+    let mut offset = 0;
+    for (block, output) in blocks.iter().zip(outputs) {
+        out.push_str(&markdown[offset..block.end]);
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        offset = block.end;
+        // An earlier render's output, after any blank lines.
+        let rest = &markdown[offset..];
+        let blank = rest.len() - rest.trim_start_matches(['\n', '\r', ' ']).len();
+        let blank = rest[..blank].rfind('\n').map_or(0, |index| index + 1);
+        if let Some(end) = fenced_block_end(&rest[blank..], "output") {
+            offset += blank + end;
+        }
+        if !output.is_empty() {
+            out.push_str("\n```output\n");
+            out.push_str(output);
+            if !output.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str("```\n");
+            if markdown[offset..].starts_with(|c: char| c != '\n') {
+                out.push('\n');
+            }
+        }
+    }
+    out.push_str(&markdown[offset..]);
+    out
+}
+
+// Where the fenced block `text` starts with ends, if its info string is
+// `wanted`.
+fn fenced_block_end(text: &str, wanted: &str) -> Option<usize> {
+    let first = text.split_inclusive('\n').next()?;
+    let (marker, length, info) = fence(first)?;
+    if info != wanted {
+        return None;
+    }
+    let mut offset = first.len();
+    for line in text[offset..].split_inclusive('\n') {
+        offset += line.len();
+        if let Some((closing, closing_length, "")) = fence(line) {
+            if closing == marker && closing_length >= length {
+                return Some(offset);
+            }
+        }
+    }
+    None
+}
@@ -1,42 +1,1941 @@
-mod lexer;
-mod parser;
-mod ast;
-mod interpreter;
-
-use clap::{Arg, Command};
-use lexer::Lexer;
-use parser::Parser;
-use interpreter::Interpreter;
+// Errors carry a span and file name for diagnostics; they are only built on
+// the failure path, so their size is not worth boxing away.
+#![allow(clippy::result_large_err)]
+
+mod watch;
+
+use clap::{Arg, ArgMatches, Command};
+use ibcspsuedolang::ast::{AstNode, NodeKind};
+use ibcspsuedolang::cache::ProgramCache;
+use ibcspsuedolang::color::{ColorChoice, Colors, Stream};
+use ibcspsuedolang::config::Config;
+use ibcspsuedolang::doc::DocFormat;
+use ibcspsuedolang::error::{Error, ErrorFormat};
+use ibcspsuedolang::flowchart::FlowchartFormat;
+use ibcspsuedolang::interpreter::{self, Interpreter, Division, Limits, RunOptions, Stats, Undefined};
+use ibcspsuedolang::keywords::Keywords;
+use ibcspsuedolang::lexer::Lexer;
+use ibcspsuedolang::parser::{Dialect, Parser};
+use ibcspsuedolang::serve::{self, ServeOptions};
+use ibcspsuedolang::value::{Preview, Value};
+use ibcspsuedolang::{batch, callgraph, cfg, desugar, doc, equivalence, exam, explore, fingerprint, fix, flowchart, formatter, generate, grammar, heap, lint, literate, metrics, mutate, railroad, reduce, refactor, rpc, sarif, semantic};
+#[cfg(feature = "jit")]
+use ibcspsuedolang::jit;
 use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Level;
+
+// Parsed files, kept for as long as the process runs, so `--watch` only parses
+// the files that changed. Keywords come from the configuration, which is the
+// same for every lookup, so they are not part of the key.
+static PROGRAMS: ProgramCache = ProgramCache::new();
 
 fn main() {
-    let matches = Command::new("ibcspsuedolang")
-        .version("1.0")
-        .author("Ray <ray@example.com>")
-        .about("Interpreter for IBC pseudocode")
-        .arg(Arg::new("file")
-            .help("The input file with IBC pseudocode")
+    let run = Command::new("run")
+        .about("Run a program")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, run as one program in the order given")
             .required(true)
+            .multiple_values(true)
             .index(1))
         .arg(Arg::new("print-ast")
             .help("Print the AST and exit")
             .short('p')
             .long("print-ast")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .possible_values(["text", "json"])
+            .default_missing_value("text"))
+        .arg(Arg::new("print-tokens")
+            .help("Print the token stream and exit")
+            .long("print-tokens")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .possible_values(["text", "json", "semantic"])
+            .default_missing_value("text"))
+        .arg(Arg::new("terminal")
+            .help("Allow the terminal builtins setColor, clearScreen and moveCursor")
+            .long("terminal")
+            .takes_value(false))
+        .arg(Arg::new("render")
+            .help("Run a Markdown file's ```ibc blocks one by one and print the document with each block's output after it")
+            .long("render")
+            .takes_value(false))
+        .arg(Arg::new("output")
+            .help("Write the program's output to this file instead of the console; errors still go to stderr")
+            .short('o')
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("render"))
+        .arg(Arg::new("tee")
+            .help("With --output, also print the program's output to the console")
+            .long("tee")
+            .takes_value(false)
+            .requires("output"))
+        .arg(Arg::new("stats")
+            .help("After the run, print to stderr the statements and loop iterations run, the deepest call, the most variables at once and the time taken")
+            .long("stats")
+            .takes_value(false)
+            .conflicts_with("render"))
+        .arg(Arg::new("describe-memory")
+            .help("After the run, list on stderr the arrays still alive, with the variables that name them and how many references each has")
+            .long("describe-memory")
+            .takes_value(false)
+            .conflicts_with("render"))
+        .arg(watch_arg());
+
+    #[cfg(feature = "jit")]
+    let run = run.arg(Arg::new("jit")
+        .help("Compile the program to native code, falling back to the interpreter for unsupported constructs")
+        .long("jit")
+        .takes_value(false));
+
+    let check = Command::new("check")
+        .about("Check a program for syntax errors without running it")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, run as one program in the order given")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("format")
+            .help("Output format for the check results")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["text", "sarif"])
+            .default_value("text"))
+        .arg(watch_arg());
+
+    let lint = Command::new("lint")
+        .about("Report style problems in a program")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, linted as one program in the order given")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("strict")
+            .help("Require every statement to be on its own line")
+            .long("strict")
+            .takes_value(false))
+        .arg(Arg::new("ban")
+            .help("Warn about uses of this builtin or method, e.g. --ban sort")
+            .long("ban")
+            .takes_value(true)
+            .multiple_occurrences(true))
+        .arg(Arg::new("max-depth")
+            .help("Warn about statements nested more blocks deep than this")
+            .long("max-depth")
+            .takes_value(true))
+        .arg(Arg::new("max-routine-lines")
+            .help("Warn about procedures longer than this many lines")
+            .long("max-routine-lines")
+            .takes_value(true))
+        .arg(Arg::new("max-globals")
+            .help("Warn when the program has more global variables than this")
+            .long("max-globals")
+            .takes_value(true))
+        .arg(Arg::new("profile")
+            .help("Also check a set of rules, and report how the program does on each")
+            .long("profile")
+            .takes_value(true)
+            .possible_values(["ib-exam"]))
+        .arg(Arg::new("format")
+            .help("Output format for the lint results")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["text", "sarif"])
+            .default_value("text"))
+        .arg(Arg::new("fix")
+            .help("Fix keyword casing, == in conditions, redundant parentheses and unused variables in the files")
+            .long("fix")
+            .takes_value(false)
+            .conflicts_with("watch"))
+        .arg(Arg::new("dry-run")
+            .help("With --fix, print the fixes as a diff instead of writing them")
+            .long("dry-run")
+            .takes_value(false)
+            .requires("fix"))
+        .arg(watch_arg());
+
+    let batch = Command::new("batch")
+        .about("Run many programs in parallel, each with its own interpreter")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("jobs")
+            .help("Number of programs to run at once (defaults to the number of CPUs)")
+            .short('j')
+            .long("jobs")
+            .takes_value(true));
+
+    let fmt = Command::new("fmt")
+        .about("Print programs in a canonical layout, optionally translating keywords to another language")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("to")
+            .help("Spell keywords in this language instead of the one the files are written in")
+            .long("to")
+            .takes_value(true))
+        .arg(Arg::new("write")
+            .help("Rewrite the files in place instead of printing them")
+            .long("write")
+            .takes_value(false))
+        .arg(Arg::new("strict")
+            .help("Rewrite conditional expressions as if statements, as IB pseudocode has no conditional expressions")
+            .long("strict")
             .takes_value(false))
+        .arg(Arg::new("canonical")
+            .help("Rewrite in IB exam notation with keywords in capitals, or from it back into lowercase keywords")
+            .long("canonical")
+            .takes_value(true)
+            .possible_values(["ib", "lowercase"])
+            .conflicts_with("to"))
+        .arg(Arg::new("end-style")
+            .help("With --canonical ib, write closing keywords as two words (END IF) or as one (ENDIF)")
+            .long("end-style")
+            .takes_value(true)
+            .possible_values(["spaced", "joined"])
+            .requires("canonical"));
+
+    let flowchart = Command::new("flowchart")
+        .about("Print the control flow of a program as a Mermaid or Graphviz DOT flowchart")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, drawn as one program in the order given")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("format")
+            .help("Flowchart format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["mermaid", "dot"])
+            .default_value("mermaid"));
+
+    let doc = Command::new("doc")
+        .about("Print reference documentation for a program's procedures from their /// comments")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, documented as one program in the order given")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("format")
+            .help("Documentation format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["markdown", "html"])
+            .default_value("markdown"));
+
+    let cfg = Command::new("cfg")
+        .about("Print the control-flow graph of a program and of each of its procedures")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, analysed as one program in the order given")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("format")
+            .help("Graph format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["dot", "json"])
+            .default_value("dot"))
+        .arg(Arg::new("procedure")
+            .help("Print only the graph of this procedure")
+            .long("procedure")
+            .takes_value(true));
+
+    let calls = Command::new("calls")
+        .about("Print which procedures call which, flagging recursion")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, analysed as one program in the order given")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("format")
+            .help("Call graph format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["text", "dot"])
+            .default_value("text"));
+
+    let metrics = Command::new("metrics")
+        .about("Report the size and complexity of a program and each of its procedures")
+        .arg(Arg::new("files")
+            .help("The input files with IBC pseudocode, measured as one program in the order given")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("format")
+            .help("Output format for the report")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["text", "json"])
+            .default_value("text"))
+        .arg(Arg::new("max-statements")
+            .help("Fail if a routine has more statements than this")
+            .long("max-statements")
+            .takes_value(true))
+        .arg(Arg::new("max-depth")
+            .help("Fail if a routine nests blocks deeper than this")
+            .long("max-depth")
+            .takes_value(true))
+        .arg(Arg::new("max-complexity")
+            .help("Fail if a routine's cyclomatic complexity is higher than this")
+            .long("max-complexity")
+            .takes_value(true))
+        .arg(Arg::new("max-variables")
+            .help("Fail if a routine uses more variables than this")
+            .long("max-variables")
+            .takes_value(true));
+
+    let diff_run = Command::new("diff-run")
+        .about("Run two programs on the same inputs and report where their output first differs")
+        .arg(Arg::new("expected")
+            .help("The reference program")
+            .required(true)
+            .index(1))
+        .arg(Arg::new("actual")
+            .help("The program to compare with it")
+            .required(true)
+            .index(2))
+        .arg(Arg::new("inputs")
+            .help("Directory with one file of standard input per test case (defaults to one case with no input)")
+            .long("inputs")
+            .takes_value(true))
+        .arg(Arg::new("max-steps")
+            .help("Most statements a program may run per case")
+            .long("max-steps")
+            .takes_value(true)
+            .default_value("10000000"))
+        .arg(Arg::new("timeout")
+            .help("Most milliseconds a program may run for per case")
+            .long("timeout")
+            .takes_value(true)
+            .default_value("5000"));
+
+    let fingerprint = Command::new("fingerprint")
+        .about("Fingerprint each submission's structure and output, and report pairs too alike to be independent work")
+        .arg(Arg::new("files")
+            .help("The submissions, one file each")
+            .required(true)
+            .multiple_values(true)
+            .index(1))
+        .arg(Arg::new("inputs")
+            .help("Directory with one file of standard input per test case (defaults to one case with no input)")
+            .long("inputs")
+            .takes_value(true))
+        .arg(Arg::new("threshold")
+            .help("Report pairs whose structure is at least this percent alike")
+            .long("threshold")
+            .takes_value(true)
+            .default_value("80"))
+        .arg(Arg::new("format")
+            .help("Output format for the report")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["text", "json"])
+            .default_value("text"))
+        .arg(Arg::new("max-steps")
+            .help("Most statements a program may run per case")
+            .long("max-steps")
+            .takes_value(true)
+            .default_value("10000000"))
+        .arg(Arg::new("timeout")
+            .help("Most milliseconds a program may run for per case")
+            .long("timeout")
+            .takes_value(true)
+            .default_value("5000"));
+
+    let generate = Command::new("generate")
+        .about("Print a random program, or generate many and check each parses and runs, to fuzz the interpreter")
+        .arg(Arg::new("seed")
+            .help("Seed for the random choices; the same seed and options give the same program (defaults to the time)")
+            .long("seed")
+            .takes_value(true))
+        .arg(Arg::new("statements")
+            .help("Statements in the main program, not counting those nested in others")
+            .long("statements")
+            .takes_value(true)
+            .default_value("8"))
+        .arg(Arg::new("depth")
+            .help("How deeply if, loop and case statements may nest")
+            .long("depth")
+            .takes_value(true)
+            .default_value("2"))
+        .arg(Arg::new("functions")
+            .help("Number of functions to define and call")
+            .long("functions")
+            .takes_value(true)
+            .default_value("1"))
+        .arg(Arg::new("mix")
+            .help("Relative weights of assign, output, if, loop, case and array statements, e.g. loop=3,case=0")
+            .long("mix")
+            .takes_value(true))
+        .arg(Arg::new("fuzz")
+            .help("Instead of printing a program, check this many, one seed after another, and report failures")
+            .long("fuzz")
+            .takes_value(true));
+
+    let mutate = Command::new("mutate")
+        .about("Make small changes to a correct program and check whether test inputs catch each one")
+        .arg(Arg::new("file")
+            .help("The correct program")
+            .required(true)
+            .index(1))
+        .arg(Arg::new("inputs")
+            .help("Directory with one file of standard input per test case (defaults to one case with no input)")
+            .long("inputs")
+            .takes_value(true))
+        .arg(Arg::new("max-steps")
+            .help("Most statements a program may run per case")
+            .long("max-steps")
+            .takes_value(true)
+            .default_value("1000000"))
+        .arg(Arg::new("timeout")
+            .help("Most milliseconds a program may run for per case")
+            .long("timeout")
+            .takes_value(true)
+            .default_value("1000"));
+
+    let quiz = Command::new("quiz")
+        .about("Show a program, ask what it will print, then run it and mark the answer line by line")
+        .arg(Arg::new("file")
+            .help("The program to quiz on")
+            .required(true)
+            .index(1))
+        .arg(Arg::new("input")
+            .help("File to give the program as standard input (defaults to none)")
+            .long("input")
+            .takes_value(true))
+        .arg(Arg::new("max-steps")
+            .help("Most statements the program may run")
+            .long("max-steps")
+            .takes_value(true)
+            .default_value("10000000"))
+        .arg(Arg::new("timeout")
+            .help("Most milliseconds the program may run for")
+            .long("timeout")
+            .takes_value(true)
+            .default_value("5000"));
+
+    let reduce = Command::new("reduce")
+        .about("Shrink a program that shows a bug to a small one that still does, for a bug report")
+        .arg(Arg::new("file")
+            .help("The program that shows the bug")
+            .required(true)
+            .index(1))
+        .arg(Arg::new("error")
+            .help("The bug is that the program stops with this error code, e.g. E0202 (by default, that the interpreter panics)")
+            .long("error")
+            .takes_value(true))
+        .arg(Arg::new("output")
+            .help("The bug is that the output contains this text")
+            .long("output")
+            .takes_value(true))
+        .arg(Arg::new("command")
+            .help("The bug is that this shell command succeeds when given the path of a candidate program")
+            .long("command")
+            .takes_value(true))
+        .arg(Arg::new("input")
+            .help("File to give the program as standard input (defaults to none)")
+            .long("input")
+            .takes_value(true))
+        .arg(Arg::new("max-steps")
+            .help("Most statements a candidate may run")
+            .long("max-steps")
+            .takes_value(true)
+            .default_value("100000"))
+        .arg(Arg::new("timeout")
+            .help("Most milliseconds a candidate may run for")
+            .long("timeout")
+            .takes_value(true)
+            .default_value("1000"));
+
+    #[cfg(feature = "jit")]
+    let reduce = reduce.arg(Arg::new("jit")
+        .help("The bug is that the program prints something different or stops differently when compiled with --jit")
+        .long("jit")
+        .takes_value(false));
+
+    let refactor = Command::new("refactor")
+        .about("Change a program's names without changing what it does, keeping its layout and comments")
+        .subcommand_required(true)
+        .subcommand(Command::new("rename")
+            .about("Rename a variable or procedure everywhere it is used")
+            .arg(Arg::new("old")
+                .help("The name to change")
+                .required(true)
+                .index(1))
+            .arg(Arg::new("new")
+                .help("The name to change it to")
+                .required(true)
+                .index(2))
+            .arg(Arg::new("file")
+                .help("The input file with IBC pseudocode")
+                .required(true)
+                .index(3))
+            .arg(Arg::new("write")
+                .help("Rewrite the file in place instead of printing it")
+                .long("write")
+                .takes_value(false)))
+        .subcommand(Command::new("extract")
+            .about("Move the statements on some lines into a new procedure and call it in their place")
+            .arg(Arg::new("lines")
+                .help("The lines to move, such as 12-18, holding whole statements of one block")
+                .required(true)
+                .index(1))
+            .arg(Arg::new("name")
+                .help("The name of the new procedure")
+                .required(true)
+                .index(2))
+            .arg(Arg::new("file")
+                .help("The input file with IBC pseudocode")
+                .required(true)
+                .index(3))
+            .arg(Arg::new("write")
+                .help("Rewrite the file in place instead of printing it")
+                .long("write")
+                .takes_value(false)));
+
+    // The limits `serve` and `rpc` put on each request, which it can lower.
+    let request_limits = [
+        Arg::new("max-steps")
+            .help("Most statements a program may run")
+            .long("max-steps")
+            .takes_value(true)
+            .default_value("10000000"),
+        Arg::new("max-output")
+            .help("Most bytes a program may write")
+            .long("max-output")
+            .takes_value(true)
+            .default_value("1000000"),
+        Arg::new("timeout")
+            .help("Most milliseconds a program may run for")
+            .long("timeout")
+            .takes_value(true)
+            .default_value("5000"),
+        Arg::new("max-memory")
+            .help("Most bytes a program's strings and arrays may take at once")
+            .long("max-memory")
+            .takes_value(true)
+            .default_value("67108864"),
+    ];
+
+    let serve = Command::new("serve")
+        .about("Run programs sent over HTTP as JSON to POST /run, with parse cache statistics at GET /stats")
+        .arg(Arg::new("address")
+            .help("Address to listen on")
+            .long("address")
+            .takes_value(true)
+            .default_value("127.0.0.1:8080"))
+        .arg(Arg::new("jobs")
+            .help("Number of requests to handle at once (defaults to the number of CPUs)")
+            .short('j')
+            .long("jobs")
+            .takes_value(true))
+        .args(request_limits.clone());
+
+    let rpc = Command::new("rpc")
+        .about("Answer JSON-RPC run, check and format requests on standard input, one per line")
+        .args(request_limits);
+
+    let explore = Command::new("explore")
+        .about("Type statements to see their tokens beside the tree they parse to, without running them");
+
+    let grammar = Command::new("grammar")
+        .about("Print a syntax highlighting grammar for editors or the EBNF of the language, using the keywords of --lang")
+        .arg(Arg::new("format")
+            .help("Grammar format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["tmlanguage", "ebnf", "svg"])
+            .default_value("tmlanguage"))
+        .arg(Arg::new("out-dir")
+            .help("Directory to write one railroad diagram per rule into, for --format svg")
+            .long("out-dir")
+            .takes_value(true)
+            .required_if_eq("format", "svg"))
+        .arg(Arg::new("check")
+            .help("Check the EBNF against the lexer's keywords and operators instead of printing a grammar")
+            .long("check"));
+
+    let matches = Command::new("ibcspsuedolang")
+        .version("1.0")
+        .author("Ray <ray@example.com>")
+        .about("Interpreter for IBC pseudocode")
+        .arg(Arg::new("color")
+            .help("When to use colored output")
+            .long("color")
+            .global(true)
+            .takes_value(true)
+            .possible_values(["auto", "always", "never"]))
+        .arg(Arg::new("error-format")
+            .help("How to print errors")
+            .long("error-format")
+            .global(true)
+            .takes_value(true)
+            .possible_values(["human", "json"]))
+        .arg(Arg::new("lang")
+            .help("Language the program's keywords are written in (en, es, fr or one defined in ibc.toml)")
+            .long("lang")
+            .global(true)
+            .takes_value(true))
+        .arg(Arg::new("dialect")
+            .help("Accept only IB pseudocode, or also extended syntax such as +=")
+            .long("dialect")
+            .global(true)
+            .takes_value(true)
+            .possible_values(["ib", "extended"]))
+        .arg(Arg::new("array-base")
+            .help("Index of the first element of an array, for teaching materials that count from 1")
+            .long("array-base")
+            .global(true)
+            .takes_value(true)
+            .possible_values(["0", "1"]))
+        .arg(Arg::new("undefined")
+            .help("Stop at a variable read before it is set, or read it as 0 (\"\" beside a string) with a warning")
+            .long("undefined")
+            .global(true)
+            .takes_value(true)
+            .possible_values(["strict", "zero"]))
+        .arg(Arg::new("division")
+            .help("Round div and mod toward zero, so -7 mod 2 is -1, or down, so -7 mod 2 is 1")
+            .long("division")
+            .global(true)
+            .takes_value(true)
+            .possible_values(["truncate", "floor"]))
+        .arg(Arg::new("config")
+            .help("Read settings from this file instead of ./ibc.toml")
+            .long("config")
+            .global(true)
+            .takes_value(true))
+        .arg(Arg::new("verbose")
+            .help("Log interpreter internals to stderr (-v for statements, -vv for tokens and values)")
+            .short('v')
+            .long("verbose")
+            .global(true)
+            .multiple_occurrences(true))
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(run)
+        .subcommand(check)
+        .subcommand(lint)
+        .subcommand(batch)
+        .subcommand(fmt)
+        .subcommand(flowchart)
+        .subcommand(doc)
+        .subcommand(cfg)
+        .subcommand(calls)
+        .subcommand(metrics)
+        .subcommand(diff_run)
+        .subcommand(fingerprint)
+        .subcommand(generate)
+        .subcommand(mutate)
+        .subcommand(quiz)
+        .subcommand(reduce)
+        .subcommand(refactor)
+        .subcommand(serve)
+        .subcommand(rpc)
+        .subcommand(explore)
+        .subcommand(grammar)
         .get_matches();
 
-    let filename = matches.value_of("file").unwrap();
-    let input = fs::read_to_string(filename).expect("Failed to read input file");
+    let mut config = Config::load(matches.value_of("config")).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+    if let Some(color) = matches.value_of("color") {
+        config.color = ColorChoice::from_name(color);
+    }
+    if let Some(format) = matches.value_of("error-format") {
+        config.error_format = ErrorFormat::from_name(format);
+    }
+    if let Some(lang) = matches.value_of("lang") {
+        config.lang = Some(lang.to_string());
+    }
+    if let Some(dialect) = matches.value_of("dialect") {
+        config.dialect = Dialect::from_name(dialect);
+    }
+    if let Some(base) = matches.value_of("array-base") {
+        config.array_base = base.parse().ok();
+    }
+    if let Some(undefined) = matches.value_of("undefined") {
+        config.undefined = Undefined::from_name(undefined);
+    }
+    if let Some(division) = matches.value_of("division") {
+        config.division = Division::from_name(division);
+    }
+    if let Some(base) = config.array_base.filter(|base| !matches!(base, 0 | 1)) {
+        eprintln!("array-base must be 0 or 1, not {}", base);
+        process::exit(2);
+    }
+
+    let level = match matches.occurrences_of("verbose") {
+        0 => Level::WARN,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(colors(&config, Stream::Stderr).enabled())
+        .without_time()
+        .init();
+
+    match matches.subcommand() {
+        Some(("run", matches)) => with_watch(matches, &config, || run_command(matches, &config)),
+        Some(("check", matches)) => with_watch(matches, &config, || check_command(matches, &config)),
+        Some(("lint", matches)) => with_watch(matches, &config, || lint_command(matches, &config)),
+        Some(("batch", matches)) => batch_command(matches, &config),
+        Some(("fmt", matches)) => exit_on_error(fmt_command(matches, &config), &input_files(matches), &config),
+        Some(("flowchart", matches)) => {
+            exit_on_error(flowchart_command(matches, &config), &input_files(matches), &config)
+        }
+        Some(("doc", matches)) => exit_on_error(doc_command(matches, &config), &input_files(matches), &config),
+        Some(("cfg", matches)) => exit_on_error(cfg_command(matches, &config), &input_files(matches), &config),
+        Some(("calls", matches)) => exit_on_error(calls_command(matches, &config), &input_files(matches), &config),
+        Some(("metrics", matches)) => exit_on_error(metrics_command(matches, &config), &input_files(matches), &config),
+        Some(("diff-run", matches)) => {
+            let files = [matches.value_of("expected").unwrap().to_string(), matches.value_of("actual").unwrap().to_string()];
+            exit_on_error(diff_run_command(matches, &config), &files, &config)
+        }
+        Some(("fingerprint", matches)) => {
+            exit_on_error(fingerprint_command(matches, &config), &input_files(matches), &config)
+        }
+        Some(("generate", matches)) => exit_on_error(generate_command(matches, &config), &[], &config),
+        Some(("mutate", matches)) => {
+            let files = [matches.value_of("file").unwrap().to_string()];
+            exit_on_error(mutate_command(matches, &config), &files, &config)
+        }
+        Some(("quiz", matches)) => {
+            let files = [matches.value_of("file").unwrap().to_string()];
+            exit_on_error(quiz_command(matches, &config), &files, &config)
+        }
+        Some(("reduce", matches)) => {
+            let files = [matches.value_of("file").unwrap().to_string()];
+            exit_on_error(reduce_command(matches, &config), &files, &config)
+        }
+        Some(("refactor", matches)) => {
+            let files = [matches.subcommand().and_then(|(_, matches)| matches.value_of("file")).unwrap().to_string()];
+            exit_on_error(refactor_command(matches, &config), &files, &config)
+        }
+        Some(("serve", matches)) => exit_on_error(serve_command(matches, &config), &[], &config),
+        Some(("grammar", matches)) => exit_on_error(grammar_command(matches, &config), &[], &config),
+        Some(("rpc", matches)) => exit_on_error(rpc_command(matches, &config), &[], &config),
+        Some(("explore", _)) => exit_on_error(explore_command(&config), &[], &config),
+        _ => unreachable!(),
+    }
+}
+
+fn watch_arg() -> Arg<'static> {
+    Arg::new("watch")
+        .help("Re-run whenever the file changes")
+        .short('w')
+        .long("watch")
+        .takes_value(false)
+}
+
+fn colors(config: &Config, stream: Stream) -> Colors {
+    Colors::new(config.color.unwrap_or(ColorChoice::Auto), stream)
+}
+
+fn error_format(config: &Config) -> ErrorFormat {
+    config.error_format.unwrap_or(ErrorFormat::Human)
+}
+
+fn keywords(config: &Config) -> Result<Keywords, Error> {
+    config.keywords(None)
+}
+
+fn dialect(config: &Config) -> Dialect {
+    config.dialect.unwrap_or_default()
+}
+
+fn input_files(matches: &ArgMatches) -> Vec<String> {
+    matches.values_of("files").unwrap().map(String::from).collect()
+}
+
+fn with_watch(matches: &ArgMatches, config: &Config, command: impl Fn() -> Result<(), Error>) {
+    let files = input_files(matches);
+
+    if matches.is_present("watch") {
+        watch::watch(&files, || {
+            if let Err(err) = command() {
+                report(err, &files, config);
+            }
+            let stats = PROGRAMS.stats();
+            tracing::debug!(hits = stats.hits, misses = stats.misses, "parsed program cache");
+        });
+    }
+
+    exit_on_error(command(), &files, config);
+}
+
+fn exit_on_error(result: Result<(), Error>, files: &[String], config: &Config) {
+    if let Err(err) = result {
+        report(err, files, config);
+        process::exit(1);
+    }
+}
+
+fn report(err: Error, files: &[String], config: &Config) {
+    let colors = colors(config, Stream::Stderr);
+    eprintln!("{}", err.with_file_names(files).format(error_format(config), &colors));
+}
+
+fn read_source(filename: &str) -> Result<String, Error> {
+    fs::read_to_string(filename).map_err(|e| Error::io(format!("Failed to read {}: {}", filename, e)))
+}
+
+// The pseudocode in a file: all of it, or the ```ibc blocks of a Markdown file
+// with the prose blanked out.
+fn read_program(filename: &str) -> Result<String, Error> {
+    let source = read_source(filename)?;
+    if literate::is_markdown(filename) {
+        return Ok(literate::extract(&source, &literate::code_blocks(&source)));
+    }
+    Ok(source)
+}
+
+// Parses each file and joins their statements into a single program. Spans
+// record which file they came from so diagnostics can name it.
+fn load_program(files: &[String], keywords: &Keywords, dialect: Dialect) -> Result<AstNode, Error> {
+    let mut statements = vec![];
+    let mut span = None;
+
+    for (index, filename) in files.iter().enumerate() {
+        let input = read_program(filename)?;
+        let ast = PROGRAMS.get_or_parse(&(&input, index, keywords, dialect), || {
+            let lexer = Lexer::for_file(&input, index).with_keywords(keywords);
+            Parser::new(lexer)?.with_dialect(dialect).parse()
+        })?;
+
+        span.get_or_insert(ast.span);
+        if let NodeKind::Program(more) = &ast.kind {
+            statements.extend(more.iter().cloned());
+        }
+    }
+
+    Ok(AstNode::new(NodeKind::Program(statements), span.unwrap_or_default()))
+}
 
-    let lexer = Lexer::new(&input);
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
+fn check_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let files = input_files(matches);
+    let keywords = keywords(config)?;
 
-    if matches.is_present("print-ast") {
-        let interpreter = Interpreter::new();
-        interpreter.print_ast(&ast, 0);
+    if matches.value_of("format") == Some("sarif") {
+        let errors: Vec<Error> = load_program(&files, &keywords, dialect(config)).err().into_iter().map(|e| e.with_file_names(&files)).collect();
+        println!("{}", serde_json::to_string_pretty(&sarif::report(&files, &errors)).unwrap());
+        if !errors.is_empty() && !matches.is_present("watch") {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    load_program(&files, &keywords, dialect(config))?;
+    let colors = colors(config, Stream::Stdout);
+    println!("{}: {}", files.join(", "), colors.success("no errors found"));
+    Ok(())
+}
+
+fn lint_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let files = input_files(matches);
+    let mut banned = config.lint.banned_builtins.clone();
+    banned.extend(matches.values_of("ban").into_iter().flatten().map(String::from));
+    let options = lint::LintOptions {
+        strict: matches.is_present("strict") || config.lint.strict == Some(true),
+        banned,
+        max_depth: number(matches, "max-depth").or(config.lint.max_depth),
+        max_routine_lines: number(matches, "max-routine-lines").or(config.lint.max_routine_lines),
+        max_globals: number(matches, "max-globals").or(config.lint.max_globals),
+    };
+    // Exam notation is checked on programs in any case, so they must parse.
+    let exam = matches.value_of("profile") == Some("ib-exam");
+    let keywords = if exam { Keywords::exam() } else { keywords(config)? };
+    if matches.is_present("fix") {
+        fix_files(&files, &keywords, dialect(config), matches.is_present("dry-run"), config)?;
+        if matches.is_present("dry-run") {
+            return Ok(());
+        }
+    }
+
+    let program = load_program(&files, &keywords, dialect(config))?;
+    let mut warnings = lint::lint(&program, &options);
+    let sources = files.iter().map(|file| read_program(file)).collect::<Result<Vec<_>, _>>()?;
+    warnings.extend(fix::suggestions(&sources, &keywords, dialect(config))?.into_iter().map(|fix| fix.warning));
+    if exam {
+        warnings.extend(exam::check(&sources, &program, &keywords)?);
+    }
+    warnings.sort_by_key(|warning| warning.span.map(|span| (span.file, span.offset)));
+    let warnings: Vec<Error> = warnings.into_iter().map(|warning| warning.with_file_names(&files)).collect();
+
+    if matches.value_of("format") == Some("sarif") {
+        println!("{}", serde_json::to_string_pretty(&sarif::report(&files, &warnings)).unwrap());
+    } else if warnings.is_empty() {
+        let colors = colors(config, Stream::Stdout);
+        println!("{}: {}", files.join(", "), colors.success("no warnings"));
     } else {
-        let mut interpreter = Interpreter::new();
-        interpreter.interpret(&ast);
+        let colors = colors(config, Stream::Stderr);
+        for warning in &warnings {
+            eprintln!("{}", warning.format(error_format(config), &colors));
+        }
+    }
+    if exam && matches.value_of("format") != Some("sarif") {
+        print_compliance(&exam::report(&warnings), &colors(config, Stream::Stdout));
+    }
+
+    if !warnings.is_empty() && !matches.is_present("watch") {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// How the program did on each rule of a `--profile`, as a mark sheet.
+fn print_compliance(report: &[exam::Compliance], colors: &Colors) {
+    let met = report.iter().filter(|rule| rule.problems == 0).count();
+    println!("IB exam notation: {} of {} rules met", met, report.len());
+    for rule in report {
+        let (mark, problems) = match rule.problems {
+            0 => (colors.success("pass"), String::new()),
+            1 => (colors.error("fail"), " (1 problem)".to_string()),
+            n => (colors.error("fail"), format!(" ({} problems)", n)),
+        };
+        println!("  {}  {} {}{}", mark, rule.code, rule.rule, colors.dim(&problems));
+    }
+}
+
+// Makes the fixes `lint --fix` makes and writes the files, or prints them as
+// a diff when `dry_run`. What was fixed goes to stderr.
+fn fix_files(
+    files: &[String],
+    keywords: &Keywords,
+    dialect: Dialect,
+    dry_run: bool,
+    config: &Config,
+) -> Result<(), Error> {
+    if let Some(file) = files.iter().find(|file| literate::is_markdown(file)) {
+        return Err(Error::io(format!("{} is a Markdown file; lint --fix only edits pseudocode files", file)));
+    }
+    let sources = files.iter().map(|file| read_source(file)).collect::<Result<Vec<_>, _>>()?;
+    let fixed = fix::fix(&sources, keywords, dialect)?;
+
+    let colors = colors(config, Stream::Stderr);
+    for warning in &fixed.fixed {
+        let warning = warning.clone().with_file_names(files);
+        eprintln!("{} {}", colors.success("fixed"), warning.format(error_format(config), &colors));
+    }
+    for ((file, old), new) in files.iter().zip(&sources).zip(&fixed.sources) {
+        if old == new {
+            continue;
+        }
+        if dry_run {
+            print!("{}", fix::diff(file, old, new));
+        } else {
+            fs::write(file, new).map_err(|e| Error::io(format!("Failed to write {}: {}", file, e)))?;
+        }
+    }
+    let verb = if dry_run { "Would fix" } else { "Fixed" };
+    eprintln!("{} {} problem{}", verb, fixed.fixed.len(), if fixed.fixed.len() == 1 { "" } else { "s" });
+    Ok(())
+}
+
+fn print_tokens(files: &[String], format: &str, keywords: &Keywords) -> Result<(), Error> {
+    for (index, filename) in files.iter().enumerate() {
+        let input = read_program(filename)?;
+        if files.len() > 1 {
+            println!("==> {} <==", filename);
+        }
+        // Highlighting classes for editors, which skip what fails to lex.
+        if format == "semantic" {
+            println!("{}", serde_json::to_string_pretty(&semantic::classify(&input, keywords)).unwrap());
+            continue;
+        }
+
+        let tokens = Lexer::for_file(&input, index).with_keywords(keywords).tokenize()?;
+        if format == "json" {
+            println!("{}", serde_json::to_string_pretty(&tokens).unwrap());
+        } else {
+            for info in tokens {
+                let span = info.span;
+                let position = format!("{}:{}-{}:{}", span.line, span.column, span.end_line, span.end_column);
+                println!("{:<16}{:?}", position, info.token);
+            }
+        }
     }
+    Ok(())
+}
+
+// Programs run on their own thread so deep recursion has room to grow.
+fn run_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn_scoped(scope, || run_program(matches, config))
+            .expect("failed to start the interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+fn run_program(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    if let Some(format) = matches.value_of("print-tokens") {
+        return print_tokens(&input_files(matches), format, &keywords);
+    }
+
+    if matches.is_present("render") {
+        return render_markdown(&input_files(matches), &keywords, matches, config);
+    }
+
+    let ast = load_program(&input_files(matches), &keywords, dialect(config))?;
+
+    match matches.value_of("print-ast") {
+        Some("json") => {
+            println!("{}", serde_json::to_string_pretty(&ast).unwrap());
+            return Ok(());
+        }
+        Some(_) => {
+            print!("{}", ast.to_pretty_string(&colors(config, Stream::Stdout)));
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let ast = desugar::desugar(ast)?;
+
+    // Compiled programs print straight to the console, keep no counts and
+    // always stop at a variable that was never set.
+    #[cfg(feature = "jit")]
+    if (matches.is_present("jit") || config.jit == Some(true))
+        && !matches.is_present("output")
+        && !matches.is_present("stats")
+        && !matches.is_present("describe-memory")
+        && config.undefined != Some(Undefined::Zero)
+    {
+        if let Some(program) = jit::compile(&ast, config.division.unwrap_or_default()) {
+            tracing::debug!("running JIT-compiled program");
+            return program.run();
+        }
+        tracing::debug!("program uses constructs the JIT does not support, falling back to the interpreter");
+    }
+
+    let mut interpreter = Interpreter::with_output(program_output(matches)?);
+    interpreter.allow_terminal(matches.is_present("terminal") || config.terminal == Some(true));
+    interpreter.set_options(config.run_options());
+    let result = interpreter.interpret(Arc::new(ast));
+    for warning in interpreter.warnings() {
+        report(warning.clone(), &input_files(matches), config);
+    }
+    // Printed even when the program fails, as the counts up to the error can
+    // show where it went wrong.
+    if matches.is_present("stats") {
+        print_stats(&interpreter.stats(), &colors(config, Stream::Stderr));
+    }
+    if matches.is_present("describe-memory") {
+        print_memory(&interpreter, &colors(config, Stream::Stderr));
+    }
+    result
+}
+
+// Lists the arrays still alive once those only kept alive by a cycle are
+// freed, each with its number, its references, the variables naming it and a
+// preview of its elements.
+fn print_memory(interpreter: &Interpreter, colors: &Colors) {
+    let freed = heap::collect_cycles();
+    let objects = heap::objects();
+    let variables = interpreter.variables();
+    eprintln!(
+        "{} live array{}, {} freed in cycles",
+        objects.len(),
+        if objects.len() == 1 { "" } else { "s" },
+        freed
+    );
+    for object in &objects {
+        let names: Vec<&str> = variables
+            .iter()
+            .filter(|(_, value)| matches!(value, Value::Array(array) if Rc::ptr_eq(array, &object.array)))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let names = if names.is_empty() { "-".to_string() } else { names.join(", ") };
+        eprintln!(
+            "{}  {}  {} ref{}  {}",
+            colors.dim(&format!("#{}", object.id)),
+            names,
+            object.references,
+            if object.references == 1 { "" } else { "s" },
+            Value::Array(object.array.clone()).preview(Preview::default())
+        );
+    }
+}
+
+fn print_stats(stats: &Stats, colors: &Colors) {
+    let rows = [
+        ("statements", stats.statements.to_string()),
+        ("loop iterations", stats.loop_iterations.to_string()),
+        ("max call depth", stats.max_call_depth.to_string()),
+        ("peak variables", stats.peak_variables.to_string()),
+        ("arrays", stats.arrays.to_string()),
+        ("time", format!("{:.3} ms", stats.time.as_secs_f64() * 1000.0)),
+    ];
+    for (name, value) in rows {
+        eprintln!("{} {:>12}", colors.dim(&format!("{:<16}", name)), value);
+    }
+}
+
+// Where `run` sends what the program outputs: the console, the file given by
+// `--output`, or with `--tee` both.
+fn program_output(matches: &ArgMatches) -> Result<Box<dyn Write>, Error> {
+    let Some(path) = matches.value_of("output") else {
+        return Ok(Box::new(io::stdout()));
+    };
+    let file = fs::File::create(path).map_err(|e| Error::io(format!("Failed to create {}: {}", path, e)))?;
+    if matches.is_present("tee") {
+        return Ok(Box::new(Tee(file, io::stdout())));
+    }
+    Ok(Box::new(file))
+}
+
+// Writes everything to two places.
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+// Runs the blocks of one Markdown file in order in one interpreter, like the
+// cells of a notebook, and prints the file with the output of each after it.
+// A block that fails gets the error as its output, and the blocks after it
+// are not run.
+fn render_markdown(files: &[String], keywords: &Keywords, matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let [file] = files else {
+        return Err(Error::io("--render takes exactly one Markdown file"));
+    };
+    if !literate::is_markdown(file) {
+        return Err(Error::io(format!("--render needs a Markdown file, not {}", file)));
+    }
+    let markdown = read_source(file)?;
+    let blocks = literate::code_blocks(&markdown);
+
+    let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+    interpreter.allow_terminal(matches.is_present("terminal") || config.terminal == Some(true));
+    interpreter.set_options(config.run_options());
+    let mut outputs = vec![];
+    let mut failure = None;
+    for block in &blocks {
+        let source = literate::extract(&markdown, std::slice::from_ref(block));
+        let start = interpreter.output_so_far().len();
+        let result = Parser::new(Lexer::new(&source).with_keywords(keywords))
+            .and_then(|parser| parser.with_dialect(dialect(config)).parse())
+            .and_then(desugar::desugar)
+            .and_then(|program| interpreter.interpret(Arc::new(program)));
+        let mut output = interpreter.output_so_far()[start..].to_string();
+        if let Err(error) = result {
+            let error = error.with_file_names(files);
+            if !output.is_empty() && !output.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str(&error.render(&Colors::plain()));
+            outputs.push(output);
+            failure = Some(error);
+            break;
+        }
+        outputs.push(output);
+    }
+    // Blocks that did not run lose any output a previous render gave them.
+    outputs.resize(blocks.len(), String::new());
+    print!("{}", literate::render(&markdown, &blocks, &outputs));
+    failure.map_or(Ok(()), Err)
+}
+
+// Each file is formatted on its own, so a file that fails to parse is reported
+// before anything is written.
+fn fmt_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let files = input_files(matches);
+    let source_keywords = keywords(config)?;
+    let canonical = matches.value_of("canonical");
+    let target_keywords = match (matches.value_of("to"), canonical) {
+        (Some(lang), _) => Keywords::for_language(lang, &config.keywords)?,
+        (None, Some("ib")) => Keywords::ib(matches.value_of("end-style") != Some("joined")),
+        (None, _) => source_keywords.clone(),
+    };
+
+    let mut formatted = vec![];
+    for (index, filename) in files.iter().enumerate() {
+        if literate::is_markdown(filename) {
+            return Err(Error::io(format!("{} is a Markdown file; fmt only formats pseudocode files", filename)));
+        }
+        let input = read_source(filename)?;
+        let parse = |keywords: &Keywords| {
+            Parser::new(Lexer::for_file(&input, index).with_keywords(keywords))?.with_dialect(dialect(config)).parse()
+        };
+        // With --canonical, a file may be in either notation already.
+        let ast = match parse(&source_keywords) {
+            Err(e) if canonical.is_some() => parse(&Keywords::ib(true)).map_err(|_| e)?,
+            result => result?,
+        };
+        let clashes = formatter::keyword_clashes(&ast, &target_keywords);
+        if !clashes.is_empty() {
+            let names: Vec<&str> = clashes.iter().map(String::as_str).collect();
+            return Err(Error::io(format!(
+                "{} uses {} as {}, which the new notation reads as keywords; rename them first",
+                filename,
+                names.join(", "),
+                if names.len() == 1 { "a name" } else { "names" }
+            )));
+        }
+        formatted.push(formatter::format(&ast, &target_keywords, matches.is_present("strict")));
+    }
+
+    for (filename, text) in files.iter().zip(formatted) {
+        if matches.is_present("write") {
+            fs::write(filename, text).map_err(|e| Error::io(format!("Failed to write {}: {}", filename, e)))?;
+        } else {
+            if files.len() > 1 {
+                println!("==> {} <==", filename);
+            }
+            print!("{}", text);
+        }
+    }
+    Ok(())
+}
+
+fn flowchart_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let program = load_program(&input_files(matches), &keywords, dialect(config))?;
+    let format = FlowchartFormat::from_name(matches.value_of("format").unwrap()).unwrap();
+    print!("{}", flowchart::flowchart(&program, &keywords, format));
+    Ok(())
+}
+
+fn doc_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let program = load_program(&input_files(matches), &keywords, dialect(config))?;
+    let format = DocFormat::from_name(matches.value_of("format").unwrap()).unwrap();
+    print!("{}", doc::document(&program, &keywords, format));
+    Ok(())
+}
+
+fn cfg_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let program = load_program(&input_files(matches), &keywords, dialect(config))?;
+    let mut graphs = cfg::build(&program);
+    if let Some(name) = matches.value_of("procedure") {
+        graphs.retain(|graph| graph.name.as_deref() == Some(name));
+        if graphs.is_empty() {
+            eprintln!("No procedure named {}", name);
+            process::exit(1);
+        }
+    }
+    match matches.value_of("format").unwrap() {
+        "json" => {
+            let graphs: Vec<_> = graphs.iter().map(cfg::Cfg::to_json).collect();
+            println!("{}", serde_json::to_string_pretty(&graphs).unwrap());
+        }
+        _ => print!("{}", cfg::to_dot(&graphs, &keywords)),
+    }
+    Ok(())
+}
+
+fn calls_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let program = load_program(&input_files(matches), &keywords(config)?, dialect(config))?;
+    let graph = callgraph::build(&program);
+    match matches.value_of("format").unwrap() {
+        "dot" => print!("{}", graph.to_dot()),
+        _ => print!("{}", graph.to_text()),
+    }
+    Ok(())
+}
+
+fn metrics_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let program = load_program(&input_files(matches), &keywords(config)?, dialect(config))?;
+    let report = metrics::measure(&program);
+    let budget = metrics::Budget {
+        statements: number(matches, "max-statements").or(config.metrics.max_statements),
+        depth: number(matches, "max-depth").or(config.metrics.max_depth),
+        complexity: number(matches, "max-complexity").or(config.metrics.max_complexity),
+        variables: number(matches, "max-variables").or(config.metrics.max_variables),
+    };
+
+    if matches.value_of("format") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        let width = report.iter().map(|routine| routine.title().len()).max().unwrap_or(0).max(7);
+        println!("{:<width$}  statements  depth  complexity  variables", "routine", width = width);
+        for routine in &report {
+            println!(
+                "{:<width$}  {:>10}  {:>5}  {:>10}  {:>9}",
+                routine.title(),
+                routine.statements,
+                routine.depth,
+                routine.complexity,
+                routine.variables,
+                width = width
+            );
+        }
+    }
+
+    let problems: Vec<String> = report.iter().flat_map(|routine| routine.over(&budget)).collect();
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if !problems.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Programs run on one thread with room for deep recursion, as in `run`.
+fn diff_run_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let names = [matches.value_of("expected").unwrap(), matches.value_of("actual").unwrap()];
+    let mut programs = vec![];
+    for name in names {
+        let program = load_program(&[name.to_string()], &keywords, dialect(config))
+            .map_err(|e| e.with_file_names(&[name.to_string()]))?;
+        programs.push(Arc::new(program));
+    }
+    let limits = case_limits(matches);
+    let options = config.run_options();
+    let cases = read_cases(matches)?;
+
+    let colors = colors(config, Stream::Stdout);
+    let mut differing = 0;
+    for (input, case) in &cases {
+        let outcomes: Vec<_> = std::thread::scope(|scope| {
+            programs
+                .iter()
+                .map(|program| {
+                    std::thread::Builder::new()
+                        .stack_size(interpreter::STACK_SIZE)
+                        .spawn_scoped(scope, || equivalence::run(program, input, limits, options))
+                        .expect("failed to start the interpreter thread")
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|thread| thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect()
+        });
+        let Some(divergence) = equivalence::compare(&outcomes[0], &outcomes[1]) else {
+            println!("{}: {}", case, colors.success("same output"));
+            continue;
+        };
+        differing += 1;
+        match divergence {
+            equivalence::Divergence::Output { line, expected, actual } => {
+                println!("{}: output differs at line {}", case, line);
+                for ((name, text), outcome) in names.iter().zip([expected, actual]).zip(&outcomes) {
+                    match (text, &outcome.error) {
+                        (Some(text), _) => println!("    {}: {:?}", name, text),
+                        (None, Some(error)) => {
+                            println!("    {}: {}", name, error.clone().with_file_names(&[name.to_string()]).render(&colors))
+                        }
+                        (None, None) => println!("    {}: (no more output)", name),
+                    }
+                }
+            }
+            equivalence::Divergence::Error => {
+                println!("{}: same output, but the programs stopped differently", case);
+                for (name, outcome) in names.iter().zip(&outcomes) {
+                    match outcome.error.clone() {
+                        Some(error) => {
+                            println!("    {}: {}", name, error.with_file_names(&[name.to_string()]).render(&colors))
+                        }
+                        None => println!("    {}: finished", name),
+                    }
+                }
+            }
+        }
+    }
+    if differing > 0 {
+        println!("{} of {} cases differ", differing, cases.len());
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Each submission is its own program. Those that don't load are reported and
+// left out, so one broken file doesn't hide the rest of the class.
+fn fingerprint_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let threshold: f64 = number(matches, "threshold").unwrap_or(80.0);
+    let limits = case_limits(matches);
+    let options = config.run_options();
+    let inputs: Vec<String> = read_cases(matches)?.into_iter().map(|(input, _)| input).collect();
+
+    let mut programs = vec![];
+    for name in input_files(matches) {
+        match load_program(std::slice::from_ref(&name), &keywords, dialect(config)) {
+            Ok(program) => programs.push((name, Arc::new(program))),
+            Err(err) => report(err, &[name], config),
+        }
+    }
+    let fingerprints: Vec<_> = std::thread::scope(|scope| {
+        programs
+            .iter()
+            .map(|(name, program)| {
+                std::thread::Builder::new()
+                    .stack_size(interpreter::STACK_SIZE)
+                    .spawn_scoped(scope, || fingerprint::fingerprint(name, program, &inputs, limits, options))
+                    .expect("failed to start the interpreter thread")
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    });
+    let pairs = fingerprint::similar(&fingerprints, threshold / 100.0);
+
+    if matches.value_of("format") == Some("json") {
+        let report = serde_json::json!({ "submissions": fingerprints, "similar": pairs });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return Ok(());
+    }
+    let colors = colors(config, Stream::Stdout);
+    let width = fingerprints.iter().map(|print| print.file.len()).max().unwrap_or(0).max(4);
+    println!("{:<width$}  structure         execution", "file", width = width);
+    for print in &fingerprints {
+        println!("{:<width$}  {}  {}", print.file, print.structure, print.execution, width = width);
+    }
+    println!();
+    if pairs.is_empty() {
+        println!("{}", colors.success(&format!("No pairs are {}% alike or more", threshold)));
+    }
+    for pair in &pairs {
+        let output = if pair.same_output { ", same output" } else { "" };
+        let similarity = format!("{:.0}% alike{}", pair.similarity * 100.0, output);
+        println!("{} and {}: {}", pair.first, pair.second, colors.warning(&similarity));
+    }
+    Ok(())
+}
+
+// The limits on each program run, from --max-steps and --timeout.
+fn case_limits(matches: &ArgMatches) -> Limits {
+    Limits {
+        steps: number(matches, "max-steps"),
+        output: None,
+        time: number(matches, "timeout").map(Duration::from_millis),
+        memory: None,
+    }
+}
+
+// The standard input and name of each test case in the --inputs directory, in
+// order of file name, or a single case with no input.
+fn read_cases(matches: &ArgMatches) -> Result<Vec<(String, String)>, Error> {
+    let Some(directory) = matches.value_of("inputs") else {
+        return Ok(vec![(String::new(), "no input".to_string())]);
+    };
+    let entries = fs::read_dir(directory).map_err(|e| Error::io(format!("Failed to read {}: {}", directory, e)))?;
+    let mut paths: Vec<_> = entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect();
+    paths.sort();
+    let mut cases = vec![];
+    for path in paths {
+        let name = path.display().to_string();
+        cases.push((read_source(&name)?, name));
+    }
+    Ok(cases)
+}
+
+fn mutate_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let file = matches.value_of("file").unwrap().to_string();
+    let program = load_program(std::slice::from_ref(&file), &keywords, dialect(config))?;
+    let limits = case_limits(matches);
+    let options = config.run_options();
+    let cases = read_cases(matches)?;
+    let mutants = mutate::mutants(&program, &keywords);
+    let program = Arc::new(program);
+
+    // The mutants run one after another; each stops at the first case that
+    // tells it apart from the original.
+    let results: Vec<Option<&str>> = std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn_scoped(scope, || {
+                let expected: Vec<_> =
+                    cases.iter().map(|(input, _)| equivalence::run(&program, input, limits, options)).collect();
+                mutants
+                    .iter()
+                    .map(|mutant| {
+                        let mutated = Arc::new(mutant.program.clone());
+                        cases.iter().zip(&expected).find_map(|((input, case), expected)| {
+                            let actual = equivalence::run(&mutated, input, limits, options);
+                            equivalence::compare(expected, &actual).map(|_| case.as_str())
+                        })
+                    })
+                    .collect()
+            })
+            .expect("failed to start the interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    });
+
+    let colors = colors(config, Stream::Stdout);
+    let mut survived = 0;
+    for (mutant, killer) in mutants.iter().zip(&results) {
+        let place = format!("{}:{}:{}", file, mutant.span.line, mutant.span.column);
+        match killer {
+            Some(case) => println!("{}: {}: {} by {}", place, mutant.description, colors.success("caught"), case),
+            None => {
+                survived += 1;
+                println!("{}: {}: {}", place, mutant.description, colors.error("survived"));
+            }
+        }
+    }
+    let caught = mutants.len() - survived;
+    let score = if mutants.is_empty() { 100 } else { caught * 100 / mutants.len() };
+    println!("{} of {} mutants caught ({}%)", caught, mutants.len(), score);
+    if survived > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn quiz_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let file = matches.value_of("file").unwrap().to_string();
+    let source = read_source(&file)?;
+    let program = Arc::new(load_program(std::slice::from_ref(&file), &keywords, dialect(config))?);
+    let input = match matches.value_of("input") {
+        Some(name) => read_source(name)?,
+        None => String::new(),
+    };
+
+    let colors = colors(config, Stream::Stdout);
+    let width = source.lines().count().to_string().len();
+    for (number, line) in source.lines().enumerate() {
+        println!("{} {}", colors.dim(&format!("{:>width$}", number + 1, width = width)), line);
+    }
+    if !input.is_empty() {
+        println!("\nIts input is:");
+        for line in input.lines() {
+            println!("    {}", line);
+        }
+    }
+    println!("\nType the output you expect, then press Ctrl-D on a line of its own (Ctrl-Z then Enter on Windows):");
+    let mut answer = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut answer)
+        .map_err(|e| Error::io(format!("Failed to read the answer: {}", e)))?;
+
+    let limits = case_limits(matches);
+    let options = config.run_options();
+    let outcome = std::thread::Builder::new()
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn(move || equivalence::run(&program, &input, limits, options))
+        .expect("failed to start the interpreter thread")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+    // Trailing spaces are invisible on screen, so they are not held against
+    // the answer.
+    let expected: Vec<&str> = outcome.output.lines().map(str::trim_end).collect();
+    let answered: Vec<&str> = answer.lines().map(str::trim_end).collect();
+    let total = expected.len().max(answered.len());
+    let mut correct = 0;
+    println!();
+    for line in 0..total {
+        match (expected.get(line), answered.get(line)) {
+            (Some(expected), Some(answered)) if expected == answered => {
+                correct += 1;
+                println!("{} {}", colors.success("✓"), expected);
+            }
+            (expected, answered) => {
+                println!("{} line {}", colors.error("✗"), line + 1);
+                println!("    expected: {}", expected.map_or("(nothing)".to_string(), |text| format!("{:?}", text)));
+                println!("    you wrote: {}", answered.map_or("(nothing)".to_string(), |text| format!("{:?}", text)));
+            }
+        }
+    }
+    if let Some(error) = outcome.error {
+        println!("The program then stopped: {}", error.with_file_names(&[file]).render(&colors));
+    }
+    println!("\n{} of {} lines right", correct, total);
+    Ok(())
+}
+
+fn reduce_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let file = matches.value_of("file").unwrap().to_string();
+    let program = load_program(std::slice::from_ref(&file), &keywords, dialect(config))?;
+    let input = match matches.value_of("input") {
+        Some(name) => read_source(name)?,
+        None => String::new(),
+    };
+    let limits = case_limits(matches);
+    let options = config.run_options();
+    #[cfg(feature = "jit")]
+    let jit = matches.is_present("jit");
+    #[cfg(not(feature = "jit"))]
+    let jit = false;
+    // Candidates checked by another process are written here.
+    let candidate = std::env::temp_dir().join(format!("ibc-reduce-{}.txt", process::id()));
+
+    let mut interesting: Box<dyn FnMut(&AstNode) -> bool> = if let Some(command) = matches.value_of("command") {
+        let keywords = keywords.clone();
+        let candidate = candidate.clone();
+        Box::new(move |program| {
+            fs::write(&candidate, formatter::format(program, &keywords, false)).is_ok()
+                && process::Command::new("sh")
+                    .arg("-c")
+                    .arg(format!("{} '{}'", command, candidate.display()))
+                    .stdout(process::Stdio::null())
+                    .stderr(process::Stdio::null())
+                    .status()
+                    .is_ok_and(|status| status.success())
+        })
+    } else if jit {
+        // The compiled code writes straight to standard output, so both
+        // ways of running are separate processes of this program.
+        let exe = std::env::current_exe().map_err(|e| Error::io(format!("Failed to find this program: {}", e)))?;
+        let candidate = candidate.clone();
+        let input = input.clone();
+        Box::new(move |program| {
+            let english = Keywords::english();
+            if fs::write(&candidate, formatter::format(program, &english, false)).is_err() {
+                return false;
+            }
+            let run = |jit: bool| {
+                let mut command = process::Command::new(&exe);
+                command.args(["--lang", "en", "--dialect", "extended", "run"]).arg(&candidate);
+                if jit {
+                    command.arg("--jit");
+                }
+                let mut child = command
+                    .stdin(process::Stdio::piped())
+                    .stdout(process::Stdio::piped())
+                    .stderr(process::Stdio::null())
+                    .spawn()
+                    .ok()?;
+                let mut stdin = child.stdin.take()?;
+                std::io::Write::write_all(&mut stdin, input.as_bytes()).ok()?;
+                drop(stdin);
+                let output = child.wait_with_output().ok()?;
+                Some((output.stdout, output.status.code()))
+            };
+            run(false) != run(true)
+        })
+    } else {
+        let code = matches.value_of("error").map(String::from);
+        let text = matches.value_of("output").map(String::from);
+        let input = input.clone();
+        Box::new(move |program| match run_caught(program, &input, limits, options) {
+            Err(_) => code.is_none() && text.is_none(),
+            Ok(outcome) => {
+                (code.is_some() || text.is_some())
+                    && code.as_deref().is_none_or(|code| outcome.error.as_ref().map(|e| e.code) == Some(code))
+                    && text.as_deref().is_none_or(|text| outcome.output.contains(text))
+            }
+        })
+    };
+
+    // Many candidates will panic on purpose; only the first message matters.
+    if let Err(message) = run_caught(&program, &input, limits, options) {
+        eprintln!("The interpreter panics: {}", message);
+    }
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let shows_bug = interesting(&program);
+    let reduction = shows_bug.then(|| reduce::reduce(&program, &keywords, dialect(config), &mut *interesting));
+    std::panic::set_hook(hook);
+    let _ = fs::remove_file(&candidate);
+
+    let Some(reduction) = reduction else {
+        eprintln!("{} does not show the bug, so there is nothing to reduce", file);
+        process::exit(1);
+    };
+    print!("{}", formatter::format(&reduction.program, &keywords, false));
+    eprintln!(
+        "Reduced from {} to {} nodes after trying {} candidates",
+        reduce::size(&program),
+        reduce::size(&reduction.program),
+        reduction.tests
+    );
+    Ok(())
+}
+
+fn refactor_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let (name, matches) = matches.subcommand().unwrap();
+    let file = matches.value_of("file").unwrap();
+    if literate::is_markdown(file) {
+        return Err(Error::io(format!("{} is a Markdown file; refactor only edits pseudocode files", file)));
+    }
+    let source = read_source(file)?;
+    let (refactored, summary) = match name {
+        "rename" => {
+            let (old, new) = (matches.value_of("old").unwrap(), matches.value_of("new").unwrap());
+            let refactored = refactor::rename(&source, &keywords, dialect(config), old, new)?;
+            let places = if refactored.changes == 1 { "place" } else { "places" };
+            let summary = format!("Renamed {} to {} in {} {}", old, new, refactored.changes, places);
+            (refactored, summary)
+        }
+        _ => {
+            let lines = matches.value_of("lines").unwrap();
+            let range = match lines.split_once('-') {
+                Some((start, end)) => start.trim().parse().and_then(|start| Ok(start..=end.trim().parse()?)),
+                None => lines.trim().parse().map(|line| line..=line),
+            };
+            let Some(range) = range.ok().filter(|range| *range.start() >= 1 && !range.is_empty()) else {
+                eprintln!("Lines must be a line number or a range such as 12-18, got {}", lines);
+                process::exit(2);
+            };
+            let name = matches.value_of("name").unwrap();
+            let refactored = refactor::extract(&source, &keywords, dialect(config), range, name)?;
+            let statements = if refactored.changes == 1 { "statement" } else { "statements" };
+            let summary = format!("Moved {} {} into {}", refactored.changes, statements, name);
+            (refactored, summary)
+        }
+    };
+    if matches.is_present("write") {
+        fs::write(file, &refactored.source).map_err(|e| Error::io(format!("Failed to write {}: {}", file, e)))?;
+    } else {
+        print!("{}", refactored.source);
+    }
+    eprintln!("{}", summary);
+    Ok(())
+}
+
+// Runs `program` on its own thread, catching a panic in the interpreter and
+// returning its message.
+fn run_caught(
+    program: &AstNode,
+    input: &str,
+    limits: Limits,
+    options: RunOptions,
+) -> Result<equivalence::Outcome, String> {
+    let program = Arc::new(program.clone());
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn_scoped(scope, || equivalence::run(&program, input, limits, options))
+            .expect("failed to start the interpreter thread")
+            .join()
+    })
+    .map_err(|panic| {
+        panic
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| panic.downcast_ref::<&str>().map(|message| message.to_string()))
+            .unwrap_or_default()
+    })
+}
+
+fn generate_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let mix = match matches.value_of("mix") {
+        Some(text) => generate::Mix::parse(text).unwrap_or_else(|message| {
+            eprintln!("--mix: {}", message);
+            process::exit(2);
+        }),
+        None => generate::Mix::default(),
+    };
+    let seed = number(matches, "seed").unwrap_or_else(|| {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        now.as_nanos() as u64
+    });
+    let mut options = generate::GeneratorOptions {
+        seed,
+        statements: number(matches, "statements").unwrap(),
+        depth: number(matches, "depth").unwrap(),
+        functions: number(matches, "functions").unwrap(),
+        mix,
+    };
+
+    let Some(count) = number::<u64>(matches, "fuzz") else {
+        print!("{}", formatter::format(&generate::generate(&options), &keywords, false));
+        return Ok(());
+    };
+    let mut failures = 0;
+    for offset in 0..count {
+        options.seed = seed.wrapping_add(offset);
+        if let Some(problem) = generate::fuzz(&options, &keywords) {
+            failures += 1;
+            println!("seed {}: {}", options.seed, problem);
+        }
+    }
+    println!("{} of {} programs failed", failures, count);
+    if failures > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn batch_command(matches: &ArgMatches, config: &Config) {
+    let files: Vec<String> = matches.values_of("files").unwrap().map(String::from).collect();
+    let jobs = match matches.value_of("jobs") {
+        Some(jobs) => jobs.parse().unwrap_or_else(|_| {
+            eprintln!("--jobs expects a positive number, got {}", jobs);
+            process::exit(2);
+        }),
+        None => config
+            .batch
+            .jobs
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+    };
+
+    let keywords = keywords(config).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+
+    // Each program's output goes to stdout under its header, and its warnings,
+    // error and the tally to stderr.
+    let error_colors = colors(config, Stream::Stderr);
+    let colors = colors(config, Stream::Stdout);
+    let format = error_format(config);
+    let mut failed = 0;
+    batch::run(&files, jobs, &keywords, dialect(config), config.run_options(), &PROGRAMS, |result| {
+        println!("{}", colors.header(&format!("==> {} <==", result.file)));
+        print!("{}", result.output);
+        let _ = io::stdout().flush();
+        for warning in &result.warnings {
+            eprintln!("{}", warning.format(format, &error_colors));
+        }
+        if let Some(err) = result.error {
+            failed += 1;
+            eprintln!("{}", err.format(format, &error_colors));
+        }
+    });
+
+    let passed = format!("{} passed", files.len() - failed);
+    let failed_text = format!("{} failed", failed);
+    eprintln!(
+        "{}, {}",
+        error_colors.success(&passed),
+        if failed > 0 { error_colors.error(&failed_text) } else { failed_text }
+    );
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+fn serve_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let options = ServeOptions {
+        jobs: number(matches, "jobs").unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        limits: request_limits(matches),
+    };
+    let address = matches.value_of("address").unwrap();
+    let listener =
+        TcpListener::bind(address).map_err(|e| Error::io(format!("Failed to listen on {}: {}", address, e)))?;
+    println!("Listening on http://{}", address);
+    serve::serve(listener, &options, config);
+    Ok(())
+}
+
+fn grammar_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    if matches.is_present("check") {
+        let problems = grammar::check_ebnf();
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if !problems.is_empty() {
+            process::exit(1);
+        }
+        println!("The grammar covers every keyword and operator");
+        return Ok(());
+    }
+    let keywords = keywords(config)?;
+    match matches.value_of("format").unwrap() {
+        "ebnf" => print!("{}", grammar::ebnf(&keywords)),
+        "svg" => {
+            let directory = Path::new(matches.value_of("out-dir").unwrap());
+            fs::create_dir_all(directory)
+                .map_err(|e| Error::io(format!("Failed to create {}: {}", directory.display(), e)))?;
+            for production in grammar::productions(&keywords) {
+                let filename = directory.join(format!("{}.svg", production.name));
+                fs::write(&filename, railroad::svg(&production))
+                    .map_err(|e| Error::io(format!("Failed to write {}: {}", filename.display(), e)))?;
+            }
+        }
+        _ => println!("{}", serde_json::to_string_pretty(&grammar::tmlanguage(&keywords)).unwrap()),
+    }
+    Ok(())
+}
+
+// The most a `serve` or `rpc` request may use, from --max-steps, --max-output,
+// --timeout and --max-memory.
+fn request_limits(matches: &ArgMatches) -> Limits {
+    Limits {
+        steps: number(matches, "max-steps"),
+        output: number(matches, "max-output"),
+        time: number(matches, "timeout").map(Duration::from_millis),
+        memory: number(matches, "max-memory"),
+    }
+}
+
+// Requests run on one thread with room for deep recursion, as in `run`.
+fn rpc_command(matches: &ArgMatches, config: &Config) -> Result<(), Error> {
+    let limits = request_limits(matches);
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn_scoped(scope, || rpc::serve(config, limits))
+            .expect("failed to start the interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+// Reads statements from standard input until it ends. A statement left open,
+// such as an `if` before its `endif`, is read on over the following lines,
+// and an empty line shows it as far as it goes.
+fn explore_command(config: &Config) -> Result<(), Error> {
+    let keywords = keywords(config)?;
+    let colors = colors(config, Stream::Stdout);
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!("Type a statement to see its tokens and tree. Nothing is run; press Ctrl-D to quit.");
+    }
+    let mut lines = io::stdin().lock().lines();
+    let mut source = String::new();
+    loop {
+        if interactive {
+            print!("{}", if source.is_empty() { "> " } else { ". " });
+            let _ = io::stdout().flush();
+        }
+        let line = lines.next().transpose().map_err(|e| Error::io(format!("Failed to read a statement: {}", e)))?;
+        let finished = line.as_deref().is_none_or(|line| line.trim().is_empty());
+        if let Some(line) = line.as_ref().filter(|_| !finished) {
+            source.push_str(line);
+            source.push('\n');
+        }
+        if source.trim().is_empty() {
+            source.clear();
+        } else if let Some(text) = explore::explore(&source, &keywords, dialect(config), &colors, finished) {
+            print!("{}", text);
+            source.clear();
+        }
+        if line.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+fn number<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    let value = matches.value_of(name)?;
+    Some(value.parse().unwrap_or_else(|_| {
+        eprintln!("--{} expects a positive number, got {}", name, value);
+        process::exit(2);
+    }))
 }
@@ -0,0 +1,162 @@
+use crate::ast::{AstNode, NodeKind};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+// Size and complexity measures for one routine: the main program or a
+// procedure.
+#[derive(Debug, Clone, Serialize)]
+pub struct Metrics {
+    // None for the main program.
+    pub name: Option<String>,
+    // Every statement, including those inside others.
+    pub statements: usize,
+    // How many blocks deep the most nested statement is.
+    pub depth: usize,
+    // McCabe's cyclomatic complexity: one plus the number of decisions, which
+    // are each `if` and `else if`, `loop`, `case` branch other than the
+    // default, `catch` and conditional expression.
+    pub complexity: usize,
+    // The distinct names assigned, read or taken as parameters.
+    pub variables: usize,
+}
+
+// The most each metric may be for any one routine.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub statements: Option<usize>,
+    pub depth: Option<usize>,
+    pub complexity: Option<usize>,
+    pub variables: Option<usize>,
+}
+
+impl Metrics {
+    pub fn title(&self) -> &str {
+        self.name.as_deref().unwrap_or("main program")
+    }
+
+    // A description of each metric over `budget`.
+    pub fn over(&self, budget: &Budget) -> Vec<String> {
+        let checks = [
+            ("statements", self.statements, budget.statements),
+            ("nesting depth", self.depth, budget.depth),
+            ("complexity", self.complexity, budget.complexity),
+            ("variables", self.variables, budget.variables),
+        ];
+        checks
+            .into_iter()
+            .filter_map(|(metric, value, limit)| {
+                let limit = limit.filter(|&limit| value > limit)?;
+                Some(format!("{}: {} {} is over the limit of {}", self.title(), metric, value, limit))
+            })
+            .collect()
+    }
+}
+
+// The metrics of the main program followed by those of each procedure, in the
+// order they are defined.
+pub fn measure(program: &AstNode) -> Vec<Metrics> {
+    let statements = match &program.kind {
+        NodeKind::Program(statements) => statements.as_slice(),
+        _ => std::slice::from_ref(program),
+    };
+    let main: Vec<&AstNode> =
+        statements.iter().filter(|statement| !matches!(statement.kind, NodeKind::Procedure(_))).collect();
+    let mut metrics = vec![routine(None, &main, vec![])];
+    for statement in statements {
+        if let NodeKind::Procedure(procedure) = &statement.kind {
+            let body: Vec<&AstNode> = procedure.body.iter().collect();
+            let mut names = vec![];
+            for param in &procedure.params {
+                names.push(param.name.clone());
+                if let Some(default) = &param.default {
+                    default.walk(&mut |node| names.extend(variable(node)));
+                }
+            }
+            metrics.push(routine(Some(procedure.name.clone()), &body, names));
+        }
+    }
+    metrics
+}
+
+fn routine(name: Option<String>, body: &[&AstNode], names: Vec<String>) -> Metrics {
+    let mut metrics = Metrics { name, statements: 0, depth: 0, complexity: 1, variables: 0 };
+    let mut variables: BTreeSet<String> = names.into_iter().collect();
+    for statement in body {
+        block_statement(statement, 0, &mut metrics, &mut variables);
+    }
+    metrics.variables = variables.len();
+    metrics
+}
+
+fn block(statements: &[AstNode], depth: usize, metrics: &mut Metrics, variables: &mut BTreeSet<String>) {
+    if !statements.is_empty() {
+        metrics.depth = metrics.depth.max(depth);
+    }
+    for statement in statements {
+        block_statement(statement, depth, metrics, variables);
+    }
+}
+
+fn block_statement(node: &AstNode, depth: usize, metrics: &mut Metrics, variables: &mut BTreeSet<String>) {
+    metrics.statements += 1;
+    let expressions: Vec<&AstNode> = match &node.kind {
+        NodeKind::If(condition, true_branch, false_branch) => {
+            metrics.complexity += 1;
+            block(true_branch, depth + 1, metrics, variables);
+            // An `else if` is an `if` alone in the else branch, but reads as
+            // another clause of this statement, so it does not nest deeper.
+            match false_branch.as_slice() {
+                [else_if @ AstNode { kind: NodeKind::If(..), .. }] => {
+                    block_statement(else_if, depth, metrics, variables);
+                    metrics.statements -= 1;
+                }
+                _ => block(false_branch, depth + 1, metrics, variables),
+            }
+            vec![condition]
+        }
+        NodeKind::Loop(condition, body) => {
+            metrics.complexity += 1;
+            block(body, depth + 1, metrics, variables);
+            vec![condition]
+        }
+        NodeKind::Case(value, branches, default) => {
+            metrics.complexity += branches.len();
+            for branch in branches {
+                block(&branch.body, depth + 1, metrics, variables);
+            }
+            block(default.as_deref().unwrap_or_default(), depth + 1, metrics, variables);
+            vec![value]
+        }
+        NodeKind::Try(body, name, handler) => {
+            metrics.complexity += 1;
+            variables.extend(name.clone());
+            block(body, depth + 1, metrics, variables);
+            block(handler, depth + 1, metrics, variables);
+            vec![]
+        }
+        NodeKind::Assignment(name, _) | NodeKind::SetElement(name, ..) | NodeKind::Input(name, _) => {
+            variables.insert(name.clone());
+            node.children()
+        }
+        NodeKind::Global(names) => {
+            variables.extend(names.iter().cloned());
+            vec![]
+        }
+        _ => node.children(),
+    };
+    for expression in expressions {
+        expression.walk(&mut |node| {
+            if let NodeKind::Conditional(..) = node.kind {
+                metrics.complexity += 1;
+            }
+            variables.extend(variable(node));
+        });
+    }
+}
+
+fn variable(node: &AstNode) -> Option<String> {
+    match &node.kind {
+        NodeKind::Identifier(name) => Some(name.clone()),
+        _ => None,
+    }
+}
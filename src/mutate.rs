@@ -0,0 +1,106 @@
+use crate::ast::{AstNode, BinaryOp, NodeKind};
+use crate::keywords::Keywords;
+use crate::lexer::Span;
+
+// A copy of a program with one small change, the kind of slip a test suite
+// should notice.
+#[derive(Debug)]
+pub struct Mutant {
+    // The operation or number that was changed.
+    pub span: Span,
+    pub description: String,
+    pub program: AstNode,
+}
+
+#[derive(Debug, Clone)]
+enum Change {
+    Operator(BinaryOp),
+    Number(i64),
+}
+
+// Every mutant of `program`, in the order the changed nodes appear. Each
+// comparison has its boundary moved (`<` to `<=`, `=` to `!=` and back),
+// each arithmetic and logical operator is swapped for its partner, and each
+// whole number compared against is moved up and down by one, which makes a
+// loop run once too often or too rarely.
+pub fn mutants(program: &AstNode, keywords: &Keywords) -> Vec<Mutant> {
+    let mut nodes = vec![];
+    program.walk(&mut |node| nodes.push(node));
+    let bounds: Vec<&AstNode> = nodes
+        .iter()
+        .filter_map(|node| match &node.kind {
+            NodeKind::BinOp(left, op, right) if op.is_comparison() => Some([left.as_ref(), right.as_ref()]),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+
+    let mut mutants = vec![];
+    for (index, node) in nodes.iter().enumerate() {
+        let changes = match &node.kind {
+            NodeKind::BinOp(left, op, right) => {
+                let text = |node: &AstNode| matches!(node.kind, NodeKind::String(_));
+                // `+` on text joins it, and text cannot be subtracted.
+                let joins = *op == BinaryOp::Plus && (text(left) || text(right));
+                let partner = partner(op).filter(|_| !joins);
+                partner
+                    .map(|partner| {
+                        let description =
+                            format!("`{}` changed to `{}`", op.spelling(keywords), partner.spelling(keywords));
+                        (Change::Operator(partner), description)
+                    })
+                    .into_iter()
+                    .collect()
+            }
+            NodeKind::Number(number) if bounds.iter().any(|bound| std::ptr::eq(*bound, *node)) => [1, -1]
+                .into_iter()
+                .filter_map(|step| number.checked_add(step).filter(|&moved| moved >= 0))
+                .map(|moved| (Change::Number(moved), format!("{} changed to {}", number, moved)))
+                .collect(),
+            _ => vec![],
+        };
+        for (change, description) in changes {
+            let mut mutated = program.clone();
+            let mut position = 0;
+            mutated.walk_mut(&mut |node| {
+                if position == index {
+                    apply(node, &change);
+                }
+                position += 1;
+            });
+            mutants.push(Mutant { span: node.span, description, program: mutated });
+        }
+    }
+    mutants
+}
+
+fn apply(node: &mut AstNode, change: &Change) {
+    match (&mut node.kind, change) {
+        (NodeKind::BinOp(_, op, _), Change::Operator(partner)) => *op = *partner,
+        (NodeKind::Number(number), Change::Number(moved)) => *number = *moved,
+        _ => {}
+    }
+}
+
+fn partner(op: &BinaryOp) -> Option<BinaryOp> {
+    let partner = match op {
+        BinaryOp::LessThan => BinaryOp::LessThanOrEqual,
+        BinaryOp::LessThanOrEqual => BinaryOp::LessThan,
+        BinaryOp::GreaterThan => BinaryOp::GreaterThanOrEqual,
+        BinaryOp::GreaterThanOrEqual => BinaryOp::GreaterThan,
+        BinaryOp::Eq => BinaryOp::NotEqual,
+        BinaryOp::NotEqual => BinaryOp::Eq,
+        BinaryOp::Plus => BinaryOp::Minus,
+        BinaryOp::Minus => BinaryOp::Plus,
+        BinaryOp::Star => BinaryOp::Slash,
+        BinaryOp::Slash => BinaryOp::Star,
+        BinaryOp::And => BinaryOp::Or,
+        BinaryOp::Or => BinaryOp::And,
+        BinaryOp::BitAnd => BinaryOp::BitOr,
+        BinaryOp::BitOr => BinaryOp::BitAnd,
+        BinaryOp::ShiftLeft => BinaryOp::ShiftRight,
+        BinaryOp::ShiftRight => BinaryOp::ShiftLeft,
+        _ => return None,
+    };
+    Some(partner)
+}
@@ -1,208 +1,774 @@
-use crate::lexer::{Lexer, Token, TokenInfo};
-use crate::ast::AstNode;
+use crate::lexer::{Lexer, Span, Token, TokenInfo};
+use crate::ast::{AstNode, BinaryOp, CaseBranch, NodeKind, Param, Procedure, ProcedureKind};
+use crate::error::Error;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::debug;
+
+// The notation a program is written in. `ib` is the pseudocode of the IB
+// guide, while `extended` also accepts conveniences such as `TOTAL += SCORE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    #[default]
+    Ib,
+    Extended,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ib" => Some(Dialect::Ib),
+            "extended" => Some(Dialect::Extended),
+            _ => None,
+        }
+    }
+}
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current_token_info: TokenInfo,
+    previous_span: Span,
+    // How many blocks enclose the current statement; procedures may only be
+    // defined at the top level.
+    depth: usize,
+    // The kind of procedure whose body is being parsed, if any.
+    procedure: Option<ProcedureKind>,
+    dialect: Dialect,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lexer<'a>) -> Self {
-        let current_token_info = lexer.get_next_token();
-        Parser { lexer, current_token_info }
+    pub fn new(mut lexer: Lexer<'a>) -> Result<Self, Error> {
+        let current_token_info = lexer.get_next_token()?;
+        Ok(Parser {
+            lexer,
+            current_token_info,
+            previous_span: Span::default(),
+            depth: 0,
+            procedure: None,
+            dialect: Dialect::default(),
+        })
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
     }
 
-    fn eat(&mut self, token: Token) {
+    fn eat(&mut self, token: Token) -> Result<(), Error> {
         if self.current_token_info.token == token {
-            self.current_token_info = self.lexer.get_next_token();
+            self.previous_span = self.current_token_info.span;
+            self.current_token_info = self.lexer.get_next_token()?;
+            Ok(())
         } else {
-            panic!(
-                "Expected {:?}, got {:?} at line {}, column {}",
+            Err(self.error(format!(
+                "Expected {:?}, got {:?}",
                 token,
-                self.current_token_info.token,
-                self.current_token_info.line,
-                self.current_token_info.column
-            );
+                self.current_token_info.token
+            )))
         }
     }
 
-    pub fn parse(&mut self) -> AstNode {
-        let mut nodes = vec![];
+    fn error(&self, message: String) -> Error {
+        Error::syntax("E0101", message, self.current_token_info.span)
+    }
+
+    fn start(&self) -> Span {
+        self.current_token_info.span
+    }
+
+    // Builds a node covering everything from `start` up to the last token eaten.
+    fn node(&self, kind: NodeKind, start: Span) -> AstNode {
+        AstNode::new(kind, start.to(self.previous_span))
+    }
+
+    pub fn parse(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let nodes = self.statements_until(&[Token::Eof])?;
+        Ok(self.node(NodeKind::Program(nodes), start))
+    }
+
+    // Parses statements until one of `end` is reached. Statements may be
+    // separated by `;` to put several on one line.
+    fn statements_until(&mut self, end: &[Token]) -> Result<Vec<AstNode>, Error> {
+        self.statements_before(|token| end.contains(token))
+    }
 
-        while self.current_token_info.token != Token::Eof {
-            nodes.push(self.statement());
+    fn statements_before(&mut self, is_end: impl Fn(&Token) -> bool) -> Result<Vec<AstNode>, Error> {
+        let mut statements = vec![];
+        loop {
+            while self.current_token_info.token == Token::Semicolon {
+                self.eat(Token::Semicolon)?;
+            }
+            if is_end(&self.current_token_info.token) || self.current_token_info.token == Token::Eof {
+                return Ok(statements);
+            }
+            statements.push(self.statement()?);
         }
+    }
 
-        AstNode::Program(nodes)
+    // The statements of a nested block, such as a loop body.
+    fn block(&mut self, end: &[Token]) -> Result<Vec<AstNode>, Error> {
+        self.depth += 1;
+        let statements = self.statements_until(end);
+        self.depth -= 1;
+        statements
     }
 
-    fn statement(&mut self) -> AstNode {
+    fn statement(&mut self) -> Result<AstNode, Error> {
+        debug!(
+            token = ?self.current_token_info.token,
+            line = self.current_token_info.span.line,
+            "parsing statement"
+        );
         match self.current_token_info.token {
-            Token::Identifier(_) => self.assignment_statement(),
+            Token::Identifier(_) => self.identifier_statement(),
             Token::Output => self.output_statement(),
+            Token::Input => self.input_statement(),
             Token::If => self.if_statement(),
             Token::Loop => self.loop_statement(),
-            _ => panic!(
-                "Unexpected token: {:?} at line {}, column {}",
-                self.current_token_info.token,
-                self.current_token_info.line,
-                self.current_token_info.column
-            ),
+            Token::Case => self.case_statement(),
+            Token::Sub | Token::Function => self.procedure(),
+            Token::Return => self.return_statement(),
+            Token::Global => self.global_statement(),
+            Token::Error => self.error_statement(),
+            Token::Try => self.try_statement(),
+            _ => Err(self.error(format!("Unexpected token: {:?}", self.current_token_info.token))),
         }
     }
 
-    fn assignment_statement(&mut self) -> AstNode {
-        if let Token::Identifier(name) = self.current_token_info.token.clone() {
-            self.eat(Token::Identifier(name.clone()));
-            self.eat(Token::Assign);
-            let expr = self.expr();
-            AstNode::Assignment(name, Box::new(expr))
-        } else {
-            panic!(
-                "Expected identifier, got {:?} at line {}, column {}",
-                self.current_token_info.token,
-                self.current_token_info.line,
-                self.current_token_info.column
-            );
+    // An assignment to a variable or array element, or a call such as
+    // `clearScreen()` or `A.append(X)` run for its effect.
+    fn identifier_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let target = self.factor()?;
+        if matches!(target.kind, NodeKind::Call(..) | NodeKind::MethodCall(..))
+            && self.current_token_info.token != Token::Assign
+        {
+            return Ok(target);
         }
-    }
+        let expr = match self.current_token_info.token.clone() {
+            token @ (Token::PlusAssign | Token::MinusAssign | Token::StarAssign) => self.compound_assignment(&target, token)?,
+            _ => {
+                self.eat(Token::Assign)?;
+                self.expr()?
+            }
+        };
+        let expr = Box::new(expr);
 
-    fn output_statement(&mut self) -> AstNode {
-        self.eat(Token::Output);
-        let expr = self.expr();
-        AstNode::Output(Box::new(expr))
+        let mut indices = vec![];
+        let mut current = target;
+        loop {
+            match current.kind {
+                NodeKind::Identifier(name) if indices.is_empty() => {
+                    return Ok(self.node(NodeKind::Assignment(name, expr), start));
+                }
+                NodeKind::Identifier(name) => {
+                    indices.reverse();
+                    return Ok(self.node(NodeKind::SetElement(name, indices, expr), start));
+                }
+                NodeKind::Index(array, index) => {
+                    indices.push(*index);
+                    current = *array;
+                }
+                _ => return Err(Error::syntax("E0101", "Can only assign to a variable or array element", current.span)),
+            }
+        }
     }
 
-    fn if_statement(&mut self) -> AstNode {
-        self.eat(Token::If);
-        let condition = self.boolean_expr();
-        self.eat(Token::Then);
-        let mut true_branch = vec![];
+    // `TOTAL += SCORE` is read as `TOTAL = TOTAL + SCORE`, so nothing after the
+    // parser needs to know about compound assignment.
+    fn compound_assignment(&mut self, target: &AstNode, token: Token) -> Result<AstNode, Error> {
+        let (op, text) = match token {
+            Token::PlusAssign => (BinaryOp::Plus, "+="),
+            Token::MinusAssign => (BinaryOp::Minus, "-="),
+            _ => (BinaryOp::Star, "*="),
+        };
+        if self.dialect != Dialect::Extended {
+            return Err(self.error(format!(
+                "{} is only allowed in the extended dialect; write X = X {} ... or use --dialect extended",
+                text,
+                &text[..1]
+            )));
+        }
+        self.eat(token)?;
+        let value = self.expr()?;
+        Ok(self.node(NodeKind::BinOp(Box::new(target.clone()), op, Box::new(value)), target.span))
+    }
 
-        while self.current_token_info.token != Token::Else && self.current_token_info.token != Token::EndIf {
-            true_branch.push(self.statement());
+    fn output_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        self.eat(Token::Output)?;
+        let expr = self.expr()?;
+        let newline = self.current_token_info.token != Token::Inline;
+        if !newline {
+            self.eat(Token::Inline)?;
         }
+        Ok(self.node(NodeKind::Output(Box::new(expr), newline), start))
+    }
+
+    fn input_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        self.eat(Token::Input)?;
+        let name = match self.current_token_info.token.clone() {
+            Token::Identifier(name) => {
+                self.eat(Token::Identifier(name.clone()))?;
+                name
+            }
+            token => return Err(self.error(format!("Expected identifier, got {:?}", token))),
+        };
+        let prompt = if self.current_token_info.token == Token::Prompt {
+            self.eat(Token::Prompt)?;
+            Some(Box::new(self.expr()?))
+        } else {
+            None
+        };
+        Ok(self.node(NodeKind::Input(name, prompt), start))
+    }
+
+    fn if_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let mut node = self.if_clause()?;
+        self.eat(Token::EndIf)?;
+        node.span = start.to(self.previous_span);
+        Ok(node)
+    }
+
+    // Parses `if ... then ...` up to, but not including, the closing `endif`.
+    fn if_clause(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        self.eat(Token::If)?;
+        let condition = self.boolean_expr()?;
+        self.eat(Token::Then)?;
+        let true_branch = self.block(&[Token::Else, Token::EndIf])?;
 
         let false_branch = if self.current_token_info.token == Token::Else {
-            self.eat(Token::Else);
-            let mut false_branch = vec![];
-            while self.current_token_info.token != Token::EndIf {
-                false_branch.push(self.statement());
+            let else_line = self.current_token_info.span.end_line;
+            self.eat(Token::Else)?;
+            // `else if` on one line continues the chain and shares its `endif`,
+            // while an `if` on the next line starts a nested statement.
+            if self.current_token_info.token == Token::If && self.current_token_info.span.line == else_line {
+                vec![self.if_clause()?]
+            } else {
+                self.block(&[Token::EndIf])?
             }
-            false_branch
         } else {
             vec![]
         };
 
-        self.eat(Token::EndIf);
+        Ok(self.node(NodeKind::If(Box::new(condition), true_branch, false_branch), start))
+    }
+
+    fn loop_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        self.eat(Token::Loop)?;
+        self.eat(Token::While)?;
+        let condition = self.boolean_expr()?;
+        let body = self.block(&[Token::EndLoop])?;
+        self.eat(Token::EndLoop)?;
+
+        Ok(self.node(NodeKind::Loop(Box::new(condition), body), start))
+    }
+
+    // `case X of` followed by branches such as `1, 2: ...` and an optional
+    // `default: ...`, closed by `endcase`. Labels are literal values, so a
+    // branch ends where the next label starts.
+    fn case_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        self.eat(Token::Case)?;
+        let value = self.expr()?;
+        self.eat(Token::Of)?;
+
+        let mut branches = vec![];
+        let mut default = None;
+        loop {
+            while self.current_token_info.token == Token::Semicolon {
+                self.eat(Token::Semicolon)?;
+            }
+            match self.current_token_info.token {
+                Token::EndCase => break,
+                Token::Default if default.is_none() => {
+                    self.eat(Token::Default)?;
+                    self.eat(Token::Colon)?;
+                    default = Some(self.case_body()?);
+                }
+                Token::Default => return Err(self.error("A case can only have one default branch".to_string())),
+                _ if default.is_some() => {
+                    return Err(self.error("The default branch must come last in a case".to_string()))
+                }
+                _ => {
+                    let mut labels = vec![self.case_label()?];
+                    while self.current_token_info.token == Token::Comma {
+                        self.eat(Token::Comma)?;
+                        labels.push(self.case_label()?);
+                    }
+                    self.eat(Token::Colon)?;
+                    let body = self.case_body()?;
+                    branches.push(CaseBranch { labels, body });
+                }
+            }
+        }
+        self.eat(Token::EndCase)?;
+
+        Ok(self.node(NodeKind::Case(Box::new(value), branches, default), start))
+    }
+
+    fn case_label(&mut self) -> Result<AstNode, Error> {
+        match self.current_token_info.token {
+            Token::Number(_) | Token::Float(_) | Token::String(_) => self.primary(),
+            ref token => Err(self.error(format!("Expected a number or string as a case label, got {:?}", token))),
+        }
+    }
+
+    fn case_body(&mut self) -> Result<Vec<AstNode>, Error> {
+        self.depth += 1;
+        let body = self.statements_before(|token| {
+            matches!(
+                token,
+                Token::Number(_) | Token::Float(_) | Token::String(_) | Token::Default | Token::EndCase
+            )
+        });
+        self.depth -= 1;
+        body
+    }
+
+    // `sub NAME(ref A, B) ... endsub` or `function NAME(X) ... endfunction`.
+    fn procedure(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let (kind, end) = match self.current_token_info.token {
+            Token::Sub => (ProcedureKind::Sub, Token::EndSub),
+            _ => (ProcedureKind::Function, Token::EndFunction),
+        };
+        if self.depth > 0 {
+            return Err(self.error("Procedures can only be defined at the top level of a program".to_string()));
+        }
+        let doc = self.lexer.doc_above(start.line);
+        self.eat(self.current_token_info.token.clone())?;
+        let name = self.name()?;
+
+        self.eat(Token::LParen)?;
+        let mut params: Vec<Param> = vec![];
+        while self.current_token_info.token != Token::RParen {
+            if !params.is_empty() {
+                self.eat(Token::Comma)?;
+            }
+            let by_ref = self.current_token_info.token == Token::Ref;
+            if by_ref {
+                self.eat(Token::Ref)?;
+            }
+            let span = self.current_token_info.span;
+            let name = self.name()?;
+            if params.iter().any(|param| param.name == name) {
+                return Err(Error::syntax("E0101", format!("Parameter {} is listed twice", name), span));
+            }
+            let default = if self.current_token_info.token == Token::Assign {
+                if by_ref {
+                    return Err(self.error(format!("ref parameter {} cannot have a default value", name)));
+                }
+                self.eat(Token::Assign)?;
+                Some(self.expr()?)
+            } else {
+                if params.iter().any(|param| param.default.is_some()) {
+                    return Err(Error::syntax(
+                        "E0101",
+                        format!("Parameter {} needs a default value because an earlier parameter has one", name),
+                        span,
+                    ));
+                }
+                None
+            };
+            params.push(Param { name, by_ref, default });
+        }
+        self.eat(Token::RParen)?;
+
+        self.procedure = Some(kind);
+        let body = self.block(std::slice::from_ref(&end));
+        self.procedure = None;
+        let body = body?;
+        self.eat(end)?;
 
-        AstNode::If(Box::new(condition), true_branch, false_branch)
+        let procedure = Procedure { name, doc, kind, params, body };
+        Ok(self.node(NodeKind::Procedure(Arc::new(procedure)), start))
+    }
+
+    // `return` leaves a sub, while `return VALUE` gives a function its result.
+    fn return_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let Some(kind) = self.procedure else {
+            return Err(self.error("return can only be used inside a sub or function".to_string()));
+        };
+        self.eat(Token::Return)?;
+        let value = match kind {
+            ProcedureKind::Sub => None,
+            ProcedureKind::Function => Some(Box::new(self.boolean_expr()?)),
+        };
+        Ok(self.node(NodeKind::Return(value), start))
     }
 
-    fn loop_statement(&mut self) -> AstNode {
-        self.eat(Token::Loop);
-        self.eat(Token::While);
-        let condition = self.boolean_expr();
-        let mut body = vec![];
+    fn error_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        self.eat(Token::Error)?;
+        let message = self.expr()?;
+        Ok(self.node(NodeKind::Raise(Box::new(message)), start))
+    }
 
-        while self.current_token_info.token != Token::EndLoop {
-            body.push(self.statement());
+    // `try ... catch ERR ... endtry` runs the catch block when a runtime error
+    // stops the try block. The variable, which must be on the `catch` line,
+    // receives the error message.
+    fn try_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        if self.dialect != Dialect::Extended {
+            return Err(self.error("try is only allowed in the extended dialect; use --dialect extended".to_string()));
         }
+        self.eat(Token::Try)?;
+        let body = self.block(&[Token::Catch])?;
+        let catch_line = self.current_token_info.span.end_line;
+        self.eat(Token::Catch)?;
+        let name = match self.current_token_info.token.clone() {
+            Token::Identifier(name) if self.current_token_info.span.line == catch_line => {
+                self.eat(Token::Identifier(name.clone()))?;
+                Some(name)
+            }
+            _ => None,
+        };
+        let handler = self.block(&[Token::EndTry])?;
+        self.eat(Token::EndTry)?;
+        Ok(self.node(NodeKind::Try(body, name, handler), start))
+    }
 
-        self.eat(Token::EndLoop);
+    // `global X, Y` lets a procedure assign to the program's variables.
+    fn global_statement(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        if self.procedure.is_none() {
+            return Err(self.error("global can only be used inside a sub or function".to_string()));
+        }
+        self.eat(Token::Global)?;
+        let mut names = vec![self.name()?];
+        while self.current_token_info.token == Token::Comma {
+            self.eat(Token::Comma)?;
+            names.push(self.name()?);
+        }
+        Ok(self.node(NodeKind::Global(names), start))
+    }
 
-        AstNode::Loop(Box::new(condition), body)
+    fn name(&mut self) -> Result<String, Error> {
+        match self.current_token_info.token.clone() {
+            Token::Identifier(name) => {
+                self.eat(Token::Identifier(name.clone()))?;
+                Ok(name)
+            }
+            token => Err(self.error(format!("Expected identifier, got {:?}", token))),
+        }
     }
 
-    fn boolean_expr(&mut self) -> AstNode {
-        let mut node = self.comparison_expr();
+    fn boolean_expr(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let mut node = self.comparison_expr()?;
 
-        while matches!(self.current_token_info.token, Token::And | Token::Or) {
-            let token = self.current_token_info.token.clone();
-            self.eat(token.clone());
-            node = AstNode::BinOp(Box::new(node), token, Box::new(self.comparison_expr()));
+        while let Some(op) = self.operator(|op| matches!(op, BinaryOp::And | BinaryOp::Or))? {
+            let right = self.comparison_expr()?;
+            node = self.node(NodeKind::BinOp(Box::new(node), op, Box::new(right)), start);
         }
 
-        node
+        Ok(node)
     }
 
-    fn comparison_expr(&mut self) -> AstNode {
-        let mut node = self.expr();
+    fn comparison_expr(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let mut node = self.expr()?;
 
-        while matches!(
-            self.current_token_info.token,
-            Token::Assign | Token::NotEqual | Token::GreaterThan | Token::GreaterThanOrEqual | Token::LessThan | Token::LessThanOrEqual
-        ) {
-            let token = self.current_token_info.token.clone();
-            self.eat(token.clone());
-            node = AstNode::BinOp(Box::new(node), token, Box::new(self.expr()));
+        while let Some(op) = self.operator(BinaryOp::is_comparison)? {
+            let right = self.expr()?;
+            node = self.node(NodeKind::BinOp(Box::new(node), op, Box::new(right)), start);
         }
 
-        node
+        Ok(node)
     }
 
-    fn expr(&mut self) -> AstNode {
-        let mut node = self.term();
+    // Takes the current token if it is an operator `accept` allows here.
+    fn operator(&mut self, accept: impl Fn(BinaryOp) -> bool) -> Result<Option<BinaryOp>, Error> {
+        let Some(op) = BinaryOp::from_token(&self.current_token_info.token).filter(|&op| accept(op)) else {
+            return Ok(None);
+        };
+        self.eat(self.current_token_info.token.clone())?;
+        Ok(Some(op))
+    }
 
-        while matches!(self.current_token_info.token, Token::Plus | Token::Minus) {
-            let token = self.current_token_info.token.clone();
-            if token == Token::Plus {
-                self.eat(Token::Plus);
-            } else {
-                self.eat(Token::Minus);
+    fn expr(&mut self) -> Result<AstNode, Error> {
+        self.bitwise(0)
+    }
+
+    // Bitwise operators bind more loosely than arithmetic and more tightly
+    // than comparisons: `bor` loosest, then `bxor`, `band` and the shifts, so
+    // `FLAGS band 1 << 3 = 0` tests one bit.
+    fn bitwise(&mut self, level: usize) -> Result<AstNode, Error> {
+        const LEVELS: [&[BinaryOp]; 4] = [
+            &[BinaryOp::BitOr],
+            &[BinaryOp::BitXor],
+            &[BinaryOp::BitAnd],
+            &[BinaryOp::ShiftLeft, BinaryOp::ShiftRight],
+        ];
+        let Some(ops) = LEVELS.get(level) else {
+            return self.additive();
+        };
+        let start = self.start();
+        let mut node = self.bitwise(level + 1)?;
+
+        while let Some(op) = self.operator(|op| ops.contains(&op))? {
+            if self.dialect != Dialect::Extended {
+                let span = self.previous_span;
+                let text = &self.lexer.source()[span.offset..span.end_offset];
+                return Err(Error::syntax(
+                    "E0101",
+                    format!("{} is only allowed in the extended dialect; use --dialect extended", text),
+                    span,
+                ));
             }
-            node = AstNode::BinOp(Box::new(node), token, Box::new(self.term()));
+            let right = self.bitwise(level + 1)?;
+            node = self.node(NodeKind::BinOp(Box::new(node), op, Box::new(right)), start);
+        }
+
+        Ok(node)
+    }
+
+    fn additive(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let mut node = self.term()?;
+
+        while let Some(op) = self.operator(|op| matches!(op, BinaryOp::Plus | BinaryOp::Minus))? {
+            let right = self.term()?;
+            node = self.node(NodeKind::BinOp(Box::new(node), op, Box::new(right)), start);
+        }
+
+        Ok(node)
+    }
+
+    fn term(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let mut node = self.power()?;
+
+        let multiplicative = |op| matches!(op, BinaryOp::Star | BinaryOp::Slash | BinaryOp::Mod | BinaryOp::Div);
+        while let Some(op) = self.operator(multiplicative)? {
+            let right = self.power()?;
+            node = self.node(NodeKind::BinOp(Box::new(node), op, Box::new(right)), start);
         }
 
-        node
+        Ok(node)
     }
 
-    fn term(&mut self) -> AstNode {
-        let mut node = self.factor();
+    // `^` (also written `**`) groups to the right, so `2 ^ 3 ^ 2` is `2 ^ 9`.
+    fn power(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let base = self.factor()?;
+        if self.current_token_info.token != Token::Caret {
+            return Ok(base);
+        }
+        self.eat(Token::Caret)?;
+        let exponent = self.power()?;
+        Ok(self.node(NodeKind::BinOp(Box::new(base), BinaryOp::Caret, Box::new(exponent)), start))
+    }
 
-        while matches!(self.current_token_info.token, Token::Star | Token::Slash | Token::Mod | Token::Div) {
-            let token = self.current_token_info.token.clone();
-            match token {
-                Token::Star => self.eat(Token::Star),
-                Token::Slash => self.eat(Token::Slash),
-                Token::Mod => self.eat(Token::Mod),
-                Token::Div => self.eat(Token::Div),
-                _ => {}
+    // A primary expression followed by any number of `[index]` accesses and
+    // `.method()` calls.
+    fn factor(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
+        let mut node = self.primary()?;
+        loop {
+            match self.current_token_info.token {
+                Token::LBracket => {
+                    let index = self.index()?;
+                    node = self.node(NodeKind::Index(Box::new(node), Box::new(index)), start);
+                }
+                Token::Dot => {
+                    self.eat(Token::Dot)?;
+                    let Token::Identifier(method) = self.current_token_info.token.clone() else {
+                        return Err(self.error(format!(
+                            "Expected a method name after '.', got {:?}",
+                            self.current_token_info.token
+                        )));
+                    };
+                    self.eat(Token::Identifier(method.clone()))?;
+                    let args = self.arguments()?;
+                    node = self.node(NodeKind::MethodCall(Box::new(node), method, args), start);
+                }
+                _ => return Ok(node),
             }
-            node = AstNode::BinOp(Box::new(node), token, Box::new(self.factor()));
         }
+    }
 
-        node
+    fn index(&mut self) -> Result<AstNode, Error> {
+        self.eat(Token::LBracket)?;
+        let index = self.expr()?;
+        self.eat(Token::RBracket)?;
+        Ok(index)
     }
 
-    fn factor(&mut self) -> AstNode {
+    fn primary(&mut self) -> Result<AstNode, Error> {
+        let start = self.start();
         match self.current_token_info.token {
             Token::Number(value) => {
-                self.eat(Token::Number(value));
-                AstNode::Number(value)
+                self.number_notation()?;
+                self.eat(Token::Number(value))?;
+                Ok(self.node(NodeKind::Number(value), start))
+            }
+            Token::Float(value) => {
+                self.number_notation()?;
+                self.eat(Token::Float(value))?;
+                Ok(self.node(NodeKind::Float(value), start))
             }
             Token::String(ref value) => {
                 let value = value.clone();
-                self.eat(Token::String(value.clone()));
-                AstNode::String(value)
+                self.eat(Token::String(value.clone()))?;
+                self.string_literal(value, start)
             }
             Token::Identifier(ref name) => {
                 let name = name.clone();
-                self.eat(Token::Identifier(name.clone()));
-                AstNode::Identifier(name)
+                self.eat(Token::Identifier(name.clone()))?;
+                if self.current_token_info.token == Token::LParen {
+                    let args = self.arguments()?;
+                    return Ok(self.node(NodeKind::Call(name, args), start));
+                }
+                Ok(self.node(NodeKind::Identifier(name), start))
+            }
+            // The else branch takes the rest of the expression, as in
+            // `if A > B then A else B + 1`.
+            Token::If => {
+                self.eat(Token::If)?;
+                let condition = self.boolean_expr()?;
+                self.eat(Token::Then)?;
+                let then = self.expr()?;
+                self.eat(Token::Else)?;
+                let otherwise = self.expr()?;
+                Ok(self.node(NodeKind::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)), start))
+            }
+            Token::Null => {
+                self.eat(Token::Null)?;
+                Ok(self.node(NodeKind::Null, start))
+            }
+            Token::LBracket => {
+                let items = self.list(Token::LBracket, Token::RBracket)?;
+                Ok(self.node(NodeKind::Array(items), start))
+            }
+            Token::New => {
+                self.eat(Token::New)?;
+                match self.current_token_info.token.clone() {
+                    Token::Identifier(name) if name == "Array" => self.eat(Token::Identifier(name))?,
+                    token => return Err(self.error(format!("Expected Array after new, got {:?}", token))),
+                }
+                let sizes = self.arguments()?;
+                if sizes.is_empty() || sizes.len() > 2 {
+                    return Err(self.error(format!(
+                        "new Array takes one or two sizes, got {}",
+                        sizes.len()
+                    )));
+                }
+                Ok(self.node(NodeKind::NewArray(sizes), start))
             }
             Token::LParen => {
-                self.eat(Token::LParen);
-                let node = self.expr();
-                self.eat(Token::RParen);
-                node
-            }
-            _ => panic!(
-                "Unexpected token: {:?} at line {}, column {}",
-                self.current_token_info.token,
-                self.current_token_info.line,
-                self.current_token_info.column
-            ),
+                self.eat(Token::LParen)?;
+                let node = self.expr()?;
+                self.eat(Token::RParen)?;
+                Ok(node)
+            }
+            _ => Err(self.error(format!("Unexpected token: {:?}", self.current_token_info.token))),
+        }
+    }
+
+    // Digit separators, exponents and `0x` and `0b` literals are read by the
+    // lexer in any dialect, so that the error can say what to write instead.
+    fn number_notation(&self) -> Result<(), Error> {
+        let span = self.current_token_info.span;
+        let text = &self.lexer.source()[span.offset..span.end_offset];
+        if self.dialect != Dialect::Extended && !text.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return Err(self.error(format!(
+                "{} is only allowed in the extended dialect; write the number in plain decimal digits \
+                 or use --dialect extended",
+                text
+            )));
+        }
+        Ok(())
+    }
+
+    // Splits `"Total is {TOTAL}"` into a concatenation of its text and the
+    // expressions in braces. `{{` and `}}` stand for literal braces.
+    fn string_literal(&mut self, text: String, span: Span) -> Result<AstNode, Error> {
+        if !text.contains(['{', '}']) {
+            return Ok(AstNode::new(NodeKind::String(text), span));
+        }
+        let string = |text: &mut String| AstNode::new(NodeKind::String(std::mem::take(text)), span);
+        // Strings have no escapes, so the text sits right before the closing
+        // quote, or the three that close a long string.
+        let quotes = if span.end_offset - span.offset - text.len() > 2 { 3 } else { 1 };
+        let content_start = span.end_offset - quotes - text.len();
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '{' | '}' if chars.peek().map(|&(_, next)| next) == Some(c) => {
+                    chars.next();
+                    literal.push(c);
+                }
+                '{' => {
+                    let Some(length) = text[index..].find('}') else {
+                        return Err(Error::syntax("E0104", "Unclosed '{' in string; write '{{' for a literal brace", span));
+                    };
+                    if !literal.is_empty() || parts.is_empty() {
+                        parts.push(string(&mut literal));
+                    }
+                    let start = content_start + index + 1;
+                    parts.push(self.interpolation(start, start + length - 1, span)?);
+                    while chars.next_if(|&(next, _)| next <= index + length).is_some() {}
+                }
+                '}' => {
+                    return Err(Error::syntax("E0104", "Unmatched '}' in string; write '}}' for a literal brace", span));
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() || parts.is_empty() {
+            parts.push(string(&mut literal));
+        }
+
+        let mut parts = parts.into_iter();
+        let first = parts.next().unwrap();
+        Ok(parts.fold(first, |left, right| {
+            AstNode::new(NodeKind::BinOp(Box::new(left), BinaryOp::Plus, Box::new(right)), span)
+        }))
+    }
+
+    fn interpolation(&self, start: usize, end: usize, span: Span) -> Result<AstNode, Error> {
+        let mut parser = Parser::new(self.lexer.slice(start, end))?.with_dialect(self.dialect);
+        if parser.current_token_info.token == Token::Eof {
+            return Err(Error::syntax("E0104", "Empty '{}' in string; write '{{}}' for literal braces", span));
+        }
+        let expr = parser.expr()?;
+        if parser.current_token_info.token != Token::Eof {
+            return Err(parser.error(format!(
+                "Expected '}}' after interpolated expression, got {:?}",
+                parser.current_token_info.token
+            )));
+        }
+        Ok(expr)
+    }
+
+    fn arguments(&mut self) -> Result<Vec<AstNode>, Error> {
+        self.list(Token::LParen, Token::RParen)
+    }
+
+    // Parses comma-separated expressions between `open` and `close`.
+    fn list(&mut self, open: Token, close: Token) -> Result<Vec<AstNode>, Error> {
+        self.eat(open)?;
+        let mut items = vec![];
+        if self.current_token_info.token != close {
+            items.push(self.expr()?);
+            while self.current_token_info.token == Token::Comma {
+                self.eat(Token::Comma)?;
+                items.push(self.expr()?);
+            }
         }
+        self.eat(close)?;
+        Ok(items)
     }
 }
@@ -0,0 +1,190 @@
+use crate::grammar::{Expr, Production};
+use std::fmt::Write;
+
+// Railroad (syntax) diagrams for the rules of the grammar, one standalone SVG
+// per rule. Each piece is laid out with its entry on the left and its exit on
+// the right of a horizontal line at y = 0, so pieces join by translation.
+
+const ARC: i32 = 10;
+const GAP: i32 = 10;
+const ROW: i32 = 10;
+const BOX_HEIGHT: i32 = 24;
+const CHAR_WIDTH: i32 = 8;
+const MARGIN: i32 = 20;
+
+const STYLE: &str = "path { fill: none; stroke: #333; stroke-width: 1.5; } \
+rect { fill: #fff; stroke: #333; stroke-width: 1.5; } \
+rect.terminal { fill: #eef6ff; } \
+rect.special { fill: #f4f4f4; stroke-dasharray: 4 2; } \
+text { font: 14px monospace; text-anchor: middle; fill: #000; } \
+text.name { font-weight: bold; text-anchor: start; }";
+
+struct Block {
+    width: i32,
+    // How far the piece reaches above and below its line.
+    up: i32,
+    down: i32,
+    svg: String,
+}
+
+// The diagram for `production`. Boxes for other rules link to `<rule>.svg`
+// beside it.
+pub fn svg(production: &Production) -> String {
+    let block = layout(&production.body);
+    let width = block.width + 2 * GAP + 2 * MARGIN;
+    let top = MARGIN + BOX_HEIGHT;
+    let height = top + block.up + block.down + MARGIN;
+    let line = top + block.up;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        width, height, width, height
+    );
+    writeln!(svg, "<title>{}</title>", escape(&production.name)).unwrap();
+    writeln!(svg, "<style>{}</style>", STYLE).unwrap();
+    writeln!(svg, "<text class=\"name\" x=\"{}\" y=\"{}\">{}</text>", MARGIN, MARGIN + 4, escape(&production.name)).unwrap();
+    // A double bar where the rule starts and ends.
+    let end = MARGIN + 2 * GAP + block.width;
+    writeln!(
+        svg,
+        "<path d=\"M{m} {a}v12M{n} {a}v12M{m} {line}h{GAP}M{e} {line}h{GAP}M{f} {a}v12M{g} {a}v12\"/>",
+        m = MARGIN,
+        n = MARGIN + 4,
+        a = line - 6,
+        e = end - GAP,
+        f = end - 4,
+        g = end,
+    )
+    .unwrap();
+    writeln!(svg, "<g transform=\"translate({} {})\">{}</g>", MARGIN + GAP, line, block.svg).unwrap();
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn layout(expr: &Expr) -> Block {
+    match expr {
+        Expr::Terminal(text) => text_box(text, "terminal", None),
+        Expr::NonTerminal(name) => text_box(name, "nonterminal", Some(name)),
+        Expr::Special(text) => text_box(text, "special", None),
+        Expr::Sequence(items) => sequence(items.iter().map(layout).collect()),
+        Expr::Choice(items) => choice(items.iter().map(layout).collect()),
+        Expr::Optional(item) => optional(layout(item)),
+        // Zero or more times: a loop that may be skipped.
+        Expr::Repeat(item) => optional(repeat(layout(item))),
+    }
+}
+
+fn text_box(text: &str, class: &str, link: Option<&str>) -> Block {
+    let width = text.chars().count() as i32 * CHAR_WIDTH + 2 * GAP;
+    let half = BOX_HEIGHT / 2;
+    let radius = if class == "terminal" { half } else { 0 };
+    let mut svg = format!(
+        "<rect class=\"{}\" x=\"0\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\"/><text x=\"{}\" y=\"5\">{}</text>",
+        class,
+        -half,
+        width,
+        BOX_HEIGHT,
+        radius,
+        width / 2,
+        escape(text)
+    );
+    if let Some(name) = link {
+        svg = format!("<a href=\"{}.svg\">{}</a>", escape(name), svg);
+    }
+    Block { width, up: half, down: half, svg }
+}
+
+fn sequence(items: Vec<Block>) -> Block {
+    let mut block = Block { width: 0, up: 0, down: 0, svg: String::new() };
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            write!(block.svg, "<path d=\"M{} 0h{}\"/>", block.width, GAP).unwrap();
+            block.width += GAP;
+        }
+        write!(block.svg, "<g transform=\"translate({} 0)\">{}</g>", block.width, item.svg).unwrap();
+        block.width += item.width;
+        block.up = block.up.max(item.up);
+        block.down = block.down.max(item.down);
+    }
+    block
+}
+
+// The first item stays on the line; the others hang below it in order.
+fn choice(items: Vec<Block>) -> Block {
+    let inner = items.iter().map(|item| item.width).max().unwrap_or(0);
+    let width = inner + 4 * ARC;
+    let mut block = Block { width, up: items.first().map_or(0, |item| item.up), down: 0, svg: String::new() };
+    let mut y = 0;
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            y = (block.down + ROW + item.up).max(2 * ARC);
+            let (left, right) = (2 * ARC, 2 * ARC + item.width);
+            write!(
+                block.svg,
+                "<path d=\"M0 0q{a} 0 {a} {a}V{v}q0 {a} {a} {a}\"/><path d=\"M{r} {y}H{h}q{a} 0 {a} {n}V{a}q0 {n} {a} {n}\"/>",
+                a = ARC,
+                n = -ARC,
+                v = y - ARC,
+                r = right,
+                y = y,
+                h = width - 2 * ARC,
+            )
+            .unwrap();
+            write!(block.svg, "<g transform=\"translate({} {})\">{}</g>", left, y, item.svg).unwrap();
+        } else {
+            write!(
+                block.svg,
+                "<path d=\"M0 0h{}M{} 0H{}\"/><g transform=\"translate({} 0)\">{}</g>",
+                2 * ARC,
+                2 * ARC + item.width,
+                width,
+                2 * ARC,
+                item.svg
+            )
+            .unwrap();
+        }
+        block.down = block.down.max(y + item.down);
+    }
+    block
+}
+
+// The item on the line with a path above that goes around it.
+fn optional(item: Block) -> Block {
+    let width = item.width + 4 * ARC;
+    let top = -(item.up + ROW).max(2 * ARC);
+    let svg = format!(
+        "<path d=\"M0 0h{a2}M{e} 0H{w}\"/><path d=\"M0 0q{a} 0 {a} {n}V{t1}q0 {n} {a} {n}H{h}q{a} 0 {a} {a}V{n}q0 {a} {a} {a}\"/>\
+<g transform=\"translate({a2} 0)\">{}</g>",
+        item.svg,
+        a = ARC,
+        a2 = 2 * ARC,
+        n = -ARC,
+        e = 2 * ARC + item.width,
+        w = width,
+        t1 = top + ARC,
+        h = width - 2 * ARC,
+    );
+    Block { width, up: -top, down: item.down, svg }
+}
+
+// The item on the line with a path below that leads back to its start.
+fn repeat(item: Block) -> Block {
+    let width = item.width + 4 * ARC;
+    let bottom = (item.down + ROW).max(2 * ARC);
+    let (left, right) = (2 * ARC, 2 * ARC + item.width);
+    let svg = format!(
+        "<path d=\"M0 0h{l}M{r} 0H{w}\"/><path d=\"M{r} 0q{a} 0 {a} {a}V{b1}q0 {a} {n} {a}H{l}q{n} 0 {n} {n}V{a}q0 {n} {a} {n}\"/>\
+<g transform=\"translate({l} 0)\">{}</g>",
+        item.svg,
+        a = ARC,
+        n = -ARC,
+        l = left,
+        r = right,
+        w = width,
+        b1 = bottom - ARC,
+    );
+    Block { width, up: item.up, down: bottom, svg }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
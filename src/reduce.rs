@@ -0,0 +1,161 @@
+use crate::ast::{AstNode, NodeKind};
+use crate::formatter;
+use crate::keywords::Keywords;
+use crate::lexer::Lexer;
+use crate::parser::{Dialect, Parser};
+use std::sync::Arc;
+
+// The smallest program found and how many candidates were tried on the way.
+#[derive(Debug)]
+pub struct Reduction {
+    pub program: AstNode,
+    pub tests: usize,
+}
+
+// One way to make a program smaller, at the node with this index in the
+// order `AstNode::walk` visits them.
+#[derive(Debug, Clone, Copy)]
+enum Edit {
+    // Deletes `count` statements starting at `start` from one of the node's
+    // blocks.
+    Remove { node: usize, block: usize, start: usize, count: usize },
+    // Replaces the statement at `position` in one of the node's blocks with
+    // the statements of its own block `inner`, e.g. an `if` by its body.
+    Hoist { node: usize, block: usize, position: usize, inner: usize },
+    // Replaces an expression with one of its operands.
+    Operand { node: usize, operand: usize },
+}
+
+// Shrinks `program` for as long as `interesting` stays true of the result,
+// in the manner of delta debugging: large runs of statements go first, then
+// smaller ones down to single statements, then blocks are flattened into
+// their parents and expressions into their operands. Every accepted edit
+// starts the search again, so the result is as small as single edits can make
+// it. `interesting` must be true of `program` itself.
+//
+// Each candidate is printed and parsed again before it is tested, so edits
+// that leave something the parser would reject, such as a `return` outside
+// any procedure, are skipped rather than run.
+pub fn reduce(
+    program: &AstNode,
+    keywords: &Keywords,
+    dialect: Dialect,
+    interesting: &mut dyn FnMut(&AstNode) -> bool,
+) -> Reduction {
+    let mut current = program.clone();
+    let mut tests = 0;
+    'search: loop {
+        for edit in edits(&current) {
+            let text = formatter::format(&apply(&current, edit), keywords, false);
+            let parsed = Parser::new(Lexer::new(&text).with_keywords(keywords))
+                .and_then(|parser| parser.with_dialect(dialect).parse());
+            // Printing can undo an edit, e.g. one that only changes spans.
+            let Some(candidate) = parsed.ok().filter(|candidate| size(candidate) < size(&current)) else {
+                continue;
+            };
+            tests += 1;
+            if interesting(&candidate) {
+                current = candidate;
+                continue 'search;
+            }
+        }
+        return Reduction { program: current, tests };
+    }
+}
+
+// How many nodes `program` has, as a measure of its size.
+pub fn size(program: &AstNode) -> usize {
+    let mut count = 0;
+    program.walk(&mut |_| count += 1);
+    count
+}
+
+fn edits(program: &AstNode) -> Vec<Edit> {
+    let mut nodes = vec![];
+    program.walk(&mut |node| nodes.push(node));
+
+    let mut removals = vec![];
+    let mut hoists = vec![];
+    let mut operands = vec![];
+    for (node, ast) in nodes.iter().enumerate() {
+        for (block, statements) in ast.kind.blocks().into_iter().enumerate() {
+            let mut count = statements.len();
+            while count > 0 {
+                for start in (0..statements.len()).step_by(count) {
+                    let count = count.min(statements.len() - start);
+                    removals.push((count, Edit::Remove { node, block, start, count }));
+                }
+                count /= 2;
+            }
+            for (position, statement) in statements.iter().enumerate() {
+                if let NodeKind::Procedure(_) = statement.kind {
+                    continue;
+                }
+                for inner in 0..statement.kind.blocks().len() {
+                    hoists.push(Edit::Hoist { node, block, position, inner });
+                }
+            }
+        }
+        let count = match &ast.kind {
+            NodeKind::BinOp(..) | NodeKind::Index(..) => 2,
+            NodeKind::Conditional(..) => 3,
+            _ => 0,
+        };
+        operands.extend((0..count).map(|operand| Edit::Operand { node, operand }));
+    }
+    // Bigger cuts first, keeping the order of the program among equals.
+    removals.sort_by_key(|&(count, _)| std::cmp::Reverse(count));
+    removals.into_iter().map(|(_, edit)| edit).chain(hoists).chain(operands).collect()
+}
+
+fn apply(program: &AstNode, edit: Edit) -> AstNode {
+    let target = match edit {
+        Edit::Remove { node, .. } | Edit::Hoist { node, .. } | Edit::Operand { node, .. } => node,
+    };
+    let mut program = program.clone();
+    let mut position = 0;
+    program.walk_mut(&mut |node| {
+        if position == target {
+            match edit {
+                Edit::Remove { block, start, count, .. } => {
+                    blocks_mut(&mut node.kind)[block].drain(start..start + count);
+                }
+                Edit::Hoist { block, position, inner, .. } => {
+                    let statements = &mut blocks_mut(&mut node.kind)[block];
+                    let mut statement = statements.remove(position);
+                    let body = std::mem::take(blocks_mut(&mut statement.kind)[inner]);
+                    statements.splice(position..position, body);
+                }
+                Edit::Operand { operand, .. } => {
+                    let replacement = match &node.kind {
+                        NodeKind::BinOp(left, _, right) | NodeKind::Index(left, right) => {
+                            [left, right][operand].as_ref().clone()
+                        }
+                        NodeKind::Conditional(condition, then, otherwise) => {
+                            [condition, then, otherwise][operand].as_ref().clone()
+                        }
+                        _ => return,
+                    };
+                    *node = replacement;
+                }
+            }
+        }
+        position += 1;
+    });
+    program
+}
+
+// The same lists as `NodeKind::blocks`, in the same order.
+fn blocks_mut(kind: &mut NodeKind) -> Vec<&mut Vec<AstNode>> {
+    match kind {
+        NodeKind::Program(statements) => vec![statements],
+        NodeKind::If(_, true_branch, false_branch) => vec![true_branch, false_branch],
+        NodeKind::Loop(_, body) => vec![body],
+        NodeKind::Case(_, branches, default) => {
+            branches.iter_mut().map(|branch| &mut branch.body).chain(default.as_mut()).collect()
+        }
+        NodeKind::Procedure(procedure) => vec![&mut Arc::make_mut(procedure).body],
+        NodeKind::Try(body, _, handler) => vec![body, handler],
+        _ => vec![],
+    }
+}
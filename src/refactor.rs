@@ -0,0 +1,521 @@
+use crate::ast::{AstNode, NodeKind, Procedure, ProcedureKind};
+use crate::error::Error;
+use crate::formatter;
+use crate::keywords::Keywords;
+use crate::lexer::{Lexer, Span, Token};
+use crate::parser::{Dialect, Parser};
+use std::collections::BTreeSet;
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+
+// How far the statements of a procedure are indented, as the formatter does.
+const INDENT: &str = "    ";
+
+// Refactorings change the text of a program in place instead of printing its
+// tree again, so the layout and comments stay as the author left them. The
+// tree decides what to change, and the changed text must parse to the tree
+// the refactoring asked for.
+
+// A program after a refactoring, with how many places in it changed.
+#[derive(Debug, Clone)]
+pub struct Refactored {
+    pub source: String,
+    pub changes: usize,
+}
+
+// The names a program uses. A procedure and a variable may share a name, as
+// calls are told apart from variables by the `(` after them.
+#[derive(Debug, Default)]
+struct Names {
+    procedures: BTreeSet<String>,
+    // Variables, including parameters and `catch` variables.
+    variables: BTreeSet<String>,
+    // Everything called, so builtins the program uses count as taken.
+    calls: BTreeSet<String>,
+}
+
+impl Names {
+    fn of(program: &AstNode) -> Self {
+        let mut names = Names::default();
+        program.walk(&mut |node| match &node.kind {
+            NodeKind::Procedure(procedure) => {
+                names.procedures.insert(procedure.name.clone());
+                names.variables.extend(procedure.params.iter().map(|param| param.name.clone()));
+            }
+            NodeKind::Call(name, _) => {
+                names.calls.insert(name.clone());
+            }
+            NodeKind::Identifier(name)
+            | NodeKind::Assignment(name, _)
+            | NodeKind::Input(name, _)
+            | NodeKind::SetElement(name, ..)
+            | NodeKind::Try(_, Some(name), _) => {
+                names.variables.insert(name.clone());
+            }
+            NodeKind::Global(globals) => names.variables.extend(globals.iter().cloned()),
+            _ => {}
+        });
+        names
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.procedures.contains(name) || self.variables.contains(name) || self.calls.contains(name)
+    }
+}
+
+// Renames the variable or procedure `old` to `new` everywhere in `source`:
+// in definitions, uses, expressions in braces inside strings and the words of
+// doc comments. Method names such as `size` in `LIST.size()` are left alone,
+// and so are calls to a builtin that shares its name with a variable.
+pub fn rename(source: &str, keywords: &Keywords, dialect: Dialect, old: &str, new: &str) -> Result<Refactored, Error> {
+    let program = parse(source, keywords, dialect)?;
+    let names = Names::of(&program);
+    let procedure = names.procedures.contains(old);
+    let variable = names.variables.contains(old);
+    if !procedure && !variable {
+        return Err(Error::refactor("E0400", format!("There is no variable or procedure named {}", old)));
+    }
+    check_new_name(new, keywords, &names)?;
+
+    let mut lexer = Lexer::new(source).with_keywords(keywords);
+    let tokens = lexer.tokenize()?;
+    let mut edits: Vec<Range<usize>> = vec![];
+    let mut strings: Vec<Range<usize>> = vec![];
+    for (index, info) in tokens.iter().enumerate() {
+        match &info.token {
+            Token::Identifier(name) if name == old => {
+                if index > 0 && tokens[index - 1].token == Token::Dot {
+                    continue;
+                }
+                let call = tokens.get(index + 1).is_some_and(|next| next.token == Token::LParen);
+                if (call && procedure) || (!call && variable) {
+                    edits.push(info.span.offset..info.span.end_offset);
+                }
+            }
+            Token::String(_) => strings.push(info.span.offset..info.span.end_offset),
+            _ => {}
+        }
+    }
+    // The lexer reads `"Total is {TOTAL}"` as one token, but the parser gives
+    // the expressions in braces spans of their own in the source.
+    program.walk(&mut |node| {
+        let renamed = match &node.kind {
+            NodeKind::Identifier(name) => variable && name == old,
+            NodeKind::Call(name, _) => procedure && name == old,
+            _ => false,
+        };
+        if renamed && strings.iter().any(|string| string.contains(&node.span.offset)) {
+            edits.push(node.span.offset..node.span.offset + old.len());
+        }
+    });
+    for comment in lexer.doc_comments() {
+        let text = &source[comment.span.offset..comment.span.end_offset];
+        let start = comment.span.offset;
+        edits.extend(words(text, old).map(|offset| start + offset..start + offset + old.len()));
+    }
+    edits.sort_by_key(|edit| edit.start);
+    edits.dedup_by_key(|edit| edit.start);
+
+    let mut renamed = String::with_capacity(source.len());
+    let mut offset = 0;
+    for edit in &edits {
+        renamed.push_str(&source[offset..edit.start]);
+        renamed.push_str(new);
+        offset = edit.end;
+    }
+    renamed.push_str(&source[offset..]);
+
+    // The new text must mean what renaming the names in the tree means.
+    let mut expected = program;
+    rename_tree(&mut expected, old, new, procedure, variable);
+    let actual = parse(&renamed, keywords, dialect).map_err(|error| {
+        Error::refactor("E0403", format!("Renaming {} to {} would break the program: {}", old, new, error.message))
+    })?;
+    if formatter::format(&actual, keywords, false) != formatter::format(&expected, keywords, false) {
+        return Err(Error::refactor(
+            "E0403",
+            format!("Renaming {} to {} would change what the program does", old, new),
+        ));
+    }
+    Ok(Refactored { source: renamed, changes: edits.len() })
+}
+
+// Moves the statements on `lines` into a new procedure called `name` and
+// calls it in their place. The lines must hold whole statements of one block.
+// Variables the statements read that already have a value are passed in. A
+// variable they set that is used afterwards comes back as the result of a
+// function, or through a `ref` parameter when there are several.
+pub fn extract(
+    source: &str,
+    keywords: &Keywords,
+    dialect: Dialect,
+    lines: RangeInclusive<usize>,
+    name: &str,
+) -> Result<Refactored, Error> {
+    let program = parse(source, keywords, dialect)?;
+    check_new_name(name, keywords, &Names::of(&program))?;
+    let described = describe(&lines);
+    let NodeKind::Program(statements) = &program.kind else {
+        unreachable!("the parser returns a program");
+    };
+    let Some(Selection { statements: selected, scope }) = select(statements, &lines, Scope::default())? else {
+        return Err(Error::refactor("E0404", format!("There are no statements on {}", described)));
+    };
+    let mut blocker = None;
+    for statement in selected {
+        statement.walk(&mut |node| {
+            let keyword = match node.kind {
+                NodeKind::Return(_) => "return",
+                NodeKind::Global(_) => "global",
+                NodeKind::Procedure(ref procedure) if procedure.kind == ProcedureKind::Sub => "sub",
+                NodeKind::Procedure(_) => "function",
+                _ => return,
+            };
+            blocker.get_or_insert(keyword);
+        });
+    }
+    if let Some(keyword) = blocker {
+        return Err(Error::refactor(
+            "E0405",
+            format!(
+                "The statements on {} use {}, so they cannot be moved into a procedure",
+                described,
+                keywords.spelling(keyword)
+            ),
+        ));
+    }
+
+    let (first, last) = (&selected[0], &selected[selected.len() - 1]);
+    let selection = first.span.offset..last.span.end_offset;
+    let mut all = vec![];
+    match scope.procedure {
+        Some(procedure) => procedure.body.iter().for_each(|statement| uses(statement, &mut all)),
+        None => statements
+            .iter()
+            .filter(|statement| !matches!(statement.kind, NodeKind::Procedure(_)))
+            .for_each(|statement| uses(statement, &mut all)),
+    }
+    let (inside, outside): (Vec<Use>, Vec<Use>) = all.into_iter().partition(|use_| selection.contains(&use_.offset));
+    let mut order: Vec<&str> = vec![];
+    for use_ in &inside {
+        if !order.contains(&use_.name) {
+            order.push(use_.name);
+        }
+    }
+    let mut globals = BTreeSet::new();
+    let parameter = |variable: &str| scope.procedure.and_then(|p| p.params.iter().find(|param| param.name == variable));
+    if let Some(procedure) = scope.procedure {
+        for statement in &procedure.body {
+            statement.walk(&mut |node| {
+                if let NodeKind::Global(names) = &node.kind {
+                    globals.extend(names.iter().map(String::as_str));
+                }
+            });
+        }
+    }
+    // Whether a variable may have a value when the lines start: it is used
+    // before them, or anywhere in a loop they are in.
+    let in_loop = |use_: &Use| scope.looping.is_some_and(|span| (span.offset..span.end_offset).contains(&use_.offset));
+    let available = |variable: &str| {
+        parameter(variable).is_some()
+            || outside.iter().any(|use_| use_.name == variable && (use_.offset < selection.start || in_loop(use_)))
+    };
+    let used_after = |variable: &str| {
+        parameter(variable).is_some_and(|param| param.by_ref)
+            || outside.iter().any(|use_| use_.name == variable && (use_.offset >= selection.end || in_loop(use_)))
+    };
+    // The end of the first selected statement that sets the variable outright.
+    let assigned = |variable: &str| {
+        selected.iter().find_map(|statement| match &statement.kind {
+            NodeKind::Assignment(name, _) | NodeKind::Input(name, _) if name == variable => {
+                Some(statement.span.end_offset)
+            }
+            _ => None,
+        })
+    };
+    let reads_first = |variable: &str| {
+        let assigned = assigned(variable).unwrap_or(usize::MAX);
+        inside.iter().any(|use_| use_.name == variable && !use_.write && use_.offset < assigned)
+    };
+
+    let locals: Vec<&str> = order.iter().copied().filter(|variable| !globals.contains(variable)).collect();
+    let outputs: Vec<&str> = locals
+        .iter()
+        .copied()
+        .filter(|&variable| inside.iter().any(|use_| use_.name == variable && use_.write) && used_after(variable))
+        .collect();
+    let fresh: Vec<&str> = outputs.iter().copied().filter(|&variable| !available(variable)).collect();
+    if fresh.len() > 1 {
+        return Err(Error::refactor(
+            "E0405",
+            format!(
+                "{} are set on {} and used afterwards, but a procedure can only return one new variable",
+                fresh[..fresh.len() - 1].join(", ") + " and " + fresh[fresh.len() - 1],
+                described
+            ),
+        ));
+    }
+    let returned = if outputs.len() == 1 { Some(outputs[0]) } else { fresh.first().copied() };
+    let by_ref = |variable: &str| outputs.contains(&variable) && returned != Some(variable);
+    let params: Vec<&str> = locals
+        .iter()
+        .copied()
+        .filter(|&variable| {
+            available(variable)
+                && (reads_first(variable)
+                    || by_ref(variable)
+                    || (returned == Some(variable) && assigned(variable).is_none()))
+        })
+        .collect();
+
+    // The new procedure, with the selected lines moved one level in.
+    let start = line_start(source, first.span.offset);
+    let end = line_end(source, last.span.end_offset);
+    if !source[start..first.span.offset].trim().is_empty() || !source[last.span.end_offset..end].trim().is_empty() {
+        return Err(Error::refactor(
+            "E0404",
+            format!("The statements on {} share a line with statements that would not be moved", described),
+        ));
+    }
+    let indent = &source[start..first.span.offset];
+    let (kind, end_kind) = match returned {
+        Some(_) => (keywords.spelling("function"), keywords.spelling("endfunction")),
+        None => (keywords.spelling("sub"), keywords.spelling("endsub")),
+    };
+    let signature: Vec<String> = params
+        .iter()
+        .map(|&param| {
+            if by_ref(param) {
+                format!("{} {}", keywords.spelling("ref"), param)
+            } else {
+                param.to_string()
+            }
+        })
+        .collect();
+    let mut procedure = format!("{} {}({})\n", kind, name, signature.join(", "));
+    let globals: Vec<&str> = order.iter().copied().filter(|variable| globals.contains(variable)).collect();
+    if !globals.is_empty() {
+        procedure.push_str(&format!("{}{} {}\n", INDENT, keywords.spelling("global"), globals.join(", ")));
+    }
+    for line in source[start..end].split_inclusive('\n') {
+        if line.trim().is_empty() {
+            procedure.push('\n');
+        } else {
+            procedure.push_str(INDENT);
+            procedure.push_str(line.strip_prefix(indent).unwrap_or(line.trim_start()));
+        }
+    }
+    if !procedure.ends_with('\n') {
+        procedure.push('\n');
+    }
+    if let Some(variable) = returned {
+        procedure.push_str(&format!("{}{} {}\n", INDENT, keywords.spelling("return"), variable));
+    }
+    procedure.push_str(&format!("{}\n", end_kind));
+
+    let result = returned.map(|variable| format!("{} = ", variable)).unwrap_or_default();
+    let mut call = format!("{}{}{}({})", indent, result, name, params.join(", "));
+    if source[..end].ends_with('\n') {
+        call.push('\n');
+    }
+
+    // A procedure goes after the one it was taken from, and one taken from the
+    // main program goes just above the statement it was in.
+    let mut extracted = String::with_capacity(source.len() + procedure.len() + call.len());
+    match (scope.procedure, scope.top) {
+        (Some(_), Some(top)) => {
+            let after = line_end(source, top.span.end_offset);
+            extracted.push_str(&source[..start]);
+            extracted.push_str(&call);
+            extracted.push_str(&source[end..after]);
+            if !extracted.ends_with('\n') {
+                extracted.push('\n');
+            }
+            extracted.push('\n');
+            extracted.push_str(&procedure);
+            if source[after..].starts_with(|c: char| c != '\n') {
+                extracted.push('\n');
+            }
+            extracted.push_str(&source[after..]);
+        }
+        (_, top) => {
+            let before = line_start(source, top.unwrap_or(first).span.offset);
+            extracted.push_str(&source[..before]);
+            extracted.push_str(&procedure);
+            extracted.push('\n');
+            extracted.push_str(&source[before..start]);
+            extracted.push_str(&call);
+            extracted.push_str(&source[end..]);
+        }
+    }
+    parse(&extracted, keywords, dialect).map_err(|error| {
+        Error::refactor("E0403", format!("Extracting {} would break the program: {}", described, error.message))
+    })?;
+    Ok(Refactored { source: extracted, changes: selected.len() })
+}
+
+// Where a block sits in the program.
+#[derive(Debug, Clone, Copy, Default)]
+struct Scope<'a> {
+    // The procedure the block is in.
+    procedure: Option<&'a Procedure>,
+    // The statement of the main program, or the procedure, the block is in.
+    top: Option<&'a AstNode>,
+    // The outermost loop around the block, whose other statements run again
+    // after the block.
+    looping: Option<Span>,
+}
+
+// The statements of one block that a line range holds.
+#[derive(Debug)]
+struct Selection<'a> {
+    statements: &'a [AstNode],
+    scope: Scope<'a>,
+}
+
+// The statements of `block` on `lines`, or of the one block inside it they
+// are in.
+fn select<'a>(
+    block: &'a [AstNode],
+    lines: &RangeInclusive<usize>,
+    scope: Scope<'a>,
+) -> Result<Option<Selection<'a>>, Error> {
+    let inside = |statement: &AstNode| lines.contains(&statement.span.line) && lines.contains(&statement.span.end_line);
+    let overlaps =
+        |statement: &AstNode| statement.span.line <= *lines.end() && statement.span.end_line >= *lines.start();
+    let cut: Vec<&AstNode> = block.iter().filter(|statement| overlaps(statement) && !inside(statement)).collect();
+    match (block.iter().position(inside), cut.as_slice()) {
+        (Some(start), []) => {
+            let count = block[start..].iter().take_while(|statement| inside(statement)).count();
+            Ok(Some(Selection { statements: &block[start..start + count], scope }))
+        }
+        (None, []) => Ok(None),
+        (None, [statement]) => {
+            let scope = Scope {
+                procedure: match &statement.kind {
+                    NodeKind::Procedure(procedure) => Some(procedure),
+                    _ => scope.procedure,
+                },
+                top: scope.top.or(Some(statement)),
+                looping: scope.looping.or(matches!(statement.kind, NodeKind::Loop(..)).then_some(statement.span)),
+            };
+            for inner in statement.kind.blocks() {
+                if let Some(selection) = select(inner, lines, scope)? {
+                    return Ok(Some(selection));
+                }
+            }
+            Err(cut_statement(statement, lines))
+        }
+        (_, [statement, ..]) => Err(cut_statement(statement, lines)),
+    }
+}
+
+fn cut_statement(statement: &AstNode, lines: &RangeInclusive<usize>) -> Error {
+    let statement_lines = statement.span.line..=statement.span.end_line;
+    Error::refactor(
+        "E0404",
+        format!("The statement on {} is only partly on {}", describe(&statement_lines), describe(lines)),
+    )
+}
+
+// `line 3` or `lines 3-8`, for messages.
+fn describe(lines: &RangeInclusive<usize>) -> String {
+    if lines.start() == lines.end() {
+        format!("line {}", lines.start())
+    } else {
+        format!("lines {}-{}", lines.start(), lines.end())
+    }
+}
+
+// A variable's name at a place in the source.
+#[derive(Debug)]
+struct Use<'a> {
+    name: &'a str,
+    offset: usize,
+    // Whether the variable is given a new value there. Setting an element
+    // changes the array the variable already holds, so it counts as a read.
+    write: bool,
+}
+
+fn uses<'a>(statement: &'a AstNode, out: &mut Vec<Use<'a>>) {
+    statement.walk(&mut |node| {
+        let (name, write) = match &node.kind {
+            NodeKind::Identifier(name) | NodeKind::SetElement(name, ..) => (name, false),
+            NodeKind::Assignment(name, _) | NodeKind::Input(name, _) | NodeKind::Try(_, Some(name), _) => (name, true),
+            _ => return,
+        };
+        out.push(Use { name, offset: node.span.offset, write });
+    });
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |index| index + 1)
+}
+
+// Just past the new line ending the line `offset` is on.
+fn line_end(source: &str, offset: usize) -> usize {
+    source[offset..].find('\n').map_or(source.len(), |index| offset + index + 1)
+}
+
+// Checks that `new` can name a variable or procedure that `names` leaves free.
+fn check_new_name(new: &str, keywords: &Keywords, names: &Names) -> Result<(), Error> {
+    let spelled = Lexer::new(new).with_keywords(keywords).tokenize().unwrap_or_default();
+    if !matches!(spelled.as_slice(), [name, _] if name.token == Token::Identifier(new.to_string())) {
+        return Err(Error::refactor("E0401", format!("{} is not a valid name", new)));
+    }
+    if names.contains(new) {
+        return Err(Error::refactor("E0402", format!("{} is already used in the program", new)));
+    }
+    Ok(())
+}
+
+fn parse(source: &str, keywords: &Keywords, dialect: Dialect) -> Result<AstNode, Error> {
+    Parser::new(Lexer::new(source).with_keywords(keywords))?.with_dialect(dialect).parse()
+}
+
+// The renaming `rename` makes, done on the tree.
+fn rename_tree(program: &mut AstNode, old: &str, new: &str, procedure: bool, variable: bool) {
+    let rename = |name: &mut String| {
+        if name == old {
+            *name = new.to_string();
+        }
+    };
+    program.walk_mut(&mut |node| match &mut node.kind {
+        NodeKind::Procedure(definition) => {
+            let definition = Arc::make_mut(definition);
+            if procedure {
+                rename(&mut definition.name);
+            }
+            if variable {
+                definition.params.iter_mut().for_each(|param| rename(&mut param.name));
+            }
+            for line in &mut definition.doc {
+                let starts: Vec<usize> = words(line, old).collect();
+                for start in starts.into_iter().rev() {
+                    line.replace_range(start..start + old.len(), new);
+                }
+            }
+        }
+        NodeKind::Call(name, _) if procedure => rename(name),
+        NodeKind::Identifier(name)
+        | NodeKind::Assignment(name, _)
+        | NodeKind::Input(name, _)
+        | NodeKind::SetElement(name, ..)
+        | NodeKind::Try(_, Some(name), _)
+            if variable =>
+        {
+            rename(name)
+        }
+        NodeKind::Global(globals) if variable => globals.iter_mut().for_each(rename),
+        _ => {}
+    });
+}
+
+// Where `word` appears in `text` as a whole word, not as part of a longer name.
+fn words<'a>(text: &'a str, word: &'a str) -> impl Iterator<Item = usize> + 'a {
+    text.match_indices(word).map(|(start, _)| start).filter(move |&start| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
@@ -0,0 +1,227 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::formatter;
+use crate::interpreter::{Interpreter, Limits};
+use crate::parser::Dialect;
+use crate::semantic;
+use crate::value::Preview;
+use crate::serve::{self, RunRequest};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Cursor, Write};
+use std::sync::Arc;
+
+// JSON-RPC 2.0 over standard input and output, one message per line. The
+// methods are:
+//
+// - `run`, with the parameters of `POST /run` in `serve`. Output is sent as it
+//   is written, in `output` notifications carrying the request's id, before
+//   the result `{"ok", "diagnostics", "stats"}`, where `stats` is as in
+//   `serve`.
+// - `check`, with `source`, `lang` and `dialect`, giving `{"diagnostics"}`.
+// - `format`, with `source`, `lang`, `dialect`, `to` and `strict` as in `fmt`,
+//   giving `{"text", "diagnostics"}`, where `text` is null if the source does
+//   not parse.
+// - `execute`, like `run` but in a session that keeps variables and
+//   procedures from one request to the next, as for notebook cells.
+// - `variables`, with optional `depth` and `items` limits, giving the
+//   session's `{"variables", "stoppedAt"}`: a map from each name to a
+//   preview of its value, such as `[1, 2, ... 8 more]`, and the span of the
+//   error the last cell stopped with, if any. After an error the variables
+//   are those where it happened, inside any procedure it stopped.
+// - `reset`, which ends the session.
+// - `semanticTokens`, with `source` and `lang`, giving `{"legend", "data"}`
+//   in the form of an LSP semantic tokens legend and response.
+//
+// Requests are handled one at a time, in order, until standard input closes.
+// Each runs within `limits`, lowered wherever it asks for less.
+pub fn serve(config: &Config, limits: Limits) -> Result<(), Error> {
+    let mut session = None;
+    for line in io::stdin().lock().lines() {
+        let line = line.map_err(|e| Error::io(format!("Failed to read a request: {}", e)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle(&line, &mut session, config, limits) {
+            send(&response).map_err(|e| Error::io(format!("Failed to write a response: {}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    // Absent for notifications, which get no response.
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FormatRequest {
+    source: String,
+    lang: Option<String>,
+    dialect: Option<Dialect>,
+    to: Option<String>,
+    strict: bool,
+}
+
+// Limits for `Value::preview`, each falling back to the default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct VariablesRequest {
+    depth: Option<usize>,
+    items: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Empty {}
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+fn handle(line: &str, session: &mut Option<Interpreter>, config: &Config, limits: Limits) -> Option<Value> {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(e) => return Some(failure(Value::Null, PARSE_ERROR, format!("Invalid JSON: {}", e))),
+    };
+    let request = match serde_json::from_value::<Request>(message) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(request) => {
+            let message = format!("Unsupported JSON-RPC version {}", request.jsonrpc);
+            return Some(failure(request.id.unwrap_or_default(), INVALID_REQUEST, message));
+        }
+        Err(e) => return Some(failure(Value::Null, INVALID_REQUEST, format!("Invalid request: {}", e))),
+    };
+    let id = request.id.clone().unwrap_or_default();
+    let params = match request.params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    let result = match request.method.as_str() {
+        "run" => {
+            serde_json::from_value(params).map(|params| run(params, &mut Interpreter::new(), &id, config, limits))
+        }
+        "execute" => serde_json::from_value(params)
+            .map(|params| run(params, session.get_or_insert_with(Interpreter::new), &id, config, limits)),
+        "variables" => serde_json::from_value(params).map(|params| variables(params, session.as_ref())),
+        "semanticTokens" => serde_json::from_value(params).map(|params| semantic_tokens(params, config)),
+        "reset" => serde_json::from_value::<Empty>(params).map(|_| {
+            *session = None;
+            Value::Null
+        }),
+        "check" => serde_json::from_value(params).map(|params| check(params, config)),
+        "format" => serde_json::from_value(params).map(|params| format(params, config)),
+        method => {
+            let message = format!("Unknown method: {}", method);
+            return request.id.map(|id| failure(id, METHOD_NOT_FOUND, message));
+        }
+    };
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => failure(id, INVALID_PARAMS, format!("Invalid parameters: {}", e)),
+    };
+    request.id.map(|_| response)
+}
+
+fn run(request: RunRequest, interpreter: &mut Interpreter, id: &Value, config: &Config, limits: Limits) -> Value {
+    interpreter.set_output(Box::new(OutputStream { id: id.clone() }));
+    interpreter.set_input(Box::new(Cursor::new(request.inputs.join("\n"))), false);
+    interpreter.set_limits(serve::lower(limits, &request.limits));
+    interpreter.set_options(config.run_options());
+    let program = request.parse_to_run(config);
+    let started = program.is_ok();
+    let result = program.and_then(|program| interpreter.interpret(Arc::new(program)));
+    let stats = started.then(|| interpreter.stats().to_json());
+    let warnings = if started { interpreter.warnings() } else { &[] };
+    let diagnostics: Vec<Value> = warnings.iter().chain(result.as_ref().err()).map(Error::to_json).collect();
+    json!({ "ok": result.is_ok(), "diagnostics": diagnostics, "stats": stats })
+}
+
+fn variables(request: VariablesRequest, session: Option<&Interpreter>) -> Value {
+    let defaults = Preview::default();
+    let limits = Preview {
+        depth: request.depth.unwrap_or(defaults.depth),
+        items: request.items.unwrap_or(defaults.items),
+    };
+    // After an error, the variables where it happened, with the locals of
+    // the calls it abandoned.
+    let failure = session.and_then(Interpreter::failure);
+    let variables: serde_json::Map<String, Value> = match failure {
+        Some(failure) => failure.variables.clone(),
+        None => session.map(Interpreter::variables).unwrap_or_default(),
+    }
+    .into_iter()
+    .map(|(name, value)| (name, Value::String(value.preview(limits))))
+    .collect();
+    json!({ "variables": variables, "stoppedAt": failure.and_then(|failure| failure.span) })
+}
+
+fn check(request: RunRequest, config: &Config) -> Value {
+    json!({ "diagnostics": diagnostics(request.parse(config).err()) })
+}
+
+fn format(request: FormatRequest, config: &Config) -> Value {
+    let target = request.to.as_deref().or(request.lang.as_deref()).map(String::from);
+    let program = RunRequest {
+        source: request.source,
+        lang: request.lang,
+        dialect: request.dialect,
+        ..RunRequest::default()
+    };
+    let formatted = program.parse(config).and_then(|program| {
+        let keywords = config.keywords(target.as_deref())?;
+        Ok(formatter::format(&program, &keywords, request.strict))
+    });
+    match formatted {
+        Ok(text) => json!({ "text": text, "diagnostics": [] }),
+        Err(e) => json!({ "text": null, "diagnostics": diagnostics(Some(e)) }),
+    }
+}
+
+fn semantic_tokens(request: RunRequest, config: &Config) -> Value {
+    match config.keywords(request.lang.as_deref()) {
+        Ok(keywords) => {
+            let tokens = semantic::classify(&request.source, &keywords);
+            json!({ "legend": semantic::LEGEND, "data": semantic::encode(&request.source, &tokens) })
+        }
+        Err(e) => json!({ "legend": semantic::LEGEND, "data": [], "diagnostics": diagnostics(Some(e)) }),
+    }
+}
+
+fn diagnostics(error: Option<Error>) -> Vec<Value> {
+    error.iter().map(Error::to_json).collect()
+}
+
+fn failure(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn send(message: &Value) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", message)?;
+    stdout.flush()
+}
+
+// Sends what a program writes as `output` notifications.
+struct OutputStream {
+    id: Value,
+}
+
+impl Write for OutputStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        send(&json!({ "jsonrpc": "2.0", "method": "output", "params": { "id": self.id, "text": text } }))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
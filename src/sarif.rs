@@ -0,0 +1,68 @@
+use crate::error::Error;
+use crate::lexer::Span;
+use serde_json::{json, Value};
+
+// Builds a SARIF 2.1.0 log for the diagnostics produced while checking `files`.
+pub fn report(files: &[String], errors: &[Error]) -> Value {
+    let mut rule_ids: Vec<&str> = errors.iter().map(|e| e.code).collect();
+    rule_ids.sort_unstable();
+    rule_ids.dedup();
+
+    let rules: Vec<Value> = rule_ids.iter().map(|id| json!({ "id": id })).collect();
+
+    let results: Vec<Value> = errors
+        .iter()
+        .map(|error| {
+            let file = error.file.as_deref().or(files.first().map(String::as_str));
+            let related: Vec<Value> = error
+                .related
+                .iter()
+                .map(|related| {
+                    let mut location = location(related.file.as_deref().or(file), Some(related.span));
+                    location["message"] = json!({ "text": related.message });
+                    location
+                })
+                .collect();
+            json!({
+                "ruleId": error.code,
+                "level": error.severity(),
+                "message": { "text": error.message },
+                "locations": [location(file, error.span)],
+                "relatedLocations": related,
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "artifacts": files.iter().map(|file| json!({ "location": { "uri": file } })).collect::<Vec<_>>(),
+            "results": results,
+        }]
+    })
+}
+
+fn location(file: Option<&str>, span: Option<Span>) -> Value {
+    let mut location = json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": file },
+        }
+    });
+    if let Some(span) = span {
+        location["physicalLocation"]["region"] = json!({
+            "startLine": span.line,
+            "startColumn": span.column,
+            "endLine": span.end_line,
+            "endColumn": span.end_column,
+        });
+    }
+    location
+}
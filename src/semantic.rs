@@ -0,0 +1,109 @@
+use crate::keywords::Keywords;
+use crate::lexer::{Lexer, Span, Token};
+use serde::Serialize;
+
+// How an editor should color a token. The only comments are the `///` doc
+// comments above procedures.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenClass {
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Operator,
+    Comment,
+}
+
+// The LSP token types for each class, indexed by `TokenClass as u32`.
+pub const LEGEND: [&str; 6] = ["keyword", "variable", "number", "string", "operator", "comment"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SemanticToken {
+    pub class: TokenClass,
+    pub span: Span,
+}
+
+// Classifies every token of `source` with the real lexer, so keywords in any
+// language and configured aliases are highlighted as the interpreter reads
+// them. Stops at the first lexical error, keeping the tokens before it, so a
+// half-typed string does not lose the highlighting of the lines above it.
+pub fn classify(source: &str, keywords: &Keywords) -> Vec<SemanticToken> {
+    let mut lexer = Lexer::new(source).with_keywords(keywords);
+    let mut tokens: Vec<SemanticToken> = vec![];
+    let mut comments = 0;
+    while let Ok(info) = lexer.get_next_token() {
+        // Comments are skipped on the way to a token, so any new ones come
+        // before it.
+        for comment in &lexer.doc_comments()[comments..] {
+            tokens.push(SemanticToken { class: TokenClass::Comment, span: comment.span });
+        }
+        comments = lexer.doc_comments().len();
+        let class = match info.token {
+            Token::Eof => break,
+            Token::Identifier(_) => TokenClass::Identifier,
+            Token::Number(_) | Token::Float(_) => TokenClass::Number,
+            Token::String(_) => TokenClass::String,
+            Token::Assign
+            | Token::Plus
+            | Token::Minus
+            | Token::Star
+            | Token::Slash
+            | Token::Caret
+            | Token::PlusAssign
+            | Token::MinusAssign
+            | Token::StarAssign
+            | Token::LParen
+            | Token::RParen
+            | Token::LBracket
+            | Token::RBracket
+            | Token::Comma
+            | Token::Colon
+            | Token::Dot
+            | Token::Semicolon
+            | Token::NotEqual
+            | Token::GreaterThan
+            | Token::GreaterThanOrEqual
+            | Token::LessThan
+            | Token::LessThanOrEqual
+            | Token::ShiftLeft
+            | Token::ShiftRight => TokenClass::Operator,
+            _ => TokenClass::Keyword,
+        };
+        // A multi-word alias such as `end if` becomes several tokens with one
+        // span; it is highlighted once.
+        if tokens.last().is_some_and(|last| last.span.offset == info.span.offset) {
+            continue;
+        }
+        tokens.push(SemanticToken { class, span: info.span });
+    }
+    tokens
+}
+
+// Encodes tokens as LSP `SemanticTokens.data`: five numbers per token giving
+// the line and start relative to the previous token, the length, the index in
+// LEGEND and no modifiers. Positions count UTF-16 code units, as LSP expects.
+// Tokens may not span lines, so a long string is sent one line at a time.
+pub fn encode(source: &str, tokens: &[SemanticToken]) -> Vec<u32> {
+    let utf16 = |text: &str| text.encode_utf16().count() as u32;
+    let mut data = vec![];
+    let (mut line, mut start) = (0, 0);
+    for token in tokens {
+        let span = token.span;
+        let mut offset = span.offset;
+        for (index, text) in source[span.offset..span.end_offset].split('\n').enumerate() {
+            let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+            let token_line = (span.line + index) as u32 - 1;
+            let token_start = utf16(&source[line_start..offset]);
+            offset += text.len() + 1;
+            let text = text.trim_end_matches('\r');
+            if text.is_empty() {
+                continue;
+            }
+            let delta_start = if token_line == line { token_start - start } else { token_start };
+            data.extend([token_line - line, delta_start, utf16(text), token.class as u32, 0]);
+            (line, start) = (token_line, token_start);
+        }
+    }
+    data
+}
@@ -0,0 +1,202 @@
+use crate::ast::AstNode;
+use crate::cache::ProgramCache;
+use crate::config::Config;
+use crate::desugar;
+use crate::error::Error;
+use crate::interpreter::{Interpreter, Limits, STACK_SIZE};
+use crate::lexer::Lexer;
+use crate::parser::{Dialect, Parser};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub struct ServeOptions {
+    pub jobs: usize,
+    // The most a request may use; each request can lower these for itself.
+    pub limits: Limits,
+}
+
+// A program to run, as the body of `POST /run` or the parameters of an `rpc`
+// run request.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RunRequest {
+    pub(crate) source: String,
+    // Lines read by `input`, in order. Reading past the last is an error.
+    pub(crate) inputs: Vec<String>,
+    pub(crate) lang: Option<String>,
+    pub(crate) dialect: Option<Dialect>,
+    pub(crate) limits: RequestLimits,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RequestLimits {
+    steps: Option<u64>,
+    output: Option<usize>,
+    time_ms: Option<u64>,
+    memory: Option<usize>,
+}
+
+impl RunRequest {
+    // The program as written, for tools such as `format`.
+    pub(crate) fn parse(&self, config: &Config) -> Result<AstNode, Error> {
+        let keywords = config.keywords(self.lang.as_deref())?;
+        Parser::new(Lexer::new(&self.source).with_keywords(&keywords))?.with_dialect(self.dialect(config)).parse()
+    }
+
+    // The program ready to run, desugared as every runner reads it.
+    pub(crate) fn parse_to_run(&self, config: &Config) -> Result<AstNode, Error> {
+        let keywords = config.keywords(self.lang.as_deref())?;
+        desugar::parse(Lexer::new(&self.source).with_keywords(&keywords), self.dialect(config))
+    }
+
+    // Like `parse_to_run`, but keeping the program in `cache` by its source,
+    // language and dialect.
+    pub(crate) fn parse_cached(&self, config: &Config, cache: &ProgramCache) -> Result<Arc<AstNode>, Error> {
+        cache.get_or_parse(&(&self.source, &self.lang, self.dialect), || self.parse_to_run(config))
+    }
+
+    fn dialect(&self, config: &Config) -> Dialect {
+        self.dialect.or(config.dialect).unwrap_or_default()
+    }
+}
+
+const MAX_BODY: usize = 1024 * 1024;
+
+// Programs have no way to reach files or the network, and the terminal
+// builtins stay disabled, so the limits are what keep one request from
+// starving the others. `jobs` requests are handled at once; others wait for a
+// free worker. Workers share one cache of parsed programs, whose hit rate
+// `GET /stats` reports.
+pub fn serve(listener: TcpListener, options: &ServeOptions, config: &Config) {
+    let cache = ProgramCache::new();
+    thread::scope(|scope| {
+        for _ in 0..options.jobs.max(1) {
+            let (listener, cache) = (&listener, &cache);
+            thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn_scoped(scope, move || loop {
+                    match listener.accept() {
+                        // A request that panics loses its connection, and the
+                        // worker goes on to the next.
+                        Ok((stream, _)) => match panic::catch_unwind(|| handle(stream, options, config, cache)) {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => tracing::debug!("connection failed: {}", e),
+                            Err(_) => tracing::error!("a request panicked"),
+                        },
+                        Err(e) => tracing::warn!("failed to accept a connection: {}", e),
+                    }
+                })
+                .expect("failed to start a server worker thread");
+        }
+    });
+}
+
+// Answers one request and closes the connection.
+fn handle(stream: TcpStream, options: &ServeOptions, config: &Config, cache: &ProgramCache) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(usize::MAX);
+            }
+        }
+    }
+
+    let (status, body) = match (method, path) {
+        ("POST", "/run") if length > MAX_BODY => {
+            (413, json!({ "error": format!("Request bodies are limited to {} bytes", MAX_BODY) }))
+        }
+        ("POST", "/run") => {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            match serde_json::from_slice::<RunRequest>(&body) {
+                Ok(request) => (200, run(request, options, config, cache)),
+                Err(e) => (400, json!({ "error": format!("Invalid request: {}", e) })),
+            }
+        }
+        (_, "/run") => (405, json!({ "error": "Use POST /run" })),
+        ("GET", "/stats") => (200, json!({ "cache": cache.stats() })),
+        (_, "/stats") => (405, json!({ "error": "Use GET /stats" })),
+        _ => (404, json!({ "error": format!("No such endpoint: {}", path) })),
+    };
+    respond(&stream, status, &body)
+}
+
+fn respond(mut stream: &TcpStream, status: u16, body: &serde_json::Value) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Payload Too Large",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// Runs a request's program and describes how it went. A program that fails
+// still gets a 200 response, with the error among the diagnostics after any
+// warnings. `stats` says what the run used, from `Interpreter::stats`, and is
+// null when the program did not parse.
+fn run(request: RunRequest, options: &ServeOptions, config: &Config, cache: &ProgramCache) -> serde_json::Value {
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    interpreter.set_input(Box::new(Cursor::new(request.inputs.join("\n"))), false);
+    interpreter.set_limits(lower(options.limits, &request.limits));
+    interpreter.set_options(config.run_options());
+
+    let program = request.parse_cached(config, cache);
+    let started = program.is_ok();
+    let result = program.and_then(|program| interpreter.interpret(program));
+
+    let warnings = if started { interpreter.warnings() } else { &[] };
+    let diagnostics: Vec<serde_json::Value> =
+        warnings.iter().chain(result.as_ref().err()).map(Error::to_json).collect();
+    json!({
+        "ok": result.is_ok(),
+        "output": interpreter.output_so_far(),
+        "diagnostics": diagnostics,
+        "stats": started.then(|| interpreter.stats().to_json()),
+    })
+}
+
+// The server's limits, lowered wherever the request asks for less.
+pub(crate) fn lower(limits: Limits, request: &RequestLimits) -> Limits {
+    fn min<T: Ord>(server: Option<T>, request: Option<T>) -> Option<T> {
+        match (server, request) {
+            (Some(server), Some(request)) => Some(server.min(request)),
+            (server, request) => server.or(request),
+        }
+    }
+    Limits {
+        steps: min(limits.steps, request.steps),
+        output: min(limits.output, request.output),
+        time: min(limits.time, request.time_ms.map(Duration::from_millis)),
+        memory: min(limits.memory, request.memory),
+    }
+}
@@ -0,0 +1,99 @@
+use crate::ast::AstNode;
+use crate::color::Colors;
+use crate::desugar;
+use crate::error::Error;
+use crate::interpreter::{Interpreter, Limits, Stats, STACK_SIZE};
+use crate::lexer::Lexer;
+use crate::parser::Dialect;
+use std::collections::BTreeMap;
+use std::io::{self, Cursor};
+use std::sync::Arc;
+
+// Helpers for golden tests of programs and of tools built on the interpreter.
+// They return what a program did and what its tree looks like as text, so a
+// test can compare them with a saved copy instead of capturing stdout.
+
+// Stops a program that never ends from hanging a test suite. Only steps are
+// limited, as a time limit would make results depend on the machine.
+const MAX_STEPS: u64 = 10_000_000;
+
+// Everything one run of a program did.
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    // What the program wrote, without input prompts.
+    pub output: String,
+    // The error it stopped with: a syntax error if it did not parse.
+    pub error: Option<Error>,
+    // The variables at the end, printed as `output` would print them.
+    pub variables: BTreeMap<String, String>,
+    // How much work the run took. A program that did not parse did none.
+    pub stats: Stats,
+}
+
+impl RunReport {
+    // The report as text for a snapshot file. Each part has a header line,
+    // and output that does not end with a new line is marked as such.
+    pub fn to_snapshot(&self) -> String {
+        let mut text = String::from("--- output\n");
+        text.push_str(&self.output);
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            text.push_str("\n(no newline at end of output)\n");
+        }
+        if let Some(error) = &self.error {
+            text.push_str("--- error\n");
+            text.push_str(&error.render(&Colors::plain()));
+            text.push('\n');
+        }
+        text.push_str("--- variables\n");
+        for (name, value) in &self.variables {
+            text.push_str(&format!("{} = {}\n", name, value));
+        }
+        text
+    }
+}
+
+// Parses `source` in the extended dialect, which accepts every IB program,
+// and runs it with `inputs` as its lines of standard input.
+pub fn run_to_string(source: &str, inputs: &[&str]) -> RunReport {
+    match desugar::parse(Lexer::new(source), Dialect::Extended) {
+        Ok(program) => run_program(program, inputs),
+        Err(error) => RunReport {
+            output: String::new(),
+            error: Some(error),
+            variables: BTreeMap::new(),
+            stats: Stats::default(),
+        },
+    }
+}
+
+// Runs a parsed program the way `run_to_string` does.
+pub fn run_program(program: AstNode, inputs: &[&str]) -> RunReport {
+    let input: String = inputs.iter().map(|line| format!("{}\n", line)).collect();
+    let program = Arc::new(program);
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+            interpreter.set_input(Box::new(Cursor::new(input)), false);
+            interpreter.set_limits(Limits { steps: Some(MAX_STEPS), ..Limits::default() });
+            let error = interpreter.interpret(program).err();
+            let variables =
+                interpreter.variables().into_iter().map(|(name, value)| (name, value.to_string())).collect();
+            RunReport {
+                output: interpreter.output_so_far().to_string(),
+                error,
+                variables,
+                stats: interpreter.stats(),
+            }
+        })
+        .expect("failed to start the interpreter thread")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+// The tree of `program` as `run --print-ast` shows it, without color. Spans
+// are left out, so editing one part of a program leaves the rest of its
+// snapshot alone.
+pub fn ast_snapshot(program: &AstNode) -> String {
+    program.to_string()
+}
@@ -0,0 +1,52 @@
+use crate::error::Error;
+use crate::value::Value;
+
+// Builtins that draw on the terminal with ANSI escape sequences. They only run
+// when terminal access is allowed, since they write control codes into the
+// program's output.
+pub const BUILTINS: [&str; 3] = ["setColor", "clearScreen", "moveCursor"];
+
+// Returns the escape sequence for a terminal builtin.
+pub fn sequence(name: &str, args: &[Value]) -> Result<String, Error> {
+    match (name, args) {
+        ("setColor", [Value::String(color)]) => {
+            let code = match color.to_lowercase().as_str() {
+                "black" => 30,
+                "red" => 31,
+                "green" => 32,
+                "yellow" => 33,
+                "blue" => 34,
+                "magenta" => 35,
+                "cyan" => 36,
+                "white" => 37,
+                "default" | "reset" => 0,
+                _ => {
+                    return Err(Error::runtime(
+                        "E0209",
+                        format!(
+                            "Unknown color '{}', expected black, red, green, yellow, blue, magenta, cyan, white or default",
+                            color
+                        ),
+                    ))
+                }
+            };
+            Ok(format!("\x1b[{}m", code))
+        }
+        ("clearScreen", []) => Ok("\x1b[2J\x1b[H".to_string()),
+        ("moveCursor", [Value::Number(row), Value::Number(column)]) if *row >= 1 && *column >= 1 => {
+            Ok(format!("\x1b[{};{}H", row, column))
+        }
+        ("moveCursor", [Value::Number(_), Value::Number(_)]) => {
+            Err(Error::runtime("E0209", "moveCursor expects a row and column of at least 1"))
+        }
+        _ => Err(Error::runtime("E0208", format!("{} {}", name, usage(name)))),
+    }
+}
+
+fn usage(name: &str) -> &'static str {
+    match name {
+        "setColor" => "expects one string argument, such as \"red\"",
+        "clearScreen" => "takes no arguments",
+        _ => "expects a row and a column number",
+    }
+}
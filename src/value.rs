@@ -0,0 +1,248 @@
+use crate::heap;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+// Numbers and strings are copied on assignment. Strings are never changed in
+// place, so copies share their text behind an `Rc`, and assigning or passing
+// a long string costs no more than a number; joining strings makes a new one.
+// Arrays are references, as in IB pseudocode: after `B = A` both names share
+// one array, so changing an element through either is visible through the
+// other. Use `A.copy()` for an independent array. `Null` stands for no value,
+// such as the result of a sub. See `heap` for how arrays are kept and freed.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Number(i64),
+    Float(f64),
+    String(Rc<str>),
+    Array(Array),
+    Null,
+}
+
+pub type Array = Rc<RefCell<Vec<Value>>>;
+
+impl Value {
+    pub fn string(text: impl Into<Rc<str>>) -> Self {
+        Value::String(text.into())
+    }
+
+    pub fn array(items: Vec<Value>) -> Self {
+        Value::Array(heap::allocate(items))
+    }
+
+    // Input lines that look like numbers become numbers, anything else stays text.
+    pub fn from_input(line: &str) -> Self {
+        let trimmed = line.trim();
+        if let Ok(number) = trimmed.parse() {
+            return Value::Number(number);
+        }
+        match trimmed.parse::<f64>() {
+            Ok(number) if number.is_finite() && trimmed.contains(|c: char| c.is_ascii_digit()) => Value::Float(number),
+            _ => Value::string(line),
+        }
+    }
+
+    // The type with its article, for messages like "got an array".
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "a number",
+            Value::Float(_) => "a real number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "an array",
+            Value::Null => "null",
+        }
+    }
+
+    // Orders two numbers or two strings; other pairs have no order. Zero
+    // and negative zero are equal, and NaN has no order.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(l), Value::Number(r)) => Some(l.cmp(r)),
+            (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+            _ => self.as_f64()?.partial_cmp(&other.as_f64()?),
+        }
+    }
+
+    // Bytes a string's text or an array's elements take, with the text of
+    // strings among the elements but not what arrays among them hold.
+    pub fn size(&self) -> usize {
+        match self {
+            Value::String(text) => text.len(),
+            Value::Array(items) => {
+                let items = items.borrow();
+                let text: usize = items.iter().filter(|item| matches!(item, Value::String(_))).map(Value::size).sum();
+                items.len() * std::mem::size_of::<Value>() + text
+            }
+            Value::Number(_) | Value::Float(_) | Value::Null => 0,
+        }
+    }
+
+    // Either kind of number as a float, for arithmetic that mixes them.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n as f64),
+            Value::Float(n) => Some(*n),
+            Value::String(_) | Value::Array(_) | Value::Null => None,
+        }
+    }
+}
+
+// Numbers compare by value, so `1 = 1.0` holds. Null equals only null. Arrays
+// are equal when they hold equal elements in the same order, however deeply
+// they nest, so `RESULT = [1, 2, 3]` checks a whole result. Values of
+// different types are never equal.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        equal(self, other, &mut vec![])
+    }
+}
+
+// An array's identity, for noticing one that contains itself.
+type ArrayId = *const RefCell<Vec<Value>>;
+
+// `open` holds the pairs of arrays being compared further up. Meeting a pair
+// again means it has matched all the way round a cycle, so only a difference
+// found elsewhere makes the two unequal.
+fn equal(left: &Value, right: &Value, open: &mut Vec<(ArrayId, ArrayId)>) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l == r,
+        (Value::String(l), Value::String(r)) => l == r,
+        (Value::Array(l), Value::Array(r)) => {
+            let pair = (Rc::as_ptr(l), Rc::as_ptr(r));
+            if Rc::ptr_eq(l, r) || open.contains(&pair) {
+                return true;
+            }
+            let (l, r) = (l.borrow(), r.borrow());
+            if l.len() != r.len() {
+                return false;
+            }
+            open.push(pair);
+            let equal = l.iter().zip(r.iter()).all(|(l, r)| equal(l, r, open));
+            open.pop();
+            equal
+        }
+        (Value::Null, Value::Null) => true,
+        (Value::String(_) | Value::Array(_), _) | (_, Value::String(_) | Value::Array(_)) => false,
+        _ => left.as_f64() == right.as_f64(),
+    }
+}
+
+// Floats print rounded to ten decimal places with trailing zeros dropped, so
+// `0.1 + 0.2` shows as 0.3 rather than 0.30000000000000004. A whole float
+// keeps one decimal place to tell it apart from an integer.
+pub fn format_float(value: f64) -> String {
+    let text = format!("{:.10}", value);
+    let text = text.trim_end_matches('0');
+    let text = if text.ends_with('.') { format!("{}0", text) } else { text.to_string() };
+    if text == "-0.0" {
+        "0.0".to_string()
+    } else {
+        text
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", format_float(*value)),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Array(items) => write_array(f, items, &mut vec![]),
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+// `open` holds the arrays being printed, so an array that contains itself
+// prints as `[...]` instead of recursing forever.
+fn write_array(f: &mut fmt::Formatter, array: &Array, open: &mut Vec<ArrayId>) -> fmt::Result {
+    if open.contains(&Rc::as_ptr(array)) {
+        return write!(f, "[...]");
+    }
+    open.push(Rc::as_ptr(array));
+    write!(f, "[")?;
+    for (index, item) in array.borrow().iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        // Quote strings so `["a, b"]` and `["a", "b"]` print differently.
+        match item {
+            Value::String(text) => write!(f, "\"{}\"", text)?,
+            Value::Array(inner) => write_array(f, inner, open)?,
+            _ => write!(f, "{}", item)?,
+        }
+    }
+    open.pop();
+    write!(f, "]")
+}
+
+// How much of a value `Value::preview` shows. Arrays nested `depth` levels in
+// are summed up by their length, and only the first `items` elements of each
+// array are shown.
+#[derive(Debug, Clone, Copy)]
+pub struct Preview {
+    pub depth: usize,
+    pub items: usize,
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Preview { depth: 3, items: 10 }
+    }
+}
+
+impl Value {
+    // The value as a notebook or debugger shows it, unlike `output`: strings
+    // are quoted, so `"7"` and `7` look different, and large arrays are cut
+    // short, as in `[[1, 2], [... 40 items], 3, ... 97 more]`.
+    pub fn preview(&self, limits: Preview) -> String {
+        let mut out = String::new();
+        write_preview(&mut out, self, limits, 0, &mut vec![]);
+        out
+    }
+}
+
+fn write_preview(
+    out: &mut String,
+    value: &Value,
+    limits: Preview,
+    depth: usize,
+    open: &mut Vec<ArrayId>,
+) {
+    let array = match value {
+        Value::String(text) => {
+            out.push_str(&format!("\"{}\"", text));
+            return;
+        }
+        Value::Array(array) if !open.contains(&Rc::as_ptr(array)) => array,
+        Value::Array(_) => {
+            out.push_str("[...]");
+            return;
+        }
+        _ => {
+            out.push_str(&value.to_string());
+            return;
+        }
+    };
+    let items = array.borrow();
+    if depth >= limits.depth && !items.is_empty() {
+        let noun = if items.len() == 1 { "item" } else { "items" };
+        out.push_str(&format!("[... {} {}]", items.len(), noun));
+        return;
+    }
+    open.push(Rc::as_ptr(array));
+    out.push('[');
+    for (index, item) in items.iter().take(limits.items).enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        write_preview(out, item, limits, depth + 1, open);
+    }
+    if items.len() > limits.items {
+        let separator = if limits.items > 0 { ", " } else { "" };
+        out.push_str(&format!("{}... {} more", separator, items.len() - limits.items));
+    }
+    open.pop();
+    out.push(']');
+}
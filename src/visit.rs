@@ -0,0 +1,215 @@
+use crate::ast::{AstNode, CaseBranch, NodeKind, Param, Procedure};
+use std::sync::Arc;
+
+// Passes over a tree, written as traits so a pass only says what it does with
+// the nodes it cares about. Each method's default goes on into the children,
+// through the function of the same name here; a pass that overrides a method
+// calls that function to keep going, or leaves it out to skip the children.
+//
+//     struct Calls(Vec<String>);
+//
+//     impl<'a> Visit<'a> for Calls {
+//         fn visit_node(&mut self, node: &'a AstNode) {
+//             if let NodeKind::Call(name, _) = &node.kind {
+//                 self.0.push(name.clone());
+//             }
+//             visit::walk_node(self, node);
+//         }
+//     }
+//
+// Children are visited in source order, as `AstNode::children` lists them.
+// Lists of statements go through `visit_block`, and definitions through
+// `visit_procedure`, so a pass can track blocks and procedures without
+// matching on every kind of node that holds them.
+
+// Looks at a tree without changing it.
+pub trait Visit<'a> {
+    fn visit_node(&mut self, node: &'a AstNode) {
+        walk_node(self, node);
+    }
+
+    fn visit_block(&mut self, statements: &'a [AstNode]) {
+        walk_block(self, statements);
+    }
+
+    fn visit_procedure(&mut self, procedure: &'a Procedure) {
+        walk_procedure(self, procedure);
+    }
+}
+
+pub fn walk_node<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, node: &'a AstNode) {
+    match &node.kind {
+        NodeKind::Program(statements) => visitor.visit_block(statements),
+        NodeKind::Array(items) | NodeKind::NewArray(items) | NodeKind::Call(_, items) => {
+            items.iter().for_each(|item| visitor.visit_node(item));
+        }
+        NodeKind::Assignment(_, expr) | NodeKind::Output(expr, _) | NodeKind::Raise(expr) => visitor.visit_node(expr),
+        NodeKind::Input(_, prompt) => prompt.iter().for_each(|prompt| visitor.visit_node(prompt)),
+        NodeKind::If(condition, true_branch, false_branch) => {
+            visitor.visit_node(condition);
+            visitor.visit_block(true_branch);
+            visitor.visit_block(false_branch);
+        }
+        NodeKind::Loop(condition, body) => {
+            visitor.visit_node(condition);
+            visitor.visit_block(body);
+        }
+        NodeKind::Case(value, branches, default) => {
+            visitor.visit_node(value);
+            for branch in branches {
+                branch.labels.iter().for_each(|label| visitor.visit_node(label));
+                visitor.visit_block(&branch.body);
+            }
+            if let Some(default) = default {
+                visitor.visit_block(default);
+            }
+        }
+        NodeKind::BinOp(left, _, right) | NodeKind::Index(left, right) => {
+            visitor.visit_node(left);
+            visitor.visit_node(right);
+        }
+        NodeKind::Conditional(condition, then, otherwise) => {
+            visitor.visit_node(condition);
+            visitor.visit_node(then);
+            visitor.visit_node(otherwise);
+        }
+        NodeKind::MethodCall(receiver, _, args) => {
+            visitor.visit_node(receiver);
+            args.iter().for_each(|arg| visitor.visit_node(arg));
+        }
+        NodeKind::SetElement(_, indices, expr) => {
+            indices.iter().for_each(|index| visitor.visit_node(index));
+            visitor.visit_node(expr);
+        }
+        NodeKind::Procedure(procedure) => visitor.visit_procedure(procedure),
+        NodeKind::Return(value) => value.iter().for_each(|value| visitor.visit_node(value)),
+        NodeKind::Try(body, _, handler) => {
+            visitor.visit_block(body);
+            visitor.visit_block(handler);
+        }
+        NodeKind::Number(_) | NodeKind::Float(_) | NodeKind::String(_) | NodeKind::Identifier(_) => {}
+        NodeKind::Global(_) | NodeKind::Null => {}
+    }
+}
+
+pub fn walk_block<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, statements: &'a [AstNode]) {
+    for statement in statements {
+        visitor.visit_node(statement);
+    }
+}
+
+// Parameter defaults come before the body, as they are evaluated first.
+pub fn walk_procedure<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, procedure: &'a Procedure) {
+    for default in procedure.params.iter().filter_map(|param| param.default.as_ref()) {
+        visitor.visit_node(default);
+    }
+    visitor.visit_block(&procedure.body);
+}
+
+// Builds a new tree from an old one, taking it apart as it goes. A pass can
+// replace a node, or drop and add statements in a block.
+pub trait Fold {
+    fn fold_node(&mut self, node: AstNode) -> AstNode {
+        fold_node(self, node)
+    }
+
+    fn fold_block(&mut self, statements: Vec<AstNode>) -> Vec<AstNode> {
+        fold_block(self, statements)
+    }
+
+    fn fold_procedure(&mut self, procedure: Procedure) -> Procedure {
+        fold_procedure(self, procedure)
+    }
+}
+
+pub fn fold_node<F: Fold + ?Sized>(folder: &mut F, node: AstNode) -> AstNode {
+    let kind = match node.kind {
+        NodeKind::Program(statements) => NodeKind::Program(folder.fold_block(statements)),
+        NodeKind::Assignment(name, expr) => NodeKind::Assignment(name, fold_boxed(folder, expr)),
+        NodeKind::Output(expr, newline) => NodeKind::Output(fold_boxed(folder, expr), newline),
+        NodeKind::Input(name, prompt) => NodeKind::Input(name, prompt.map(|node| fold_boxed(folder, node))),
+        NodeKind::If(condition, true_branch, false_branch) => {
+            let condition = fold_boxed(folder, condition);
+            NodeKind::If(condition, folder.fold_block(true_branch), folder.fold_block(false_branch))
+        }
+        NodeKind::Loop(condition, body) => {
+            let condition = fold_boxed(folder, condition);
+            NodeKind::Loop(condition, folder.fold_block(body))
+        }
+        NodeKind::Case(value, branches, default) => {
+            let value = fold_boxed(folder, value);
+            let branches = branches
+                .into_iter()
+                .map(|branch| CaseBranch {
+                    labels: branch.labels.into_iter().map(|label| folder.fold_node(label)).collect(),
+                    body: folder.fold_block(branch.body),
+                })
+                .collect();
+            NodeKind::Case(value, branches, default.map(|default| folder.fold_block(default)))
+        }
+        NodeKind::BinOp(left, op, right) => {
+            let left = fold_boxed(folder, left);
+            NodeKind::BinOp(left, op, fold_boxed(folder, right))
+        }
+        NodeKind::Conditional(condition, then, otherwise) => {
+            let (condition, then) = (fold_boxed(folder, condition), fold_boxed(folder, then));
+            NodeKind::Conditional(condition, then, fold_boxed(folder, otherwise))
+        }
+        NodeKind::Call(name, args) => NodeKind::Call(name, fold_all(folder, args)),
+        NodeKind::Array(items) => NodeKind::Array(fold_all(folder, items)),
+        NodeKind::NewArray(sizes) => NodeKind::NewArray(fold_all(folder, sizes)),
+        NodeKind::Index(array, index) => {
+            let array = fold_boxed(folder, array);
+            NodeKind::Index(array, fold_boxed(folder, index))
+        }
+        NodeKind::MethodCall(receiver, name, args) => {
+            let receiver = fold_boxed(folder, receiver);
+            NodeKind::MethodCall(receiver, name, fold_all(folder, args))
+        }
+        NodeKind::SetElement(name, indices, expr) => {
+            let indices = fold_all(folder, indices);
+            NodeKind::SetElement(name, indices, fold_boxed(folder, expr))
+        }
+        // A procedure shared with another tree is copied, leaving that tree
+        // as it was.
+        NodeKind::Procedure(procedure) => {
+            NodeKind::Procedure(Arc::new(folder.fold_procedure(Arc::unwrap_or_clone(procedure))))
+        }
+        NodeKind::Return(value) => NodeKind::Return(value.map(|node| fold_boxed(folder, node))),
+        NodeKind::Raise(message) => NodeKind::Raise(fold_boxed(folder, message)),
+        NodeKind::Try(body, name, handler) => {
+            let body = folder.fold_block(body);
+            NodeKind::Try(body, name, folder.fold_block(handler))
+        }
+        kind @ (NodeKind::Number(_)
+        | NodeKind::Float(_)
+        | NodeKind::String(_)
+        | NodeKind::Identifier(_)
+        | NodeKind::Global(_)
+        | NodeKind::Null) => kind,
+    };
+    AstNode::new(kind, node.span)
+}
+
+pub fn fold_block<F: Fold + ?Sized>(folder: &mut F, statements: Vec<AstNode>) -> Vec<AstNode> {
+    fold_all(folder, statements)
+}
+
+pub fn fold_procedure<F: Fold + ?Sized>(folder: &mut F, procedure: Procedure) -> Procedure {
+    let params = procedure
+        .params
+        .into_iter()
+        .map(|param| Param { default: param.default.map(|default| folder.fold_node(default)), ..param })
+        .collect();
+    Procedure { params, body: folder.fold_block(procedure.body), ..procedure }
+}
+
+fn fold_all<F: Fold + ?Sized>(folder: &mut F, nodes: Vec<AstNode>) -> Vec<AstNode> {
+    nodes.into_iter().map(|node| folder.fold_node(node)).collect()
+}
+
+// Folds the node in a box, keeping the box.
+fn fold_boxed<F: Fold + ?Sized>(folder: &mut F, mut node: Box<AstNode>) -> Box<AstNode> {
+    *node = folder.fold_node(*node);
+    node
+}
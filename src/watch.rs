@@ -0,0 +1,32 @@
+use std::fs;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn modified_times(files: &[String]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+// Calls `action` once immediately and again every time one of `files`
+// changes on disk, clearing the terminal first. The clearing and the status
+// line go to stderr, so only the action's own output reaches a pipe. Never
+// returns.
+pub fn watch(files: &[String], mut action: impl FnMut()) -> ! {
+    let mut last_seen = None;
+    loop {
+        let current = modified_times(files);
+        if last_seen.as_ref() != Some(&current) {
+            last_seen = Some(current);
+            eprint!("\x1b[2J\x1b[H");
+            action();
+            let _ = io::stdout().flush();
+            eprintln!("\n[watching {} for changes]", files.join(", "));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
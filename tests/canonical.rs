@@ -0,0 +1,70 @@
+use ibcspsuedolang::ast::AstNode;
+use ibcspsuedolang::keywords::Keywords;
+use ibcspsuedolang::lexer::Lexer;
+use ibcspsuedolang::parser::{Dialect, Parser};
+use ibcspsuedolang::snapshot;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// `fmt --canonical ib` and `fmt --canonical lowercase` undo each other: an
+// example formatted in lowercase, rewritten in IB notation and back, comes
+// out byte for byte as it went in, and the IB version parses to the same tree.
+
+const EXAMPLES: [&str; 5] = ["cipher", "conditional", "procedures", "references", "equality"];
+
+// Runs `fmt` with `args` on `source`, written to a file of its own.
+fn fmt(source: &str, name: &str, args: &[&str]) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!("canonical-{}-{}.txt", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ibcspsuedolang"))
+        .args(["--dialect", "extended", "fmt"])
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn parse(source: &str, keywords: &Keywords) -> AstNode {
+    Parser::new(Lexer::new(source).with_keywords(keywords)).unwrap().with_dialect(Dialect::Extended).parse().unwrap()
+}
+
+fn round_trip(end_style: &str) {
+    for example in EXAMPLES {
+        let source = fs::read_to_string(format!("examples/{}.txt", example)).unwrap();
+        // Tests run at once, so each file is named for its end style too.
+        let name = format!("{}-{}", example, end_style);
+        let lowercase = fmt(&source, &name, &[]);
+        let ib = fmt(&lowercase, &name, &["--canonical", "ib", "--end-style", end_style]);
+        assert_eq!(
+            snapshot::ast_snapshot(&parse(&ib, &Keywords::ib(true))),
+            snapshot::ast_snapshot(&parse(&lowercase, &Keywords::english())),
+            "{} in IB notation parses differently",
+            example
+        );
+        assert_eq!(fmt(&ib, &name, &["--canonical", "lowercase"]), lowercase, "{} did not round-trip", example);
+    }
+}
+
+#[test]
+fn round_trips_with_spaced_end_keywords() {
+    round_trip("spaced");
+}
+
+#[test]
+fn round_trips_with_joined_end_keywords() {
+    round_trip("joined");
+}
+
+#[test]
+fn end_style_chooses_the_closing_keywords() {
+    let source = "if X > 1 then\n    output X\nendif\n";
+    assert_eq!(fmt(source, "end-style-spaced", &["--canonical", "ib"]), "IF X > 1 THEN\n    OUTPUT X\nEND IF\n");
+    assert_eq!(
+        fmt(source, "end-style-joined", &["--canonical", "ib", "--end-style", "joined"]),
+        "IF X > 1 THEN\n    OUTPUT X\nENDIF\n"
+    );
+}
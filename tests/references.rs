@@ -0,0 +1,93 @@
+use ibcspsuedolang::snapshot::{self, RunReport};
+
+// Arrays are shared by reference, as IB pseudocode expects; numbers and
+// strings are copied. Each test runs a program and checks its variables at
+// the end, as `output` would print them.
+
+fn run(source: &str) -> RunReport {
+    let report = snapshot::run_to_string(source, &[]);
+    assert!(report.error.is_none(), "{:?}", report.error);
+    report
+}
+
+fn variable<'a>(report: &'a RunReport, name: &str) -> &'a str {
+    report.variables.get(name).unwrap_or_else(|| panic!("{} is not set", name))
+}
+
+#[test]
+fn assigning_an_array_shares_it() {
+    let report = run("A = [1, 2, 3]\nB = A\nB[0] = 9\nB.append(4)\n");
+    assert_eq!(variable(&report, "A"), "[9, 2, 3, 4]");
+    assert_eq!(variable(&report, "B"), "[9, 2, 3, 4]");
+}
+
+#[test]
+fn assigning_a_number_or_string_copies_it() {
+    let report = run("N = 5\nM = N\nM = 6\nS = \"abc\"\nT = S\nT = T + \"d\"\n");
+    assert_eq!(variable(&report, "N"), "5");
+    assert_eq!(variable(&report, "S"), "abc");
+}
+
+#[test]
+fn copy_makes_an_independent_array() {
+    let report = run("A = [1, 2, 3]\nC = A.copy()\nC[1] = 7\nC.append(4)\nA[0] = 0\n");
+    assert_eq!(variable(&report, "A"), "[0, 2, 3]");
+    assert_eq!(variable(&report, "C"), "[1, 7, 3, 4]");
+}
+
+#[test]
+fn a_procedure_changes_the_callers_array_without_ref() {
+    let source = "\
+sub fill(ITEMS)
+    ITEMS[0] = 9
+    ITEMS.append(4)
+endsub
+
+A = [1, 2, 3]
+fill(A)
+";
+    assert_eq!(variable(&run(source), "A"), "[9, 2, 3, 4]");
+}
+
+#[test]
+fn a_procedure_changes_the_callers_array_with_ref() {
+    let source = "\
+sub fill(ref ITEMS)
+    ITEMS[0] = 9
+endsub
+
+A = [1, 2, 3]
+fill(A)
+";
+    assert_eq!(variable(&run(source), "A"), "[9, 2, 3]");
+}
+
+#[test]
+fn reassigning_a_parameter_leaves_the_caller_alone() {
+    let source = "\
+sub replace(ITEMS, N)
+    ITEMS = [0]
+    N = N + 1
+endsub
+
+A = [1, 2, 3]
+COUNT = 10
+replace(A, COUNT)
+";
+    let report = run(source);
+    assert_eq!(variable(&report, "A"), "[1, 2, 3]");
+    assert_eq!(variable(&report, "COUNT"), "10");
+}
+
+#[test]
+fn reassigning_a_ref_parameter_changes_the_caller() {
+    let source = "\
+sub replace(ref ITEMS)
+    ITEMS = [0]
+endsub
+
+A = [1, 2, 3]
+replace(A)
+";
+    assert_eq!(variable(&run(source), "A"), "[0]");
+}